@@ -0,0 +1,822 @@
+//! Off-chain client for the SPL token escrow program
+//!
+//! Derives the program's addresses, builds its instructions from the
+//! Anchor-generated `spl_escrow::accounts` and `spl_escrow::instruction`
+//! types, and decodes its accounts, so bots and backends need neither
+//! hand-rolled seeds nor discriminators. Fetching is left to the caller's
+//! RPC client through [`AccountFetcher`].
+//!
+//! Builders take amounts as [`RawAmount`]s, which carry their mint's
+//! decimals, so amounts typed in whole tokens go through [`UiAmount`]
+//! rather than a hand-written factor of `10^decimals`. Failed
+//! instructions decode through [`decode_error`] into a [`DecodedError`]
+//! with a suggested fix where one is known.
+
+use std::mem::size_of;
+
+use anchor_lang::{
+    error::{ErrorCode, ERROR_CODE_OFFSET},
+    prelude::*,
+    solana_program::instruction::{Instruction, InstructionError},
+    system_program, Discriminator, InstructionData,
+};
+use anchor_spl::{
+    associated_token::{self, get_associated_token_address_with_program_id},
+    token::spl_token,
+    token_2022::spl_token_2022::{self, error::TokenError},
+    token_interface::Mint,
+};
+use spl_escrow::{
+    accounts, instruction, Config, Escrow, EscrowError, ACCOUNT_COMPRESSION_PROGRAM_ID,
+    NOOP_PROGRAM_ID,
+};
+
+pub use spl_escrow::ID as PROGRAM_ID;
+
+/// Seeds identifying an escrow. `seller` is the seller the escrow was
+/// listed by ([`Escrow::listed_by`]), which the builders here also sign
+/// as; an escrow whose ownership has since been transferred keeps its
+/// address under the original seller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EscrowKey {
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    pub escrow_id: u64,
+}
+
+impl EscrowKey {
+    pub fn address(&self) -> Pubkey {
+        find_escrow_address(
+            &self.seller,
+            &self.offer_mint,
+            &self.request_mint,
+            self.escrow_id,
+        )
+        .0
+    }
+}
+
+/// Amount of a mint in its base units, carrying the mint's decimals
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawAmount {
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+impl RawAmount {
+    pub fn new(amount: u64, decimals: u8) -> Self {
+        Self { amount, decimals }
+    }
+
+    /// The amount in whole tokens, e.g. 1500000 at 6 decimals is `1.5`
+    pub fn to_ui(self) -> UiAmount {
+        UiAmount {
+            value: spl_token_2022::amount_to_ui_amount_string_trimmed(self.amount, self.decimals),
+            raw: self,
+        }
+    }
+}
+
+/// Amount of a mint in whole tokens as people write it, such as `1.5`.
+/// Interest-bearing mints are not scaled; their UI amounts are quoted by
+/// the program's events instead
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UiAmount {
+    value: String,
+    raw: RawAmount,
+}
+
+impl UiAmount {
+    /// Parse `value` for a mint of `decimals` decimals; values with more
+    /// fractional digits than the mint has, or beyond `u64` base units,
+    /// are refused rather than rounded
+    pub fn parse(value: &str, decimals: u8) -> Result<Self> {
+        let amount = spl_token_2022::try_ui_amount_into_amount(value.to_string(), decimals)
+            .map_err(|_| error!(EscrowError::InvalidUiAmount))?;
+        Ok(RawAmount::new(amount, decimals).to_ui())
+    }
+
+    pub fn to_raw(&self) -> RawAmount {
+        self.raw
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+}
+
+impl std::fmt::Display for UiAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.value)
+    }
+}
+
+pub fn find_escrow_address(
+    seller: &Pubkey,
+    offer_mint: &Pubkey,
+    request_mint: &Pubkey,
+    escrow_id: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"escrow",
+            seller.as_ref(),
+            offer_mint.as_ref(),
+            request_mint.as_ref(),
+            &escrow_id.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Vault of a custodial escrow: its associated token account of the offer
+/// mint under the mint's token program. Escrows created before layout
+/// version 12 keep a `["vault", escrow]` PDA instead, and delegated
+/// escrows pull from the seller's account; [`Escrow::vault`] resolves
+/// any escrow's vault from its state
+pub fn find_vault_address(escrow: &Pubkey, offer_mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    get_associated_token_address_with_program_id(escrow, offer_mint, token_program)
+}
+
+pub fn find_config_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], &PROGRAM_ID)
+}
+
+pub fn find_pair_index_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"pair_index"], &PROGRAM_ID)
+}
+
+pub fn find_stats_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"stats"], &PROGRAM_ID)
+}
+
+pub fn find_seller_index_address(seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"seller_index", seller.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_reputation_address(seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"reputation", seller.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_mint_policy_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"mint_policy", mint.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_seller_ban_address(seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"seller_ban", seller.as_ref()], &PROGRAM_ID)
+}
+
+/// Page `page` of a mint pair's market index
+pub fn find_market_index_address(
+    offer_mint: &Pubkey,
+    request_mint: &Pubkey,
+    page: u32,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"market_index",
+            offer_mint.as_ref(),
+            request_mint.as_ref(),
+            &page.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+pub fn find_mint_limits_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"mint_limits", mint.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_proceeds_address(escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"proceeds", escrow.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_fee_vault_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"fee_vault", mint.as_ref()], &PROGRAM_ID)
+}
+
+/// Bid escrow a buyer posted for a mint pair
+pub fn find_bid_address(
+    buyer: &Pubkey,
+    offer_mint: &Pubkey,
+    request_mint: &Pubkey,
+    bid_id: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"bid_escrow",
+            buyer.as_ref(),
+            offer_mint.as_ref(),
+            request_mint.as_ref(),
+            &bid_id.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+pub fn find_bid_vault_address(bid: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"bid_vault", bid.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_rfq_address(buyer: &Pubkey, rfq_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"rfq", buyer.as_ref(), &rfq_id.to_le_bytes()],
+        &PROGRAM_ID,
+    )
+}
+
+/// A seller's quote on an RFQ
+pub fn find_rfq_quote_address(rfq: &Pubkey, seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"rfq_quote", rfq.as_ref(), seller.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_rfq_quote_vault_address(quote: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"rfq_quote_vault", quote.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_session_address(owner: &Pubkey, session_key: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"session", owner.as_ref(), session_key.as_ref()],
+        &PROGRAM_ID,
+    )
+}
+
+/// Signer of the program's self-CPI event logs
+pub fn find_event_authority_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"__event_authority"], &PROGRAM_ID)
+}
+
+/// Any instruction of the program from its generated accounts and data
+pub fn build_instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// `create_escrow` for a plain listing, funded by `payer`; the seller and
+/// payer sign. `seller_offer_token` is `None` when offering native SOL,
+/// and `activity_log` is the config's activity log tree, if it keeps one.
+/// The amounts are in base units of the offer and request mints, whose
+/// decimals, as read by [`fetch_mint_decimals`], they must carry
+#[allow(clippy::too_many_arguments)]
+pub fn create_escrow(
+    key: &EscrowKey,
+    payer: &Pubkey,
+    seller_offer_token: Option<Pubkey>,
+    token_program: &Pubkey,
+    offer_amount: RawAmount,
+    request_amount: RawAmount,
+    offer_mint_decimals: u8,
+    request_mint_decimals: u8,
+    expires_at: Option<i64>,
+    activity_log: Option<Pubkey>,
+) -> Result<Instruction> {
+    if offer_amount.decimals != offer_mint_decimals
+        || request_amount.decimals != request_mint_decimals
+    {
+        return Err(ProgramError::from(TokenError::MintDecimalsMismatch).into());
+    }
+    let escrow = key.address();
+    Ok(build_instruction(
+        accounts::CreateEscrow {
+            seller: key.seller,
+            payer: *payer,
+            offer_mint: key.offer_mint,
+            request_mint: key.request_mint,
+            seller_offer_token,
+            market: None,
+            offer_metadata: None,
+            buyer_allowlist: None,
+            seller_stake: None,
+            escrow,
+            vault: find_vault_address(&escrow, &key.offer_mint, token_program),
+            pair_index: find_pair_index_address().0,
+            stats: find_stats_address().0,
+            seller_index: find_seller_index_address(&key.seller).0,
+            registry_page: None,
+            market_index: None,
+            config: find_config_address().0,
+            activity_log,
+            log_wrapper: activity_log.map(|_| NOOP_PROGRAM_ID),
+            compression_program: activity_log.map(|_| ACCOUNT_COMPRESSION_PROGRAM_ID),
+            offer_mint_policy: find_mint_policy_address(&key.offer_mint).0,
+            request_mint_policy: find_mint_policy_address(&key.request_mint).0,
+            seller_ban: find_seller_ban_address(&key.seller).0,
+            offer_mint_limits: find_mint_limits_address(&key.offer_mint).0,
+            request_mint_limits: find_mint_limits_address(&key.request_mint).0,
+            token_program: *token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CreateEscrow {
+            escrow_id: key.escrow_id,
+            offer_amount: offer_amount.amount,
+            request_amount: request_amount.amount,
+            expires_at,
+            designated_buyer: None,
+            nft: None,
+        },
+    ))
+}
+
+/// `cancel_escrow` of an unreceipted escrow, signed by its seller.
+/// `vault` and `market_index` come from [`Escrow::vault`] and
+/// [`Escrow::market_index`]; `rent_recipient` is needed when the escrow's
+/// rent returns to someone other than the seller, and `activity_log`
+/// while the config keeps one
+pub fn cancel_escrow(
+    key: &EscrowKey,
+    vault: &Pubkey,
+    market_index: Option<Pubkey>,
+    seller_offer_token: Option<Pubkey>,
+    rent_recipient: Option<Pubkey>,
+    token_program: &Pubkey,
+    activity_log: Option<Pubkey>,
+) -> Instruction {
+    build_instruction(
+        accounts::CancelEscrow {
+            authority: key.seller,
+            seller: key.seller,
+            offer_mint: key.offer_mint,
+            escrow: key.address(),
+            seller_operator: None,
+            session: None,
+            memo_program: None,
+            vault: *vault,
+            seller_offer_token,
+            receipt_mint: None,
+            seller_receipt_token: None,
+            proceeds_vault: None,
+            request_mint: None,
+            request_token_program: None,
+            pair_index: find_pair_index_address().0,
+            stats: find_stats_address().0,
+            seller_index: find_seller_index_address(&key.seller).0,
+            seller_reputation: find_reputation_address(&key.seller).0,
+            rent_recipient,
+            registry_page: None,
+            market_index,
+            // The config holds the minimum listing duration of partly filled escrows
+            config: Some(find_config_address().0),
+            treasury: None,
+            activity_log,
+            log_wrapper: activity_log.map(|_| NOOP_PROGRAM_ID),
+            compression_program: activity_log.map(|_| ACCOUNT_COMPRESSION_PROGRAM_ID),
+            token_program: *token_program,
+            system_program: system_program::ID,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CancelEscrow { memo: None },
+    )
+}
+
+/// Permissionless `expire_escrow` of an escrow past its expiry, taking
+/// the same accounts as [`cancel_escrow`]
+pub fn expire_escrow(
+    key: &EscrowKey,
+    vault: &Pubkey,
+    market_index: Option<Pubkey>,
+    seller_offer_token: Option<Pubkey>,
+    rent_recipient: Option<Pubkey>,
+    token_program: &Pubkey,
+    activity_log: Option<Pubkey>,
+) -> Instruction {
+    build_instruction(
+        accounts::RefundEscrow {
+            seller: key.seller,
+            offer_mint: key.offer_mint,
+            escrow: key.address(),
+            vault: *vault,
+            seller_offer_token,
+            pair_index: find_pair_index_address().0,
+            stats: find_stats_address().0,
+            seller_index: find_seller_index_address(&key.seller).0,
+            market_index,
+            rent_recipient,
+            config: find_config_address().0,
+            activity_log,
+            log_wrapper: activity_log.map(|_| NOOP_PROGRAM_ID),
+            compression_program: activity_log.map(|_| ACCOUNT_COMPRESSION_PROGRAM_ID),
+            cranker: None,
+            authority: None,
+            token_program: *token_program,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ExpireEscrow {},
+    )
+}
+
+/// Decode an escrow account's data. Escrows written before the current
+/// layout are shorter and must be migrated with `migrate_escrow` first
+pub fn deserialize_escrow(data: &[u8]) -> Result<Escrow> {
+    require!(
+        data.len() >= 8 + size_of::<Escrow>(),
+        ErrorCode::AccountDidNotDeserialize
+    );
+    require!(
+        data[..8] == Escrow::DISCRIMINATOR,
+        ErrorCode::AccountDiscriminatorMismatch
+    );
+    Ok(bytemuck::pod_read_unaligned(
+        &data[8..8 + size_of::<Escrow>()],
+    ))
+}
+
+/// Decode any of the program's Borsh accounts, such as [`Config`]
+pub fn deserialize_account<T: AccountDeserialize>(data: &[u8]) -> Result<T> {
+    T::try_deserialize(&mut &data[..])
+}
+
+/// Source of raw account data, implemented by callers over their RPC
+/// client of choice
+pub trait AccountFetcher {
+    type Error;
+
+    fn account_data(&self, address: &Pubkey) -> std::result::Result<Vec<u8>, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum ClientError<E> {
+    Fetch(E),
+    Deserialize(anchor_lang::error::Error),
+}
+
+pub fn fetch_escrow<F: AccountFetcher>(
+    fetcher: &F,
+    address: &Pubkey,
+) -> std::result::Result<Escrow, ClientError<F::Error>> {
+    let data = fetcher.account_data(address).map_err(ClientError::Fetch)?;
+    deserialize_escrow(&data).map_err(ClientError::Deserialize)
+}
+
+/// Decimals of `mint`, of either token program
+pub fn fetch_mint_decimals<F: AccountFetcher>(
+    fetcher: &F,
+    mint: &Pubkey,
+) -> std::result::Result<u8, ClientError<F::Error>> {
+    let data = fetcher.account_data(mint).map_err(ClientError::Fetch)?;
+    deserialize_account::<Mint>(&data)
+        .map(|mint| mint.decimals)
+        .map_err(ClientError::Deserialize)
+}
+
+pub fn fetch_config<F: AccountFetcher>(
+    fetcher: &F,
+) -> std::result::Result<Config, ClientError<F::Error>> {
+    let data = fetcher
+        .account_data(&find_config_address().0)
+        .map_err(ClientError::Fetch)?;
+    deserialize_account(&data).map_err(ClientError::Deserialize)
+}
+
+/// Anchor errors a client can act on; others decode as
+/// [`DecodedError::Unknown`]
+const ANCHOR_ERRORS: &[ErrorCode] = &[
+    ErrorCode::InstructionFallbackNotFound,
+    ErrorCode::InstructionDidNotDeserialize,
+    ErrorCode::ConstraintMut,
+    ErrorCode::ConstraintHasOne,
+    ErrorCode::ConstraintSigner,
+    ErrorCode::ConstraintSeeds,
+    ErrorCode::ConstraintAddress,
+    ErrorCode::ConstraintTokenMint,
+    ErrorCode::ConstraintTokenOwner,
+    ErrorCode::ConstraintAssociated,
+    ErrorCode::AccountDiscriminatorMismatch,
+    ErrorCode::AccountDidNotDeserialize,
+    ErrorCode::AccountNotEnoughKeys,
+    ErrorCode::AccountNotMutable,
+    ErrorCode::AccountOwnedByWrongProgram,
+    ErrorCode::AccountNotSigner,
+    ErrorCode::AccountNotInitialized,
+];
+
+/// Token program errors a client can act on, numbered alike by both
+/// token programs
+const TOKEN_ERRORS: &[TokenError] = &[
+    TokenError::NotRentExempt,
+    TokenError::InsufficientFunds,
+    TokenError::MintMismatch,
+    TokenError::OwnerMismatch,
+    TokenError::UninitializedState,
+    TokenError::AccountFrozen,
+    TokenError::MintDecimalsMismatch,
+];
+
+/// A failed instruction's custom error code, decoded against the program
+/// that raised it
+#[derive(Clone, Debug)]
+pub enum DecodedError {
+    /// One of the escrow program's own errors
+    Escrow(EscrowError),
+    /// An Anchor framework error raised by the escrow program, such as a
+    /// failed account constraint
+    Anchor(ErrorCode),
+    /// An error of either token program
+    Token(TokenError),
+    Unknown {
+        program_id: Pubkey,
+        code: u32,
+    },
+}
+
+impl DecodedError {
+    /// Suggested fix for the error, if one is known
+    pub fn hint(&self) -> Option<&'static str> {
+        let hint = match self {
+            DecodedError::Escrow(error) => match error {
+                EscrowError::EscrowExpired => {
+                    "The escrow has expired; anyone can close it with `expire_escrow`"
+                }
+                EscrowError::EscrowNotOpen | EscrowError::EscrowAlreadyFilled => {
+                    "The escrow was already filled or closed; refetch it before retrying"
+                }
+                EscrowError::ProtocolPaused => "The protocol is paused; retry once it resumes",
+                EscrowError::SlippageExceeded | EscrowError::RequestUiAmountExceeded => {
+                    "The price moved; re-quote with `get_quote` and raise the maximum paid"
+                }
+                EscrowError::StaleOracle => {
+                    "Post a fresh price update in the same transaction as the fill"
+                }
+                EscrowError::InvalidUiAmount => {
+                    "UI amounts may not carry more decimals than the mint; parse them with `UiAmount`"
+                }
+                EscrowError::InvalidTokenAccountOwner => {
+                    "Token accounts must belong to the wallet the instruction names; pass its associated token account"
+                }
+                EscrowError::MissingOfferTokenAccount | EscrowError::MissingRequestTokenAccount => {
+                    "Pass the token account; add an idempotent create-ATA instruction first if it does not exist"
+                }
+                EscrowError::MissingConfigAccount => "Pass the config from `find_config_address`",
+                EscrowError::MissingActivityLogAccount => {
+                    "The config keeps an activity log; pass it with the noop and account compression programs"
+                }
+                EscrowError::ListingTooRecent => {
+                    "Partly filled escrows stay listed for the config's minimum listing duration; retry once it passes"
+                }
+                EscrowError::TooManyOpenEscrows => {
+                    "The seller has the most open escrows allowed; cancel or fill one first"
+                }
+                EscrowError::SellerBanned => "The seller is banned from listing by the config authority",
+                EscrowError::EscrowLocked => "The escrow is locked; its locker must unlock it first",
+                EscrowError::TransferFeeTooHigh => {
+                    "The mint's transfer fee consumes the amount; send a larger amount"
+                }
+                _ => return None,
+            },
+            DecodedError::Anchor(error) => match error {
+                ErrorCode::AccountNotInitialized => {
+                    "An account the instruction needs does not exist yet; create it first, e.g. the buyer's associated token account with an idempotent create-ATA instruction"
+                }
+                ErrorCode::AccountDiscriminatorMismatch | ErrorCode::AccountDidNotDeserialize => {
+                    "An account has an unexpected layout; escrows written by older builds must be migrated with `migrate_escrow`"
+                }
+                ErrorCode::ConstraintSeeds | ErrorCode::ConstraintAddress => {
+                    "An address was derived from the wrong seeds; derive it with this crate's `find_*_address` helpers"
+                }
+                ErrorCode::ConstraintTokenMint | ErrorCode::ConstraintTokenOwner => {
+                    "A token account has the wrong mint or owner for its role"
+                }
+                ErrorCode::InstructionFallbackNotFound | ErrorCode::InstructionDidNotDeserialize => {
+                    "The instruction does not match the deployed program; rebuild against its version"
+                }
+                _ => return None,
+            },
+            DecodedError::Token(error) => match error {
+                TokenError::InsufficientFunds => {
+                    "The paying token account holds too little; fund it or lower the amount"
+                }
+                TokenError::AccountFrozen => "A token account is frozen by its mint's freeze authority",
+                TokenError::UninitializedState => {
+                    "A token account does not exist yet; add an idempotent create-ATA instruction first"
+                }
+                _ => return None,
+            },
+            DecodedError::Unknown { .. } => return None,
+        };
+        Some(hint)
+    }
+}
+
+impl std::fmt::Display for DecodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodedError::Escrow(error) => write!(f, "{}: {}", error.name(), error)?,
+            DecodedError::Anchor(error) => write!(f, "{}: {}", error.name(), error)?,
+            DecodedError::Token(error) => write!(f, "{:?}: {}", error, error)?,
+            DecodedError::Unknown { program_id, code } => {
+                write!(f, "Custom error {} of program {}", code, program_id)?
+            }
+        }
+        if let Some(hint) = self.hint() {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode custom error `code` raised by `program_id`, the program of the
+/// failed instruction
+pub fn decode_error(program_id: &Pubkey, code: u32) -> DecodedError {
+    let decoded = if *program_id == PROGRAM_ID {
+        match code.checked_sub(ERROR_CODE_OFFSET) {
+            Some(index) => EscrowError::try_from(index).ok().map(DecodedError::Escrow),
+            None => ANCHOR_ERRORS
+                .iter()
+                .find(|error| u32::from(**error) == code)
+                .map(|error| DecodedError::Anchor(*error)),
+        }
+    } else if *program_id == spl_token::ID || *program_id == spl_token_2022::ID {
+        TOKEN_ERRORS
+            .iter()
+            .find(|error| (*error).clone() as u32 == code)
+            .map(|error| DecodedError::Token(error.clone()))
+    } else {
+        None
+    };
+    decoded.unwrap_or(DecodedError::Unknown {
+        program_id: *program_id,
+        code,
+    })
+}
+
+/// Decode the error of a failed instruction of `program_id`, if it raised
+/// a custom one
+pub fn decode_instruction_error(
+    program_id: &Pubkey,
+    error: &InstructionError,
+) -> Option<DecodedError> {
+    match error {
+        InstructionError::Custom(code) => Some(decode_error(program_id, *code)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ui_amounts_into_base_units() {
+        let amount = UiAmount::parse("1.5", 6).unwrap();
+        assert_eq!(amount.to_raw(), RawAmount::new(1_500_000, 6));
+        assert_eq!(amount.as_str(), "1.5");
+        assert_eq!(
+            UiAmount::parse("42", 0).unwrap().to_raw(),
+            RawAmount::new(42, 0)
+        );
+    }
+
+    #[test]
+    fn refuses_more_decimals_than_the_mint() {
+        assert_eq!(
+            UiAmount::parse("1.0000001", 6).unwrap_err(),
+            error!(EscrowError::InvalidUiAmount)
+        );
+        assert!(UiAmount::parse("0.5", 0).is_err());
+    }
+
+    #[test]
+    fn refuses_amounts_beyond_u64() {
+        assert_eq!(
+            UiAmount::parse("18446744073709551616", 0).unwrap_err(),
+            error!(EscrowError::InvalidUiAmount)
+        );
+        assert!(UiAmount::parse("18446744073709.551616", 6).is_err());
+        assert_eq!(
+            UiAmount::parse("18446744073709.551615", 6)
+                .unwrap()
+                .to_raw(),
+            RawAmount::new(u64::MAX, 6)
+        );
+    }
+
+    #[test]
+    fn raw_amounts_round_trip_through_ui_amounts() {
+        for raw in [
+            RawAmount::new(0, 6),
+            RawAmount::new(1, 9),
+            RawAmount::new(1_500_000, 6),
+            RawAmount::new(100, 2),
+            RawAmount::new(u64::MAX, 0),
+            RawAmount::new(u64::MAX, 9),
+        ] {
+            let ui = raw.to_ui();
+            assert_eq!(ui.to_raw(), raw);
+            assert_eq!(
+                UiAmount::parse(ui.as_str(), raw.decimals).unwrap().to_raw(),
+                raw
+            );
+        }
+        assert_eq!(RawAmount::new(1_500_000, 6).to_ui().to_string(), "1.5");
+        assert_eq!(RawAmount::new(100, 2).to_ui().to_string(), "1");
+    }
+
+    #[test]
+    fn create_escrow_checks_amounts_against_the_mint_decimals() {
+        let key = EscrowKey {
+            seller: Pubkey::new_unique(),
+            offer_mint: Pubkey::new_unique(),
+            request_mint: Pubkey::new_unique(),
+            escrow_id: 0,
+        };
+        let build = |offer_mint_decimals, request_mint_decimals| {
+            create_escrow(
+                &key,
+                &key.seller,
+                Some(Pubkey::new_unique()),
+                &spl_token::ID,
+                RawAmount::new(1_000_000, 6),
+                RawAmount::new(5_000, 3),
+                offer_mint_decimals,
+                request_mint_decimals,
+                None,
+                None,
+            )
+        };
+        assert!(build(6, 3).is_ok());
+        let mismatch: Error = ProgramError::from(TokenError::MintDecimalsMismatch).into();
+        assert_eq!(build(9, 3).unwrap_err(), mismatch);
+        assert_eq!(build(6, 6).unwrap_err(), mismatch);
+    }
+
+    #[test]
+    fn decodes_program_errors_with_hints() {
+        let decoded = decode_error(&PROGRAM_ID, EscrowError::EscrowExpired.into());
+        assert!(matches!(
+            decoded,
+            DecodedError::Escrow(EscrowError::EscrowExpired)
+        ));
+        assert_eq!(
+            decoded.hint(),
+            Some("The escrow has expired; anyone can close it with `expire_escrow`")
+        );
+
+        let decoded = decode_error(&PROGRAM_ID, EscrowError::MathOverflow.into());
+        assert!(matches!(
+            decoded,
+            DecodedError::Escrow(EscrowError::MathOverflow)
+        ));
+        assert_eq!(decoded.hint(), None);
+    }
+
+    #[test]
+    fn decodes_anchor_constraint_errors_with_hints() {
+        let decoded = decode_error(&PROGRAM_ID, ErrorCode::ConstraintSeeds.into());
+        assert!(matches!(
+            decoded,
+            DecodedError::Anchor(ErrorCode::ConstraintSeeds)
+        ));
+        assert_eq!(
+            decoded.hint(),
+            Some("An address was derived from the wrong seeds; derive it with this crate's `find_*_address` helpers")
+        );
+    }
+
+    #[test]
+    fn decodes_token_errors_of_either_token_program() {
+        for program_id in [spl_token::ID, spl_token_2022::ID] {
+            let decoded = decode_error(&program_id, TokenError::InsufficientFunds as u32);
+            assert!(matches!(
+                decoded,
+                DecodedError::Token(TokenError::InsufficientFunds)
+            ));
+            assert_eq!(
+                decoded.hint(),
+                Some("The paying token account holds too little; fund it or lower the amount")
+            );
+        }
+    }
+
+    #[test]
+    fn falls_through_to_unknown_errors() {
+        let other_program = Pubkey::new_unique();
+        for (program_id, code) in [
+            // Another program's code, even one the escrow program also uses
+            (other_program, u32::from(EscrowError::EscrowExpired)),
+            // An Anchor error outside the decoded set
+            (PROGRAM_ID, u32::from(ErrorCode::ConstraintRaw)),
+            // Past the end of the escrow program's errors
+            (PROGRAM_ID, u32::MAX),
+            // A token error outside the decoded set
+            (spl_token::ID, TokenError::InvalidMint as u32),
+        ] {
+            let decoded = decode_error(&program_id, code);
+            assert!(matches!(
+                decoded,
+                DecodedError::Unknown { program_id: id, code: c } if id == program_id && c == code
+            ));
+            assert_eq!(decoded.hint(), None);
+        }
+        assert!(
+            decode_instruction_error(&PROGRAM_ID, &InstructionError::InvalidArgument).is_none()
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Test program standing in for a lending protocol that takes escrow
+//! positions as collateral
+//!
+//! Locks and unlocks escrows through CPI, signing with its
+//! `escrow_locker` PDA as `spl_escrow` requires of approved lockers.
+
+use anchor_lang::prelude::*;
+use spl_escrow::{program::SplEscrow, Escrow, Market, LOCKER_AUTHORITY_SEED};
+
+declare_id!("HAPnmbqvPVnN3XH6tb7j5S4JsjsJ68pLXVNNXDD7nZcr");
+
+#[program]
+pub mod mock_locker {
+    use super::*;
+
+    /// Lock `escrow` with the seller's consent
+    pub fn lock(ctx: Context<Lock>) -> Result<()> {
+        let seeds: &[&[u8]] = &[LOCKER_AUTHORITY_SEED, &[ctx.bumps.locker_authority]];
+        spl_escrow::cpi::lock_escrow(CpiContext::new_with_signer(
+            ctx.accounts.escrow_program.to_account_info(),
+            spl_escrow::cpi::accounts::LockEscrow {
+                seller: ctx.accounts.seller.to_account_info(),
+                escrow: ctx.accounts.escrow.to_account_info(),
+                market: ctx.accounts.market.to_account_info(),
+                locker_authority: ctx.accounts.locker_authority.to_account_info(),
+                locker_program: ctx.accounts.locker_program.to_account_info(),
+            },
+            &[seeds],
+        ))
+    }
+
+    /// Release the lock placed by `lock`
+    pub fn unlock(ctx: Context<Unlock>) -> Result<()> {
+        let seeds: &[&[u8]] = &[LOCKER_AUTHORITY_SEED, &[ctx.bumps.locker_authority]];
+        spl_escrow::cpi::unlock_escrow(CpiContext::new_with_signer(
+            ctx.accounts.escrow_program.to_account_info(),
+            spl_escrow::cpi::accounts::UnlockEscrow {
+                escrow: ctx.accounts.escrow.to_account_info(),
+                locker_authority: ctx.accounts.locker_authority.to_account_info(),
+                locker_program: ctx.accounts.locker_program.to_account_info(),
+            },
+            &[seeds],
+        ))
+    }
+}
+
+#[derive(Accounts)]
+pub struct Lock<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(mut)]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    pub market: Box<Account<'info, Market>>,
+
+    /// CHECK: Signs the CPI as this program's locker PDA
+    #[account(seeds = [LOCKER_AUTHORITY_SEED], bump)]
+    pub locker_authority: UncheckedAccount<'info>,
+
+    /// CHECK: This program, which the escrow records as its locker
+    #[account(address = crate::ID, executable)]
+    pub locker_program: UncheckedAccount<'info>,
+
+    pub escrow_program: Program<'info, SplEscrow>,
+}
+
+#[derive(Accounts)]
+pub struct Unlock<'info> {
+    #[account(mut)]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// CHECK: Signs the CPI as this program's locker PDA
+    #[account(seeds = [LOCKER_AUTHORITY_SEED], bump)]
+    pub locker_authority: UncheckedAccount<'info>,
+
+    /// CHECK: This program, which the escrow records as its locker
+    #[account(address = crate::ID, executable)]
+    pub locker_program: UncheckedAccount<'info>,
+
+    pub escrow_program: Program<'info, SplEscrow>,
+}
@@ -0,0 +1,124 @@
+//! Test transfer-hook program that counts the transfers of its mints
+//!
+//! Each hooked mint lists the mint's `Counter` as an extra account, so a
+//! transfer only succeeds when the caller resolves the hook's extra
+//! accounts, and the count shows the hook ran.
+
+use anchor_lang::prelude::*;
+use spl_tlv_account_resolution::{
+    account::ExtraAccountMeta, seeds::Seed, state::ExtraAccountMetaList,
+};
+use spl_transfer_hook_interface::instruction::{ExecuteInstruction, TransferHookInstruction};
+
+declare_id!("DM45tYoJuXqp6NVLPB582QavcK38g8NJ9qk2xRYCwdQ9");
+
+#[program]
+pub mod mock_transfer_hook {
+    use super::*;
+
+    /// Create the extra account list and transfer counter of `mint`
+    pub fn initialize_extra_account_meta_list(
+        ctx: Context<InitializeExtraAccountMetaList>,
+    ) -> Result<()> {
+        ExtraAccountMetaList::init::<ExecuteInstruction>(
+            &mut ctx.accounts.extra_account_meta_list.try_borrow_mut_data()?,
+            &extra_account_metas()?,
+        )?;
+        ctx.accounts.counter.transfers = 0;
+        Ok(())
+    }
+
+    /// Count a transfer of the hooked mint
+    pub fn transfer_hook(ctx: Context<TransferHook>, _amount: u64) -> Result<()> {
+        let counter = &mut ctx.accounts.counter;
+        counter.transfers = counter.transfers.saturating_add(1);
+        Ok(())
+    }
+
+    /// Route the transfer-hook interface's `Execute` to `transfer_hook`
+    pub fn fallback<'info>(
+        program_id: &Pubkey,
+        accounts: &'info [AccountInfo<'info>],
+        data: &[u8],
+    ) -> Result<()> {
+        match TransferHookInstruction::unpack(data)? {
+            TransferHookInstruction::Execute { amount } => {
+                __private::__global::transfer_hook(program_id, accounts, &amount.to_le_bytes())
+            }
+            _ => Err(ProgramError::InvalidInstructionData.into()),
+        }
+    }
+}
+
+/// The mint's counter, at `["counter", mint]`
+fn extra_account_metas() -> Result<Vec<ExtraAccountMeta>> {
+    Ok(vec![ExtraAccountMeta::new_with_seeds(
+        &[
+            Seed::Literal {
+                bytes: b"counter".to_vec(),
+            },
+            Seed::AccountKey { index: 1 },
+        ],
+        false,
+        true,
+    )?])
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Counter {
+    pub transfers: u64,
+}
+
+#[derive(Accounts)]
+pub struct InitializeExtraAccountMetaList<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: The hooked mint
+    pub mint: UncheckedAccount<'info>,
+
+    /// CHECK: Written as a TLV extra account list
+    #[account(
+        init,
+        payer = payer,
+        space = ExtraAccountMetaList::size_of(extra_account_metas()?.len())?,
+        seeds = [b"extra-account-metas", mint.key().as_ref()],
+        bump,
+    )]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Counter::INIT_SPACE,
+        seeds = [b"counter", mint.key().as_ref()],
+        bump,
+    )]
+    pub counter: Account<'info, Counter>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts of the transfer-hook interface's `Execute`, in its order
+#[derive(Accounts)]
+pub struct TransferHook<'info> {
+    /// CHECK: Source token account of the transfer
+    pub source_token: UncheckedAccount<'info>,
+
+    /// CHECK: The hooked mint
+    pub mint: UncheckedAccount<'info>,
+
+    /// CHECK: Destination token account of the transfer
+    pub destination_token: UncheckedAccount<'info>,
+
+    /// CHECK: Owner or delegate of the source account
+    pub owner: UncheckedAccount<'info>,
+
+    /// CHECK: The mint's extra account list
+    #[account(seeds = [b"extra-account-metas", mint.key().as_ref()], bump)]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"counter", mint.key().as_ref()], bump)]
+    pub counter: Account<'info, Counter>,
+}
@@ -0,0 +1,83 @@
+//! Protocol-wide limits, seeds and external program ids
+
+use crate::*;
+
+/// Denominator for all basis-point fee rates
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Upper bound on the fee a market operator may charge
+pub const MAX_MARKET_FEE_BPS: u16 = 1_000;
+/// Upper bound on the protocol fee
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 1_000;
+/// Upper bound on the premium an insurance pool charges for coverage
+pub const MAX_INSURANCE_PREMIUM_BPS: u16 = 1_000;
+/// Maximum number of entries in each market allowlist
+pub const MAX_MARKET_ALLOWLIST_LEN: usize = 16;
+/// Seed external programs use to derive the authority that locks escrows
+pub const LOCKER_AUTHORITY_SEED: &[u8] = b"escrow_locker";
+/// Maximum number of volume tiers per pair
+pub const MAX_VOLUME_TIERS: usize = 4;
+/// Maximum number of protocol fee tiers for staked tokens
+pub const MAX_STAKE_TIERS: usize = 4;
+/// Maximum number of programs barred from fill transactions
+pub const MAX_GUARDED_PROGRAMS: usize = 8;
+/// Maximum number of recipients sharing the protocol fee
+pub const MAX_FEE_SPLITS: usize = 4;
+/// Most recent actions a held settlement keeps for its arbiter
+pub const SETTLEMENT_HISTORY_LEN: usize = 8;
+/// Length of one trader volume window (30 days)
+pub const VOLUME_WINDOW_SECONDS: i64 = 30 * 24 * 60 * 60;
+/// Pyth pull-oracle receiver program that owns `PriceUpdateV2` accounts
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+/// Switchboard On-Demand program that owns pull feed accounts
+pub const SWITCHBOARD_ON_DEMAND_PROGRAM_ID: Pubkey =
+    pubkey!("SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv");
+/// Metaplex Bubblegum program that owns compressed NFT trees
+pub const BUBBLEGUM_PROGRAM_ID: Pubkey = pubkey!("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY");
+/// Wormhole core bridge that verifies and owns posted VAA accounts
+pub const WORMHOLE_CORE_BRIDGE_PROGRAM_ID: Pubkey =
+    pubkey!("worm2ZoG2kUd4vFXhvjh93UUH596ayRfgQ2MgjNMTth");
+/// SPL account compression program backing Bubblegum trees
+pub const ACCOUNT_COMPRESSION_PROGRAM_ID: Pubkey =
+    pubkey!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+/// SPL Memo program settlement memos are logged through
+pub const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+/// SPL noop program Bubblegum logs leaf changes through
+pub const NOOP_PROGRAM_ID: Pubkey = pubkey!("noopb9bkMVfRPU8ShW8aGLs1UyUSHCLMgxDCmCdrtYe");
+/// Anchor discriminator of Bubblegum's `transfer` instruction
+pub(crate) const BUBBLEGUM_TRANSFER_DISCRIMINATOR: [u8; 8] = [163, 52, 200, 231, 140, 3, 69, 186];
+/// Anchor discriminators of account compression's `init_empty_merkle_tree`
+/// and `append` instructions
+pub(crate) const COMPRESSION_INIT_TREE_DISCRIMINATOR: [u8; 8] =
+    [191, 11, 119, 7, 180, 107, 220, 110];
+pub(crate) const COMPRESSION_APPEND_DISCRIMINATOR: [u8; 8] = [149, 120, 18, 222, 236, 225, 88, 203];
+/// Maximum number of distinct mint pairs tracked by the pair index
+pub const MAX_INDEXED_PAIRS: usize = 128;
+/// Maximum number of mints in one offer basket
+pub const MAX_BASKET_LEGS: usize = 4;
+/// Maximum number of extra mints an escrow may request
+pub const MAX_REQUEST_LEGS: usize = 4;
+/// Maximum number of buyers on a seller's allowlist
+pub const MAX_BUYER_ALLOWLIST_LEN: usize = 32;
+/// Layout version of escrows written by this build
+pub const ESCROW_VERSION: u8 = 27;
+/// Maximum length in bytes of an escrow's label
+pub const MAX_LABEL_LEN: usize = 64;
+/// Maximum length in bytes of an escrow's metadata URI
+pub const MAX_METADATA_URI_LEN: usize = 200;
+/// Longest a buyer may hold an escrow off the market, in seconds
+pub const MAX_RESERVATION_DURATION: i64 = 7 * 24 * 60 * 60;
+/// Longest a seller may make buyers wait between committing to a fill
+/// and settling it, in seconds
+pub const MAX_REVEAL_DELAY: i64 = 10 * 60;
+/// Maximum number of escrows listed on one offer registry page
+pub const MAX_REGISTRY_PAGE_LEN: usize = 64;
+/// Maximum number of escrows linked into one market index page
+pub const MAX_MARKET_INDEX_LEN: usize = 64;
+/// Maximum length in bytes of a settlement memo
+pub const MAX_MEMO_LEN: usize = 128;
+/// Longest a session key may act for its owner, in seconds
+pub const MAX_SESSION_DURATION: i64 = 24 * 60 * 60;
+/// Session permission to accept escrows for the owner
+pub const SESSION_ACCEPT: u8 = 1 << 0;
+/// Session permission to cancel the owner's escrows
+pub const SESSION_CANCEL: u8 = 1 << 1;
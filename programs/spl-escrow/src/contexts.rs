@@ -0,0 +1,6475 @@
+//! Account contexts of every instruction
+
+use crate::*;
+
+#[derive(Accounts)]
+#[instruction(escrow_id: u64)]
+#[event_cpi]
+pub struct CreateEscrow<'info> {
+    /// A wallet, or another program's PDA signing through CPI; offering
+    /// native SOL needs it to hold no data so it can fund the vault
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    /// Funds the accounts opened for the escrow; may be a sponsor other
+    /// than the seller
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Not needed when offering native SOL
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Optional market the escrow is listed under
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// Metaplex metadata of an offered NFT
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Seller's buyer allowlist, to gate the escrow on membership
+    #[account(
+        seeds = [b"buyer_allowlist", seller.key().as_ref()],
+        bump = buyer_allowlist.bump,
+    )]
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    /// Seller's stake, to list at their protocol fee tier
+    #[account(seeds = [b"stake", seller.key().as_ref()], bump = seller_stake.bump)]
+    pub seller_stake: Option<Box<Account<'info, Stake>>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [
+            b"escrow",
+            seller.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = escrow,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + SellerIndex::INIT_SPACE,
+        seeds = [b"seller_index", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_index: Box<Account<'info, SellerIndex>>,
+
+    /// Offer registry page to list the escrow on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    /// Market index page of the escrow's mint pair to link it into, if any
+    #[account(
+        mut,
+        constraint = market_index.offer_mint == offer_mint.key() @ EscrowError::InvalidMarketIndex,
+        constraint = market_index.request_mint == request_mint.key() @ EscrowError::InvalidMarketIndex,
+    )]
+    pub market_index: Option<Box<Account<'info, MarketIndex>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The offer mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", request_mint.key().as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(escrow_id: u64)]
+#[event_cpi]
+pub struct CreateDelegatedEscrow<'info> {
+    pub seller: Signer<'info>,
+
+    /// Funds the escrow account; may be a sponsor other than the seller
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Stays in the seller's hands, with the offer delegated to the escrow
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [
+            b"escrow",
+            seller.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + SellerIndex::INIT_SPACE,
+        seeds = [b"seller_index", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_index: Box<Account<'info, SellerIndex>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The offer mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", request_mint.key().as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct AcceptEscrow<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// Funds the accounts opened for the fill; may be a relayer other
+    /// than the buyer
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Omit to pay a native SOL request in lamports; owned by
+    /// `beneficiary` when the buyer accepts as its delegate
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() || beneficiary.is_some() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Buyer's offer-mint ATA, created if missing; not needed when the
+    /// offer is native SOL or goes to a beneficiary
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: Owner of `buyer_request_token` when the buyer accepts as its
+    /// delegate; takes the fill in the buyer's place
+    #[account(mut)]
+    pub beneficiary: Option<UncheckedAccount<'info>>,
+
+    /// Beneficiary's offer-mint token account, receiving the offer
+    #[account(
+        mut,
+        constraint = beneficiary_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = beneficiary.as_ref().is_some_and(|beneficiary| beneficiary.key() == beneficiary_offer_token.owner) @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub beneficiary_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Offer-mint token account of any owner to deliver the offer to in
+    /// place of `buyer_offer_token`, such as a cold wallet or treasury
+    #[account(
+        mut,
+        constraint = recipient_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+    )]
+    pub recipient_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// The buyer's reservation of the escrow, closed to refund its deposit
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [b"reservation", escrow.key().as_ref()],
+        bump = reservation.bump,
+        constraint = reservation.buyer == buyer.key() @ EscrowError::InvalidReservation,
+    )]
+    pub reservation: Option<Box<Account<'info, Reservation>>>,
+
+    /// The buyer's revealed commitment to this fill, required when the
+    /// escrow settles by commit and reveal; closed once used
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [b"fill_commitment", escrow.key().as_ref(), buyer.key().as_ref()],
+        bump = fill_commitment.bump,
+    )]
+    pub fill_commitment: Option<Box<Account<'info, FillCommitment>>>,
+
+    /// Wrapped SOL account unwrapped to the buyer, required for native SOL
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"unwrap", escrow.key().as_ref()],
+        bump,
+        token::mint = offer_mint,
+        token::authority = escrow,
+    )]
+    pub offer_unwrap: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Seller's request-mint ATA, created at the payer's expense if
+    /// missing; not needed when the buyer pays in lamports
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = request_mint,
+        associated_token::authority = seller,
+        associated_token::token_program = request_token_program,
+    )]
+    pub seller_request_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Request-mint token account of the escrow's proceeds recipient,
+    /// required in place of `seller_request_token` when it has one
+    #[account(mut)]
+    pub proceeds_recipient_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// Market fee receiver's request-mint token account
+    #[account(mut)]
+    pub market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Buyer's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub buyer_stats: Option<UncheckedAccount<'info>>,
+
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Request-mint token account of the frontend that referred the buyer
+    #[account(mut)]
+    pub referrer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Offered NFT's metadata, required when the escrow pays royalties
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Buyer's token account meeting the escrow's token gate
+    pub gate_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Metadata of `gate_token`'s mint, required for collection gates
+    pub gate_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Buyer's NFT of the config's fee discount collection, and the
+    /// metadata of its mint, to claim the discount
+    pub discount_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub discount_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Session of the beneficiary whose key signs as `buyer`; pays as the
+    /// delegate of `buyer_request_token`
+    #[account(
+        mut,
+        seeds = [b"session", session.owner.as_ref(), session.session_key.as_ref()],
+        bump = session.bump,
+    )]
+    pub session: Option<Box<Account<'info, SessionAuthority>>>,
+
+    /// CHECK: The permission program's approval of the taker, required
+    /// when the escrow is permissioned; validated in Permission::check
+    pub permission_approval: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Pyth price update or Switchboard feed, required when the
+    /// escrow is oracle-priced; validated in OracleKind::load
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The instructions sysvar, required when the escrow is
+    /// oracle-priced; guards the fill against the rest of its transaction
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// Vesting for this fill, required when the escrow vests its offer
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Vesting::INIT_SPACE,
+        seeds = [
+            b"vesting",
+            escrow.key().as_ref(),
+            buyer.key().as_ref(),
+            escrow.load()?.offer_amount.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub vesting: Option<Box<Account<'info, Vesting>>>,
+
+    /// Holds this fill's offer tokens until they are claimed
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"vesting_vault",
+            escrow.key().as_ref(),
+            buyer.key().as_ref(),
+            escrow.load()?.offer_amount.to_le_bytes().as_ref(),
+        ],
+        bump,
+        token::mint = offer_mint,
+        token::authority = vesting,
+        token::token_program = token_program,
+    )]
+    pub vesting_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Stream of this fill's proceeds, required when the escrow streams
+    /// them to its seller
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Stream::INIT_SPACE,
+        seeds = [
+            b"stream",
+            escrow.key().as_ref(),
+            escrow.load()?.offer_amount.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub stream: Option<Box<Account<'info, Stream>>>,
+
+    /// Holds this fill's proceeds until the seller withdraws them
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"stream_vault",
+            escrow.key().as_ref(),
+            escrow.load()?.offer_amount.to_le_bytes().as_ref(),
+        ],
+        bump,
+        token::mint = request_mint,
+        token::authority = stream,
+        token::token_program = request_token_program,
+    )]
+    pub stream_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.offer_mint.as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.request_mint.as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    /// Required when a position receipt was minted
+    #[account(
+        mut,
+        seeds = [b"proceeds", escrow.key().as_ref()],
+        bump = escrow.load()?.proceeds_bump,
+    )]
+    pub proceeds_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", seller.key().as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Offer registry page the escrow is listed on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    /// Market index page the escrow is linked into, if any
+    #[account(mut)]
+    pub market_index: Option<Box<Account<'info, MarketIndex>>>,
+
+    /// CHECK: SPL Memo program, required when a memo is passed
+    #[account(address = MEMO_PROGRAM_ID)]
+    pub memo_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetQuote<'info> {
+    #[account(address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Buyer's trader stats address; may be uninitialized
+    pub buyer_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The buyer being quoted, whose volume tier discounts the
+    /// taker fee; required alongside `buyer_stats` and `discount_token`
+    pub buyer: Option<UncheckedAccount<'info>>,
+
+    /// Buyer's NFT of the config's fee discount collection, and the
+    /// metadata of its mint, to quote the discount
+    pub discount_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub discount_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Offered NFT's metadata, required when the escrow pays royalties
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: Pyth price update or Switchboard feed, required when the
+    /// escrow is oracle-priced; validated in OracleKind::load
+    pub price_update: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct GetEscrowState<'info> {
+    pub escrow: AccountLoader<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct AcceptMany<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Buyer's offer-mint ATA, created if missing
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Request-mint token account of the frontend that referred the buyer
+    #[account(mut)]
+    pub referrer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", request_mint.key().as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> AcceptMany<'info> {
+    /// Fill the escrows in `escrows`, six accounts each, in order until the
+    /// buyer holds `target_amount` offer tokens, passing over those no
+    /// longer open. Returns each fill's event for the caller to emit
+    pub(crate) fn sweep(
+        &mut self,
+        escrows: &'info [AccountInfo<'info>],
+        target_amount: u64,
+    ) -> Result<Vec<EscrowAccepted>> {
+        let offer_mint_key = self.offer_mint.key();
+        let request_mint_key = self.request_mint.key();
+
+        let mut filled = 0u64;
+        let mut events = Vec::new();
+        for escrow_accounts in escrows.chunks(6) {
+            if filled == target_amount {
+                break;
+            }
+            let [escrow_info, vault_info, seller, seller_request_token, seller_index, seller_reputation] =
+                escrow_accounts
+            else {
+                unreachable!()
+            };
+            let escrow = AccountLoader::<Escrow>::try_from(escrow_info)?;
+            let fill_amount = {
+                let escrow = escrow.load()?;
+                // Escrows taken since the sweep was quoted are passed over
+                if escrow.status() != EscrowStatus::Open || escrow.offer_amount == 0 {
+                    continue;
+                }
+                require_keys_eq!(escrow.offer_mint, offer_mint_key, EscrowError::InvalidMint);
+                require_keys_eq!(
+                    escrow.request_mint,
+                    request_mint_key,
+                    EscrowError::InvalidMint
+                );
+                require!(
+                    escrow.oracle_pricing().is_none(),
+                    EscrowError::MissingOracleAccount
+                );
+                require!(escrow.arbitration().is_none(), EscrowError::HeldSettlement);
+                require!(escrow.vesting().is_none(), EscrowError::VestedEscrow);
+                require!(
+                    escrow.proceeds_stream().is_none(),
+                    EscrowError::StreamedEscrow
+                );
+                require_keys_eq!(
+                    escrow.rent_recipient(),
+                    escrow.seller,
+                    EscrowError::InvalidRentRecipient
+                );
+                require_keys_eq!(seller.key(), escrow.seller, EscrowError::Unauthorized);
+                require_keys_eq!(
+                    vault_info.key(),
+                    escrow.vault(escrow_info.key, vault_info.owner),
+                    EscrowError::InvalidSweepAccount
+                );
+                target_amount
+                    .saturating_sub(filled)
+                    .min(escrow.offer_amount)
+            };
+            let (expected_index, _) =
+                Pubkey::find_program_address(&[b"seller_index", seller.key.as_ref()], &crate::ID);
+            require_keys_eq!(
+                seller_index.key(),
+                expected_index,
+                EscrowError::InvalidSweepAccount
+            );
+            let (expected_reputation, _) =
+                Pubkey::find_program_address(&[b"reputation", seller.key.as_ref()], &crate::ID);
+            require_keys_eq!(
+                seller_reputation.key(),
+                expected_reputation,
+                EscrowError::InvalidSweepAccount
+            );
+            let seller_token = InterfaceAccount::<TokenAccount>::try_from(seller_request_token)?;
+            require_keys_eq!(
+                seller_token.mint,
+                request_mint_key,
+                EscrowError::InvalidMint
+            );
+            require_keys_eq!(
+                seller_token.owner,
+                seller.key(),
+                EscrowError::InvalidTokenAccountOwner
+            );
+            let vault = InterfaceAccount::<TokenAccount>::try_from(vault_info)?;
+
+            let event = Fill {
+                escrow: &escrow,
+                vault: &vault,
+                seller: seller.clone(),
+                seller_request_token: Some(seller_request_token.clone()),
+                proceeds_recipient_token: None,
+                proceeds_vault: None,
+                market: None,
+                market_fee_token: None,
+                pair_fees: None,
+                seller_stats: None,
+                taker_stats: None,
+                seller_index: seller_index.clone(),
+                seller_reputation: seller_reputation.clone(),
+                rent_recipient: None,
+                stats: self.stats.to_account_info(),
+                pair_stats: self.pair_stats.to_account_info(),
+                config: &self.config,
+                activity_log: self.activity_log.as_ref(),
+                log_wrapper: self.log_wrapper.as_ref(),
+                compression_program: self.compression_program.as_ref(),
+                fee_vault: self.fee_vault.as_deref(),
+                taker_fee_vault: self.taker_fee_vault.as_deref(),
+                referrer_token: self.referrer_token.as_deref(),
+                offer_metadata: None,
+                buyer_allowlist: None,
+                gate_token: None,
+                gate_metadata: None,
+                discount_token: None,
+                discount_metadata: None,
+                permission_approval: None,
+                fill_commitment: None,
+                taker: self.buyer.key(),
+                payer_token: Some(self.buyer_request_token.to_account_info()),
+                payer_authority: self.buyer.to_account_info(),
+                offer_destination: self.buyer_offer_token.to_account_info(),
+                offer_mint: &self.offer_mint,
+                request_mint: &self.request_mint,
+                offer_mint_limits: Some(self.offer_mint_limits.to_account_info()),
+                request_mint_limits: Some(self.request_mint_limits.to_account_info()),
+                offer_token_program: self.token_program.to_account_info(),
+                request_token_program: self.request_token_program.to_account_info(),
+                system_program: self.system_program.to_account_info(),
+                remaining_accounts: &[],
+            }
+            .settle(&mut self.pair_index, fill_amount, &[])?;
+            filled = filled
+                .checked_add(fill_amount)
+                .ok_or(EscrowError::MathOverflow)?;
+            events.push(event);
+        }
+
+        Ok(events)
+    }
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct SettleChain<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            first_escrow.load()?.listed_by().as_ref(),
+            first_escrow.load()?.offer_mint.as_ref(),
+            first_escrow.load()?.request_mint.as_ref(),
+            first_escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = first_escrow.load()?.escrow_bump,
+    )]
+    pub first_escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = first_escrow.load()?.vault(&first_escrow.key(), first_vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub first_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Validated via first_escrow.seller constraint
+    #[account(mut, address = first_escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub first_seller: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = first_seller_request_token.mint == first_escrow.load()?.request_mint @ EscrowError::InvalidMint,
+        constraint = first_seller_request_token.owner == first_escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub first_seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub first_market: Option<Box<Account<'info, Market>>>,
+
+    #[account(mut)]
+    pub first_market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub first_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `first_fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub first_taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub first_pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub first_seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Taker's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub first_taker_stats: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"proceeds", first_escrow.key().as_ref()],
+        bump = first_escrow.load()?.proceeds_bump,
+    )]
+    pub first_proceeds_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            second_escrow.load()?.listed_by().as_ref(),
+            second_escrow.load()?.offer_mint.as_ref(),
+            second_escrow.load()?.request_mint.as_ref(),
+            second_escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = second_escrow.load()?.escrow_bump,
+    )]
+    pub second_escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = second_escrow.load()?.vault(&second_escrow.key(), second_vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub second_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Validated via second_escrow.seller constraint
+    #[account(mut, address = second_escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub second_seller: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = second_seller_request_token.mint == second_escrow.load()?.request_mint @ EscrowError::InvalidMint,
+        constraint = second_seller_request_token.owner == second_escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub second_seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub second_market: Option<Box<Account<'info, Market>>>,
+
+    #[account(mut)]
+    pub second_market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub second_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `second_fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub second_taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub second_pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub second_seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Taker's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub second_taker_stats: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"proceeds", second_escrow.key().as_ref()],
+        bump = second_escrow.load()?.proceeds_bump,
+    )]
+    pub second_proceeds_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Mint the taker pays in (writable mints let withheld transfer fees
+    /// be harvested when closing accounts)
+    #[account(mut, address = first_escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub input_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = first_escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub intermediate_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Mint the taker receives
+    #[account(mut, address = second_escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub output_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Holds the intermediate leg for the duration of the instruction
+    #[account(
+        init,
+        payer = taker,
+        seeds = [b"transient", first_escrow.key().as_ref()],
+        bump,
+        token::mint = intermediate_mint,
+        token::authority = first_escrow,
+    )]
+    pub transient: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = taker_input_token.mint == first_escrow.load()?.request_mint @ EscrowError::InvalidMint,
+        constraint = taker_input_token.owner == taker.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub taker_input_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = taker_output_token.mint == second_escrow.load()?.offer_mint @ EscrowError::InvalidMint,
+        constraint = taker_output_token.owner == taker.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub taker_output_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Receives intermediate surplus, required when there is any
+    #[account(
+        mut,
+        constraint = taker_intermediate_token.mint == first_escrow.load()?.offer_mint @ EscrowError::InvalidMint,
+        constraint = taker_intermediate_token.owner == taker.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub taker_intermediate_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: Each mint's limits address, checked against what partial
+    /// fills leave listed; may be uninitialized
+    #[account(seeds = [b"mint_limits", input_mint.key().as_ref()], bump)]
+    pub input_mint_limits: UncheckedAccount<'info>,
+    /// CHECK: See `input_mint_limits`
+    #[account(seeds = [b"mint_limits", intermediate_mint.key().as_ref()], bump)]
+    pub intermediate_mint_limits: UncheckedAccount<'info>,
+    /// CHECK: See `input_mint_limits`
+    #[account(seeds = [b"mint_limits", output_mint.key().as_ref()], bump)]
+    pub output_mint_limits: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", intermediate_mint.key().as_ref(), input_mint.key().as_ref()],
+        bump,
+    )]
+    pub first_pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", output_mint.key().as_ref(), intermediate_mint.key().as_ref()],
+        bump,
+    )]
+    pub second_pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", first_seller.key().as_ref()], bump)]
+    pub first_seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", first_seller.key().as_ref()], bump)]
+    pub first_seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub first_rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", second_seller.key().as_ref()], bump)]
+    pub second_seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", second_seller.key().as_ref()], bump)]
+    pub second_seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub second_rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the intermediate mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub input_token_program: Interface<'info, TokenInterface>,
+    pub output_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct CancelEscrow<'info> {
+    /// The seller or their operator, either of which may be another
+    /// program's PDA signing through CPI
+    pub authority: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint; receives the rent
+    #[account(
+        mut,
+        address = escrow.load()?.seller @ EscrowError::Unauthorized,
+    )]
+    pub seller: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = matches!(
+            escrow.load()?.status(),
+            EscrowStatus::Open | EscrowStatus::Stuck
+        ) @ EscrowError::EscrowNotOpen,
+        constraint = SellerOperator::authorizes(
+            &authority.key(),
+            &escrow.load()?.seller,
+            seller_operator.as_ref().map(|operator| operator.operator),
+        ) || session.as_ref().map_or(Ok(false), |session| {
+            session.authorizes(&authority.key(), &escrow.load()?.seller, SESSION_CANCEL)
+        })? @ EscrowError::Unauthorized,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// The seller's operator, required when it signs as `authority`
+    #[account(
+        seeds = [b"seller_operator", escrow.load()?.seller.as_ref()],
+        bump = seller_operator.bump,
+    )]
+    pub seller_operator: Option<Box<Account<'info, SellerOperator>>>,
+
+    /// The seller's session, required when its key signs as `authority`
+    #[account(
+        seeds = [b"session", session.owner.as_ref(), session.session_key.as_ref()],
+        bump = session.bump,
+    )]
+    pub session: Option<Box<Account<'info, SessionAuthority>>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Not needed when offering native SOL
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when a position receipt was minted
+    #[account(mut)]
+    pub receipt_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+
+    #[account(mut)]
+    pub seller_receipt_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        mut,
+        seeds = [b"proceeds", escrow.key().as_ref()],
+        bump = escrow.load()?.proceeds_bump,
+    )]
+    pub proceeds_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub request_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+
+    pub request_token_program: Option<Interface<'info, TokenInterface>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", seller.key().as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Offer registry page the escrow is listed on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    /// Market index page the escrow is linked into, if any
+    #[account(mut)]
+    pub market_index: Option<Box<Account<'info, MarketIndex>>>,
+
+    /// Required to cancel while a cancellation bond is locked or the
+    /// escrow has been partly filled, and for the cancellation to reach the
+    /// activity log
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Option<Box<Account<'info, Config>>>,
+
+    /// CHECK: The config's treasury wallet, receiving a forfeited bond
+    #[account(
+        mut,
+        constraint = config.as_ref().is_some_and(|config| config.treasury == treasury.key()) @ EscrowError::MissingTreasuryAccount,
+    )]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL Memo program, required when a memo is passed
+    #[account(address = MEMO_PROGRAM_ID)]
+    pub memo_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint, which also owns the receipt mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MintReceipt<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"receipt", escrow.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = escrow,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = seller,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = seller,
+    )]
+    pub seller_receipt_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Holds fill proceeds until the receipt is redeemed
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"proceeds", escrow.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = escrow,
+        token::token_program = request_token_program,
+    )]
+    pub proceeds_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Created by the token metadata program; address verified via seeds
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            receipt_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub receipt_metadata: UncheckedAccount<'info>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+    /// Token program of the offer mint; the receipt mint is created under it
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemReceipt<'info> {
+    pub holder: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint; receives rent
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        constraint = Some(receipt_mint.key()) == escrow.load()?.receipt_mint() @ EscrowError::InvalidReceipt,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = holder_receipt_token.mint == receipt_mint.key() @ EscrowError::InvalidReceipt,
+        constraint = holder_receipt_token.owner == holder.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub holder_receipt_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required while the offer is still unfilled
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        mut,
+        seeds = [b"proceeds", escrow.key().as_ref()],
+        bump = escrow.load()?.proceeds_bump,
+    )]
+    pub proceeds_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = holder_offer_token.mint == escrow.load()?.offer_mint @ EscrowError::InvalidMint,
+        constraint = holder_offer_token.owner == holder.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub holder_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = holder_request_token.mint == escrow.load()?.request_mint @ EscrowError::InvalidMint,
+        constraint = holder_request_token.owner == holder.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub holder_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint, which also owns the receipt mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct LockEscrow<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    pub market: Box<Account<'info, Market>>,
+
+    /// PDA of the locking program derived from `escrow_locker`
+    pub locker_authority: Signer<'info>,
+
+    /// CHECK: Must be executable and approved by the market
+    #[account(executable)]
+    pub locker_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// PDA of the locking program derived from `escrow_locker`
+    pub locker_authority: Signer<'info>,
+
+    /// CHECK: Compared against escrow.locked_by()
+    #[account(executable)]
+    pub locker_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TopUpEscrow<'info> {
+    /// The seller or their operator, funding the top-up as owner or
+    /// delegate of `seller_offer_token`
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = SellerOperator::authorizes(
+            &authority.key(),
+            &escrow.load()?.seller,
+            seller_operator.as_ref().map(|operator| operator.operator),
+        ) @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// The seller's operator, required when it signs as `authority`
+    #[account(
+        seeds = [b"seller_operator", escrow.load()?.seller.as_ref()],
+        bump = seller_operator.bump,
+    )]
+    pub seller_operator: Option<Box<Account<'info, SellerOperator>>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Not needed when offering native SOL
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.offer_mint.as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.request_mint.as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromEscrow<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Wrapped SOL account when the offer is native SOL
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.offer_mint.as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.request_mint.as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEscrow<'info> {
+    /// The seller or their operator
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.offer_mint.as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", escrow.load()?.request_mint.as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = SellerOperator::authorizes(
+            &authority.key(),
+            &escrow.load()?.seller,
+            seller_operator.as_ref().map(|operator| operator.operator),
+        ) @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// The seller's operator, required when it signs as `authority`
+    #[account(
+        seeds = [b"seller_operator", escrow.load()?.seller.as_ref()],
+        bump = seller_operator.bump,
+    )]
+    pub seller_operator: Option<Box<Account<'info, SellerOperator>>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateCounterOffer<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + CounterOffer::INIT_SPACE,
+        seeds = [b"counter_offer", escrow.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub counter_offer: Box<Account<'info, CounterOffer>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        seeds = [b"counter_vault", counter_offer.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = counter_offer,
+        token::token_program = token_program,
+    )]
+    pub counter_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct AcceptCounterOffer<'info> {
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: Signer<'info>,
+
+    /// CHECK: Checked against the counter offer; receives its rent
+    #[account(mut, address = counter_offer.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"counter_offer", escrow.key().as_ref(), buyer.key().as_ref()],
+        bump = counter_offer.bump,
+        close = buyer,
+    )]
+    pub counter_offer: Box<Account<'info, CounterOffer>>,
+
+    #[account(
+        mut,
+        seeds = [b"counter_vault", counter_offer.key().as_ref()],
+        bump = counter_offer.vault_bump,
+    )]
+    pub counter_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_offer_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Receives what is left of the deposit
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// Market fee receiver's request-mint token account
+    #[account(mut)]
+    pub market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Buyer's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub buyer_stats: Option<UncheckedAccount<'info>>,
+
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Offered NFT's metadata, required when the escrow pays royalties
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Required when a position receipt was minted
+    #[account(
+        mut,
+        seeds = [b"proceeds", escrow.key().as_ref()],
+        bump = escrow.load()?.proceeds_bump,
+    )]
+    pub proceeds_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", seller.key().as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseCounterOffer<'info> {
+    /// The escrow's seller to reject, or the buyer to withdraw
+    pub authority: Signer<'info>,
+
+    /// CHECK: Checked against the counter offer; receives its rent
+    #[account(mut, address = counter_offer.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"counter_offer",
+            counter_offer.escrow.as_ref(),
+            counter_offer.buyer.as_ref(),
+        ],
+        bump = counter_offer.bump,
+        close = buyer,
+    )]
+    pub counter_offer: Box<Account<'info, CounterOffer>>,
+
+    #[account(
+        mut,
+        seeds = [b"counter_vault", counter_offer.key().as_ref()],
+        bump = counter_offer.vault_bump,
+    )]
+    pub counter_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = counter_offer.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> CloseCounterOffer<'info> {
+    /// Return the deposit to the buyer and close the counter vault; the
+    /// counter offer itself is closed by its `close = buyer` constraint
+    pub(crate) fn refund(&self, remaining_accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let counter = &self.counter_offer;
+        let counter_seeds = &[
+            b"counter_offer",
+            counter.escrow.as_ref(),
+            counter.buyer.as_ref(),
+            &[counter.bump],
+        ];
+        let signer_seeds = &[&counter_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                TransferChecked {
+                    from: self.counter_vault.to_account_info(),
+                    mint: self.request_mint.to_account_info(),
+                    to: self.buyer_request_token.to_account_info(),
+                    authority: counter.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(remaining_accounts.to_vec()),
+            self.counter_vault.amount,
+            self.request_mint.decimals,
+        )?;
+
+        close_token_account(
+            self.token_program.to_account_info(),
+            self.counter_vault.to_account_info(),
+            self.request_mint.to_account_info(),
+            self.buyer.to_account_info(),
+            counter.to_account_info(),
+            signer_seeds,
+        )
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(bid_id: u64)]
+pub struct CreateBidEscrow<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + BidEscrow::INIT_SPACE,
+        seeds = [
+            b"bid_escrow",
+            buyer.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            bid_id.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub bid: Box<Account<'info, BidEscrow>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        seeds = [b"bid_vault", bid.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = bid,
+        token::token_program = token_program,
+    )]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The offer mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The bidder's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", buyer.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", request_mint.key().as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct AcceptBidEscrow<'info> {
+    pub seller: Signer<'info>,
+
+    /// CHECK: Checked against the bid; receives its rent once filled
+    #[account(mut, address = bid.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+
+    #[account(address = bid.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = bid.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"bid_escrow",
+            bid.buyer.as_ref(),
+            bid.offer_mint.as_ref(),
+            bid.request_mint.as_ref(),
+            bid.bid_id.to_le_bytes().as_ref(),
+        ],
+        bump = bid.bump,
+    )]
+    pub bid: Box<Account<'info, BidEscrow>>,
+
+    #[account(
+        mut,
+        seeds = [b"bid_vault", bid.key().as_ref()],
+        bump = bid.vault_bump,
+    )]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_offer_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// Program fee vault of the fee currency's mint, required while the
+    /// protocol fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Seller's NFT of the config's fee discount collection, and the
+    /// metadata of its mint, to claim the discount
+    pub discount_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub discount_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CancelBidEscrow<'info> {
+    #[account(mut, address = bid.buyer @ EscrowError::Unauthorized)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"bid_escrow",
+            bid.buyer.as_ref(),
+            bid.offer_mint.as_ref(),
+            bid.request_mint.as_ref(),
+            bid.bid_id.to_le_bytes().as_ref(),
+        ],
+        bump = bid.bump,
+        close = buyer,
+    )]
+    pub bid: Box<Account<'info, BidEscrow>>,
+
+    #[account(
+        mut,
+        seeds = [b"bid_vault", bid.key().as_ref()],
+        bump = bid.vault_bump,
+    )]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = bid.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(rfq_id: u64)]
+pub struct CreateRfq<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + Rfq::INIT_SPACE,
+        seeds = [b"rfq", buyer.key().as_ref(), rfq_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub rfq: Box<Account<'info, Rfq>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The offer mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The buyer's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", buyer.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitQuote<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq", rfq.buyer.as_ref(), rfq.rfq_id.to_le_bytes().as_ref()],
+        bump = rfq.bump,
+    )]
+    pub rfq: Box<Account<'info, Rfq>>,
+
+    #[account(address = rfq.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + RfqQuote::INIT_SPACE,
+        seeds = [b"rfq_quote", rfq.key().as_ref(), seller.key().as_ref()],
+        bump,
+    )]
+    pub quote: Box<Account<'info, RfqQuote>>,
+
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"rfq_quote_vault", quote.key().as_ref()],
+        bump,
+        token::mint = offer_mint,
+        token::authority = quote,
+        token::token_program = token_program,
+    )]
+    pub quote_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The offer mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", rfq.offer_mint.as_ref()], bump)]
+    pub offer_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", rfq.request_mint.as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", rfq.offer_mint.as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", rfq.request_mint.as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptQuote<'info> {
+    #[account(address = rfq.buyer @ EscrowError::Unauthorized)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Checked against the quote; receives its rent
+    #[account(mut, address = quote.seller @ EscrowError::Unauthorized)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq", rfq.buyer.as_ref(), rfq.rfq_id.to_le_bytes().as_ref()],
+        bump = rfq.bump,
+    )]
+    pub rfq: Box<Account<'info, Rfq>>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq_quote", rfq.key().as_ref(), seller.key().as_ref()],
+        bump = quote.bump,
+        close = seller,
+    )]
+    pub quote: Box<Account<'info, RfqQuote>>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq_quote_vault", quote.key().as_ref()],
+        bump = quote.vault_bump,
+    )]
+    pub quote_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = rfq.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = rfq.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_offer_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseQuote<'info> {
+    /// The quote's seller, or anyone once the RFQ has closed for quotes
+    pub authority: Signer<'info>,
+
+    /// CHECK: Checked against the quote; receives its offer and rent
+    #[account(mut, address = quote.seller @ EscrowError::Unauthorized)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq", rfq.buyer.as_ref(), rfq.rfq_id.to_le_bytes().as_ref()],
+        bump = rfq.bump,
+    )]
+    pub rfq: Box<Account<'info, Rfq>>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq_quote", rfq.key().as_ref(), seller.key().as_ref()],
+        bump = quote.bump,
+        close = seller,
+    )]
+    pub quote: Box<Account<'info, RfqQuote>>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq_quote_vault", quote.key().as_ref()],
+        bump = quote.vault_bump,
+    )]
+    pub quote_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = rfq.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRfq<'info> {
+    #[account(mut, address = rfq.buyer @ EscrowError::Unauthorized)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"rfq", rfq.buyer.as_ref(), rfq.rfq_id.to_le_bytes().as_ref()],
+        bump = rfq.bump,
+        close = buyer,
+    )]
+    pub rfq: Box<Account<'info, Rfq>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateAuction<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + Auction::INIT_SPACE,
+        seeds = [b"auction", escrow.key().as_ref()],
+        bump,
+    )]
+    pub auction: Box<Account<'info, Auction>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CommitBid<'info> {
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"auction", auction.escrow.as_ref()],
+        bump = auction.bump,
+    )]
+    pub auction: Box<Account<'info, Auction>>,
+
+    #[account(address = auction.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = bidder_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = bidder_request_token.owner == bidder.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub bidder_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = bidder,
+        space = 8 + Bid::INIT_SPACE,
+        seeds = [b"bid", auction.key().as_ref(), bidder.key().as_ref()],
+        bump,
+    )]
+    pub bid: Box<Account<'info, Bid>>,
+
+    #[account(
+        init,
+        payer = bidder,
+        seeds = [b"bid_vault", bid.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = bid,
+        token::token_program = token_program,
+    )]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevealBid<'info> {
+    pub bidder: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"auction", auction.escrow.as_ref()],
+        bump = auction.bump,
+    )]
+    pub auction: Box<Account<'info, Auction>>,
+
+    #[account(
+        mut,
+        seeds = [b"bid", auction.key().as_ref(), bidder.key().as_ref()],
+        bump = bid.bump,
+    )]
+    pub bid: Box<Account<'info, Bid>>,
+
+    #[account(seeds = [b"bid_vault", bid.key().as_ref()], bump = bid.vault_bump)]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct SettleAuction<'info> {
+    /// CHECK: Validated via escrow.seller constraint; receives the
+    /// auction's rent
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the winning bid; receives its rent
+    #[account(mut, address = bid.bidder @ EscrowError::Unauthorized)]
+    pub winner: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"auction", escrow.key().as_ref()],
+        bump = auction.bump,
+        close = seller,
+    )]
+    pub auction: Box<Account<'info, Auction>>,
+
+    #[account(
+        mut,
+        seeds = [b"bid", auction.key().as_ref(), winner.key().as_ref()],
+        bump = bid.bump,
+        constraint = auction.highest_bidder == Some(bid.bidder) @ EscrowError::NoWinningBid,
+        close = winner,
+    )]
+    pub bid: Box<Account<'info, Bid>>,
+
+    #[account(
+        mut,
+        seeds = [b"bid_vault", bid.key().as_ref()],
+        bump = bid.vault_bump,
+    )]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = winner_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = winner_offer_token.owner == winner.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub winner_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Receives what is left of the winner's deposit
+    #[account(
+        mut,
+        constraint = winner_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = winner_request_token.owner == winner.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub winner_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// Market fee receiver's request-mint token account
+    #[account(mut)]
+    pub market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Winner's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub winner_stats: Option<UncheckedAccount<'info>>,
+
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Offered NFT's metadata, required when the escrow pays royalties
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", seller.key().as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The instructions sysvar; guards the settlement against the
+    /// rest of its transaction
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RefundBid<'info> {
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+
+    /// CHECK: The bid's auction; closed once settled or cancelled
+    #[account(address = bid.auction)]
+    pub auction: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"bid", bid.auction.as_ref(), bidder.key().as_ref()],
+        bump = bid.bump,
+        close = bidder,
+    )]
+    pub bid: Box<Account<'info, Bid>>,
+
+    #[account(
+        mut,
+        seeds = [b"bid_vault", bid.key().as_ref()],
+        bump = bid.vault_bump,
+    )]
+    pub bid_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = bid_vault.mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = bidder_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = bidder_request_token.owner == bidder.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub bidder_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAuction<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"auction", escrow.key().as_ref()],
+        bump = auction.bump,
+        close = seller,
+    )]
+    pub auction: Box<Account<'info, Auction>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateCrowdfill<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + Crowdfill::INIT_SPACE,
+        seeds = [b"crowdfill", escrow.key().as_ref()],
+        bump,
+    )]
+    pub crowdfill: Box<Account<'info, Crowdfill>>,
+
+    /// Holds contributions until settlement or reclaim
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"crowdfill_vault", crowdfill.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = crowdfill,
+        token::token_program = request_token_program,
+    )]
+    pub crowdfill_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Holds the bought offer tokens until contributors claim them
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"crowdfill_offer_vault", crowdfill.key().as_ref()],
+        bump,
+        token::mint = offer_mint,
+        token::authority = crowdfill,
+        token::token_program = token_program,
+    )]
+    pub crowdfill_offer_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Contribute<'info> {
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill", crowdfill.escrow.as_ref()],
+        bump = crowdfill.bump,
+    )]
+    pub crowdfill: Box<Account<'info, Crowdfill>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.vault_bump,
+    )]
+    pub crowdfill_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = contributor,
+        space = 8 + Contribution::INIT_SPACE,
+        seeds = [b"contribution", crowdfill.key().as_ref(), contributor.key().as_ref()],
+        bump,
+    )]
+    pub contribution: Box<Account<'info, Contribution>>,
+
+    #[account(address = crowdfill.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = contributor_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = contributor_request_token.owner == contributor.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub contributor_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct SettleCrowdfill<'info> {
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill", escrow.key().as_ref()],
+        bump = crowdfill.bump,
+    )]
+    pub crowdfill: Box<Account<'info, Crowdfill>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.vault_bump,
+    )]
+    pub crowdfill_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_offer_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.offer_vault_bump,
+    )]
+    pub crowdfill_offer_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// Market fee receiver's request-mint token account
+    #[account(mut)]
+    pub market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The crowdfill's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub crowdfill_stats: Option<UncheckedAccount<'info>>,
+
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Offered NFT's metadata, required when the escrow pays royalties
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", seller.key().as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCrowdfill<'info> {
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill", crowdfill.escrow.as_ref()],
+        bump = crowdfill.bump,
+    )]
+    pub crowdfill: Box<Account<'info, Crowdfill>>,
+
+    #[account(
+        mut,
+        seeds = [b"contribution", crowdfill.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump,
+        close = contributor,
+    )]
+    pub contribution: Box<Account<'info, Contribution>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.vault_bump,
+    )]
+    pub crowdfill_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_offer_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.offer_vault_bump,
+    )]
+    pub crowdfill_offer_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = crowdfill.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = crowdfill.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = contributor_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = contributor_offer_token.owner == contributor.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub contributor_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = contributor_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = contributor_request_token.owner == contributor.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub contributor_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimContribution<'info> {
+    #[account(mut)]
+    pub contributor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill", crowdfill.escrow.as_ref()],
+        bump = crowdfill.bump,
+    )]
+    pub crowdfill: Box<Account<'info, Crowdfill>>,
+
+    #[account(
+        mut,
+        seeds = [b"contribution", crowdfill.key().as_ref(), contributor.key().as_ref()],
+        bump = contribution.bump,
+        close = contributor,
+    )]
+    pub contribution: Box<Account<'info, Contribution>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.vault_bump,
+    )]
+    pub crowdfill_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = crowdfill.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = contributor_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = contributor_request_token.owner == contributor.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub contributor_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseCrowdfill<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    /// The crowdfill's escrow, unlocked when closing an unsettled crowdfill
+    #[account(mut, address = crowdfill.escrow)]
+    pub escrow: Option<AccountLoader<'info, Escrow>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill", crowdfill.escrow.as_ref()],
+        bump = crowdfill.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        close = seller,
+    )]
+    pub crowdfill: Box<Account<'info, Crowdfill>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.vault_bump,
+    )]
+    pub crowdfill_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"crowdfill_offer_vault", crowdfill.key().as_ref()],
+        bump = crowdfill.offer_vault_bump,
+    )]
+    pub crowdfill_offer_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = crowdfill.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = crowdfill.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ReserveEscrow<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + Reservation::INIT_SPACE,
+        seeds = [b"reservation", escrow.key().as_ref()],
+        bump,
+    )]
+    pub reservation: Box<Account<'info, Reservation>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseReservation<'info> {
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [b"reservation", reservation.escrow.as_ref()],
+        bump = reservation.bump,
+    )]
+    pub reservation: Box<Account<'info, Reservation>>,
+
+    /// CHECK: The reserved escrow, open or already closed
+    #[account(address = reservation.escrow @ EscrowError::InvalidReservation)]
+    pub escrow: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the reservation; receives a forfeited deposit
+    #[account(mut, address = reservation.seller @ EscrowError::Unauthorized)]
+    pub seller: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the reservation; receives its rent
+    #[account(mut, address = reservation.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CommitFill<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + FillCommitment::INIT_SPACE,
+        seeds = [b"fill_commitment", escrow.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub fill_commitment: Box<Account<'info, FillCommitment>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevealFill<'info> {
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"fill_commitment", fill_commitment.escrow.as_ref(), buyer.key().as_ref()],
+        bump = fill_commitment.bump,
+    )]
+    pub fill_commitment: Box<Account<'info, FillCommitment>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseFillCommitment<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [b"fill_commitment", fill_commitment.escrow.as_ref(), buyer.key().as_ref()],
+        bump = fill_commitment.bump,
+    )]
+    pub fill_commitment: Box<Account<'info, FillCommitment>>,
+}
+
+#[derive(Accounts)]
+pub struct BuyOption<'info> {
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FundLoan<'info> {
+    /// Disburses the principal and funds the loan account
+    #[account(mut)]
+    pub lender: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint; the borrower
+    #[account(address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = lender,
+        space = 8 + Loan::INIT_SPACE,
+        seeds = [b"loan", escrow.key().as_ref()],
+        bump,
+    )]
+    pub loan: Box<Account<'info, Loan>>,
+
+    #[account(
+        mut,
+        constraint = lender_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = lender_request_token.owner == lender.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub lender_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// Offer registry page the escrow is listed on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RepayLoan<'info> {
+    /// The borrower
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: Signer<'info>,
+
+    /// CHECK: Validated via loan.lender constraint; receives the loan
+    /// account's rent
+    #[account(mut, address = loan.lender @ EscrowError::Unauthorized)]
+    pub lender: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Lent @ EscrowError::NotALoan,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        close = lender,
+        seeds = [b"loan", escrow.key().as_ref()],
+        bump = loan.bump,
+    )]
+    pub loan: Box<Account<'info, Loan>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = lender_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = lender_request_token.owner == lender.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub lender_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidateLoan<'info> {
+    /// Funds the lender's token account if needed; anyone may liquidate
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// CHECK: Validated via loan.lender constraint; receives the collateral
+    /// and the loan account's rent
+    #[account(mut, address = loan.lender @ EscrowError::Unauthorized)]
+    pub lender: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Lent @ EscrowError::NotALoan,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        close = lender,
+        seeds = [b"loan", escrow.key().as_ref()],
+        bump = loan.bump,
+    )]
+    pub loan: Box<Account<'info, Loan>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = lender,
+        associated_token::token_program = token_program,
+    )]
+    pub lender_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StartPaymentPlan<'info> {
+    /// Pays the first installment and funds the plan's accounts
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + PaymentPlan::INIT_SPACE,
+        seeds = [b"payment_plan", escrow.key().as_ref()],
+        bump,
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+
+    /// Holds payments against offer not yet released to the buyer
+    #[account(
+        init,
+        payer = buyer,
+        seeds = [b"payment_plan_vault", escrow.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = payment_plan,
+        token::token_program = request_token_program,
+    )]
+    pub plan_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// Offer registry page the escrow is listed on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    /// Market index page the escrow is linked into, if any
+    #[account(mut)]
+    pub market_index: Option<Box<Account<'info, MarketIndex>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayInstallment<'info> {
+    #[account(mut, address = payment_plan.buyer @ EscrowError::Unauthorized)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint; receives the escrow's
+    /// rent on completion
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::PaymentPlan @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"payment_plan", escrow.key().as_ref()],
+        bump = payment_plan.bump,
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+
+    #[account(
+        mut,
+        address = payment_plan.vault @ EscrowError::InvalidVault,
+    )]
+    pub plan_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_offer_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimPaymentPlan<'info> {
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: Signer<'info>,
+
+    /// CHECK: Validated via payment_plan.buyer constraint; receives the
+    /// plan's rent and the payments the seller doesn't keep
+    #[account(mut, address = payment_plan.buyer @ EscrowError::Unauthorized)]
+    pub buyer: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::PaymentPlan @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        close = buyer,
+        seeds = [b"payment_plan", escrow.key().as_ref()],
+        bump = payment_plan.bump,
+    )]
+    pub payment_plan: Box<Account<'info, PaymentPlan>>,
+
+    #[account(
+        mut,
+        address = payment_plan.vault @ EscrowError::InvalidVault,
+    )]
+    pub plan_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct HoldEscrow<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + Settlement::INIT_SPACE,
+        seeds = [b"settlement", escrow.key().as_ref()],
+        bump,
+    )]
+    pub settlement: Box<Account<'info, Settlement>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        seeds = [b"settlement_vault", settlement.key().as_ref()],
+        bump,
+        token::mint = request_mint,
+        token::authority = settlement,
+        token::token_program = token_program,
+    )]
+    pub settlement_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseDispute<'info> {
+    /// The escrow's seller or the settlement's buyer
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement", escrow.key().as_ref()],
+        bump = settlement.bump,
+    )]
+    pub settlement: Box<Account<'info, Settlement>>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveSettlement<'info> {
+    /// The escrow's seller, the settlement's buyer or the arbiter
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement", escrow.key().as_ref()],
+        bump = settlement.bump,
+    )]
+    pub settlement: Box<Account<'info, Settlement>>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    pub arbiter: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement", escrow.key().as_ref()],
+        bump = settlement.bump,
+    )]
+    pub settlement: Box<Account<'info, Settlement>>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", escrow.load()?.seller.as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct ReleaseSettlement<'info> {
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the settlement; receives its rent
+    #[account(mut, address = settlement.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement", escrow.key().as_ref()],
+        bump = settlement.bump,
+        close = buyer,
+    )]
+    pub settlement: Box<Account<'info, Settlement>>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement_vault", settlement.key().as_ref()],
+        bump = settlement.vault_bump,
+    )]
+    pub settlement_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = buyer_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_offer_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Receives what is left of the buyer's deposit
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    /// Market fee receiver's request-mint token account
+    #[account(mut)]
+    pub market_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The market's pair fee address; may be uninitialized
+    pub pair_fees: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Seller's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub seller_stats: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Buyer's trader stats address; may be uninitialized
+    #[account(mut)]
+    pub buyer_stats: Option<UncheckedAccount<'info>>,
+
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<Box<Account<'info, BuyerAllowlist>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(mut)]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Program fee vault of the request mint for the taker's protocol
+    /// fee, required while it is non-zero and `fee_vault` holds the offer
+    /// mint
+    #[account(mut)]
+    pub taker_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Offered NFT's metadata, required when the escrow pays royalties
+    #[account(
+        constraint = offer_metadata.mint == offer_mint.key() @ EscrowError::InvalidMetadata,
+    )]
+    pub offer_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The pair's `PairStats` address, initialized or not
+    #[account(
+        mut,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `Reputation` address, initialized or not
+    #[account(mut, seeds = [b"reputation", seller.key().as_ref()], bump)]
+    pub seller_reputation: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub request_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RefundSettlement<'info> {
+    /// CHECK: Checked against the settlement; receives its rent
+    #[account(mut, address = settlement.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement", escrow.key().as_ref()],
+        bump = settlement.bump,
+        close = buyer,
+    )]
+    pub settlement: Box<Account<'info, Settlement>>,
+
+    #[account(
+        mut,
+        seeds = [b"settlement_vault", settlement.key().as_ref()],
+        bump = settlement.vault_bump,
+    )]
+    pub settlement_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+
+    #[account(mut, has_one = beneficiary @ EscrowError::Unauthorized)]
+    pub vesting: Box<Account<'info, Vesting>>,
+
+    #[account(mut, address = vesting.vault)]
+    pub vesting_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = vesting.mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = beneficiary_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = beneficiary_token.owner == beneficiary.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub beneficiary_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the offer mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStream<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(mut, has_one = seller @ EscrowError::Unauthorized)]
+    pub stream: Box<Account<'info, Stream>>,
+
+    #[account(mut, address = stream.vault)]
+    pub stream_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Receives the stream's rent once it is fully withdrawn
+    #[account(mut, address = stream.buyer @ EscrowError::Unauthorized)]
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = stream.mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct AddAllowedBuyer<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = 8 + BuyerAllowlist::INIT_SPACE,
+        seeds = [b"buyer_allowlist", seller.key().as_ref()],
+        bump,
+    )]
+    pub buyer_allowlist: Box<Account<'info, BuyerAllowlist>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveAllowedBuyer<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"buyer_allowlist", seller.key().as_ref()],
+        bump = buyer_allowlist.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+    )]
+    pub buyer_allowlist: Box<Account<'info, BuyerAllowlist>>,
+}
+
+#[derive(Accounts)]
+pub struct SetSellerOperator<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = 8 + SellerOperator::INIT_SPACE,
+        seeds = [b"seller_operator", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_operator: Box<Account<'info, SellerOperator>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveSellerOperator<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"seller_operator", seller.key().as_ref()],
+        bump = seller_operator.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        close = seller,
+    )]
+    pub seller_operator: Box<Account<'info, SellerOperator>>,
+}
+
+#[derive(Accounts)]
+#[instruction(session_key: Pubkey)]
+pub struct CreateSession<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + SessionAuthority::INIT_SPACE,
+        seeds = [b"session", owner.key().as_ref(), session_key.as_ref()],
+        bump,
+    )]
+    pub session: Box<Account<'info, SessionAuthority>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    /// The session's owner, or its key ending the session early
+    #[account(
+        constraint = authority.key() == session.owner
+            || authority.key() == session.session_key @ EscrowError::Unauthorized,
+    )]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Checked against the session; receives its rent
+    #[account(mut, address = session.owner @ EscrowError::Unauthorized)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"session", session.owner.as_ref(), session.session_key.as_ref()],
+        bump = session.bump,
+        close = owner,
+    )]
+    pub session: Box<Account<'info, SessionAuthority>>,
+}
+
+#[derive(Accounts)]
+pub struct SetEscrowCondition<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveCondition<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// CHECK: Pyth price update; validated in PythPrice::load
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    /// Signs for attested conditions
+    pub resolver: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct ClaimHashlock<'info> {
+    /// Presents the preimage and funds the buyer's token account if
+    /// needed; anyone may claim on the buyer's behalf
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// CHECK: Validated via escrow.designated_buyer constraint
+    #[account(
+        address = escrow.load()?.designated_buyer().unwrap_or_default() @ EscrowError::NotDesignatedBuyer,
+    )]
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Offer registry page the escrow is listed on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(emitter_chain: u16, sequence: u64)]
+#[event_cpi]
+pub struct AcceptWormholeEscrow<'info> {
+    /// Relays the VAA and funds the accounts it opens; anyone may relay
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Validated via escrow.seller constraint
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// CHECK: Solana wallet named in the VAA, which receives the offer
+    pub recipient: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = recipient,
+        associated_token::token_program = token_program,
+    )]
+    pub recipient_offer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"foreign_emitter", emitter_chain.to_le_bytes().as_ref()],
+        bump = foreign_emitter.bump,
+    )]
+    pub foreign_emitter: Box<Account<'info, ForeignEmitter>>,
+
+    /// CHECK: Core bridge `PostedVAA` account; validated in
+    /// WormholePayment::load
+    pub posted_vaa: UncheckedAccount<'info>,
+
+    /// Fails to initialize if the message was already consumed
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + ReceivedMessage::INIT_SPACE,
+        seeds = [
+            b"received_message",
+            emitter_chain.to_le_bytes().as_ref(),
+            sequence.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub received_message: Box<Account<'info, ReceivedMessage>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    /// Offer registry page the escrow is listed on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct RefundEscrow<'info> {
+    /// CHECK: Validated via escrow.seller constraint; receives the refund rent
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Not needed when the offer is native SOL
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == escrow.load()?.offer_mint @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == escrow.load()?.seller @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    /// Market index page the escrow is linked into, if any
+    #[account(mut)]
+    pub market_index: Option<Box<Account<'info, MarketIndex>>>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// Receives the bounty when cranking an expired escrow
+    #[account(mut)]
+    pub cranker: Option<Signer<'info>>,
+
+    /// Config authority, when cancelling the escrow as an admin
+    pub authority: Option<Signer<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> RefundEscrow<'info> {
+    /// Return the remaining offer to the seller and close the vault, then
+    /// close the escrow unless it is kept as a record with `status`
+    pub(crate) fn refund(
+        &mut self,
+        remaining_accounts: &[AccountInfo<'info>],
+        status: EscrowStatus,
+    ) -> Result<()> {
+        let escrow = self.escrow.load()?;
+        // Receipted positions are refunded through redeem_receipt
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        let offer_amount = escrow.offer_amount;
+        let request_amount = escrow.request_amount;
+        let market_index = escrow.market_index();
+        let delegated = escrow.delegated_source().is_some();
+        let rent_destination =
+            escrow.rent_destination(&self.seller, self.rent_recipient.as_deref())?;
+
+        let seller_key = escrow.seller;
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
+
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        // Native SOL unwraps when the vault is closed to the seller, and a
+        // delegated offer never left the seller
+        if !delegated && !is_native_mint(&offer_mint_key) {
+            let seller_offer_token = self
+                .seller_offer_token
+                .as_ref()
+                .ok_or(EscrowError::MissingOfferTokenAccount)?;
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    TransferChecked {
+                        from: self.vault.to_account_info(),
+                        mint: self.offer_mint.to_account_info(),
+                        to: seller_offer_token.to_account_info(),
+                        authority: self.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(remaining_accounts.to_vec()),
+                offer_amount,
+                self.offer_mint.decimals,
+            )?;
+        }
+
+        // A native SOL vault unwraps to the seller, rent included, and a
+        // delegated escrow's vault is the seller's own account
+        if !delegated {
+            let vault_destination = if is_native_mint(&offer_mint_key) {
+                self.seller.to_account_info()
+            } else {
+                rent_destination.clone()
+            };
+            close_token_account(
+                self.token_program.to_account_info(),
+                self.vault.to_account_info(),
+                self.offer_mint.to_account_info(),
+                vault_destination,
+                self.escrow.to_account_info(),
+                signer_seeds,
+            )?;
+        }
+
+        self.pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&self.seller_index)?;
+        MarketIndex::unlink(
+            self.market_index.as_deref_mut(),
+            market_index,
+            &self.escrow.key(),
+        )?;
+        Stats::record(&self.stats, |stats| match status {
+            EscrowStatus::Cancelled => {
+                stats.escrows_cancelled = stats.escrows_cancelled.saturating_add(1)
+            }
+            _ => stats.escrows_expired = stats.escrows_expired.saturating_add(1),
+        })?;
+
+        let mut escrow = self.escrow.load_mut()?;
+        escrow.set_status(status);
+        let keep_record = escrow.keep_record();
+        drop(escrow);
+        if !keep_record {
+            close_escrow(
+                &self.escrow,
+                self.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
+
+        ActivityLog {
+            config: &self.config,
+            merkle_tree: self.activity_log.as_ref(),
+            log_wrapper: self.log_wrapper.as_ref(),
+            compression_program: self.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: match status {
+                EscrowStatus::Cancelled => ActivityKind::Cancelled,
+                _ => ActivityKind::Expired,
+            },
+            escrow: self.escrow.key(),
+            seller: seller_key,
+            buyer: None,
+            offer_amount,
+            request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+pub struct SetRentRecipient<'info> {
+    /// The escrow's current rent recipient
+    pub rent_recipient: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.rent_recipient() == rent_recipient.key() @ EscrowError::InvalidRentRecipient,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRecord<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() != EscrowStatus::Open @ EscrowError::EscrowStillOpen,
+        // A stuck escrow still holds its vault, recovered by cancelling
+        constraint = escrow.load()?.status() != EscrowStatus::Stuck @ EscrowError::EscrowStuck,
+        // Receipted escrows close when the receipt is redeemed
+        constraint = escrow.load()?.receipt_mint().is_none() @ EscrowError::ReceiptOutstanding,
+        // Lent collateral is released by repaying or liquidating the loan
+        constraint = escrow.load()?.status() != EscrowStatus::Lent @ EscrowError::LoanOutstanding,
+        // An escrow under a payment plan closes once it completes or lapses
+        constraint = escrow.load()?.status() != EscrowStatus::PaymentPlan @ EscrowError::EscrowStillOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+#[instruction(request_mint: Pubkey, escrow_id: u64)]
+pub struct RecoverVault<'info> {
+    /// The seller, or the config authority recovering on their behalf
+    pub authority: Signer<'info>,
+
+    /// CHECK: The seller the escrow address is derived from; receives the
+    /// vault's rent
+    #[account(mut)]
+    pub seller: UncheckedAccount<'info>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: The escrow's address, checked to hold no live escrow
+    #[account(
+        seeds = [
+            b"escrow",
+            seller.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.as_ref(),
+            escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::mint = offer_mint,
+        token::authority = escrow,
+        token::token_program = token_program,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Not needed when the offer is native SOL
+    #[account(
+        mut,
+        token::mint = offer_mint,
+        token::authority = seller,
+        token::token_program = token_program,
+    )]
+    pub seller_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PostCancellationBond<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferEscrowOwnership<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptEscrowOwnership<'info> {
+    /// The pending seller; a program PDA signs through its program
+    #[account(mut)]
+    pub new_seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.pending_seller() == Some(new_seller.key()) @ EscrowError::NoPendingSeller,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    /// CHECK: The outgoing seller's `SellerIndex` address, initialized or
+    /// not
+    #[account(mut, seeds = [b"seller_index", escrow.load()?.seller.as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = new_seller,
+        space = 8 + SellerIndex::INIT_SPACE,
+        seeds = [b"seller_index", new_seller.key().as_ref()],
+        bump,
+    )]
+    pub new_seller_index: Box<Account<'info, SellerIndex>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The new seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", new_seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// Required when the escrow was listed under a market
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MarkEscrowStuck<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+        constraint = vault.is_frozen() @ EscrowError::VaultNotFrozen,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct ExportEscrow<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ EscrowError::Unauthorized)]
+    pub program: Program<'info, crate::program::SplEscrow>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ EscrowError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+
+    /// CHECK: Validated via escrow.seller constraint; receives the vault rent
+    #[account(mut, address = escrow.load()?.seller @ EscrowError::Unauthorized)]
+    pub seller: AccountInfo<'info>,
+
+    /// CHECK: The escrow's rent recipient, when not the seller; validated
+    /// against the escrow and receives the rent on close
+    #[account(mut)]
+    pub rent_recipient: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, address = escrow.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        mut,
+        address = escrow.load()?.vault(&escrow.key(), vault.to_account_info().owner) @ EscrowError::InvalidVault,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Checked to be executable in the handler
+    pub target_program: UncheckedAccount<'info>,
+
+    /// CHECK: PDA of the target program that signs for the holding account
+    #[account(
+        seeds = [b"import", escrow.key().as_ref()],
+        bump,
+        seeds::program = target_program.key(),
+    )]
+    pub import_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"migration", escrow.key().as_ref()],
+        bump,
+        token::mint = offer_mint,
+        token::authority = import_authority,
+    )]
+    pub holding: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + MigrationRecord::INIT_SPACE,
+        seeds = [b"migration_record", escrow.key().as_ref()],
+        bump,
+    )]
+    pub migration_record: Box<Account<'info, MigrationRecord>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The seller's `SellerIndex` address, initialized or not
+    #[account(mut, seeds = [b"seller_index", seller.key().as_ref()], bump)]
+    pub seller_index: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(escrow_id: u64)]
+pub struct ImportEscrow<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ EscrowError::Unauthorized)]
+    pub program: Program<'info, crate::program::SplEscrow>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ EscrowError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+
+    /// CHECK: Deployment the escrow is migrating from
+    pub source_program: UncheckedAccount<'info>,
+
+    /// CHECK: Escrow address under the source program; only its key is used
+    pub source_escrow: UncheckedAccount<'info>,
+
+    /// CHECK: Owned by the source program; validated in MigrationRecord::load
+    #[account(
+        seeds = [b"migration_record", source_escrow.key().as_ref()],
+        bump,
+        seeds::program = source_program.key(),
+    )]
+    pub migration_record: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"migration", source_escrow.key().as_ref()],
+        bump,
+        seeds::program = source_program.key(),
+    )]
+    pub holding: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: PDA signing for the holding account
+    #[account(seeds = [b"import", source_escrow.key().as_ref()], bump)]
+    pub import_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the migration record
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Market the escrow was listed under, if any
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [
+            b"escrow",
+            seller.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        associated_token::mint = offer_mint,
+        associated_token::authority = escrow,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + SellerIndex::INIT_SPACE,
+        seeds = [b"seller_index", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_index: Box<Account<'info, SellerIndex>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateEscrow<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        realloc = 8 + Escrow::INIT_SPACE,
+        realloc::payer = payer,
+        realloc::zero = true,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(escrow_id: u64)]
+#[event_cpi]
+pub struct CloneEscrow<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    /// Funds the accounts opened for the escrow; may be a sponsor other
+    /// than the seller
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// The seller's escrow whose terms are copied
+    #[account(
+        constraint = template.load()?.seller == seller.key() @ EscrowError::Unauthorized,
+    )]
+    pub template: AccountLoader<'info, Escrow>,
+
+    #[account(address = template.load()?.offer_mint @ EscrowError::InvalidMint)]
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = template.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Not needed when offering native SOL
+    #[account(
+        mut,
+        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_offer_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Market the template is listed under, if any
+    pub market: Option<Box<Account<'info, Market>>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [
+            b"escrow",
+            seller.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = offer_mint,
+        associated_token::authority = escrow,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"pair_index"], bump = pair_index.bump)]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    /// CHECK: The protocol `Stats` address, initialized or not
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + SellerIndex::INIT_SPACE,
+        seeds = [b"seller_index", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_index: Box<Account<'info, SellerIndex>>,
+
+    /// Offer registry page to list the escrow on, if any
+    #[account(mut)]
+    pub registry_page: Option<Box<Account<'info, RegistryPage>>>,
+
+    /// Market index page of the escrow's mint pair to link it into, if any
+    #[account(
+        mut,
+        constraint = market_index.offer_mint == offer_mint.key() @ EscrowError::InvalidMarketIndex,
+        constraint = market_index.request_mint == request_mint.key() @ EscrowError::InvalidMarketIndex,
+    )]
+    pub market_index: Option<Box<Account<'info, MarketIndex>>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: The config's activity log tree, checked against the config;
+    /// required while it keeps one
+    #[account(mut)]
+    pub activity_log: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The offer mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    /// CHECK: The offer mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", offer_mint.key().as_ref()], bump)]
+    pub offer_mint_limits: UncheckedAccount<'info>,
+
+    /// CHECK: The request mint's limits address; may be uninitialized
+    #[account(seeds = [b"mint_limits", request_mint.key().as_ref()], bump)]
+    pub request_mint_limits: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(leaf: CnftLeaf)]
+pub struct CreateCnftEscrow<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    /// CHECK: Current delegate of the leaf, usually the seller; verified by
+    /// Bubblegum as part of the leaf hash
+    pub leaf_delegate: UncheckedAccount<'info>,
+
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + CnftEscrow::INIT_SPACE,
+        seeds = [
+            b"cnft_escrow",
+            merkle_tree.key().as_ref(),
+            leaf.nonce.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub cnft_escrow: Box<Account<'info, CnftEscrow>>,
+
+    /// CHECK: Bubblegum's tree config; verified via seeds
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+        seeds::program = bubblegum_program.key(),
+    )]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Verified by Bubblegum against the tree authority
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: Bubblegum program
+    #[account(address = BUBBLEGUM_PROGRAM_ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptCnftEscrow<'info> {
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Receives the escrow's rent; checked against the escrow
+    #[account(mut)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"cnft_escrow",
+            cnft_escrow.merkle_tree.as_ref(),
+            cnft_escrow.leaf.nonce.to_le_bytes().as_ref(),
+        ],
+        bump = cnft_escrow.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        has_one = merkle_tree @ EscrowError::InvalidTree,
+        has_one = request_mint @ EscrowError::InvalidMint,
+        close = seller,
+    )]
+    pub cnft_escrow: Box<Account<'info, CnftEscrow>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(
+        mut,
+        constraint = fee_vault.mint == request_mint.key() @ EscrowError::InvalidMint,
+    )]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: Bubblegum's tree config; verified via seeds
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+        seeds::program = bubblegum_program.key(),
+    )]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Verified by Bubblegum against the tree authority
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: Bubblegum program
+    #[account(address = BUBBLEGUM_PROGRAM_ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelCnftEscrow<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"cnft_escrow",
+            cnft_escrow.merkle_tree.as_ref(),
+            cnft_escrow.leaf.nonce.to_le_bytes().as_ref(),
+        ],
+        bump = cnft_escrow.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        has_one = merkle_tree @ EscrowError::InvalidTree,
+        close = seller,
+    )]
+    pub cnft_escrow: Box<Account<'info, CnftEscrow>>,
+
+    /// CHECK: Bubblegum's tree config; verified via seeds
+    #[account(
+        seeds = [merkle_tree.key().as_ref()],
+        bump,
+        seeds::program = bubblegum_program.key(),
+    )]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Verified by Bubblegum against the tree authority
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: Bubblegum program
+    #[account(address = BUBBLEGUM_PROGRAM_ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(basket_id: u64)]
+pub struct CreateBasketEscrow<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: The request mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", request_mint.key().as_ref()], bump)]
+    pub request_mint_policy: UncheckedAccount<'info>,
+
+    /// CHECK: The seller's ban address; may be uninitialized
+    #[account(seeds = [b"seller_ban", seller.key().as_ref()], bump)]
+    pub seller_ban: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + BasketEscrow::INIT_SPACE,
+        seeds = [b"basket", seller.key().as_ref(), basket_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddBasketLeg<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"basket",
+            seller.key().as_ref(),
+            basket.basket_id.to_le_bytes().as_ref(),
+        ],
+        bump = basket.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: The mint's policy address; may be uninitialized
+    #[account(seeds = [b"mint_policy", mint.key().as_ref()], bump)]
+    pub mint_policy: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Not needed when adding native SOL
+    #[account(
+        mut,
+        constraint = seller_token.mint == mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"basket_vault", basket.key().as_ref(), mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = basket,
+        token::token_program = token_program,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptBasketEscrow<'info> {
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Receives the basket's rent; checked against the basket
+    #[account(mut)]
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"basket",
+            seller.key().as_ref(),
+            basket.basket_id.to_le_bytes().as_ref(),
+        ],
+        bump = basket.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        has_one = request_mint @ EscrowError::InvalidMint,
+        close = seller,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub buyer_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
+        constraint = seller_request_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub seller_request_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Program fee vault of the request mint, required while the protocol
+    /// fee is non-zero
+    #[account(
+        mut,
+        constraint = fee_vault.mint == request_mint.key() @ EscrowError::InvalidMint,
+    )]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Token program of the request mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CancelBasketEscrow<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"basket",
+            seller.key().as_ref(),
+            basket.basket_id.to_le_bytes().as_ref(),
+        ],
+        bump = basket.bump,
+        has_one = seller @ EscrowError::Unauthorized,
+        close = seller,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ EscrowError::Unauthorized)]
+    pub program: Program<'info, crate::program::SplEscrow>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ EscrowError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Config::INIT_SPACE,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeActivityLog<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: Allocated by the caller; initialized by the account
+    /// compression program
+    #[account(mut, owner = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: SPL noop program
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+
+    /// CHECK: SPL account compression program
+    #[account(address = ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFeeVault<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"fee_vault", mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub fee_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimFees<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump, has_one = authority @ EscrowError::Unauthorized)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// Writable for burning the configured share of the fees
+    #[account(mut)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = fee_vault.mint == mint.key() @ EscrowError::InvalidMint,
+        constraint = fee_vault.owner == config.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub fee_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = treasury_token.mint == mint.key() @ EscrowError::InvalidMint,
+        constraint = treasury_token.owner == config.treasury @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub treasury_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CreateInsurancePool<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump, has_one = authority @ EscrowError::Unauthorized)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + InsurancePool::INIT_SPACE,
+        seeds = [b"insurance_pool", mint.key().as_ref()],
+        bump,
+    )]
+    pub pool: Box<Account<'info, InsurancePool>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"insurance_vault", mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = pool,
+        token::token_program = token_program,
+    )]
+    pub pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InsureEscrow<'info> {
+    /// Seller or protocol paying the premium
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"escrow",
+            escrow.load()?.listed_by().as_ref(),
+            escrow.load()?.offer_mint.as_ref(),
+            escrow.load()?.request_mint.as_ref(),
+            escrow.load()?.escrow_id.to_le_bytes().as_ref(),
+        ],
+        bump = escrow.load()?.escrow_bump,
+        constraint = escrow.load()?.status() == EscrowStatus::Open @ EscrowError::EscrowNotOpen,
+    )]
+    pub escrow: AccountLoader<'info, Escrow>,
+
+    #[account(address = escrow.load()?.request_mint @ EscrowError::InvalidMint)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(seeds = [b"insurance_pool", mint.key().as_ref()], bump = pool.bump)]
+    pub pool: Box<Account<'info, InsurancePool>>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance_vault", mint.key().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = payer_token.mint == mint.key() @ EscrowError::InvalidMint,
+        constraint = payer_token.owner == payer.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub payer_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Coverage::INIT_SPACE,
+        seeds = [b"coverage", escrow.key().as_ref()],
+        bump,
+    )]
+    pub coverage: Box<Account<'info, Coverage>>,
+
+    /// Token program of the mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[event_cpi]
+pub struct PayInsuranceClaim<'info> {
+    pub claims_authority: Signer<'info>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [b"insurance_pool", mint.key().as_ref()],
+        bump = pool.bump,
+        has_one = claims_authority @ EscrowError::Unauthorized,
+    )]
+    pub pool: Box<Account<'info, InsurancePool>>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance_vault", mint.key().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"coverage", coverage.escrow.as_ref()],
+        bump = coverage.bump,
+        has_one = pool @ EscrowError::InvalidMint,
+    )]
+    pub coverage: Box<Account<'info, Coverage>>,
+
+    /// Counterparty harmed by the covered failure
+    #[account(
+        mut,
+        constraint = claimant_token.mint == mint.key() @ EscrowError::InvalidMint,
+    )]
+    pub claimant_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ExecutePendingConfig<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    /// The pending authority; a governance PDA signs through its program
+    pub new_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.pending_authority == Some(new_authority.key()) @ EscrowError::NoPendingAuthority,
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct StakeTokens<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        constraint = config.stake_mint == Some(stake_mint.key()) @ EscrowError::StakingDisabled,
+    )]
+    pub stake_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + Stake::INIT_SPACE,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump,
+    )]
+    pub stake: Box<Account<'info, Stake>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [b"stake_vault", owner.key().as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = stake,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_stake_token.mint == stake_mint.key() @ EscrowError::InvalidMint,
+        constraint = owner_stake_token.owner == owner.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub owner_stake_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the stake mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeTokens<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump = stake.bump,
+        has_one = owner @ EscrowError::Unauthorized,
+    )]
+    pub stake: Box<Account<'info, Stake>>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", owner.key().as_ref()],
+        bump = stake.vault_bump,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Writable so withheld transfer fees can be harvested on close
+    #[account(mut, address = stake.mint @ EscrowError::InvalidMint)]
+    pub stake_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = owner_stake_token.mint == stake_mint.key() @ EscrowError::InvalidMint,
+        constraint = owner_stake_token.owner == owner.key() @ EscrowError::InvalidTokenAccountOwner,
+    )]
+    pub owner_stake_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of the stake mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetMintPolicy<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + MintPolicy::INIT_SPACE,
+        seeds = [b"mint_policy", mint.key().as_ref()],
+        bump,
+    )]
+    pub mint_policy: Box<Account<'info, MintPolicy>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveMintPolicy<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_policy", mint_policy.mint.as_ref()],
+        bump = mint_policy.bump,
+        close = authority,
+    )]
+    pub mint_policy: Box<Account<'info, MintPolicy>>,
+}
+
+#[derive(Accounts)]
+pub struct BanSeller<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: Any wallet; only its address is recorded
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SellerBan::INIT_SPACE,
+        seeds = [b"seller_ban", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_ban: Box<Account<'info, SellerBan>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnbanSeller<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        mut,
+        seeds = [b"seller_ban", seller_ban.seller.as_ref()],
+        bump = seller_ban.bump,
+        close = authority,
+    )]
+    pub seller_ban: Box<Account<'info, SellerBan>>,
+}
+
+#[derive(Accounts)]
+#[instruction(chain: u16)]
+pub struct SetForeignEmitter<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + ForeignEmitter::INIT_SPACE,
+        seeds = [b"foreign_emitter", chain.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub foreign_emitter: Box<Account<'info, ForeignEmitter>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMintLimits<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + MintLimits::INIT_SPACE,
+        seeds = [b"mint_limits", mint.key().as_ref()],
+        bump,
+    )]
+    pub mint_limits: Box<Account<'info, MintLimits>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveMintLimits<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ EscrowError::Unauthorized,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_limits", mint_limits.mint.as_ref()],
+        bump = mint_limits.bump,
+        close = authority,
+    )]
+    pub mint_limits: Box<Account<'info, MintLimits>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePairIndex<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + PairIndex::INIT_SPACE,
+        seeds = [b"pair_index"],
+        bump,
+    )]
+    pub pair_index: Box<Account<'info, PairIndex>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePairStats<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + PairStats::INIT_SPACE,
+        seeds = [b"pair_stats", offer_mint.key().as_ref(), request_mint.key().as_ref()],
+        bump,
+    )]
+    pub pair_stats: Box<Account<'info, PairStats>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeReputation<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Any wallet; only keys the reputation's address
+    pub seller: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Reputation::INIT_SPACE,
+        seeds = [b"reputation", seller.key().as_ref()],
+        bump,
+    )]
+    pub reputation: Box<Account<'info, Reputation>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeStats<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Stats::INIT_SPACE,
+        seeds = [b"stats"],
+        bump,
+    )]
+    pub stats: Box<Account<'info, Stats>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeOfferRegistry<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + OfferRegistry::INIT_SPACE,
+        seeds = [b"offer_registry"],
+        bump,
+    )]
+    pub offer_registry: Box<Account<'info, OfferRegistry>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddRegistryPage<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(mut, seeds = [b"offer_registry"], bump = offer_registry.bump)]
+    pub offer_registry: Box<Account<'info, OfferRegistry>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + RegistryPage::INIT_SPACE,
+        seeds = [b"registry_page", offer_registry.page_count.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub registry_page: Box<Account<'info, RegistryPage>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PruneRegistry<'info> {
+    #[account(mut)]
+    pub registry_page: Box<Account<'info, RegistryPage>>,
+}
+
+#[derive(Accounts)]
+#[instruction(page: u32)]
+pub struct InitializeMarketIndex<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// The pair's page before `page`, required past page zero
+    #[account(
+        seeds = [
+            b"market_index",
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            page.saturating_sub(1).to_le_bytes().as_ref(),
+        ],
+        bump = previous_page.bump,
+    )]
+    pub previous_page: Option<Box<Account<'info, MarketIndex>>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + MarketIndex::INIT_SPACE,
+        seeds = [
+            b"market_index",
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+            page.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub market_index: Box<Account<'info, MarketIndex>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PruneMarketIndex<'info> {
+    #[account(mut)]
+    pub market_index: Box<Account<'info, MarketIndex>>,
+}
+
+#[derive(Accounts)]
+#[instruction(branding_id: [u8; 32])]
+pub struct CreateMarket<'info> {
+    #[account(mut)]
+    pub operator: Signer<'info>,
+
+    #[account(
+        init,
+        payer = operator,
+        space = 8 + Market::INIT_SPACE,
+        seeds = [b"market", operator.key().as_ref(), branding_id.as_ref()],
+        bump,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarket<'info> {
+    pub operator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market.operator.as_ref(), market.branding_id.as_ref()],
+        bump = market.bump,
+        has_one = operator @ EscrowError::Unauthorized,
+    )]
+    pub market: Box<Account<'info, Market>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPairFees<'info> {
+    #[account(mut)]
+    pub operator: Signer<'info>,
+
+    #[account(
+        seeds = [b"market", market.operator.as_ref(), market.branding_id.as_ref()],
+        bump = market.bump,
+        has_one = operator @ EscrowError::Unauthorized,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    pub offer_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub request_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = operator,
+        space = 8 + PairFees::INIT_SPACE,
+        seeds = [
+            b"pair_fees",
+            market.key().as_ref(),
+            offer_mint.key().as_ref(),
+            request_mint.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub pair_fees: Box<Account<'info, PairFees>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetVolumeTiers<'info> {
+    pub operator: Signer<'info>,
+
+    #[account(
+        seeds = [b"market", market.operator.as_ref(), market.branding_id.as_ref()],
+        bump = market.bump,
+        has_one = operator @ EscrowError::Unauthorized,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"pair_fees",
+            market.key().as_ref(),
+            pair_fees.offer_mint.as_ref(),
+            pair_fees.request_mint.as_ref(),
+        ],
+        bump = pair_fees.bump,
+    )]
+    pub pair_fees: Box<Account<'info, PairFees>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTraderStats<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Any wallet may have stats opened for it
+    pub trader: UncheckedAccount<'info>,
+
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + TraderStats::INIT_SPACE,
+        seeds = [b"trader_stats", trader.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub trader_stats: Box<Account<'info, TraderStats>>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -0,0 +1,397 @@
+//! Program error codes
+
+use crate::*;
+use num_enum::TryFromPrimitive;
+
+/// Numbered from `ERROR_CODE_OFFSET` in declaration order; new errors are
+/// appended so clients can decode the codes of earlier builds
+#[error_code]
+#[derive(TryFromPrimitive)]
+pub enum EscrowError {
+    #[msg("Unauthorized: Only the seller can perform this action")]
+    Unauthorized,
+    #[msg("Invalid token mint")]
+    InvalidMint,
+    #[msg("Invalid token account owner")]
+    InvalidTokenAccountOwner,
+    #[msg("Invalid amount: must be greater than zero")]
+    InvalidAmount,
+    #[msg("Invalid fee: exceeds the maximum allowed rate")]
+    InvalidFee,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Market account does not match the escrow")]
+    InvalidMarket,
+    #[msg("Escrow is listed under a market; market accounts are required")]
+    MissingMarketAccount,
+    #[msg("Allowlist is full")]
+    AllowlistFull,
+    #[msg("Mint is not allowed in this market")]
+    MintNotAllowed,
+    #[msg("Seller is not allowed in this market")]
+    SellerNotAllowed,
+    #[msg("A receipt has already been minted for this escrow")]
+    ReceiptAlreadyMinted,
+    #[msg("Escrow has a position receipt; receipt accounts are required")]
+    MissingReceiptAccount,
+    #[msg("Receipt does not belong to this escrow")]
+    InvalidReceipt,
+    #[msg("Escrow has already been filled")]
+    EscrowAlreadyFilled,
+    #[msg("Vault account is required while the offer is unfilled")]
+    MissingVaultAccount,
+    #[msg("Escrow is locked as collateral")]
+    EscrowLocked,
+    #[msg("Locker program is not approved by the market")]
+    LockerNotApproved,
+    #[msg("Escrows do not form a valid chain")]
+    InvalidChain,
+    #[msg("Intermediate token account is required to receive the surplus")]
+    MissingIntermediateAccount,
+    #[msg("Pair fee account does not match the market and mint pair")]
+    InvalidPairFees,
+    #[msg("Trader stats account does not match the trader and mint")]
+    InvalidTraderStats,
+    #[msg("Deadline must be in the future")]
+    InvalidDeadline,
+    #[msg("Escrow already has a condition")]
+    ConditionAlreadySet,
+    #[msg("Escrow has no condition")]
+    NoCondition,
+    #[msg("Escrow condition has not been met")]
+    ConditionNotMet,
+    #[msg("Escrow condition has already been met")]
+    ConditionAlreadyMet,
+    #[msg("Escrow condition deadline has passed")]
+    ConditionExpired,
+    #[msg("Escrow condition deadline has not passed yet")]
+    ConditionNotExpired,
+    #[msg("Oracle account is required")]
+    MissingOracleAccount,
+    #[msg("Invalid oracle account")]
+    InvalidOracle,
+    #[msg("Oracle price is stale")]
+    StaleOracle,
+    #[msg("Position receipt is outstanding; redeem it instead")]
+    ReceiptOutstanding,
+    #[msg("Migration target must be another executable program")]
+    InvalidMigrationTarget,
+    #[msg("Migration record does not match the escrow being imported")]
+    InvalidMigrationRecord,
+    #[msg("Escrow has expired")]
+    EscrowExpired,
+    #[msg("Escrow has not expired")]
+    EscrowNotExpired,
+    #[msg("Token transfer fees would consume the amount being traded")]
+    TransferFeeTooHigh,
+    #[msg("Offer token account is required")]
+    MissingOfferTokenAccount,
+    #[msg("Request token account is required")]
+    MissingRequestTokenAccount,
+    #[msg("Escrow is reserved for a different buyer")]
+    NotDesignatedBuyer,
+    #[msg("Escrow is gated on a buyer allowlist; the allowlist is required")]
+    MissingAllowlistAccount,
+    #[msg("Buyer allowlist does not match the escrow")]
+    InvalidAllowlist,
+    #[msg("Buyer is not on the seller's allowlist")]
+    BuyerNotAllowed,
+    #[msg("Protocol fee is charged; the fee vault is required")]
+    MissingTreasuryAccount,
+    #[msg("Escrow rent returns to another account; its rent recipient is required")]
+    InvalidRentRecipient,
+    #[msg("Expiry exceeds the maximum listing duration")]
+    ExpiryTooLong,
+    #[msg("Protocol is paused")]
+    ProtocolPaused,
+    #[msg("Mint is banned from listing")]
+    MintBanned,
+    #[msg("Mint has not been approved for listing")]
+    MintNotApproved,
+    #[msg("Offer is below the mint's minimum")]
+    OfferBelowMinimum,
+    #[msg("Offer is above the mint's maximum")]
+    OfferAboveMaximum,
+    #[msg("Request is below the mint's minimum notional")]
+    NotionalBelowMinimum,
+    #[msg("Offer is not a single non-fungible token")]
+    NotAnNft,
+    #[msg("Metadata does not belong to the offer mint")]
+    InvalidMetadata,
+    #[msg("NFT escrows cannot change size")]
+    NftEscrow,
+    #[msg("Escrow is listed by delegation and holds none of its offer")]
+    DelegatedEscrow,
+    #[msg("Seller's balance or delegation no longer covers the fill")]
+    DelegationLapsed,
+    #[msg("Hashlocked escrows are released only by their preimage")]
+    Hashlocked,
+    #[msg("Escrow is committed to a counterparty until it expires")]
+    EscrowCommitted,
+    #[msg("Hashlocks need a designated buyer and a future expiry")]
+    InvalidHashlock,
+    #[msg("Preimage does not match the escrow's hashlock")]
+    InvalidPreimage,
+    #[msg("Escrow does not settle over Wormhole from this chain")]
+    NotWormholeSettled,
+    #[msg("Invalid Wormhole VAA account")]
+    InvalidVaa,
+    #[msg("VAA was not emitted by the chain's trusted emitter")]
+    UntrustedEmitter,
+    #[msg("Attested payment does not match the escrow")]
+    InvalidWormholePayment,
+    #[msg("Escrow is not sold as an option")]
+    NotAnOption,
+    #[msg("Options need a premium and a future expiry")]
+    InvalidOption,
+    #[msg("Loans must repay at least their principal over a positive duration")]
+    InvalidLoanTerms,
+    #[msg("Escrow is not offered as a loan")]
+    NotALoan,
+    #[msg("Escrow secures an outstanding loan")]
+    LoanOutstanding,
+    #[msg("Loan is not yet due")]
+    LoanNotDue,
+    #[msg("Loan is past due")]
+    LoanOverdue,
+    #[msg("Vault holds fewer offer tokens than the escrow lists")]
+    VaultShortfall,
+    #[msg("Missing the instructions sysvar")]
+    MissingInstructionsSysvar,
+    #[msg("Transaction includes an instruction barred alongside this fill")]
+    GuardedInstruction,
+    #[msg("Transaction targets the escrow more than once")]
+    DuplicateEscrowInstruction,
+    #[msg("Too many guarded programs")]
+    TooManyGuardedPrograms,
+    #[msg("Offer recipient cannot be used with this fill")]
+    InvalidRecipient,
+    #[msg("Missing the proceeds recipient's token account")]
+    MissingProceedsRecipient,
+    #[msg("Fee splits exceed the whole fee or the recipient limit")]
+    InvalidFeeSplit,
+    #[msg("Missing a fee split recipient's account")]
+    MissingFeeSplitAccount,
+    #[msg("Oracle quote is outside the seller's bounds")]
+    OracleQuoteOutOfBounds,
+    #[msg("Escrow settles against a revealed fill commitment")]
+    MissingFillCommitment,
+    #[msg("Fill commitment does not match this fill")]
+    InvalidFillCommitment,
+    #[msg("Fill commitment has not aged past the reveal delay")]
+    CommitmentTooRecent,
+    #[msg("Fill landed after its deadline slot")]
+    FillDeadlinePassed,
+    #[msg("Seller is banned from listing")]
+    SellerBanned,
+    #[msg("Claim exceeds the escrow's remaining coverage")]
+    CoverageExceeded,
+    #[msg("Activity log is kept; its tree and programs are required")]
+    MissingActivityLogAccount,
+    #[msg("Merkle tree is not the config's activity log")]
+    InvalidActivityLog,
+    #[msg("Market index page is full")]
+    MarketIndexFull,
+    #[msg("Market index page is not the escrow's pair or page")]
+    InvalidMarketIndex,
+    #[msg("Escrow is linked into a market index; its page is required")]
+    MissingMarketIndexAccount,
+    #[msg("Escrow account is live; its vault is not stranded")]
+    VaultNotStranded,
+    #[msg("Invalid installment terms")]
+    InvalidInstallmentTerms,
+    #[msg("Escrow is not sold in installments")]
+    NoInstallmentTerms,
+    #[msg("Installment is overdue")]
+    InstallmentOverdue,
+    #[msg("No installment is overdue")]
+    InstallmentNotOverdue,
+    #[msg("Merkle tree does not match the escrow")]
+    InvalidTree,
+    #[msg("Missing the offered NFT's metadata account")]
+    MissingMetadataAccount,
+    #[msg("Missing a creator's royalty account")]
+    MissingCreatorAccount,
+    #[msg("Royalty account does not belong to the creator")]
+    InvalidCreatorAccount,
+    #[msg("Basket already holds the maximum number of mints")]
+    BasketFull,
+    #[msg("Basket holds no mints")]
+    EmptyBasket,
+    #[msg("Missing an account for a basket leg")]
+    MissingBasketAccount,
+    #[msg("Account does not match the basket leg")]
+    InvalidBasketAccount,
+    #[msg("Request legs must be distinct non-zero amounts of other mints")]
+    InvalidRequestLegs,
+    #[msg("Not allowed on escrows with extra request legs")]
+    HasRequestLegs,
+    #[msg("Missing an account for a request leg")]
+    MissingRequestLegAccount,
+    #[msg("Account does not match the request leg")]
+    InvalidRequestLegAccount,
+    #[msg("Escrow has changed since the counter offer was made")]
+    CounterOfferStale,
+    #[msg("Auction phases must be in order and end before the escrow expires")]
+    InvalidAuctionSchedule,
+    #[msg("Bidding has closed")]
+    BiddingClosed,
+    #[msg("Bids can only be revealed during the reveal phase")]
+    RevealNotOpen,
+    #[msg("Revealed bid does not match its commitment or deposit")]
+    InvalidReveal,
+    #[msg("Auction is still running")]
+    AuctionNotEnded,
+    #[msg("Bid is not the auction's winning bid")]
+    NoWinningBid,
+    #[msg("Winning bids are settled, not refunded")]
+    WinningBid,
+    #[msg("Auction has bids outstanding")]
+    AuctionHasBids,
+    #[msg("Crowdfill must close for contributions before the escrow expires")]
+    InvalidCrowdfillExpiry,
+    #[msg("Crowdfill no longer takes contributions")]
+    CrowdfillClosed,
+    #[msg("Crowdfill still takes contributions")]
+    CrowdfillOpen,
+    #[msg("Crowdfill has not raised its target")]
+    CrowdfillNotRaised,
+    #[msg("Crowdfill was settled")]
+    CrowdfillSettled,
+    #[msg("Crowdfill has not been settled")]
+    CrowdfillNotSettled,
+    #[msg("Crowdfill has contributions outstanding")]
+    CrowdfillHasContributions,
+    #[msg("Escrow account is required")]
+    MissingEscrowAccount,
+    #[msg("Staking is not enabled for this mint")]
+    StakingDisabled,
+    #[msg("Stake is still locked")]
+    StakeLocked,
+    #[msg("No authority handover to this signer is pending")]
+    NoPendingAuthority,
+    #[msg("No settings update is pending")]
+    NoPendingConfig,
+    #[msg("Pending settings update is not yet executable")]
+    ConfigTimelocked,
+    #[msg("Fee vault holds no fees to claim")]
+    NothingToClaim,
+    #[msg("Oracle price confidence interval is too wide")]
+    OracleConfidenceTooWide,
+    #[msg("Escrow cannot be both oracle-priced and unit-priced")]
+    ConflictingPricing,
+    #[msg("Fill is below the escrow's minimum fill amount")]
+    FillBelowMinimum,
+    #[msg("Arbitrated escrows settle through a held settlement")]
+    HeldSettlement,
+    #[msg("Escrow has no arbitration")]
+    NoArbitration,
+    #[msg("Dispute window must be positive")]
+    InvalidDisputeWindow,
+    #[msg("Settlement is not in a state that allows this")]
+    InvalidSettlementStatus,
+    #[msg("Dispute window has closed")]
+    DisputeWindowClosed,
+    #[msg("Vested escrows can only be accepted directly")]
+    VestedEscrow,
+    #[msg("Vesting accounts are required exactly when the escrow vests")]
+    InvalidVestingAccount,
+    #[msg("Vesting cliff must fall within a positive duration")]
+    InvalidVestingSchedule,
+    #[msg("Nothing has vested since the last claim")]
+    NothingVested,
+    #[msg("Escrow streams its proceeds to the seller")]
+    StreamedEscrow,
+    #[msg("Stream accounts are required exactly when the escrow streams its proceeds")]
+    InvalidStreamAccount,
+    #[msg("Stream duration must be positive")]
+    InvalidStreamDuration,
+    #[msg("Nothing has streamed since the last withdrawal")]
+    NothingStreamed,
+    #[msg("Escrow has not opened for fills yet")]
+    EscrowNotStarted,
+    #[msg("Escrow must open before it expires")]
+    InvalidStartTime,
+    #[msg("Escrow is no longer open")]
+    EscrowNotOpen,
+    #[msg("Escrow record is still open")]
+    EscrowStillOpen,
+    #[msg("Escrow already uses the current layout")]
+    EscrowUpToDate,
+    #[msg("Label exceeds the maximum length")]
+    LabelTooLong,
+    #[msg("Metadata URI exceeds the maximum length")]
+    MetadataUriTooLong,
+    #[msg("Registry page is full")]
+    RegistryPageFull,
+    #[msg("Escrow is not listed on this registry page")]
+    EscrowNotRegistered,
+    #[msg("Seller has too many open escrows")]
+    TooManyOpenEscrows,
+    #[msg("UI amount could not be converted to a raw amount")]
+    InvalidUiAmount,
+    #[msg("Fill costs more than the buyer's maximum UI amount")]
+    RequestUiAmountExceeded,
+    #[msg("Fill is worse than the buyer's price bounds")]
+    SlippageExceeded,
+    #[msg("Offer mint has a permanent delegate")]
+    PermanentDelegateMint,
+    #[msg("Offer mint has a freeze authority")]
+    FreezableMint,
+    #[msg("Escrow vault is not frozen")]
+    VaultNotFrozen,
+    #[msg("Escrow is stuck on a frozen vault")]
+    EscrowStuck,
+    #[msg("Account does not match the swept escrow")]
+    InvalidSweepAccount,
+    #[msg("Vault does not match the escrow")]
+    InvalidVault,
+    #[msg("No escrow was filled")]
+    NothingFilled,
+    #[msg("Fills cost more than the buyer's maximum amount")]
+    SweepCostExceeded,
+    #[msg("Invalid cancellation bond")]
+    InvalidCancellationBond,
+    #[msg("Escrow already has a cancellation bond")]
+    BondAlreadyPosted,
+    #[msg("Escrow is reserved by another buyer")]
+    EscrowReserved,
+    #[msg("Invalid reservation")]
+    InvalidReservation,
+    #[msg("Reservation is still active")]
+    ReservationActive,
+    #[msg("Token gate account required")]
+    MissingGateAccount,
+    #[msg("Buyer does not hold the escrow's gate token")]
+    GateNotMet,
+    #[msg("Escrow does not replenish")]
+    NotRecurring,
+    #[msg("Escrow is already at its target size")]
+    NothingToReplenish,
+    #[msg("Gated escrows are accepted directly")]
+    GatedEscrow,
+    #[msg("Account is not the buyer's approval")]
+    InvalidPermissionAccount,
+    #[msg("Buyer is not approved by the escrow's permission program")]
+    BuyerNotApproved,
+    #[msg("RFQ no longer takes quotes")]
+    RfqClosed,
+    #[msg("RFQ still takes quotes")]
+    RfqOpen,
+    #[msg("RFQ has quotes outstanding")]
+    RfqHasQuotes,
+    #[msg("Session expiry or permissions are invalid")]
+    InvalidSession,
+    #[msg("Fill exceeds the session's remaining spend")]
+    SessionSpendExceeded,
+    #[msg("Memo is too long")]
+    MemoTooLong,
+    #[msg("Memo program account is required")]
+    MissingMemoProgram,
+    #[msg("Config account is required")]
+    MissingConfigAccount,
+    #[msg("Escrow has not been listed for the minimum duration")]
+    ListingTooRecent,
+    #[msg("No ownership transfer to this signer is pending")]
+    NoPendingSeller,
+}
@@ -0,0 +1,175 @@
+//! Events emitted on escrow lifecycle changes
+
+use crate::*;
+
+#[event]
+pub struct EscrowCreated {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Net of any transfer fee on the deposit
+    pub offer_amount: u64,
+    pub request_amount: u64,
+    pub expires_at: Option<i64>,
+    pub market: Option<Pubkey>,
+    /// Verified collection of an offered NFT
+    pub collection: Option<Pubkey>,
+}
+
+/// One fill of an escrow, partial or full
+#[event]
+pub struct EscrowAccepted {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Offer tokens released by this fill
+    pub offer_amount: u64,
+    /// Request tokens owed for the fill, before market fees
+    pub request_amount: u64,
+    pub maker_fee: i64,
+    pub taker_fee: u64,
+    pub protocol_fee: u64,
+    /// Protocol fee taken out of `offer_amount` instead, when the config
+    /// takes fees in the offer mint
+    pub offer_protocol_fee: u64,
+    /// Protocol fee paid by the buyer on top of the request tokens
+    pub protocol_taker_fee: u64,
+    /// Wallet credited with a share of the protocol fee
+    pub referrer: Option<Pubkey>,
+    /// Part of the protocol fee paid to the referrer, in the fee's mint
+    pub referral_fee: u64,
+    /// Paid to the offered NFT's creators
+    pub royalty: u64,
+    /// Offer left in the escrow; zero once fully filled
+    pub remaining_offer_amount: u64,
+    /// `offer_amount` in UI terms at fill time, with any accrued interest
+    pub offer_ui_amount: String,
+    /// `request_amount` in UI terms at fill time, with any accrued interest
+    pub request_ui_amount: String,
+    /// Seller's label on the escrow
+    pub label: String,
+}
+
+/// Warns that an escrow's offer mint has a permanent delegate, who can
+/// move the escrowed tokens without the program
+#[event]
+pub struct PermanentDelegateListed {
+    pub escrow: Pubkey,
+    pub mint: Pubkey,
+    pub delegate: Pubkey,
+}
+
+#[event]
+pub struct EscrowCancelled {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Offer returned to the seller
+    pub offer_amount: u64,
+    /// Lamports of the cancellation bond forfeited to the treasury
+    pub bond_penalty: u64,
+    /// Seller's label on the escrow
+    pub label: String,
+}
+
+/// One fill of a bid escrow by a seller, partial or full
+#[event]
+pub struct BidAccepted {
+    pub bid: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Offer tokens delivered to the buyer
+    pub offer_amount: u64,
+    /// Request tokens paid out of the bid, before protocol fees
+    pub request_amount: u64,
+    /// Maker's protocol fee, in the mint of the config's fee currency
+    pub protocol_fee: u64,
+    /// Seller's protocol taker fee, in the request mint
+    pub protocol_taker_fee: u64,
+    /// Offer still wanted; zero once fully filled
+    pub remaining_offer_amount: u64,
+}
+
+/// Protocol fees swept out of a fee vault
+#[event]
+pub struct FeesClaimed {
+    pub mint: Pubkey,
+    /// Sent to the treasury
+    pub amount: u64,
+    /// Burned under the config's fee burn share
+    pub burned: u64,
+}
+
+/// Insurance pool compensating a counterparty of a covered escrow
+#[event]
+pub struct InsuranceClaimPaid {
+    pub escrow: Pubkey,
+    pub mint: Pubkey,
+    pub claimant: Pubkey,
+    pub amount: u64,
+}
+
+/// An escrow closed by the config authority, with its offer returned to
+/// the seller
+#[event]
+pub struct AdminCancelled {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Offer returned to the seller
+    pub offer_amount: u64,
+    pub authority: Pubkey,
+    /// Seller's label on the escrow
+    pub label: String,
+}
+
+#[event]
+pub struct HashlockClaimed {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub offer_mint: Pubkey,
+    pub offer_amount: u64,
+    /// Revealed secret, which the seller redeems on the counterparty chain
+    pub preimage: [u8; 32],
+}
+
+/// Escrow settled by a payment attested over Wormhole
+#[event]
+pub struct WormholeEscrowAccepted {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub recipient: Pubkey,
+    pub offer_mint: Pubkey,
+    pub offer_amount: u64,
+    /// Price paid on the emitter chain
+    pub request_amount: u64,
+    pub emitter_chain: u16,
+    pub sequence: u64,
+}
+
+/// Listing refused because its seller is banned; logged by the failed
+/// transaction, so only seen by indexers reading failed transactions
+#[event]
+pub struct ListingBlocked {
+    pub seller: Pubkey,
+}
+
+#[event]
+pub struct EscrowExpired {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Offer returned to the seller
+    pub offer_amount: u64,
+    /// Seller's label on the escrow
+    pub label: String,
+}
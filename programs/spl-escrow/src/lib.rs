@@ -1,344 +1,8421 @@
-use anchor_lang::prelude::*;
+//! SPL token escrow program
+//!
+//! Other programs integrate over CPI by depending on this crate with the
+//! `cpi` feature and calling `spl_escrow::cpi::create_escrow`,
+//! `accept_escrow`, `cancel_escrow` and the rest with the matching
+//! `spl_escrow::cpi::accounts` structs. A program PDA can act as the
+//! seller or buyer by signing with its seeds through
+//! `CpiContext::new_with_signer`. The accounts a trade opens are funded
+//! by the separate `payer` signer, so the PDA only needs to hold lamports
+//! when it pays them itself or trades native SOL.
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        hash::{hash, hashv},
+        instruction::{AccountMeta, Instruction},
+        program::{invoke, invoke_signed},
+        program_option::COption,
+        sysvar::instructions::{
+            self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked,
+        },
+    },
+    system_program, Discriminator,
+};
 use anchor_spl::{
-    associated_token::AssociatedToken,
-    token::{close_account, transfer, CloseAccount, Mint, Token, TokenAccount, Transfer},
+    associated_token::{get_associated_token_address_with_program_id, AssociatedToken},
+    metadata::{
+        create_metadata_accounts_v3,
+        mpl_token_metadata::types::{Creator, DataV2},
+        CreateMetadataAccountsV3, Metadata, MetadataAccount,
+    },
+    token::spl_token,
+    token_2022::spl_token_2022::{
+        self,
+        extension::{
+            interest_bearing_mint::InterestBearingConfig,
+            permanent_delegate::get_permanent_delegate,
+            transfer_fee::{TransferFee, TransferFeeAmount, TransferFeeConfig},
+            BaseStateWithExtensions, StateWithExtensions,
+        },
+    },
+    token_interface::{
+        approve, burn, close_account, harvest_withheld_tokens_to_mint, mint_to, sync_native,
+        Approve, Burn, CloseAccount, HarvestWithheldTokensToMint, Mint, MintTo, SyncNative,
+        Token2022, TokenAccount, TokenInterface, TransferChecked,
+    },
 };
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
+pub mod constants;
+pub mod contexts;
+pub mod error;
+pub mod events;
+pub mod settlement;
+pub mod state;
+pub mod utils;
+
+pub use constants::*;
+pub use contexts::*;
+pub use error::*;
+pub use events::*;
+pub use settlement::*;
+pub use state::*;
+pub use utils::*;
+
 #[program]
 pub mod spl_escrow {
     use super::*;
 
     /// Create a new escrow offer
-    /// - Lock seller's tokens in escrow vault PDA
+    /// - Lock seller's tokens in the escrow's associated token account
     /// - Store escrow details (seller, amounts, mints)
-    pub fn create_escrow(
-        ctx: Context<CreateEscrow>,
+    /// - `designated_buyer` makes the offer private to one counterparty;
+    ///   passing the seller's buyer allowlist gates it on membership
+    /// - `nft` lists a single non-fungible token; passing its Metaplex
+    ///   metadata records the NFT's verified collection and allows paying
+    ///   its creators royalties
+    /// - The seller may be another program's PDA signing through CPI, such
+    ///   as a multisig vault or DAO treasury; a separate `payer` funds the
+    ///   escrow's accounts and takes back their rent on close
+    /// - An escrow with an expiry also takes the config's crank tip from
+    ///   the seller, paid out by `crank_expired`
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn create_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateEscrow<'info>>,
+        escrow_id: u64,
         offer_amount: u64,
         request_amount: u64,
+        expires_at: Option<i64>,
+        designated_buyer: Option<Pubkey>,
+        nft: Option<NftListing>,
     ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
         require!(offer_amount > 0, EscrowError::InvalidAmount);
         require!(request_amount > 0, EscrowError::InvalidAmount);
+        ctx.accounts.config.check_expiry(expires_at)?;
+
+        // An NFT is the one token of a zero-decimal mint
+        let collection = if let Some(listing) = &nft {
+            let offer_mint = &ctx.accounts.offer_mint;
+            require!(
+                offer_amount == 1 && offer_mint.decimals == 0 && offer_mint.supply == 1,
+                EscrowError::NotAnNft
+            );
+            require!(
+                !listing.pay_royalties || ctx.accounts.offer_metadata.is_some(),
+                EscrowError::MissingMetadataAccount
+            );
+            ctx.accounts
+                .offer_metadata
+                .as_ref()
+                .and_then(|metadata| metadata.collection.as_ref())
+                .filter(|collection| collection.verified)
+                .map(|collection| collection.key)
+        } else {
+            require!(ctx.accounts.offer_metadata.is_none(), EscrowError::NotAnNft);
+            None
+        };
+
+        // Enforce the protocol's mint policies, then the market's allowlists
+        // when listing under a market
+        let config = &ctx.accounts.config;
+        MintPolicy::check_listing(&ctx.accounts.offer_mint_policy, config)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+        if let Some(market) = &ctx.accounts.market {
+            market.check_listing(
+                &ctx.accounts.seller.key(),
+                &ctx.accounts.offer_mint.key(),
+                &ctx.accounts.request_mint.key(),
+            )?;
+        }
+
+        // A freeze authority can freeze the vault, stranding the escrow
+        require!(
+            ctx.accounts.offer_mint.freeze_authority.is_none() || !config.reject_freezable_mints,
+            EscrowError::FreezableMint
+        );
+        // A permanent delegate can move the offer out of the vault at will
+        let offer_delegate = permanent_delegate(&ctx.accounts.offer_mint.to_account_info())?;
+        require!(
+            offer_delegate.is_none() || !config.reject_permanent_delegate,
+            EscrowError::PermanentDelegateMint
+        );
+
+        // Transfer tokens from seller to escrow vault
+        Deposit {
+            seller: ctx.accounts.seller.to_account_info(),
+            seller_offer_token: ctx
+                .accounts
+                .seller_offer_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            vault: ctx.accounts.vault.to_account_info(),
+            offer_mint: &ctx.accounts.offer_mint,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .deposit(offer_amount)?;
+
+        // Only what reaches the vault after transfer fees is on offer
+        ctx.accounts.vault.reload()?;
+        let offer_amount = ctx.accounts.vault.amount;
+        require!(offer_amount > 0, EscrowError::TransferFeeTooHigh);
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            offer_amount,
+            request_amount,
+        )?;
+
+        // The seller tips whoever cranks the escrow once it expires; any
+        // other close returns the tip to them with the rent
+        let crank_tip = match expires_at {
+            Some(_) => ctx.accounts.config.crank_tip_lamports,
+            None => 0,
+        };
+        if crank_tip > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.seller.to_account_info(),
+                        to: ctx.accounts.escrow.to_account_info(),
+                    },
+                ),
+                crank_tip,
+            )?;
+        }
 
-        // Initialize escrow state
-        let escrow = &mut ctx.accounts.escrow;
+        // Initialize escrow state; the vault is funded first since the
+        // escrow cannot be reloaded before its discriminator is written
+        let mut escrow = ctx.accounts.escrow.load_init()?;
         escrow.seller = ctx.accounts.seller.key();
+        escrow.set_market(ctx.accounts.market.as_ref().map(|market| market.key()));
         escrow.offer_mint = ctx.accounts.offer_mint.key();
         escrow.request_mint = ctx.accounts.request_mint.key();
+        escrow.escrow_id = escrow_id;
         escrow.offer_amount = offer_amount;
         escrow.request_amount = request_amount;
+        escrow.set_expires_at(expires_at);
+        escrow.set_listed_at(Clock::get()?.unix_timestamp);
+        escrow.set_designated_buyer(designated_buyer);
+        escrow.set_crank_tip(crank_tip);
+        escrow.set_protocol_fee_discount_bps(
+            ctx.accounts
+                .seller_stake
+                .as_ref()
+                .map_or(0, |stake| ctx.accounts.config.stake_discount(stake)),
+        );
+        escrow.set_rent_recipient(ctx.accounts.payer.key());
+        escrow.set_nft(nft.is_some());
+        escrow.set_collection(collection);
+        escrow.set_pay_royalties(nft.is_some_and(|listing| listing.pay_royalties));
+        escrow.set_buyer_allowlist(
+            ctx.accounts
+                .buyer_allowlist
+                .as_ref()
+                .map(|allowlist| allowlist.key()),
+        );
         escrow.escrow_bump = ctx.bumps.escrow;
-        escrow.vault_bump = ctx.bumps.vault;
+        escrow.set_ata_vault();
+        escrow.set_status(EscrowStatus::Open);
+        escrow.version = ESCROW_VERSION;
 
-        // Transfer tokens from seller to escrow vault
-        transfer(
+        ctx.accounts
+            .pair_index
+            .record_open(escrow.offer_mint, escrow.request_mint);
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.register(ctx.accounts.escrow.key())?;
+        }
+        if let Some(market_index) = &mut ctx.accounts.market_index {
+            market_index.link(ctx.accounts.escrow.key())?;
+            escrow.set_market_index(Some(market_index.key()));
+        }
+        let seller_index = &mut ctx.accounts.seller_index;
+        seller_index.seller = ctx.accounts.seller.key();
+        seller_index.bump = ctx.bumps.seller_index;
+        seller_index.record_open(escrow_id)?;
+        ctx.accounts
+            .config
+            .check_open_escrows(seller_index.open_escrows)?;
+        Stats::record(&ctx.accounts.stats, |stats| {
+            stats.escrows_created = stats.escrows_created.saturating_add(1)
+        })?;
+
+        msg!(
+            "Escrow created: {} tokens offered for {} tokens requested",
+            offer_amount,
+            request_amount
+        );
+        if let Some(market) = escrow.market() {
+            msg!("Listed under market {}", market);
+        }
+        let event = EscrowCreated {
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            offer_mint: escrow.offer_mint,
+            request_mint: escrow.request_mint,
+            offer_amount,
+            request_amount,
+            expires_at,
+            market: escrow.market(),
+            collection: escrow.collection(),
+        };
+        emit!(event);
+        emit_cpi!(event);
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Created,
+            escrow: event.escrow,
+            seller: event.seller,
+            buyer: None,
+            offer_amount,
+            request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+        if let Some(delegate) = offer_delegate {
+            let warning = PermanentDelegateListed {
+                escrow: ctx.accounts.escrow.key(),
+                mint: escrow.offer_mint,
+                delegate,
+            };
+            emit!(warning);
+            emit_cpi!(warning);
+        }
+
+        Ok(())
+    }
+
+    /// Create an escrow offer without taking custody of it
+    /// - The seller delegates the offer amount to the escrow PDA and keeps
+    ///   the tokens, which are pulled from their account at accept
+    /// - Fills fail with `DelegationLapsed` once the seller's balance or
+    ///   approval no longer covers them; a token account holds a single
+    ///   delegation, so listing it again replaces the earlier approval
+    /// - Delegated escrows cannot be topped up, withdrawn from, receipted
+    ///   or exported
+    pub fn create_delegated_escrow(
+        ctx: Context<CreateDelegatedEscrow>,
+        escrow_id: u64,
+        offer_amount: u64,
+        request_amount: u64,
+        expires_at: Option<i64>,
+        designated_buyer: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(offer_amount > 0, EscrowError::InvalidAmount);
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        require!(
+            ctx.accounts.seller_offer_token.amount >= offer_amount,
+            EscrowError::DelegationLapsed
+        );
+        let config = &ctx.accounts.config;
+        config.check_expiry(expires_at)?;
+        MintPolicy::check_listing(&ctx.accounts.offer_mint_policy, config)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+        require!(
+            ctx.accounts.offer_mint.freeze_authority.is_none() || !config.reject_freezable_mints,
+            EscrowError::FreezableMint
+        );
+        require!(
+            permanent_delegate(&ctx.accounts.offer_mint.to_account_info())?.is_none()
+                || !config.reject_permanent_delegate,
+            EscrowError::PermanentDelegateMint
+        );
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            offer_amount,
+            request_amount,
+        )?;
+
+        approve(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.seller_offer_token.to_account_info(),
-                    to: ctx.accounts.vault.to_account_info(),
+                Approve {
+                    to: ctx.accounts.seller_offer_token.to_account_info(),
+                    delegate: ctx.accounts.escrow.to_account_info(),
                     authority: ctx.accounts.seller.to_account_info(),
                 },
             ),
             offer_amount,
         )?;
 
+        let mut escrow = ctx.accounts.escrow.load_init()?;
+        escrow.seller = ctx.accounts.seller.key();
+        escrow.offer_mint = ctx.accounts.offer_mint.key();
+        escrow.request_mint = ctx.accounts.request_mint.key();
+        escrow.escrow_id = escrow_id;
+        escrow.offer_amount = offer_amount;
+        escrow.request_amount = request_amount;
+        escrow.set_expires_at(expires_at);
+        escrow.set_listed_at(Clock::get()?.unix_timestamp);
+        escrow.set_designated_buyer(designated_buyer);
+        escrow.set_rent_recipient(ctx.accounts.payer.key());
+        escrow.set_delegated_source(Some(ctx.accounts.seller_offer_token.key()));
+        escrow.escrow_bump = ctx.bumps.escrow;
+        escrow.set_status(EscrowStatus::Open);
+        escrow.version = ESCROW_VERSION;
+
+        ctx.accounts
+            .pair_index
+            .record_open(escrow.offer_mint, escrow.request_mint);
+        let seller_index = &mut ctx.accounts.seller_index;
+        seller_index.seller = ctx.accounts.seller.key();
+        seller_index.bump = ctx.bumps.seller_index;
+        seller_index.record_open(escrow_id)?;
+        ctx.accounts
+            .config
+            .check_open_escrows(seller_index.open_escrows)?;
+        Stats::record(&ctx.accounts.stats, |stats| {
+            stats.escrows_created = stats.escrows_created.saturating_add(1)
+        })?;
+
         msg!(
-            "Escrow created: {} tokens offered for {} tokens requested",
+            "Delegated escrow created: {} tokens offered for {} tokens requested",
+            offer_amount,
+            request_amount
+        );
+        let event = EscrowCreated {
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            offer_mint: escrow.offer_mint,
+            request_mint: escrow.request_mint,
+            offer_amount,
+            request_amount,
+            expires_at,
+            market: None,
+            collection: None,
+        };
+        emit!(event);
+        emit_cpi!(event);
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Created,
+            escrow: event.escrow,
+            seller: event.seller,
+            buyer: None,
+            offer_amount,
+            request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// List a new escrow on the terms of one of the seller's escrows,
+    /// depositing only `offer_amount`
+    /// - The template may be open or a kept record, but must still have
+    ///   offer left to price from; the new request amount keeps its price
+    ///   ratio, rounded up in the seller's favour
+    /// - Copies its mints, listing duration, counterparty and gating terms,
+    ///   pricing mode, settlement terms, label and metadata; one-off state
+    ///   such as conditions, request legs, options, loans and bonds is not
+    ///   copied
+    /// - A template listed under a market needs that market passed
+    /// - Lists on a registry page and market index, and logs to the
+    ///   activity log, like `create_escrow`
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn clone_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloneEscrow<'info>>,
+        escrow_id: u64,
+        offer_amount: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(offer_amount > 0, EscrowError::InvalidAmount);
+        let template = *ctx.accounts.template.load()?;
+        require!(template.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(!template.nft(), EscrowError::NotAnNft);
+        let now = Clock::get()?.unix_timestamp;
+        let expires_at = match (template.expires_at(), template.listed_at()) {
+            (Some(expires_at), Some(listed_at)) => {
+                let duration = expires_at
+                    .checked_sub(listed_at)
+                    .filter(|duration| *duration > 0)
+                    .ok_or(EscrowError::MathOverflow)?;
+                Some(now.checked_add(duration).ok_or(EscrowError::MathOverflow)?)
+            }
+            (expires_at, _) => expires_at,
+        };
+        let config = &ctx.accounts.config;
+        config.check_expiry(expires_at)?;
+        MintPolicy::check_listing(&ctx.accounts.offer_mint_policy, config)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+        if let Some(market_key) = template.market() {
+            let market = ctx
+                .accounts
+                .market
+                .as_ref()
+                .ok_or(EscrowError::MissingMarketAccount)?;
+            require_keys_eq!(market.key(), market_key, EscrowError::InvalidMarket);
+            market.check_listing(
+                &ctx.accounts.seller.key(),
+                &template.offer_mint,
+                &template.request_mint,
+            )?;
+        }
+        require!(
+            ctx.accounts.offer_mint.freeze_authority.is_none() || !config.reject_freezable_mints,
+            EscrowError::FreezableMint
+        );
+        require!(
+            permanent_delegate(&ctx.accounts.offer_mint.to_account_info())?.is_none()
+                || !config.reject_permanent_delegate,
+            EscrowError::PermanentDelegateMint
+        );
+
+        Deposit {
+            seller: ctx.accounts.seller.to_account_info(),
+            seller_offer_token: ctx
+                .accounts
+                .seller_offer_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            vault: ctx.accounts.vault.to_account_info(),
+            offer_mint: &ctx.accounts.offer_mint,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .deposit(offer_amount)?;
+
+        ctx.accounts.vault.reload()?;
+        let offer_amount = ctx.accounts.vault.amount;
+        require!(offer_amount > 0, EscrowError::TransferFeeTooHigh);
+        let request_amount = u64::try_from(
+            (offer_amount as u128 * template.request_amount as u128)
+                .div_ceil(template.offer_amount as u128),
+        )
+        .map_err(|_| error!(EscrowError::MathOverflow))?;
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            offer_amount,
+            request_amount,
+        )?;
+
+        let mut escrow = ctx.accounts.escrow.load_init()?;
+        escrow.seller = ctx.accounts.seller.key();
+        escrow.offer_mint = template.offer_mint;
+        escrow.request_mint = template.request_mint;
+        escrow.escrow_id = escrow_id;
+        escrow.offer_amount = offer_amount;
+        escrow.request_amount = request_amount;
+        escrow.set_expires_at(expires_at);
+        escrow.set_listed_at(now);
+        escrow.set_market(template.market());
+        escrow.set_designated_buyer(template.designated_buyer());
+        escrow.set_buyer_allowlist(template.buyer_allowlist());
+        escrow.set_token_gate(template.token_gate());
+        escrow.set_permission(template.permission());
+        escrow.set_unit_price(template.unit_price());
+        escrow.set_oracle_pricing(template.oracle_pricing());
+        escrow.set_arbitration(template.arbitration());
+        escrow.set_vesting(template.vesting());
+        escrow.set_proceeds_stream(template.proceeds_stream());
+        escrow.set_proceeds_recipient(template.proceeds_recipient());
+        escrow.set_reveal_delay(template.reveal_delay());
+        escrow.set_keep_record(template.keep_record());
+        escrow.set_label(&template.label());
+        escrow.set_metadata_uri(template.metadata_uri().as_deref());
+        escrow.set_rent_recipient(ctx.accounts.payer.key());
+        escrow.escrow_bump = ctx.bumps.escrow;
+        escrow.set_ata_vault();
+        escrow.set_status(EscrowStatus::Open);
+        escrow.version = ESCROW_VERSION;
+
+        ctx.accounts
+            .pair_index
+            .record_open(escrow.offer_mint, escrow.request_mint);
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.register(ctx.accounts.escrow.key())?;
+        }
+        if let Some(market_index) = &mut ctx.accounts.market_index {
+            market_index.link(ctx.accounts.escrow.key())?;
+            escrow.set_market_index(Some(market_index.key()));
+        }
+        let seller_index = &mut ctx.accounts.seller_index;
+        seller_index.seller = ctx.accounts.seller.key();
+        seller_index.bump = ctx.bumps.seller_index;
+        seller_index.record_open(escrow_id)?;
+        ctx.accounts
+            .config
+            .check_open_escrows(seller_index.open_escrows)?;
+        Stats::record(&ctx.accounts.stats, |stats| {
+            stats.escrows_created = stats.escrows_created.saturating_add(1)
+        })?;
+
+        msg!(
+            "Escrow {} cloned: {} tokens offered for {} tokens requested",
+            ctx.accounts.template.key(),
             offer_amount,
             request_amount
         );
+        let event = EscrowCreated {
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            offer_mint: escrow.offer_mint,
+            request_mint: escrow.request_mint,
+            offer_amount,
+            request_amount,
+            expires_at,
+            market: escrow.market(),
+            collection: None,
+        };
+        emit!(event);
+        emit_cpi!(event);
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Created,
+            escrow: event.escrow,
+            seller: event.seller,
+            buyer: None,
+            offer_amount,
+            request_amount,
+            timestamp: now,
+        })?;
 
         Ok(())
     }
 
     /// Accept an escrow offer
-    /// - Transfer buyer's tokens to seller
-    /// - Transfer escrowed tokens to buyer
-    /// - Close escrow accounts
-    pub fn accept_escrow(ctx: Context<AcceptEscrow>) -> Result<()> {
-        let escrow = &ctx.accounts.escrow;
-        let offer_amount = escrow.offer_amount;
-        let request_amount = escrow.request_amount;
+    /// - Transfer buyer's tokens to seller, pro-rated to `fill_amount`;
+    ///   a native SOL request may be paid in lamports without a wSOL account
+    /// - Transfer `fill_amount` escrowed tokens to buyer, or to
+    ///   `recipient_offer_token` of any owner when given
+    /// - Close escrow accounts once fully filled
+    /// - Remaining accounts supply creator royalty and extra request leg
+    ///   accounts as laid out in `Fill`, then transfer-hook accounts
+    /// - `max_request_amount` bounds the request tokens paid, including any
+    ///   market taker fee, and `min_offer_out` the offer tokens received
+    ///   after transfer fees, guarding against re-priced escrows
+    /// - `valid_until_slot` fails a transaction landing after that slot
+    ///   rather than settle a stale trade
+    /// - `memo` is logged through the SPL Memo program alongside the
+    ///   fill's transfers, for reconciling them with the escrow
+    pub fn accept_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptEscrow<'info>>,
+        fill_amount: u64,
+        max_request_ui_amount: Option<String>,
+        max_request_amount: Option<u64>,
+        min_offer_out: Option<u64>,
+        valid_until_slot: Option<u64>,
+        memo: Option<String>,
+    ) -> Result<()> {
+        if let Some(valid_until_slot) = valid_until_slot {
+            require!(
+                Clock::get()?.slot <= valid_until_slot,
+                EscrowError::FillDeadlinePassed
+            );
+        }
+        let accounts = &mut *ctx.accounts;
+        let escrow = accounts.escrow.load()?;
+        // Arbitrated escrows settle through `hold_escrow`
+        require!(escrow.arbitration().is_none(), EscrowError::HeldSettlement);
 
-        // Create signer seeds for the escrow PDA
+        // Vested escrows deliver into a fresh vesting vault, and native SOL
+        // is released through a wrapped account that is closed to the
+        // buyer, unwrapping it to lamports
+        let vesting_schedule = escrow.vesting();
+        require!(
+            vesting_schedule.is_some()
+                == (accounts.vesting.is_some() && accounts.vesting_vault.is_some()),
+            EscrowError::InvalidVestingAccount
+        );
+        let stream_seconds = escrow.proceeds_stream();
+        require!(
+            stream_seconds.is_some()
+                == (accounts.stream.is_some() && accounts.stream_vault.is_some()),
+            EscrowError::InvalidStreamAccount
+        );
+        let nonce = escrow.offer_amount;
+        let oracle_pricing = escrow.oracle_pricing();
+        let market = escrow.market();
+        let recurring = escrow.recurring().is_some();
+        let market_index = escrow.market_index();
         let seller_key = escrow.seller;
+        let listed_by = escrow.listed_by();
         let offer_mint_key = escrow.offer_mint;
         let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
         let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
+        let escrow_info = accounts.escrow.to_account_info();
+        let native = is_native_mint(&accounts.offer_mint.key());
 
-        let escrow_seeds = &[
-            b"escrow",
-            seller_key.as_ref(),
-            offer_mint_key.as_ref(),
-            request_mint_key.as_ref(),
-            &[escrow_bump],
-        ];
-        let signer_seeds = &[&escrow_seeds[..]];
+        // An oracle-priced fill must not share its transaction with a price
+        // update or flash loan that could skew the price it settles at
+        match &accounts.instructions {
+            Some(instructions) => check_transaction_guard(
+                instructions,
+                &accounts.escrow.key(),
+                &accounts.config.guarded_programs,
+                oracle_pricing.is_some(),
+            )?,
+            None => require!(
+                oracle_pricing.is_none(),
+                EscrowError::MissingInstructionsSysvar
+            ),
+        }
 
-        // Transfer request tokens from buyer to seller
-        transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.buyer_request_token.to_account_info(),
-                    to: ctx.accounts.seller_request_token.to_account_info(),
-                    authority: ctx.accounts.buyer.to_account_info(),
+        // A delegate of the buyer's request tokens accepts on behalf of
+        // their owner, who is the taker and receives the offer
+        let beneficiary = match &accounts.beneficiary {
+            Some(beneficiary) => {
+                let buyer_request_token = accounts
+                    .buyer_request_token
+                    .as_ref()
+                    .ok_or(EscrowError::MissingRequestTokenAccount)?;
+                require_keys_eq!(
+                    buyer_request_token.owner,
+                    beneficiary.key(),
+                    EscrowError::InvalidTokenAccountOwner
+                );
+                beneficiary.to_account_info()
+            }
+            None => accounts.buyer.to_account_info(),
+        };
+        // A session key accepts for its owner, the beneficiary, with the
+        // session account paying as the delegate of their request tokens
+        let (payer_authority, session_seeds) = match &accounts.session {
+            Some(session) => {
+                require!(
+                    session.authorizes(
+                        &accounts.buyer.key(),
+                        &beneficiary.key(),
+                        SESSION_ACCEPT
+                    )?,
+                    EscrowError::Unauthorized
+                );
+                (
+                    session.to_account_info(),
+                    Some((session.owner, session.session_key, [session.bump])),
+                )
+            }
+            None => (accounts.buyer.to_account_info(), None),
+        };
+        let session_signer = session_seeds.as_ref().map(|(owner, session_key, bump)| {
+            [
+                b"session".as_ref(),
+                owner.as_ref(),
+                session_key.as_ref(),
+                bump.as_ref(),
+            ]
+        });
+        let session_signer = session_signer.as_ref().map(|seeds| &seeds[..]);
+        let payer_seeds = session_signer.as_slice();
+        // Only the buyer's own purchase may be redirected, and only where
+        // the offer is delivered as tokens
+        require!(
+            accounts.recipient_offer_token.is_none()
+                || (accounts.beneficiary.is_none() && accounts.vesting_vault.is_none() && !native),
+            EscrowError::InvalidRecipient
+        );
+        let offer_destination = if let Some(vesting_vault) = &accounts.vesting_vault {
+            vesting_vault.to_account_info()
+        } else if let Some(recipient_offer_token) = &accounts.recipient_offer_token {
+            recipient_offer_token.to_account_info()
+        } else if native {
+            accounts
+                .offer_unwrap
+                .as_ref()
+                .ok_or(EscrowError::MissingOfferTokenAccount)?
+                .to_account_info()
+        } else if accounts.beneficiary.is_some() {
+            accounts
+                .beneficiary_offer_token
+                .as_ref()
+                .ok_or(EscrowError::MissingOfferTokenAccount)?
+                .to_account_info()
+        } else {
+            accounts
+                .buyer_offer_token
+                .as_ref()
+                .ok_or(EscrowError::MissingOfferTokenAccount)?
+                .to_account_info()
+        };
+        // Oracle-priced escrows re-quote the remaining offer at the current
+        // price before filling
+        if let Some(pricing) = oracle_pricing {
+            let price_update = accounts
+                .price_update
+                .as_ref()
+                .ok_or(EscrowError::MissingOracleAccount)?;
+            let mut escrow = accounts.escrow.load_mut()?;
+            escrow.request_amount = pricing.quote(
+                &pricing.load(price_update)?,
+                escrow.offer_amount,
+                escrow.oracle_listed_offer(),
+                accounts.offer_mint.decimals,
+                accounts.request_mint.decimals,
+            )?;
+        }
+
+        let event = Fill {
+            escrow: &accounts.escrow,
+            vault: &accounts.vault,
+            seller: accounts.seller.to_account_info(),
+            seller_request_token: accounts
+                .seller_request_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            proceeds_recipient_token: accounts
+                .proceeds_recipient_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            proceeds_vault: accounts
+                .proceeds_vault
+                .as_deref()
+                .or(accounts.stream_vault.as_deref()),
+            market: accounts.market.as_deref(),
+            market_fee_token: accounts.market_fee_token.as_deref(),
+            pair_fees: accounts
+                .pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .buyer_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.seller_index.to_account_info(),
+            seller_reputation: accounts.seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            taker_fee_vault: accounts.taker_fee_vault.as_deref(),
+            referrer_token: accounts.referrer_token.as_deref(),
+            offer_metadata: accounts.offer_metadata.as_deref(),
+            buyer_allowlist: accounts.buyer_allowlist.as_deref(),
+            gate_token: accounts.gate_token.as_deref(),
+            gate_metadata: accounts.gate_metadata.as_deref(),
+            discount_token: accounts.discount_token.as_deref(),
+            discount_metadata: accounts.discount_metadata.as_deref(),
+            permission_approval: accounts
+                .permission_approval
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            fill_commitment: accounts.fill_commitment.as_deref(),
+            taker: beneficiary.key(),
+            payer_token: accounts
+                .buyer_request_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            payer_authority,
+            offer_destination: offer_destination.clone(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            offer_mint_limits: Some(accounts.offer_mint_limits.to_account_info()),
+            request_mint_limits: Some(accounts.request_mint_limits.to_account_info()),
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(&mut accounts.pair_index, fill_amount, payer_seeds)?;
+
+        // Buyers may bound the price in UI terms, which drift from the raw
+        // amounts on interest-bearing request mints
+        if let Some(max_request_ui_amount) = max_request_ui_amount {
+            let max_request_amount = raw_amount(
+                &accounts.request_mint.to_account_info(),
+                &max_request_ui_amount,
+                accounts.request_mint.decimals,
+            )?;
+            require!(
+                event.request_amount <= max_request_amount,
+                EscrowError::RequestUiAmountExceeded
+            );
+        }
+        // The taker's protocol fee rides on the payment, whichever leg the
+        // maker's is taken from
+        let cost = event
+            .request_amount
+            .checked_add(event.taker_fee)
+            .and_then(|cost| cost.checked_add(event.protocol_taker_fee))
+            .ok_or(EscrowError::MathOverflow)?;
+        if let Some(max_request_amount) = max_request_amount {
+            require!(cost <= max_request_amount, EscrowError::SlippageExceeded);
+        }
+        if let Some(session) = &mut accounts.session {
+            session.spend_remaining = session
+                .spend_remaining
+                .checked_sub(cost)
+                .ok_or(EscrowError::SessionSpendExceeded)?;
+        }
+        if let Some(min_offer_out) = min_offer_out {
+            let delivered = event
+                .offer_amount
+                .checked_sub(event.offer_protocol_fee)
+                .ok_or(EscrowError::MathOverflow)?;
+            let offer_fee = transfer_fee(&accounts.offer_mint.to_account_info(), delivered)?;
+            require!(
+                delivered
+                    .checked_sub(offer_fee)
+                    .ok_or(EscrowError::MathOverflow)?
+                    >= min_offer_out,
+                EscrowError::SlippageExceeded
+            );
+        }
+
+        // A fully filled escrow leaves the offer registry and its market
+        // index, unless it recurs
+        if event.remaining_offer_amount == 0 && !recurring {
+            if let Some(registry_page) = &mut accounts.registry_page {
+                registry_page.deregister(&escrow_info.key())?;
+            }
+            MarketIndex::unlink(
+                accounts.market_index.as_deref_mut(),
+                market_index,
+                &escrow_info.key(),
+            )?;
+        } else if accounts.reservation.is_some() {
+            // A redeemed reservation returns the rest to the market
+            accounts.escrow.load_mut()?.set_reservation(None);
+        }
+
+        if let (Some(seconds), Some(stream), Some(stream_vault)) = (
+            stream_seconds,
+            accounts.stream.as_mut(),
+            accounts.stream_vault.as_mut(),
+        ) {
+            // Stream what reached the vault after transfer fees
+            stream_vault.reload()?;
+            let start_at = Clock::get()?.unix_timestamp;
+            stream.seller = seller_key;
+            stream.buyer = accounts.payer.key();
+            stream.escrow = escrow_info.key();
+            stream.nonce = nonce;
+            stream.mint = request_mint_key;
+            stream.vault = stream_vault.key();
+            stream.total_amount = stream_vault.amount;
+            stream.withdrawn_amount = 0;
+            stream.start_at = start_at;
+            stream.end_at = start_at
+                .checked_add(seconds)
+                .ok_or(EscrowError::MathOverflow)?;
+            stream.bump = ctx.bumps.stream.ok_or(EscrowError::InvalidStreamAccount)?;
+        }
+
+        if let (Some(schedule), Some(vesting), Some(vesting_vault)) = (
+            vesting_schedule,
+            accounts.vesting.as_mut(),
+            accounts.vesting_vault.as_mut(),
+        ) {
+            // Vest what reached the vault after transfer fees
+            vesting_vault.reload()?;
+            let start_at = Clock::get()?.unix_timestamp;
+            vesting.beneficiary = beneficiary.key();
+            vesting.escrow = escrow_info.key();
+            vesting.nonce = nonce;
+            vesting.mint = offer_mint_key;
+            vesting.vault = vesting_vault.key();
+            vesting.total_amount = vesting_vault.amount;
+            vesting.claimed_amount = 0;
+            vesting.start_at = start_at;
+            vesting.cliff_at = start_at
+                .checked_add(schedule.cliff_seconds)
+                .ok_or(EscrowError::MathOverflow)?;
+            vesting.end_at = start_at
+                .checked_add(schedule.duration_seconds)
+                .ok_or(EscrowError::MathOverflow)?;
+            vesting.bump = ctx
+                .bumps
+                .vesting
+                .ok_or(EscrowError::InvalidVestingAccount)?;
+        } else if native {
+            let escrow_seeds = &[
+                b"escrow",
+                listed_by.as_ref(),
+                offer_mint_key.as_ref(),
+                request_mint_key.as_ref(),
+                escrow_id.as_ref(),
+                &[escrow_bump],
+            ];
+            close_account(CpiContext::new_with_signer(
+                accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: offer_destination,
+                    destination: beneficiary,
+                    authority: escrow_info,
                 },
+                &[&escrow_seeds[..]],
+            ))?;
+        }
+
+        msg!("Escrow accepted successfully");
+        if let Some(market) = market {
+            msg!(
+                "Market {} maker fee: {}, taker fee: {}",
+                market,
+                event.maker_fee,
+                event.taker_fee
+            );
+        }
+        emit!(event);
+        emit_cpi!(event);
+        if let Some(memo) = &memo {
+            log_memo(ctx.accounts.memo_program.as_deref(), memo)?;
+        }
+
+        Ok(())
+    }
+
+    /// Accept an escrow for exactly `offer_out` offer tokens received
+    /// - Releases enough of the offer to cover the offer mint's transfer
+    ///   fee, and charges the escrow's price for it rounded up in the
+    ///   seller's favor
+    /// - `max_request_amount` bounds the request tokens paid and
+    ///   `valid_until_slot` the landing slot, as in `accept_escrow`
+    pub fn accept_escrow_exact_out<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptEscrow<'info>>,
+        offer_out: u64,
+        max_request_amount: Option<u64>,
+        valid_until_slot: Option<u64>,
+    ) -> Result<()> {
+        let mut fill_amount = gross_amount(&ctx.accounts.offer_mint.to_account_info(), offer_out)?;
+        // A maker fee taken in the offer mint comes out of the fill too;
+        // the taker's fee is paid on top of the request tokens
+        if ctx.accounts.config.fee_currency == FeeCurrency::Offer {
+            let rate = discount_rate(
+                ctx.accounts.config.protocol_fee_bps,
+                ctx.accounts.escrow.load()?.protocol_fee_discount_bps(),
+            );
+            fill_amount = gross_of_bps(fill_amount, rate)?;
+        }
+        accept_escrow(
+            ctx,
+            fill_amount,
+            None,
+            max_request_amount,
+            Some(offer_out),
+            valid_until_slot,
+            None,
+        )
+    }
+
+    /// Price a fill of `fill_amount` as `accept_escrow` would settle it now
+    /// - Read-only: clients simulate it and decode the returned `Quote`
+    /// - Re-quotes oracle-priced escrows from `price_update`, and applies
+    ///   the market's fees, the protocol fees and royalties, with the
+    ///   quoted buyer's collection discount on the taker fee
+    /// - Without trader stats, volume discounts are not applied, so the
+    ///   quoted fees are an upper bound
+    /// - Fails for escrows that cannot be filled, as `accept_escrow` would
+    pub fn get_quote(ctx: Context<GetQuote>, fill_amount: u64) -> Result<Quote> {
+        let accounts = &ctx.accounts;
+        let mut escrow = *accounts.escrow.load()?;
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        require!(
+            escrow.status() == EscrowStatus::Open,
+            EscrowError::EscrowNotOpen
+        );
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            fill_amount > 0 && fill_amount <= escrow.offer_amount,
+            EscrowError::InvalidAmount
+        );
+        if let Some(starts_at) = escrow.starts_at() {
+            require!(now >= starts_at, EscrowError::EscrowNotStarted);
+        }
+        if let Some(expires_at) = escrow.expires_at() {
+            require!(now < expires_at, EscrowError::EscrowExpired);
+        }
+        require!(
+            fill_amount == escrow.offer_amount || !escrow.has_extra_payments(),
+            EscrowError::HasRequestLegs
+        );
+
+        if let Some(pricing) = escrow.oracle_pricing() {
+            let price_update = accounts
+                .price_update
+                .as_ref()
+                .ok_or(EscrowError::MissingOracleAccount)?;
+            escrow.request_amount = pricing.quote(
+                &pricing.load(price_update)?,
+                escrow.offer_amount,
+                escrow.oracle_listed_offer(),
+                accounts.offer_mint.decimals,
+                accounts.request_mint.decimals,
+            )?;
+        }
+        let request_amount = escrow.fill_cost(fill_amount)?;
+
+        let mut fees = match escrow.market() {
+            Some(market_key) => {
+                let market = accounts
+                    .market
+                    .as_ref()
+                    .ok_or(EscrowError::MissingMarketAccount)?;
+                require_keys_eq!(market.key(), market_key, EscrowError::InvalidMarket);
+                let pair_fees = accounts
+                    .pair_fees
+                    .as_ref()
+                    .ok_or(EscrowError::MissingMarketAccount)?;
+                let seller_volume = match &accounts.seller_stats {
+                    Some(stats) => TraderStats::load(stats, &escrow.seller, &escrow.request_mint)?,
+                    None => None,
+                }
+                .map_or(0, |stats| stats.volume(now));
+                let buyer_volume = match (&accounts.buyer_stats, &accounts.buyer) {
+                    (Some(stats), Some(buyer)) => {
+                        TraderStats::load(stats, &buyer.key(), &escrow.request_mint)?
+                    }
+                    _ => None,
+                }
+                .map_or(0, |stats| stats.volume(now));
+                market
+                    .fee_schedule(
+                        &market_key,
+                        pair_fees,
+                        &escrow.offer_mint,
+                        &escrow.request_mint,
+                    )?
+                    .discounted(seller_volume, buyer_volume)
+                    .apply(request_amount)?
+            }
+            None => MarketFees::default(),
+        };
+        let fee_on_offer = accounts.config.fee_currency == FeeCurrency::Offer;
+        let fee_base = if fee_on_offer {
+            fill_amount
+        } else {
+            request_amount
+        };
+        let protocol_fee = bps_of(
+            fee_base,
+            discount_rate(
+                accounts.config.protocol_fee_bps,
+                escrow.protocol_fee_discount_bps(),
             ),
+        )?;
+        let taker_discount = match &accounts.buyer {
+            Some(buyer) => accounts.config.taker_discount(
+                &buyer.key(),
+                accounts.discount_token.as_deref().map(|token| &**token),
+                accounts
+                    .discount_metadata
+                    .as_deref()
+                    .map(|metadata| &**metadata),
+            )?,
+            None => 0,
+        };
+        fees.protocol_taker_fee = bps_of(
             request_amount,
+            discount_rate(accounts.config.protocol_taker_fee_bps, taker_discount),
         )?;
+        let delivered = if fee_on_offer {
+            fill_amount
+                .checked_sub(protocol_fee)
+                .ok_or(EscrowError::MathOverflow)?
+        } else {
+            fees.protocol_fee = protocol_fee;
+            fill_amount
+        };
+        if escrow.pay_royalties() {
+            let metadata = accounts
+                .offer_metadata
+                .as_ref()
+                .ok_or(EscrowError::MissingMetadataAccount)?;
+            let royalty = bps_of(request_amount, metadata.seller_fee_basis_points)?;
+            fees.royalty = metadata
+                .creators
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .try_fold(0u64, |total, creator| {
+                    royalty
+                        .checked_mul(creator.share as u64)
+                        .map(|share| share / 100)
+                        .and_then(|share| total.checked_add(share))
+                })
+                .ok_or(EscrowError::MathOverflow)?;
+        }
 
-        // Transfer offer tokens from vault to buyer
-        transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.buyer_offer_token.to_account_info(),
-                    authority: ctx.accounts.escrow.to_account_info(),
-                },
-                signer_seeds,
-            ),
-            offer_amount,
+        let offer_fee = epoch_transfer_fee(&accounts.offer_mint.to_account_info(), clock.epoch)?;
+        Ok(Quote {
+            fill_amount,
+            offer_out: delivered
+                .checked_sub(fee_of(offer_fee.as_ref(), delivered)?)
+                .ok_or(EscrowError::MathOverflow)?,
+            request_amount,
+            maker_fee: fees.maker_fee,
+            taker_fee: fees.taker_fee,
+            protocol_fee: fees.protocol_fee,
+            offer_protocol_fee: if fee_on_offer { protocol_fee } else { 0 },
+            protocol_taker_fee: fees.protocol_taker_fee,
+            royalty: fees.royalty,
+            total_cost: fees.taker_cost(request_amount)?,
+            seller_proceeds: fees.seller_proceeds(request_amount)?,
+            request_lamports: escrow.request_lamports(),
+        })
+    }
+
+    /// Report an escrow's status, amounts and expiry as of now
+    /// - Read-only: clients simulate it and decode the returned
+    ///   `EscrowState`
+    pub fn get_escrow_state(ctx: Context<GetEscrowState>) -> Result<EscrowState> {
+        let escrow = ctx.accounts.escrow.load()?;
+        let now = Clock::get()?.unix_timestamp;
+        let expired = escrow
+            .expires_at()
+            .is_some_and(|expires_at| now >= expires_at);
+        let pending = escrow.starts_at().is_some_and(|starts_at| now < starts_at);
+        Ok(EscrowState {
+            status: escrow.status(),
+            offer_amount: escrow.offer_amount,
+            request_amount: escrow.request_amount,
+            starts_at: escrow.starts_at(),
+            expires_at: escrow.expires_at(),
+            expired,
+            fillable: escrow.status() == EscrowStatus::Open
+                && escrow.offer_amount > 0
+                && !pending
+                && !expired,
+        })
+    }
+
+    /// Fill several escrows of one mint pair in a single instruction
+    /// - Remaining accounts supply, per escrow in fill order, the escrow,
+    ///   its vault, its seller, the seller's request-mint token account and
+    ///   the seller's `SellerIndex` and `Reputation` addresses
+    /// - Each escrow settles at its own price until the buyer holds
+    ///   `target_amount` offer tokens; escrows no longer open are skipped
+    /// - Fails when nothing fills or the fills cost more than
+    ///   `max_request_amount` in total
+    /// - Escrows under a market, allowlist, receipt, royalties, request
+    ///   legs, oracle pricing, vesting, arbitration, transfer hooks or
+    ///   a rent recipient other than the seller are accepted individually
+    pub fn accept_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptMany<'info>>,
+        target_amount: u64,
+        max_request_amount: u64,
+    ) -> Result<()> {
+        require!(target_amount > 0, EscrowError::InvalidAmount);
+        require!(
+            ctx.remaining_accounts.len().is_multiple_of(6),
+            EscrowError::InvalidSweepAccount
+        );
+        let events = ctx.accounts.sweep(ctx.remaining_accounts, target_amount)?;
+        let (filled, spent) = events
+            .iter()
+            .try_fold((0u64, 0u64), |(filled, spent), event| {
+                Some((
+                    filled.checked_add(event.offer_amount)?,
+                    spent.checked_add(event.request_amount)?,
+                ))
+            })
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(filled > 0, EscrowError::NothingFilled);
+        require!(spent <= max_request_amount, EscrowError::SweepCostExceeded);
+
+        msg!(
+            "Swept {} escrows for {} offer tokens at {} request tokens",
+            events.len(),
+            filled,
+            spent
+        );
+        for event in events {
+            emit!(event);
+            emit_cpi!(event);
+        }
+
+        Ok(())
+    }
+
+    /// Fill `fill_amount` of whichever of several escrows of one mint pair
+    /// sells it cheapest
+    /// - Remaining accounts supply candidates as for `accept_many`; those
+    ///   no longer open or too small for the fill are passed over, and ties
+    ///   go to the earliest
+    /// - Fails when no candidate fills for `max_request_amount` or less
+    /// - Candidates are restricted as for `accept_many`
+    pub fn accept_best<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptMany<'info>>,
+        fill_amount: u64,
+        max_request_amount: u64,
+    ) -> Result<()> {
+        require!(fill_amount > 0, EscrowError::InvalidAmount);
+        require!(
+            ctx.remaining_accounts.len().is_multiple_of(6),
+            EscrowError::InvalidSweepAccount
+        );
+        let mut best: Option<(usize, u64)> = None;
+        for (i, escrow_accounts) in ctx.remaining_accounts.chunks(6).enumerate() {
+            let escrow = AccountLoader::<Escrow>::try_from(&escrow_accounts[0])?;
+            let escrow = escrow.load()?;
+            if escrow.status() != EscrowStatus::Open || escrow.offer_amount < fill_amount {
+                continue;
+            }
+            require_keys_eq!(
+                escrow.offer_mint,
+                ctx.accounts.offer_mint.key(),
+                EscrowError::InvalidMint
+            );
+            require_keys_eq!(
+                escrow.request_mint,
+                ctx.accounts.request_mint.key(),
+                EscrowError::InvalidMint
+            );
+            // Oracle-priced escrows cannot be quoted without their feed
+            if escrow.oracle_pricing().is_some() {
+                continue;
+            }
+            // Nor can escrows whose remainder would be left unfillable
+            let Ok(cost) = escrow.fill_cost(fill_amount) else {
+                continue;
+            };
+            match best {
+                Some((_, best_cost)) if best_cost <= cost => {}
+                _ => best = Some((i, cost)),
+            }
+        }
+        let (best, cost) = best.ok_or(EscrowError::NothingFilled)?;
+        require!(cost <= max_request_amount, EscrowError::SlippageExceeded);
+
+        let events = ctx
+            .accounts
+            .sweep(&ctx.remaining_accounts[best * 6..][..6], fill_amount)?;
+
+        msg!(
+            "Filled best of {} escrows for {} offer tokens at {} request tokens",
+            ctx.remaining_accounts.len() / 6,
+            fill_amount,
+            cost
+        );
+        for event in events {
+            emit!(event);
+            emit_cpi!(event);
+        }
+
+        Ok(())
+    }
+
+    /// Settle two escrows back to back in one transaction
+    /// - `first_escrow` must offer the mint that `second_escrow` requests
+    /// - The taker pays the first escrow's request and receives the second
+    ///   escrow's offer; the intermediate leg passes through a transient
+    ///   token account that is closed before returning
+    /// - Any intermediate surplus is delivered to the taker
+    pub fn settle_chain<'info>(ctx: Context<'_, '_, '_, 'info, SettleChain<'info>>) -> Result<()> {
+        let first_fill_amount = ctx.accounts.first_escrow.load()?.offer_amount;
+        let second_fill_amount = ctx.accounts.second_escrow.load()?.offer_amount;
+        route_fill(ctx, first_fill_amount, second_fill_amount)
+    }
+
+    /// Fill part of two escrows back to back in one transaction, routing
+    /// through the mint the first offers and the second requests
+    /// - Takes `first_fill_amount` of the first escrow's offer into a
+    ///   transient token account, which pays for `second_fill_amount` of
+    ///   the second's
+    /// - Either fill may be partial; `settle_chain` fills both in full
+    /// - Any intermediate surplus is delivered to the taker
+    pub fn route_fill<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleChain<'info>>,
+        first_fill_amount: u64,
+        second_fill_amount: u64,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        let first_escrow = accounts.first_escrow.load()?;
+        let second_escrow = accounts.second_escrow.load()?;
+        require_keys_eq!(
+            first_escrow.offer_mint,
+            second_escrow.request_mint,
+            EscrowError::InvalidChain
+        );
+        require!(
+            second_fill_amount > 0 && second_fill_amount <= second_escrow.offer_amount,
+            EscrowError::InvalidAmount
+        );
+        require!(
+            first_fill_amount >= second_escrow.fill_cost(second_fill_amount)?,
+            EscrowError::InvalidChain
+        );
+        // Chains settle through a transient account holding only the
+        // intermediate mint
+        require!(
+            !first_escrow.has_extra_payments() && !second_escrow.has_extra_payments(),
+            EscrowError::InvalidChain
+        );
+        require!(
+            first_escrow.oracle_pricing().is_none() && second_escrow.oracle_pricing().is_none(),
+            EscrowError::InvalidChain
+        );
+        require!(
+            first_escrow.arbitration().is_none() && second_escrow.arbitration().is_none(),
+            EscrowError::HeldSettlement
+        );
+        require!(
+            first_escrow.vesting().is_none() && second_escrow.vesting().is_none(),
+            EscrowError::VestedEscrow
+        );
+        require!(
+            first_escrow.proceeds_stream().is_none() && second_escrow.proceeds_stream().is_none(),
+            EscrowError::StreamedEscrow
+        );
+
+        let first_listed_by = first_escrow.listed_by();
+        let first_offer_mint_key = first_escrow.offer_mint;
+        let first_request_mint_key = first_escrow.request_mint;
+        let first_escrow_id = first_escrow.escrow_id.to_le_bytes();
+        let first_escrow_bump = first_escrow.escrow_bump;
+        drop((first_escrow, second_escrow));
+
+        // The transient account is owned by the first escrow's PDA
+        let first_escrow_seeds = &[
+            b"escrow",
+            first_listed_by.as_ref(),
+            first_offer_mint_key.as_ref(),
+            first_request_mint_key.as_ref(),
+            first_escrow_id.as_ref(),
+            &[first_escrow_bump],
+        ];
+        let transient_signer = &[&first_escrow_seeds[..]];
+        let first_escrow_info = accounts.first_escrow.to_account_info();
+
+        // Hop 1: taker pays the first seller, intermediate lands in transient
+        let first_fill = Fill {
+            escrow: &accounts.first_escrow,
+            vault: &accounts.first_vault,
+            seller: accounts.first_seller.to_account_info(),
+            seller_request_token: Some(accounts.first_seller_request_token.to_account_info()),
+            proceeds_recipient_token: None,
+            proceeds_vault: accounts.first_proceeds_vault.as_deref(),
+            market: accounts.first_market.as_deref(),
+            market_fee_token: accounts.first_market_fee_token.as_deref(),
+            pair_fees: accounts
+                .first_pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .first_seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .first_taker_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.first_seller_index.to_account_info(),
+            seller_reputation: accounts.first_seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .first_rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.first_pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.first_fee_vault.as_deref(),
+            taker_fee_vault: accounts.first_taker_fee_vault.as_deref(),
+            referrer_token: None,
+            offer_metadata: None,
+            // Allowlist-gated escrows can only be filled directly
+            buyer_allowlist: None,
+            gate_token: None,
+            gate_metadata: None,
+            discount_token: None,
+            discount_metadata: None,
+            permission_approval: None,
+            fill_commitment: None,
+            taker: accounts.taker.key(),
+            payer_token: Some(accounts.taker_input_token.to_account_info()),
+            payer_authority: accounts.taker.to_account_info(),
+            offer_destination: accounts.transient.to_account_info(),
+            offer_mint: &accounts.intermediate_mint,
+            request_mint: &accounts.input_mint,
+            offer_mint_limits: Some(accounts.intermediate_mint_limits.to_account_info()),
+            request_mint_limits: Some(accounts.input_mint_limits.to_account_info()),
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.input_token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(&mut accounts.pair_index, first_fill_amount, &[])?;
+
+        // Hop 2: transient pays the second seller, output lands with taker
+        let second_fill = Fill {
+            escrow: &accounts.second_escrow,
+            vault: &accounts.second_vault,
+            seller: accounts.second_seller.to_account_info(),
+            seller_request_token: Some(accounts.second_seller_request_token.to_account_info()),
+            proceeds_recipient_token: None,
+            proceeds_vault: accounts.second_proceeds_vault.as_deref(),
+            market: accounts.second_market.as_deref(),
+            market_fee_token: accounts.second_market_fee_token.as_deref(),
+            pair_fees: accounts
+                .second_pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .second_seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .second_taker_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.second_seller_index.to_account_info(),
+            seller_reputation: accounts.second_seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .second_rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.second_pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.second_fee_vault.as_deref(),
+            taker_fee_vault: accounts.second_taker_fee_vault.as_deref(),
+            referrer_token: None,
+            offer_metadata: None,
+            // Allowlist-gated escrows can only be filled directly
+            buyer_allowlist: None,
+            gate_token: None,
+            gate_metadata: None,
+            discount_token: None,
+            discount_metadata: None,
+            permission_approval: None,
+            fill_commitment: None,
+            taker: accounts.taker.key(),
+            payer_token: Some(accounts.transient.to_account_info()),
+            payer_authority: first_escrow_info.clone(),
+            offer_destination: accounts.taker_output_token.to_account_info(),
+            offer_mint: &accounts.output_mint,
+            request_mint: &accounts.intermediate_mint,
+            offer_mint_limits: Some(accounts.output_mint_limits.to_account_info()),
+            request_mint_limits: Some(accounts.intermediate_mint_limits.to_account_info()),
+            offer_token_program: accounts.output_token_program.to_account_info(),
+            request_token_program: accounts.token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(
+            &mut accounts.pair_index,
+            second_fill_amount,
+            transient_signer,
         )?;
 
-        // Close the vault token account and return rent to seller
-        close_account(CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            CloseAccount {
-                account: ctx.accounts.vault.to_account_info(),
-                destination: ctx.accounts.seller.to_account_info(),
-                authority: ctx.accounts.escrow.to_account_info(),
-            },
-            signer_seeds,
-        ))?;
+        // Hand any intermediate surplus to the taker, then close transient
+        accounts.transient.reload()?;
+        let surplus = accounts.transient.amount;
+        if surplus > 0 {
+            let taker_intermediate_token = accounts
+                .taker_intermediate_token
+                .as_ref()
+                .ok_or(EscrowError::MissingIntermediateAccount)?;
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.transient.to_account_info(),
+                        mint: accounts.intermediate_mint.to_account_info(),
+                        to: taker_intermediate_token.to_account_info(),
+                        authority: first_escrow_info.clone(),
+                    },
+                    transient_signer,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                surplus,
+                accounts.intermediate_mint.decimals,
+            )?;
+        }
 
-        msg!("Escrow accepted successfully");
+        close_token_account(
+            accounts.token_program.to_account_info(),
+            accounts.transient.to_account_info(),
+            accounts.intermediate_mint.to_account_info(),
+            accounts.taker.to_account_info(),
+            first_escrow_info,
+            transient_signer,
+        )?;
+
+        msg!("Escrow chain settled, {} intermediate surplus", surplus);
+        for fill in [first_fill, second_fill] {
+            emit!(fill);
+            emit_cpi!(fill);
+        }
 
         Ok(())
     }
 
     /// Cancel an escrow offer
     /// - Refund escrowed tokens to seller
-    /// - Close escrow accounts
-    pub fn cancel_escrow(ctx: Context<CancelEscrow>) -> Result<()> {
-        let escrow = &ctx.accounts.escrow;
+    /// - Close escrow accounts, keeping the escrow as a cancelled record
+    ///   when it was created with `keep_record`
+    /// - Rent returns to the escrow's rent recipient, passed as
+    ///   `rent_recipient` when that is not the seller
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    /// - `memo` is logged through the SPL Memo program alongside the
+    ///   refund, as in `accept_escrow`
+    pub fn cancel_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelEscrow<'info>>,
+        memo: Option<String>,
+    ) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
         let offer_amount = escrow.offer_amount;
+        let request_amount = escrow.request_amount;
+        let market_index = escrow.market_index();
+        let receipt_mint_key = escrow.receipt_mint();
+        let keep_record = escrow.keep_record();
+        let label = escrow.label();
+        // Cancelling a stuck escrow is not the seller's doing
+        let cancelled_open = escrow.status() == EscrowStatus::Open;
+        let delegated = escrow.delegated_source().is_some();
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+        // A partly filled escrow stays listed for the config's minimum
+        if cancelled_open && escrow.fill_count() > 0 {
+            let config = ctx
+                .accounts
+                .config
+                .as_ref()
+                .ok_or(EscrowError::MissingConfigAccount)?;
+            require!(
+                !escrow.cancel_too_early(config.min_listing_duration, Clock::get()?.unix_timestamp),
+                EscrowError::ListingTooRecent
+            );
+        }
+        let bond_penalty = match escrow.cancellation_bond() {
+            Some(bond) if escrow.status() == EscrowStatus::Open => {
+                bond.penalty(Clock::get()?.unix_timestamp)?
+            }
+            _ => 0,
+        };
 
         // Create signer seeds for the escrow PDA
         let seller_key = escrow.seller;
+        let listed_by = escrow.listed_by();
         let offer_mint_key = escrow.offer_mint;
         let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
         let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
 
         let escrow_seeds = &[
             b"escrow",
-            seller_key.as_ref(),
+            listed_by.as_ref(),
             offer_mint_key.as_ref(),
             request_mint_key.as_ref(),
+            escrow_id.as_ref(),
             &[escrow_bump],
         ];
         let signer_seeds = &[&escrow_seeds[..]];
 
-        // Transfer tokens back to seller
-        transfer(
-            CpiContext::new_with_signer(
+        // The position receipt must be surrendered to cancel
+        if let Some(receipt_mint_key) = receipt_mint_key {
+            let receipt_mint = ctx
+                .accounts
+                .receipt_mint
+                .as_ref()
+                .ok_or(EscrowError::MissingReceiptAccount)?;
+            let seller_receipt_token = ctx
+                .accounts
+                .seller_receipt_token
+                .as_ref()
+                .ok_or(EscrowError::MissingReceiptAccount)?;
+            require_keys_eq!(
+                receipt_mint.key(),
+                receipt_mint_key,
+                EscrowError::InvalidReceipt
+            );
+
+            burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: receipt_mint.to_account_info(),
+                        from: seller_receipt_token.to_account_info(),
+                        authority: ctx.accounts.authority.to_account_info(),
+                    },
+                ),
+                1,
+            )?;
+
+            let proceeds_vault = ctx
+                .accounts
+                .proceeds_vault
+                .as_ref()
+                .ok_or(EscrowError::MissingReceiptAccount)?;
+            let request_mint = ctx
+                .accounts
+                .request_mint
+                .as_ref()
+                .ok_or(EscrowError::MissingReceiptAccount)?;
+            let request_token_program = ctx
+                .accounts
+                .request_token_program
+                .as_ref()
+                .ok_or(EscrowError::MissingReceiptAccount)?;
+            require_keys_eq!(
+                request_mint.key(),
+                request_mint_key,
+                EscrowError::InvalidMint
+            );
+            close_token_account(
+                request_token_program.to_account_info(),
+                proceeds_vault.to_account_info(),
+                request_mint.to_account_info(),
+                ctx.accounts.seller.to_account_info(),
+                ctx.accounts.escrow.to_account_info(),
+                signer_seeds,
+            )?;
+        }
+
+        // Transfer tokens back to seller; native SOL instead unwraps when
+        // the vault is closed to the seller below, and a delegated offer
+        // never left the seller
+        if !delegated {
+            require!(
+                ctx.accounts.vault.amount >= offer_amount,
+                EscrowError::VaultShortfall
+            );
+        }
+        if !delegated && !is_native_mint(&offer_mint_key) {
+            let seller_offer_token = ctx
+                .accounts
+                .seller_offer_token
+                .as_ref()
+                .ok_or(EscrowError::MissingOfferTokenAccount)?;
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.vault.to_account_info(),
+                        mint: ctx.accounts.offer_mint.to_account_info(),
+                        to: seller_offer_token.to_account_info(),
+                        authority: ctx.accounts.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                offer_amount,
+                ctx.accounts.offer_mint.decimals,
+            )?;
+        }
+
+        // Close the vault token account and return its rent; a native SOL
+        // vault unwraps to the seller, rent included, and a delegated
+        // escrow's vault is the seller's own account
+        if !delegated {
+            let vault_destination = if is_native_mint(&offer_mint_key) {
+                ctx.accounts.seller.to_account_info()
+            } else {
+                rent_destination.clone()
+            };
+            close_token_account(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.seller_offer_token.to_account_info(),
-                    authority: ctx.accounts.escrow.to_account_info(),
-                },
+                ctx.accounts.vault.to_account_info(),
+                ctx.accounts.offer_mint.to_account_info(),
+                vault_destination,
+                ctx.accounts.escrow.to_account_info(),
                 signer_seeds,
-            ),
-            offer_amount,
+            )?;
+        }
+
+        ctx.accounts
+            .pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        Stats::record(&ctx.accounts.stats, |stats| {
+            stats.escrows_cancelled = stats.escrows_cancelled.saturating_add(1)
+        })?;
+        if cancelled_open {
+            Reputation::record(&ctx.accounts.seller_reputation, |reputation| {
+                reputation.cancellations = reputation.cancellations.saturating_add(1)
+            })?;
+        }
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.deregister(&ctx.accounts.escrow.key())?;
+        }
+        MarketIndex::unlink(
+            ctx.accounts.market_index.as_deref_mut(),
+            market_index,
+            &ctx.accounts.escrow.key(),
         )?;
 
-        // Close the vault token account and return rent to seller
-        close_account(CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            CloseAccount {
-                account: ctx.accounts.vault.to_account_info(),
-                destination: ctx.accounts.seller.to_account_info(),
-                authority: ctx.accounts.escrow.to_account_info(),
-            },
-            signer_seeds,
-        ))?;
+        // Early cancellation forfeits part of the bond before the escrow's
+        // lamports return to the seller
+        if bond_penalty > 0 {
+            let treasury = ctx
+                .accounts
+                .treasury
+                .as_ref()
+                .ok_or(EscrowError::MissingTreasuryAccount)?;
+            **ctx
+                .accounts
+                .escrow
+                .to_account_info()
+                .try_borrow_mut_lamports()? -= bond_penalty;
+            **treasury.try_borrow_mut_lamports()? += bond_penalty;
+        }
+
+        {
+            let mut escrow = ctx.accounts.escrow.load_mut()?;
+            escrow.set_status(EscrowStatus::Cancelled);
+            escrow.set_cancellation_bond(None);
+        }
+        if !keep_record {
+            close_escrow(
+                &ctx.accounts.escrow,
+                ctx.accounts.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
 
         msg!("Escrow cancelled, tokens returned to seller");
+        let event = EscrowCancelled {
+            escrow: ctx.accounts.escrow.key(),
+            seller: seller_key,
+            offer_mint: offer_mint_key,
+            request_mint: request_mint_key,
+            offer_amount,
+            bond_penalty,
+            label,
+        };
+        emit!(event);
+        emit_cpi!(event);
+        if let Some(config) = &ctx.accounts.config {
+            ActivityLog {
+                config,
+                merkle_tree: ctx.accounts.activity_log.as_ref(),
+                log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+                compression_program: ctx.accounts.compression_program.as_ref(),
+            }
+            .append(&ActivityRecord {
+                kind: ActivityKind::Cancelled,
+                escrow: event.escrow,
+                seller: seller_key,
+                buyer: None,
+                offer_amount,
+                request_amount,
+                timestamp: Clock::get()?.unix_timestamp,
+            })?;
+        }
+        if let Some(memo) = &memo {
+            log_memo(ctx.accounts.memo_program.as_deref(), memo)?;
+        }
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct CreateEscrow<'info> {
-    #[account(mut)]
-    pub seller: Signer<'info>,
-
-    pub offer_mint: Box<Account<'info, Mint>>,
-    pub request_mint: Box<Account<'info, Mint>>,
-
-    #[account(
-        mut,
-        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
-        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
-    )]
-    pub seller_offer_token: Box<Account<'info, TokenAccount>>,
-
-    #[account(
-        init,
-        payer = seller,
-        space = 8 + Escrow::INIT_SPACE,
-        seeds = [
-            b"escrow",
-            seller.key().as_ref(),
-            offer_mint.key().as_ref(),
-            request_mint.key().as_ref(),
-        ],
-        bump,
-    )]
-    pub escrow: Box<Account<'info, Escrow>>,
-
-    #[account(
-        init,
-        payer = seller,
-        seeds = [b"vault", escrow.key().as_ref()],
-        bump,
-        token::mint = offer_mint,
-        token::authority = escrow,
-    )]
-    pub vault: Box<Account<'info, TokenAccount>>,
-
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+    /// Add offer tokens to an open escrow
+    /// - `request_amount` sets the new total price, replacing any unit
+    ///   price; without it the price scales with the offer, rounded up in
+    ///   the seller's favour
+    /// - Not allowed while the escrow is committed to a counterparty
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn top_up_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, TopUpEscrow<'info>>,
+        amount: u64,
+        request_amount: Option<u64>,
+    ) -> Result<()> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(
+            escrow.status() == EscrowStatus::Open && !escrow.in_settlement(),
+            EscrowError::EscrowNotOpen
+        );
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(!escrow.nft(), EscrowError::NftEscrow);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+        drop(escrow);
+
+        let vault_before = ctx.accounts.vault.amount;
+        Deposit {
+            seller: ctx.accounts.authority.to_account_info(),
+            seller_offer_token: ctx
+                .accounts
+                .seller_offer_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            vault: ctx.accounts.vault.to_account_info(),
+            offer_mint: &ctx.accounts.offer_mint,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .deposit(amount)?;
+
+        // Only what reaches the vault after transfer fees is added
+        ctx.accounts.vault.reload()?;
+        let added = ctx
+            .accounts
+            .vault
+            .amount
+            .checked_sub(vault_before)
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(added > 0, EscrowError::TransferFeeTooHigh);
+
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        let offer_amount = escrow
+            .offer_amount
+            .checked_add(added)
+            .ok_or(EscrowError::MathOverflow)?;
+        let request_amount = match request_amount {
+            Some(request_amount) => {
+                escrow.set_unit_price(None);
+                request_amount
+            }
+            None => escrow.scaled_request(offer_amount)?,
+        };
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            offer_amount,
+            request_amount,
+        )?;
+        escrow.offer_amount = offer_amount;
+        escrow.request_amount = request_amount;
+
+        msg!(
+            "Escrow topped up: {} tokens offered for {} tokens requested",
+            offer_amount,
+            request_amount
+        );
 
-#[derive(Accounts)]
-pub struct AcceptEscrow<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
+        Ok(())
+    }
 
-    /// CHECK: Validated via escrow.seller constraint
-    #[account(mut, address = escrow.seller @ EscrowError::Unauthorized)]
-    pub seller: AccountInfo<'info>,
+    /// Make an escrow a standing offer at its current size and price, or
+    /// stop it recurring
+    /// - A recurring escrow stays open once fully filled, for `replenish`
+    ///   to top it back up; cancel it to close it out
+    pub fn set_recurring(ctx: Context<UpdateEscrow>, recurring: bool) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        let target = if recurring {
+            require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+            require!(!escrow.nft(), EscrowError::NftEscrow);
+            require!(
+                escrow.receipt_mint().is_none(),
+                EscrowError::ReceiptOutstanding
+            );
+            Some(RecurringTarget {
+                offer_amount: escrow.offer_amount,
+                request_amount: escrow.request_amount,
+            })
+        } else {
+            None
+        };
+        escrow.set_recurring(target);
 
-    #[account(address = escrow.offer_mint @ EscrowError::InvalidMint)]
-    pub offer_mint: Box<Account<'info, Mint>>,
+        msg!(
+            "Escrow {} recurring: {}",
+            ctx.accounts.escrow.key(),
+            recurring
+        );
 
-    #[account(address = escrow.request_mint @ EscrowError::InvalidMint)]
-    pub request_mint: Box<Account<'info, Mint>>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [
-            b"escrow",
-            escrow.seller.as_ref(),
-            escrow.offer_mint.as_ref(),
-            escrow.request_mint.as_ref(),
-        ],
-        bump = escrow.escrow_bump,
-        close = seller,
-    )]
-    pub escrow: Box<Account<'info, Escrow>>,
-
-    #[account(
-        mut,
-        seeds = [b"vault", escrow.key().as_ref()],
-        bump = escrow.vault_bump,
-    )]
-    pub vault: Box<Account<'info, TokenAccount>>,
-
-    #[account(
-        mut,
-        constraint = buyer_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
-        constraint = buyer_request_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
-    )]
-    pub buyer_request_token: Box<Account<'info, TokenAccount>>,
-
-    #[account(
-        mut,
-        constraint = buyer_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
-        constraint = buyer_offer_token.owner == buyer.key() @ EscrowError::InvalidTokenAccountOwner,
-    )]
-    pub buyer_offer_token: Box<Account<'info, TokenAccount>>,
-
-    #[account(
-        mut,
-        constraint = seller_request_token.mint == request_mint.key() @ EscrowError::InvalidMint,
-        constraint = seller_request_token.owner == escrow.seller @ EscrowError::InvalidTokenAccountOwner,
-    )]
-    pub seller_request_token: Box<Account<'info, TokenAccount>>,
-
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
-}
+    /// Top a recurring escrow back up to its target size at its target
+    /// price, from the seller's offer tokens
+    /// - Deposits enough to cover offer-mint transfer fees
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn replenish<'info>(ctx: Context<'_, '_, '_, 'info, TopUpEscrow<'info>>) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        let target = escrow.recurring().ok_or(EscrowError::NotRecurring)?;
+        require!(
+            escrow.status() == EscrowStatus::Open && !escrow.in_settlement(),
+            EscrowError::EscrowNotOpen
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+        let missing = target.offer_amount.saturating_sub(escrow.offer_amount);
+        require!(missing > 0, EscrowError::NothingToReplenish);
+        drop(escrow);
 
-#[derive(Accounts)]
-pub struct CancelEscrow<'info> {
-    #[account(
-        mut,
-        address = escrow.seller @ EscrowError::Unauthorized,
-    )]
-    pub seller: Signer<'info>,
+        let vault_before = ctx.accounts.vault.amount;
+        Deposit {
+            seller: ctx.accounts.authority.to_account_info(),
+            seller_offer_token: ctx
+                .accounts
+                .seller_offer_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            vault: ctx.accounts.vault.to_account_info(),
+            offer_mint: &ctx.accounts.offer_mint,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .deposit(gross_amount(
+            &ctx.accounts.offer_mint.to_account_info(),
+            missing,
+        )?)?;
 
-    #[account(address = escrow.offer_mint @ EscrowError::InvalidMint)]
-    pub offer_mint: Box<Account<'info, Mint>>,
+        ctx.accounts.vault.reload()?;
+        require!(
+            ctx.accounts
+                .vault
+                .amount
+                .checked_sub(vault_before)
+                .ok_or(EscrowError::MathOverflow)?
+                >= missing,
+            EscrowError::TransferFeeTooHigh
+        );
 
-    #[account(
-        mut,
-        seeds = [
-            b"escrow",
-            escrow.seller.as_ref(),
-            escrow.offer_mint.as_ref(),
-            escrow.request_mint.as_ref(),
-        ],
-        bump = escrow.escrow_bump,
-        close = seller,
-    )]
-    pub escrow: Box<Account<'info, Escrow>>,
-
-    #[account(
-        mut,
-        seeds = [b"vault", escrow.key().as_ref()],
-        bump = escrow.vault_bump,
-    )]
-    pub vault: Box<Account<'info, TokenAccount>>,
-
-    #[account(
-        mut,
-        constraint = seller_offer_token.mint == offer_mint.key() @ EscrowError::InvalidMint,
-        constraint = seller_offer_token.owner == seller.key() @ EscrowError::InvalidTokenAccountOwner,
-    )]
-    pub seller_offer_token: Box<Account<'info, TokenAccount>>,
-
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        escrow.offer_amount = target.offer_amount;
+        escrow.request_amount = target.request_amount;
 
-#[account]
-#[derive(InitSpace)]
-pub struct Escrow {
-    pub seller: Pubkey,
-    pub offer_mint: Pubkey,
-    pub request_mint: Pubkey,
-    pub offer_amount: u64,
-    pub request_amount: u64,
-    pub escrow_bump: u8,
-    pub vault_bump: u8,
-}
+        msg!(
+            "Escrow replenished: {} tokens offered for {} tokens requested",
+            target.offer_amount,
+            target.request_amount
+        );
+
+        Ok(())
+    }
 
-#[error_code]
-pub enum EscrowError {
-    #[msg("Unauthorized: Only the seller can perform this action")]
-    Unauthorized,
-    #[msg("Invalid token mint")]
-    InvalidMint,
-    #[msg("Invalid token account owner")]
-    InvalidTokenAccountOwner,
-    #[msg("Invalid amount: must be greater than zero")]
-    InvalidAmount,
+    /// Pull some offer tokens back out of an open escrow
+    /// - The request amount scales down with the offer, rounded up in the
+    ///   seller's favour; withdrawing everything is a cancel
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn withdraw_from_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawFromEscrow<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(!escrow.nft(), EscrowError::NftEscrow);
+        require!(
+            amount > 0 && amount < escrow.offer_amount,
+            EscrowError::InvalidAmount
+        );
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+
+        let offer_amount = escrow
+            .offer_amount
+            .checked_sub(amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        let request_amount = escrow.scaled_request(offer_amount)?;
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            offer_amount,
+            request_amount,
+        )?;
+
+        // Create signer seeds for the escrow PDA
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
+
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.seller_offer_token.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        escrow.offer_amount = offer_amount;
+        escrow.request_amount = request_amount;
+
+        msg!(
+            "Escrow resized: {} tokens offered for {} tokens requested",
+            offer_amount,
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Replace the terms of an open escrow in place
+    /// - Sets the request amount, expiry and designated buyer; a fixed
+    ///   request amount replaces any unit price
+    /// - Not allowed while a receipt is outstanding or the escrow is locked,
+    ///   since the position's value belongs to someone else then
+    pub fn update_escrow(
+        ctx: Context<UpdateEscrow>,
+        request_amount: u64,
+        expires_at: Option<i64>,
+        designated_buyer: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        ctx.accounts.config.check_expiry(expires_at)?;
+
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            escrow.offer_amount,
+            request_amount,
+        )?;
+        require!(
+            escrow
+                .starts_at()
+                .zip(expires_at)
+                .is_none_or(|(starts_at, expires_at)| starts_at < expires_at),
+            EscrowError::InvalidStartTime
+        );
+
+        escrow.request_amount = request_amount;
+        escrow.set_unit_price(None);
+        escrow.set_expires_at(expires_at);
+        escrow.set_designated_buyer(designated_buyer);
+
+        msg!(
+            "Escrow {} updated: {} tokens requested",
+            ctx.accounts.escrow.key(),
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Gate an escrow on the buyer holding a collection NFT or a minimum
+    /// balance of a mint, or lift the gate with `None`
+    /// - Checked on `accept_escrow` against the buyer's token account and,
+    ///   for collections, its mint's metadata
+    /// - Gated escrows cannot be auctioned or held for arbitration
+    pub fn set_token_gate(ctx: Context<UpdateEscrow>, gate: Option<TokenGate>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        escrow.set_token_gate(gate);
+
+        msg!(
+            "Escrow {} token gate set: {}",
+            ctx.accounts.escrow.key(),
+            gate.is_some()
+        );
+
+        Ok(())
+    }
+
+    /// Require buyers to be approved by an external permission program,
+    /// e.g. a KYC provider, or lift the requirement with `None`
+    /// - The program approves a buyer by owning a non-empty account at its
+    ///   PDA `["approval", account, buyer]`, checked on `accept_escrow`
+    /// - Permissioned escrows cannot be auctioned or held for arbitration
+    pub fn set_permission(
+        ctx: Context<UpdateEscrow>,
+        permission: Option<Permission>,
+    ) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        escrow.set_permission(permission);
+
+        msg!(
+            "Escrow {} permission program: {:?}",
+            ctx.accounts.escrow.key(),
+            permission.map(|permission| permission.program)
+        );
+
+        Ok(())
+    }
+
+    /// Schedule when an escrow opens for fills, or open it immediately with
+    /// `None`
+    /// - Lets a sale be listed in advance; the start must precede any
+    ///   expiry
+    pub fn set_starts_at(ctx: Context<UpdateEscrow>, starts_at: Option<i64>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            starts_at
+                .zip(escrow.expires_at())
+                .is_none_or(|(starts_at, expires_at)| starts_at < expires_at),
+            EscrowError::InvalidStartTime
+        );
+
+        escrow.set_starts_at(starts_at);
+
+        msg!(
+            "Escrow {} opens at {:?}",
+            ctx.accounts.escrow.key(),
+            starts_at
+        );
+
+        Ok(())
+    }
+
+    /// Keep an escrow as an on-chain record once it is filled, cancelled or
+    /// expired, instead of closing it
+    /// - The seller reclaims the record's rent later with `close_record`
+    pub fn set_keep_record(ctx: Context<UpdateEscrow>, keep_record: bool) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        escrow.set_keep_record(keep_record);
+
+        msg!(
+            "Escrow {} keeps its record: {}",
+            ctx.accounts.escrow.key(),
+            keep_record
+        );
+
+        Ok(())
+    }
+
+    /// Lock a lamport bond against cancelling an escrow early
+    /// - Cancelling before `locked_until` forfeits `penalty_bps` of the
+    ///   bond to the treasury; the rest returns to the seller with the
+    ///   escrow's rent whenever it closes
+    /// - Posted once, by the seller
+    pub fn post_cancellation_bond(
+        ctx: Context<PostCancellationBond>,
+        bond: CancellationBond,
+    ) -> Result<()> {
+        require!(
+            bond.lamports > 0
+                && bond.penalty_bps as u64 <= BPS_DENOMINATOR
+                && bond.locked_until > Clock::get()?.unix_timestamp,
+            EscrowError::InvalidCancellationBond
+        );
+        require!(
+            ctx.accounts.escrow.load()?.cancellation_bond().is_none(),
+            EscrowError::BondAlreadyPosted
+        );
+
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.seller.to_account_info(),
+                    to: ctx.accounts.escrow.to_account_info(),
+                },
+            ),
+            bond.lamports,
+        )?;
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_cancellation_bond(Some(bond));
+
+        msg!(
+            "Escrow {} bonded with {} lamports until {}",
+            ctx.accounts.escrow.key(),
+            bond.lamports,
+            bond.locked_until
+        );
+
+        Ok(())
+    }
+
+    /// Offer an open escrow's ownership to `new_seller`, such as a rotated
+    /// key or another trader's desk, without cancelling and relisting it
+    /// - Takes effect once `new_seller` signs `accept_escrow_ownership`;
+    ///   passing the current seller withdraws a pending offer
+    /// - Delegated escrows pull from the seller's own account and cannot
+    ///   change hands
+    pub fn transfer_escrow_ownership(
+        ctx: Context<TransferEscrowOwnership>,
+        new_seller: Pubkey,
+    ) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+        require!(
+            new_seller != Pubkey::default(),
+            EscrowError::InvalidRecipient
+        );
+        let pending_seller = (new_seller != escrow.seller).then_some(new_seller);
+        escrow.set_pending_seller(pending_seller);
+
+        msg!(
+            "Escrow {} ownership pending: {:?}",
+            ctx.accounts.escrow.key(),
+            pending_seller
+        );
+
+        Ok(())
+    }
+
+    /// Take over an escrow whose ownership was offered by
+    /// `transfer_escrow_ownership`
+    /// - The vault and the escrow's address stay put; its proceeds, rent
+    ///   and cancellation go to the new seller from here on
+    /// - A proceeds recipient the outgoing seller set is cleared, and
+    ///   their seller index no longer counts the escrow as open
+    /// - The new seller must be able to list it themselves: not banned,
+    ///   within the open escrow limit and on the market's seller allowlist
+    pub fn accept_escrow_ownership(ctx: Context<AcceptEscrowOwnership>) -> Result<()> {
+        let new_seller = ctx.accounts.new_seller.key();
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+        if let Some(market_key) = escrow.market() {
+            let market = ctx
+                .accounts
+                .market
+                .as_ref()
+                .ok_or(EscrowError::MissingMarketAccount)?;
+            require_keys_eq!(market.key(), market_key, EscrowError::InvalidMarket);
+            market.check_listing(&new_seller, &escrow.offer_mint, &escrow.request_mint)?;
+        }
+
+        let previous_seller = escrow.seller;
+        escrow.transfer_ownership(new_seller);
+
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        let new_seller_index = &mut ctx.accounts.new_seller_index;
+        new_seller_index.seller = new_seller;
+        new_seller_index.bump = ctx.bumps.new_seller_index;
+        new_seller_index.record_open(escrow.escrow_id)?;
+        ctx.accounts
+            .config
+            .check_open_escrows(new_seller_index.open_escrows)?;
+
+        msg!(
+            "Escrow {} transferred from {} to {}",
+            ctx.accounts.escrow.key(),
+            previous_seller,
+            new_seller
+        );
+
+        Ok(())
+    }
+
+    /// Attach a short label to an escrow, e.g. the invoice it settles
+    /// - At most `MAX_LABEL_LEN` bytes of UTF-8; an empty label clears it
+    /// - Carried on the escrow's fill, cancel and expiry events
+    pub fn set_label(ctx: Context<UpdateEscrow>, label: String) -> Result<()> {
+        require!(label.len() <= MAX_LABEL_LEN, EscrowError::LabelTooLong);
+        ctx.accounts.escrow.load_mut()?.set_label(&label);
+
+        msg!("Escrow {} labelled: {}", ctx.accounts.escrow.key(), label);
+
+        Ok(())
+    }
+
+    /// Point an escrow at an off-chain JSON document describing the deal
+    /// terms for frontends to display, or clear it with `None`
+    /// - At most `MAX_METADATA_URI_LEN` bytes
+    pub fn set_metadata_uri(
+        ctx: Context<UpdateEscrow>,
+        metadata_uri: Option<String>,
+    ) -> Result<()> {
+        require!(
+            metadata_uri
+                .as_ref()
+                .is_none_or(|uri| uri.len() <= MAX_METADATA_URI_LEN),
+            EscrowError::MetadataUriTooLong
+        );
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_metadata_uri(metadata_uri.as_deref());
+
+        msg!(
+            "Escrow {} metadata URI: {:?}",
+            ctx.accounts.escrow.key(),
+            metadata_uri
+        );
+
+        Ok(())
+    }
+
+    /// Replace the extra mints an escrow requests on top of its request mint
+    /// - Each leg is paid in full to the seller, so escrows with legs only
+    ///   fill in full
+    /// - Not allowed while a receipt is outstanding or the escrow is locked
+    pub fn set_request_legs(ctx: Context<UpdateEscrow>, legs: Vec<RequestLeg>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            legs.len() <= MAX_REQUEST_LEGS,
+            EscrowError::InvalidRequestLegs
+        );
+        for (i, leg) in legs.iter().enumerate() {
+            require!(leg.amount > 0, EscrowError::InvalidRequestLegs);
+            require!(
+                leg.mint != escrow.request_mint
+                    && legs[..i].iter().all(|other| other.mint != leg.mint),
+                EscrowError::InvalidRequestLegs
+            );
+        }
+
+        escrow.set_request_legs(&legs);
+
+        msg!(
+            "Escrow {} now requests {} extra mints",
+            ctx.accounts.escrow.key(),
+            escrow.request_legs().len()
+        );
+
+        Ok(())
+    }
+
+    /// Request `lamports` of SOL on top of the request tokens, or none
+    /// with 0
+    /// - The lamports are paid to the seller's wallet in full, so such
+    ///   escrows only fill in full
+    /// - Not allowed while a receipt is outstanding or the escrow is locked
+    pub fn set_request_lamports(ctx: Context<UpdateEscrow>, lamports: u64) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        escrow.set_request_lamports(lamports);
+
+        msg!(
+            "Escrow {} now requests {} lamports",
+            ctx.accounts.escrow.key(),
+            lamports
+        );
+
+        Ok(())
+    }
+
+    /// Price an escrow in USD off a Pyth or Switchboard feed for the
+    /// request mint, or return it to its fixed `request_amount` with `None`
+    /// - Each accept re-quotes the remaining offer from a price no older
+    ///   than `max_age` seconds whose confidence interval is within
+    ///   `max_confidence_bps` of the price
+    /// - A `usd_total` price is for the whole offer as it stands now, and
+    ///   fills pay their share of it
+    /// - Quotes outside `min_request_amount`..`max_request_amount` for the
+    ///   current offer, scaled to what remains, are refused
+    /// - `use_ema` settles Pyth-priced escrows against the feed's EMA price
+    ///   so a single manipulated slot cannot move the fill
+    pub fn set_oracle_pricing(
+        ctx: Context<UpdateEscrow>,
+        pricing: Option<OraclePricing>,
+    ) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        if let Some(pricing) = &pricing {
+            require!(
+                pricing.usd_price > 0
+                    && pricing.max_age > 0
+                    && pricing.max_confidence_bps <= 10_000
+                    && (pricing.max_request_amount == 0
+                        || pricing.min_request_amount <= pricing.max_request_amount),
+                EscrowError::InvalidAmount
+            );
+            require!(
+                escrow.unit_price().is_none(),
+                EscrowError::ConflictingPricing
+            );
+            require!(
+                !pricing.use_ema || matches!(pricing.oracle, OracleKind::Pyth { .. }),
+                EscrowError::InvalidOracle
+            );
+        }
+
+        escrow.set_oracle_pricing(pricing);
+
+        msg!(
+            "Escrow {} oracle pricing {}",
+            ctx.accounts.escrow.key(),
+            if pricing.is_some() { "set" } else { "cleared" }
+        );
+
+        Ok(())
+    }
+
+    /// Price an escrow per unit of offer so it fills like a limit order, or
+    /// keep its current total price with `None`
+    /// - Each fill costs `fill_amount * price / unit`, rounded up in the
+    ///   seller's favour
+    /// - Fills below `min_fill_amount` are rejected unless they take the
+    ///   whole remainder
+    pub fn set_unit_price(ctx: Context<UpdateEscrow>, unit_price: Option<UnitPrice>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        if let Some(unit_price) = &unit_price {
+            require!(
+                unit_price.price > 0 && unit_price.unit > 0,
+                EscrowError::InvalidAmount
+            );
+            require!(
+                escrow.oracle_pricing().is_none(),
+                EscrowError::ConflictingPricing
+            );
+            let request_amount = unit_price.cost(escrow.offer_amount)?;
+            require!(request_amount > 0, EscrowError::InvalidAmount);
+            MintLimits::check(
+                &ctx.accounts.offer_mint_limits,
+                &ctx.accounts.request_mint_limits,
+                escrow.offer_amount,
+                request_amount,
+            )?;
+            escrow.request_amount = request_amount;
+        }
+
+        escrow.set_unit_price(unit_price);
+
+        msg!(
+            "Escrow {} now requests {} tokens",
+            ctx.accounts.escrow.key(),
+            escrow.request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Propose a different price for the whole of an open escrow
+    /// - Locks `deposit` request tokens, at least `request_amount`, to
+    ///   cover the countered price and any taker fees
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn create_counter_offer<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateCounterOffer<'info>>,
+        request_amount: u64,
+        deposit: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        // Extra legs would be paid from the buyer's own accounts
+        require!(!escrow.has_extra_payments(), EscrowError::HasRequestLegs);
+        require!(escrow.arbitration().is_none(), EscrowError::HeldSettlement);
+        require!(escrow.vesting().is_none(), EscrowError::VestedEscrow);
+        require!(
+            escrow.proceeds_stream().is_none(),
+            EscrowError::StreamedEscrow
+        );
+        require!(!escrow.is_gated(), EscrowError::GatedEscrow);
+        require!(
+            request_amount > 0 && deposit >= request_amount,
+            EscrowError::InvalidAmount
+        );
+
+        let counter = &mut ctx.accounts.counter_offer;
+        counter.escrow = ctx.accounts.escrow.key();
+        counter.seller = escrow.seller;
+        counter.buyer = ctx.accounts.buyer.key();
+        counter.request_mint = escrow.request_mint;
+        counter.offer_amount = escrow.offer_amount;
+        counter.request_amount = request_amount;
+        counter.bump = ctx.bumps.counter_offer;
+        counter.vault_bump = ctx.bumps.counter_vault;
+        drop(escrow);
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.buyer_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.counter_vault.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            deposit,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        msg!(
+            "Counter offer on escrow {}: {} tokens requested",
+            ctx.accounts.escrow.key(),
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Accept a counter offer, settling the whole escrow at its price
+    /// - Pays from the counter deposit and returns what is left of it to
+    ///   the buyer
+    /// - Remaining accounts supply creator royalty accounts as laid out in
+    ///   `Fill`, then transfer-hook accounts
+    pub fn accept_counter_offer<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptCounterOffer<'info>>,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        let counter = &accounts.counter_offer;
+        // The counter was priced against the escrow as it stood
+        let mut escrow = accounts.escrow.load_mut()?;
+        require!(
+            escrow.offer_amount == counter.offer_amount,
+            EscrowError::CounterOfferStale
+        );
+        let fill_amount = counter.offer_amount;
+        let escrow_key = counter.escrow;
+        let buyer_key = counter.buyer;
+        let counter_bump = counter.bump;
+        escrow.request_amount = counter.request_amount;
+        escrow.set_unit_price(None);
+        drop(escrow);
+
+        let counter_seeds = &[
+            b"counter_offer",
+            escrow_key.as_ref(),
+            buyer_key.as_ref(),
+            &[counter_bump],
+        ];
+        let payer_seeds = &[&counter_seeds[..]];
+        let counter_info = accounts.counter_offer.to_account_info();
+
+        let event = Fill {
+            escrow: &accounts.escrow,
+            vault: &accounts.vault,
+            seller: accounts.seller.to_account_info(),
+            seller_request_token: accounts
+                .seller_request_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            proceeds_recipient_token: None,
+            proceeds_vault: accounts.proceeds_vault.as_deref(),
+            market: accounts.market.as_deref(),
+            market_fee_token: accounts.market_fee_token.as_deref(),
+            pair_fees: accounts
+                .pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .buyer_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.seller_index.to_account_info(),
+            seller_reputation: accounts.seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            taker_fee_vault: accounts.taker_fee_vault.as_deref(),
+            referrer_token: None,
+            offer_metadata: accounts.offer_metadata.as_deref(),
+            buyer_allowlist: accounts.buyer_allowlist.as_deref(),
+            gate_token: None,
+            gate_metadata: None,
+            discount_token: None,
+            discount_metadata: None,
+            permission_approval: None,
+            fill_commitment: None,
+            taker: buyer_key,
+            payer_token: Some(accounts.counter_vault.to_account_info()),
+            payer_authority: counter_info.clone(),
+            offer_destination: accounts.buyer_offer_token.to_account_info(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            // Counter offers always fill in full
+            offer_mint_limits: None,
+            request_mint_limits: None,
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(&mut accounts.pair_index, fill_amount, payer_seeds)?;
+
+        // Return the unused deposit, then close the counter vault
+        accounts.counter_vault.reload()?;
+        let leftover = accounts.counter_vault.amount;
+        if leftover > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.counter_vault.to_account_info(),
+                        mint: accounts.request_mint.to_account_info(),
+                        to: accounts.buyer_request_token.to_account_info(),
+                        authority: counter_info.clone(),
+                    },
+                    payer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                leftover,
+                accounts.request_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            accounts.request_token_program.to_account_info(),
+            accounts.counter_vault.to_account_info(),
+            accounts.request_mint.to_account_info(),
+            accounts.buyer.to_account_info(),
+            counter_info,
+            payer_seeds,
+        )?;
+
+        msg!("Counter offer accepted at {} tokens", event.request_amount);
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Reject a counter offer on one of the seller's escrows, returning the
+    /// deposit to the buyer
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn reject_counter_offer<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloseCounterOffer<'info>>,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            ctx.accounts.counter_offer.seller,
+            EscrowError::Unauthorized
+        );
+        ctx.accounts.refund(ctx.remaining_accounts)?;
+
+        msg!("Counter offer rejected");
+
+        Ok(())
+    }
+
+    /// Withdraw one's own counter offer and its deposit
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn withdraw_counter_offer<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloseCounterOffer<'info>>,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            ctx.accounts.counter_offer.buyer,
+            EscrowError::Unauthorized
+        );
+        ctx.accounts.refund(ctx.remaining_accounts)?;
+
+        msg!("Counter offer withdrawn");
+
+        Ok(())
+    }
+
+    /// Post a bid for `offer_amount` of the offer mint, locking
+    /// `request_amount` request tokens to pay for it
+    /// - Any holder of the offer mint may fill the bid with `accept_bid`
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn create_bid<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateBidEscrow<'info>>,
+        bid_id: u64,
+        offer_amount: u64,
+        request_amount: u64,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(
+            offer_amount > 0 && request_amount > 0,
+            EscrowError::InvalidAmount
+        );
+        ctx.accounts.config.check_expiry(expires_at)?;
+        MintPolicy::check_listing(&ctx.accounts.offer_mint_policy, &ctx.accounts.config)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, &ctx.accounts.config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            offer_amount,
+            request_amount,
+        )?;
+
+        let bid = &mut ctx.accounts.bid;
+        bid.buyer = ctx.accounts.buyer.key();
+        bid.offer_mint = ctx.accounts.offer_mint.key();
+        bid.request_mint = ctx.accounts.request_mint.key();
+        bid.bid_id = bid_id;
+        bid.offer_amount = offer_amount;
+        bid.request_amount = request_amount;
+        bid.expires_at = expires_at;
+        bid.bump = ctx.bumps.bid;
+        bid.vault_bump = ctx.bumps.bid_vault;
+
+        // Lock the full price net of any transfer fee
+        let request_mint = ctx.accounts.request_mint.to_account_info();
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.buyer_request_token.to_account_info(),
+                    mint: request_mint.clone(),
+                    to: ctx.accounts.bid_vault.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            gross_amount(&request_mint, request_amount)?,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        msg!(
+            "Bid {} for {} tokens at {} tokens",
+            ctx.accounts.bid.key(),
+            offer_amount,
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Fill `fill_amount` of a bid by delivering offer tokens to its buyer
+    /// - Pays the bid's price pro rata, rounded down, out of its vault
+    /// - Protocol fees follow the config's fee schedule: the maker's fee,
+    ///   shared out among the fee splits, comes out of the seller's
+    ///   proceeds or the delivered offer as the fee currency says, and the
+    ///   seller pays the taker's fee, less any collection discount, out of
+    ///   their proceeds
+    /// - The bid closes to its buyer once fully filled
+    /// - Remaining accounts start with one account per fee split, the
+    ///   recipient's token account of the fee's mint, followed by
+    ///   transfer-hook accounts for the offer and request mints
+    pub fn accept_bid<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptBidEscrow<'info>>,
+        fill_amount: u64,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        require!(!accounts.config.paused, EscrowError::ProtocolPaused);
+        SellerBan::check_listing(&accounts.seller_ban)?;
+        let bid = &accounts.bid;
+        if let Some(expires_at) = bid.expires_at {
+            require!(
+                Clock::get()?.unix_timestamp < expires_at,
+                EscrowError::EscrowExpired
+            );
+        }
+        require!(
+            fill_amount > 0 && fill_amount <= bid.offer_amount,
+            EscrowError::InvalidAmount
+        );
+        let payment = if fill_amount == bid.offer_amount {
+            bid.request_amount
+        } else {
+            u64::try_from(
+                (bid.request_amount as u128)
+                    .checked_mul(fill_amount as u128)
+                    .ok_or(EscrowError::MathOverflow)?
+                    / bid.offer_amount as u128,
+            )
+            .map_err(|_| error!(EscrowError::MathOverflow))?
+        };
+        require!(payment > 0, EscrowError::FillBelowMinimum);
+
+        // The bid's buyer made the market, so the seller filling it is the
+        // taker
+        let config = &accounts.config;
+        let fee_on_offer = config.fee_currency == FeeCurrency::Offer;
+        let (fee_base, fee_mint_key) = if fee_on_offer {
+            (fill_amount, bid.offer_mint)
+        } else {
+            (payment, bid.request_mint)
+        };
+        let protocol_fee = bps_of(fee_base, config.protocol_fee_bps)?;
+        let taker_discount = config.taker_discount(
+            &accounts.seller.key(),
+            accounts.discount_token.as_deref().map(|token| &**token),
+            accounts
+                .discount_metadata
+                .as_deref()
+                .map(|metadata| &**metadata),
+        )?;
+        let protocol_taker_fee = bps_of(
+            payment,
+            discount_rate(config.protocol_taker_fee_bps, taker_discount),
+        )?;
+        let (split_payments, treasury_fee) = config.split_fee(protocol_fee)?;
+
+        let split_accounts = ctx
+            .remaining_accounts
+            .get(..split_payments.len())
+            .ok_or(EscrowError::MissingFeeSplitAccount)?;
+        let mut fee_payments = Vec::with_capacity(split_payments.len() + 1);
+        for ((recipient, amount), destination) in split_payments.into_iter().zip(split_accounts) {
+            let token = TokenAccount::try_deserialize(&mut &destination.data.borrow()[..])?;
+            require_keys_eq!(token.mint, fee_mint_key, EscrowError::InvalidMint);
+            require_keys_eq!(
+                token.owner,
+                recipient,
+                EscrowError::InvalidTokenAccountOwner
+            );
+            fee_payments.push((destination.clone(), amount));
+        }
+        if treasury_fee > 0 {
+            let fee_vault = accounts
+                .fee_vault
+                .as_ref()
+                .ok_or(EscrowError::MissingTreasuryAccount)?;
+            require_keys_eq!(fee_vault.mint, fee_mint_key, EscrowError::InvalidMint);
+            require_keys_eq!(
+                fee_vault.owner,
+                config.key(),
+                EscrowError::InvalidTokenAccountOwner
+            );
+            fee_payments.push((fee_vault.to_account_info(), treasury_fee));
+        }
+
+        // The buyer receives the fill, less a maker's fee taken in the offer
+        // mint, net of any transfer fee
+        let delivered_amount = if fee_on_offer {
+            fill_amount
+                .checked_sub(protocol_fee)
+                .ok_or(EscrowError::MathOverflow)?
+        } else {
+            fill_amount
+        };
+        let seller_proceeds = payment
+            .checked_sub(if fee_on_offer { 0 } else { protocol_fee })
+            .and_then(|proceeds| proceeds.checked_sub(protocol_taker_fee))
+            .ok_or(EscrowError::MathOverflow)?;
+        let mut offer_payments = vec![(
+            accounts.buyer_offer_token.to_account_info(),
+            delivered_amount,
+        )];
+        let mut request_payments = vec![(
+            accounts.seller_request_token.to_account_info(),
+            seller_proceeds,
+        )];
+        if fee_on_offer {
+            offer_payments.extend(fee_payments);
+        } else {
+            request_payments.extend(fee_payments);
+        }
+        if protocol_taker_fee > 0 {
+            let taker_fee_vault = if fee_on_offer {
+                accounts.taker_fee_vault.as_ref()
+            } else {
+                accounts.fee_vault.as_ref()
+            }
+            .ok_or(EscrowError::MissingTreasuryAccount)?;
+            require_keys_eq!(
+                taker_fee_vault.mint,
+                bid.request_mint,
+                EscrowError::InvalidMint
+            );
+            require_keys_eq!(
+                taker_fee_vault.owner,
+                config.key(),
+                EscrowError::InvalidTokenAccountOwner
+            );
+            request_payments.push((taker_fee_vault.to_account_info(), protocol_taker_fee));
+        }
+
+        let offer_mint = accounts.offer_mint.to_account_info();
+        for (to, amount) in offer_payments {
+            if amount == 0 {
+                continue;
+            }
+            transfer_checked_with_hook(
+                CpiContext::new(
+                    accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.seller_offer_token.to_account_info(),
+                        mint: offer_mint.clone(),
+                        to,
+                        authority: accounts.seller.to_account_info(),
+                    },
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                gross_amount(&offer_mint, amount)?,
+                accounts.offer_mint.decimals,
+            )?;
+        }
+
+        let bid_seeds = &[
+            b"bid_escrow",
+            bid.buyer.as_ref(),
+            bid.offer_mint.as_ref(),
+            bid.request_mint.as_ref(),
+            &bid.bid_id.to_le_bytes(),
+            &[bid.bump],
+        ];
+        let signer_seeds = &[&bid_seeds[..]];
+        for (to, amount) in request_payments {
+            if amount == 0 {
+                continue;
+            }
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.bid_vault.to_account_info(),
+                        mint: accounts.request_mint.to_account_info(),
+                        to,
+                        authority: bid.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                amount,
+                accounts.request_mint.decimals,
+            )?;
+        }
+
+        let remaining_offer_amount = bid
+            .offer_amount
+            .checked_sub(fill_amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        let event = BidAccepted {
+            bid: bid.key(),
+            buyer: bid.buyer,
+            seller: accounts.seller.key(),
+            offer_mint: bid.offer_mint,
+            request_mint: bid.request_mint,
+            offer_amount: fill_amount,
+            request_amount: payment,
+            protocol_fee,
+            protocol_taker_fee,
+            remaining_offer_amount,
+        };
+        if remaining_offer_amount == 0 {
+            close_token_account(
+                accounts.request_token_program.to_account_info(),
+                accounts.bid_vault.to_account_info(),
+                accounts.request_mint.to_account_info(),
+                accounts.buyer.to_account_info(),
+                bid.to_account_info(),
+                signer_seeds,
+            )?;
+            accounts.bid.close(accounts.buyer.to_account_info())?;
+        } else {
+            let bid = &mut accounts.bid;
+            bid.offer_amount = remaining_offer_amount;
+            bid.request_amount = bid
+                .request_amount
+                .checked_sub(payment)
+                .ok_or(EscrowError::MathOverflow)?;
+        }
+
+        msg!(
+            "Bid filled: {} tokens for {} tokens",
+            event.offer_amount,
+            event.request_amount
+        );
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Cancel one's own bid, returning what is left of its deposit
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn cancel_bid<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelBidEscrow<'info>>,
+    ) -> Result<()> {
+        let bid = &ctx.accounts.bid;
+        let bid_seeds = &[
+            b"bid_escrow",
+            bid.buyer.as_ref(),
+            bid.offer_mint.as_ref(),
+            bid.request_mint.as_ref(),
+            &bid.bid_id.to_le_bytes(),
+            &[bid.bump],
+        ];
+        let signer_seeds = &[&bid_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.bid_vault.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.buyer_request_token.to_account_info(),
+                    authority: bid.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            ctx.accounts.bid_vault.amount,
+            ctx.accounts.request_mint.decimals,
+        )?;
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.bid_vault.to_account_info(),
+            ctx.accounts.request_mint.to_account_info(),
+            ctx.accounts.buyer.to_account_info(),
+            bid.to_account_info(),
+            signer_seeds,
+        )?;
+
+        msg!("Bid {} cancelled", bid.key());
+
+        Ok(())
+    }
+
+    /// Ask sellers to quote for `offer_amount` of the offer mint until
+    /// `deadline`
+    pub fn create_rfq(
+        ctx: Context<CreateRfq>,
+        rfq_id: u64,
+        offer_amount: u64,
+        deadline: i64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(offer_amount > 0, EscrowError::InvalidAmount);
+        ctx.accounts.config.check_expiry(Some(deadline))?;
+        MintPolicy::check_listing(&ctx.accounts.offer_mint_policy, &ctx.accounts.config)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, &ctx.accounts.config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+
+        let rfq = &mut ctx.accounts.rfq;
+        rfq.buyer = ctx.accounts.buyer.key();
+        rfq.offer_mint = ctx.accounts.offer_mint.key();
+        rfq.request_mint = ctx.accounts.request_mint.key();
+        rfq.rfq_id = rfq_id;
+        rfq.offer_amount = offer_amount;
+        rfq.deadline = deadline;
+        rfq.accepted_quote = None;
+        rfq.quote_count = 0;
+        rfq.bump = ctx.bumps.rfq;
+
+        msg!(
+            "RFQ {} for {} tokens until {}",
+            rfq.key(),
+            offer_amount,
+            deadline
+        );
+
+        Ok(())
+    }
+
+    /// Quote `request_amount` for an open RFQ, locking its full
+    /// `offer_amount` in the quote's vault
+    /// - Remaining accounts supply transfer-hook accounts for the offer
+    ///   mint
+    pub fn submit_quote<'info>(
+        ctx: Context<'_, '_, '_, 'info, SubmitQuote<'info>>,
+        request_amount: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        MintPolicy::check_listing(&ctx.accounts.offer_mint_policy, &ctx.accounts.config)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, &ctx.accounts.config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+        MintLimits::check(
+            &ctx.accounts.offer_mint_limits,
+            &ctx.accounts.request_mint_limits,
+            ctx.accounts.rfq.offer_amount,
+            request_amount,
+        )?;
+        let rfq = &mut ctx.accounts.rfq;
+        require!(
+            rfq.is_open(Clock::get()?.unix_timestamp),
+            EscrowError::RfqClosed
+        );
+        rfq.quote_count = rfq
+            .quote_count
+            .checked_add(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        let quote = &mut ctx.accounts.quote;
+        quote.rfq = rfq.key();
+        quote.seller = ctx.accounts.seller.key();
+        quote.request_amount = request_amount;
+        quote.bump = ctx.bumps.quote;
+        quote.vault_bump = ctx.bumps.quote_vault;
+
+        // Lock the full size net of any transfer fee
+        let offer_mint = ctx.accounts.offer_mint.to_account_info();
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.seller_offer_token.to_account_info(),
+                    mint: offer_mint.clone(),
+                    to: ctx.accounts.quote_vault.to_account_info(),
+                    authority: ctx.accounts.seller.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            gross_amount(&offer_mint, rfq.offer_amount)?,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+
+        msg!("Quote on RFQ {}: {} tokens", rfq.key(), request_amount);
+
+        Ok(())
+    }
+
+    /// Take a quote on one's own RFQ before its deadline, paying its price
+    /// to the seller and receiving its locked offer
+    /// - The RFQ closes for quotes, and the other quotes may then be
+    ///   released back to their sellers by anyone with `release_quote`
+    /// - Remaining accounts supply transfer-hook accounts for the offer
+    ///   mint
+    pub fn accept_quote<'info>(ctx: Context<'_, '_, '_, 'info, AcceptQuote<'info>>) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        require!(!accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(
+            accounts.rfq.is_open(Clock::get()?.unix_timestamp),
+            EscrowError::RfqClosed
+        );
+
+        let request_amount = accounts.quote.request_amount;
+        ListingPayment {
+            config: &accounts.config,
+            buyer: accounts.buyer.to_account_info(),
+            buyer_request_token: accounts.buyer_request_token.to_account_info(),
+            seller_request_token: accounts.seller_request_token.to_account_info(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            request_mint: &accounts.request_mint,
+            token_program: accounts.request_token_program.to_account_info(),
+        }
+        .pay(request_amount)?;
+
+        let offer_amount = accounts.quote_vault.amount;
+        QuoteRelease {
+            quote: &accounts.quote,
+            quote_vault: &accounts.quote_vault,
+            offer_mint: &accounts.offer_mint,
+            seller: accounts.seller.to_account_info(),
+            token_program: accounts.token_program.to_account_info(),
+        }
+        .release(
+            accounts.buyer_offer_token.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let rfq = &mut accounts.rfq;
+        rfq.accepted_quote = Some(accounts.quote.key());
+        rfq.quote_count = rfq
+            .quote_count
+            .checked_sub(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!(
+            "RFQ {} filled: {} tokens for {} tokens",
+            rfq.key(),
+            offer_amount,
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Return a quote's locked offer to its seller
+    /// - The seller may withdraw their quote at any time; anyone may
+    ///   release it once the RFQ has taken another quote or passed its
+    ///   deadline
+    /// - Remaining accounts supply transfer-hook accounts for the offer
+    ///   mint
+    pub fn release_quote<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseQuote<'info>>,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        if accounts.authority.key() != accounts.quote.seller {
+            require!(
+                !accounts.rfq.is_open(Clock::get()?.unix_timestamp),
+                EscrowError::RfqOpen
+            );
+        }
+
+        QuoteRelease {
+            quote: &accounts.quote,
+            quote_vault: &accounts.quote_vault,
+            offer_mint: &accounts.offer_mint,
+            seller: accounts.seller.to_account_info(),
+            token_program: accounts.token_program.to_account_info(),
+        }
+        .release(
+            accounts.seller_offer_token.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+        accounts.rfq.quote_count = accounts
+            .rfq
+            .quote_count
+            .checked_sub(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!("Quote on RFQ {} released", accounts.rfq.key());
+
+        Ok(())
+    }
+
+    /// Close one's own RFQ once it has taken a quote or passed its
+    /// deadline and every other quote has been released
+    pub fn close_rfq(ctx: Context<CloseRfq>) -> Result<()> {
+        let rfq = &ctx.accounts.rfq;
+        require!(
+            !rfq.is_open(Clock::get()?.unix_timestamp),
+            EscrowError::RfqOpen
+        );
+        require!(rfq.quote_count == 0, EscrowError::RfqHasQuotes);
+
+        msg!("RFQ {} closed", rfq.key());
+
+        Ok(())
+    }
+
+    /// Sell the whole of an escrow by sealed-bid auction
+    /// - Bids are committed until `commit_ends_at` and revealed until
+    ///   `reveal_ends_at`; the highest revealed bid at or above
+    ///   `reserve_price` wins
+    /// - The escrow is locked by this program until the auction settles or
+    ///   is cancelled
+    pub fn create_auction(
+        ctx: Context<CreateAuction>,
+        reserve_price: u64,
+        commit_ends_at: i64,
+        reveal_ends_at: i64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(reserve_price > 0, EscrowError::InvalidAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(
+            now < commit_ends_at
+                && commit_ends_at < reveal_ends_at
+                && escrow
+                    .expires_at()
+                    .is_none_or(|expires_at| reveal_ends_at < expires_at),
+            EscrowError::InvalidAuctionSchedule
+        );
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        // The winner pays from their bid vault alone
+        require!(!escrow.has_extra_payments(), EscrowError::HasRequestLegs);
+        require!(escrow.arbitration().is_none(), EscrowError::HeldSettlement);
+        require!(escrow.vesting().is_none(), EscrowError::VestedEscrow);
+        require!(
+            escrow.proceeds_stream().is_none(),
+            EscrowError::StreamedEscrow
+        );
+        require!(!escrow.is_gated(), EscrowError::GatedEscrow);
+        escrow.set_locked_by(Some(crate::ID));
+
+        let auction = &mut ctx.accounts.auction;
+        auction.escrow = ctx.accounts.escrow.key();
+        auction.seller = escrow.seller;
+        auction.request_mint = escrow.request_mint;
+        auction.reserve_price = reserve_price;
+        auction.commit_ends_at = commit_ends_at;
+        auction.reveal_ends_at = reveal_ends_at;
+        auction.bid_count = 0;
+        auction.highest_bid = 0;
+        auction.highest_bidder = None;
+        auction.bump = ctx.bumps.auction;
+
+        msg!(
+            "Auction opened on escrow {}: reserve {}",
+            ctx.accounts.escrow.key(),
+            reserve_price
+        );
+
+        Ok(())
+    }
+
+    /// Commit a sealed bid with a deposit covering it
+    /// - `commitment` is `sha256(price as u64 LE || salt)`
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn commit_bid<'info>(
+        ctx: Context<'_, '_, '_, 'info, CommitBid<'info>>,
+        commitment: [u8; 32],
+        deposit: u64,
+    ) -> Result<()> {
+        require!(
+            Clock::get()?.unix_timestamp < ctx.accounts.auction.commit_ends_at,
+            EscrowError::BiddingClosed
+        );
+        require!(deposit > 0, EscrowError::InvalidAmount);
+        ctx.accounts.auction.bid_count = ctx
+            .accounts
+            .auction
+            .bid_count
+            .checked_add(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        let bid = &mut ctx.accounts.bid;
+        bid.auction = ctx.accounts.auction.key();
+        bid.bidder = ctx.accounts.bidder.key();
+        bid.commitment = commitment;
+        bid.price = None;
+        bid.bump = ctx.bumps.bid;
+        bid.vault_bump = ctx.bumps.bid_vault;
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.bidder_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.bid_vault.to_account_info(),
+                    authority: ctx.accounts.bidder.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            deposit,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        msg!("Bid committed on auction {}", bid.auction);
+
+        Ok(())
+    }
+
+    /// Open a committed bid during the reveal phase
+    /// - The price must be covered by what reached the bid vault
+    pub fn reveal_bid(ctx: Context<RevealBid>, price: u64, salt: [u8; 32]) -> Result<()> {
+        let auction = &mut ctx.accounts.auction;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= auction.commit_ends_at && now < auction.reveal_ends_at,
+            EscrowError::RevealNotOpen
+        );
+
+        let bid = &mut ctx.accounts.bid;
+        require!(bid.price.is_none(), EscrowError::InvalidReveal);
+        require!(
+            hashv(&[&price.to_le_bytes(), &salt]).to_bytes() == bid.commitment,
+            EscrowError::InvalidReveal
+        );
+        require!(
+            price >= auction.reserve_price && price <= ctx.accounts.bid_vault.amount,
+            EscrowError::InvalidReveal
+        );
+        bid.price = Some(price);
+
+        // Ties go to the earlier reveal
+        if price > auction.highest_bid {
+            auction.highest_bid = price;
+            auction.highest_bidder = Some(bid.bidder);
+        }
+
+        msg!("Bid of {} revealed on auction {}", price, auction.key());
+
+        Ok(())
+    }
+
+    /// Settle an auction with its winning bid once reveals are over
+    /// - Pays from the winner's bid vault and returns the rest of their
+    ///   deposit; callable by anyone
+    /// - Remaining accounts supply creator royalty accounts as laid out in
+    ///   `Fill`, then transfer-hook accounts
+    pub fn settle_auction<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleAuction<'info>>,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        let auction = &accounts.auction;
+        require!(
+            Clock::get()?.unix_timestamp >= auction.reveal_ends_at,
+            EscrowError::AuctionNotEnded
+        );
+        check_transaction_guard(
+            &accounts.instructions,
+            &accounts.escrow.key(),
+            &accounts.config.guarded_programs,
+            false,
+        )?;
+        let mut escrow = accounts.escrow.load_mut()?;
+        let fill_amount = escrow.offer_amount;
+        let auction_key = auction.key();
+        let winner_key = accounts.bid.bidder;
+        let bid_bump = accounts.bid.bump;
+        escrow.request_amount = auction.highest_bid;
+        escrow.set_unit_price(None);
+        escrow.set_locked_by(None);
+        drop(escrow);
+
+        let bid_seeds = &[
+            b"bid",
+            auction_key.as_ref(),
+            winner_key.as_ref(),
+            &[bid_bump],
+        ];
+        let payer_seeds = &[&bid_seeds[..]];
+        let bid_info = accounts.bid.to_account_info();
+
+        let event = Fill {
+            escrow: &accounts.escrow,
+            vault: &accounts.vault,
+            seller: accounts.seller.to_account_info(),
+            seller_request_token: accounts
+                .seller_request_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            proceeds_recipient_token: None,
+            proceeds_vault: None,
+            market: accounts.market.as_deref(),
+            market_fee_token: accounts.market_fee_token.as_deref(),
+            pair_fees: accounts
+                .pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .winner_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.seller_index.to_account_info(),
+            seller_reputation: accounts.seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            taker_fee_vault: accounts.taker_fee_vault.as_deref(),
+            referrer_token: None,
+            offer_metadata: accounts.offer_metadata.as_deref(),
+            buyer_allowlist: accounts.buyer_allowlist.as_deref(),
+            gate_token: None,
+            gate_metadata: None,
+            discount_token: None,
+            discount_metadata: None,
+            permission_approval: None,
+            fill_commitment: None,
+            taker: winner_key,
+            payer_token: Some(accounts.bid_vault.to_account_info()),
+            payer_authority: bid_info.clone(),
+            offer_destination: accounts.winner_offer_token.to_account_info(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            // Auctions always fill in full
+            offer_mint_limits: None,
+            request_mint_limits: None,
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(&mut accounts.pair_index, fill_amount, payer_seeds)?;
+
+        // Return the rest of the winner's deposit, then close their vault
+        accounts.bid_vault.reload()?;
+        let leftover = accounts.bid_vault.amount;
+        if leftover > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.bid_vault.to_account_info(),
+                        mint: accounts.request_mint.to_account_info(),
+                        to: accounts.winner_request_token.to_account_info(),
+                        authority: bid_info.clone(),
+                    },
+                    payer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                leftover,
+                accounts.request_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            accounts.request_token_program.to_account_info(),
+            accounts.bid_vault.to_account_info(),
+            accounts.request_mint.to_account_info(),
+            accounts.winner.to_account_info(),
+            bid_info,
+            payer_seeds,
+        )?;
+
+        msg!(
+            "Auction {} settled at {} tokens",
+            auction_key,
+            event.request_amount
+        );
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Return a losing or unrevealed bid's deposit once reveals are over,
+    /// or any bid once its auction is gone
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn refund_bid<'info>(ctx: Context<'_, '_, '_, 'info, RefundBid<'info>>) -> Result<()> {
+        if let Some(auction) = load_if_initialized::<Auction>(&ctx.accounts.auction)? {
+            require!(
+                Clock::get()?.unix_timestamp >= auction.reveal_ends_at,
+                EscrowError::AuctionNotEnded
+            );
+            require!(
+                auction.highest_bidder != Some(ctx.accounts.bidder.key()),
+                EscrowError::WinningBid
+            );
+        }
+
+        let bid = &ctx.accounts.bid;
+        let bid_seeds = &[
+            b"bid",
+            bid.auction.as_ref(),
+            bid.bidder.as_ref(),
+            &[bid.bump],
+        ];
+        let signer_seeds = &[&bid_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.bid_vault.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.bidder_request_token.to_account_info(),
+                    authority: bid.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            ctx.accounts.bid_vault.amount,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.bid_vault.to_account_info(),
+            ctx.accounts.request_mint.to_account_info(),
+            ctx.accounts.bidder.to_account_info(),
+            bid.to_account_info(),
+            signer_seeds,
+        )?;
+
+        msg!("Bid deposit refunded");
+
+        Ok(())
+    }
+
+    /// Close an auction that drew no bids, or no valid bid by the end of
+    /// reveals, and unlock the escrow
+    pub fn cancel_auction(ctx: Context<CancelAuction>) -> Result<()> {
+        let auction = &ctx.accounts.auction;
+        let ended = Clock::get()?.unix_timestamp >= auction.reveal_ends_at;
+        require!(
+            auction.bid_count == 0 || (ended && auction.highest_bidder.is_none()),
+            EscrowError::AuctionHasBids
+        );
+        ctx.accounts.escrow.load_mut()?.set_locked_by(None);
+
+        msg!("Auction {} cancelled", auction.key());
+
+        Ok(())
+    }
+
+    /// Raise the whole of an escrow's request amount jointly from several
+    /// buyers
+    /// - Until `expires_at`, contributions are taken and, once they reach
+    ///   the request amount, anyone may settle the escrow; contributors
+    ///   then claim the offer tokens pro-rata
+    /// - Contributors reclaim their share of a crowdfill left unsettled at
+    ///   `expires_at`
+    /// - The escrow is locked by this program until the crowdfill settles
+    ///   or is closed
+    pub fn create_crowdfill(ctx: Context<CreateCrowdfill>, expires_at: i64) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        let now = Clock::get()?.unix_timestamp;
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(
+            now < expires_at
+                && escrow
+                    .expires_at()
+                    .is_none_or(|escrow_expires_at| expires_at < escrow_expires_at),
+            EscrowError::InvalidCrowdfillExpiry
+        );
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        // The raise pays from its vault alone
+        require!(!escrow.has_extra_payments(), EscrowError::HasRequestLegs);
+        require!(escrow.arbitration().is_none(), EscrowError::HeldSettlement);
+        require!(escrow.vesting().is_none(), EscrowError::VestedEscrow);
+        require!(
+            escrow.proceeds_stream().is_none(),
+            EscrowError::StreamedEscrow
+        );
+        require!(!escrow.is_gated(), EscrowError::GatedEscrow);
+        escrow.set_locked_by(Some(crate::ID));
+
+        let crowdfill = &mut ctx.accounts.crowdfill;
+        crowdfill.escrow = ctx.accounts.escrow.key();
+        crowdfill.seller = escrow.seller;
+        crowdfill.offer_mint = escrow.offer_mint;
+        crowdfill.request_mint = escrow.request_mint;
+        crowdfill.target = escrow.request_amount;
+        crowdfill.raised = 0;
+        crowdfill.contributors = 0;
+        crowdfill.expires_at = expires_at;
+        crowdfill.offer_amount = 0;
+        crowdfill.leftover = 0;
+        crowdfill.settled = false;
+        crowdfill.bump = ctx.bumps.crowdfill;
+        crowdfill.vault_bump = ctx.bumps.crowdfill_vault;
+        crowdfill.offer_vault_bump = ctx.bumps.crowdfill_offer_vault;
+
+        msg!(
+            "Crowdfill opened on escrow {}: raising {}",
+            ctx.accounts.escrow.key(),
+            crowdfill.target
+        );
+
+        Ok(())
+    }
+
+    /// Contribute request tokens to a crowdfill
+    /// - Contributions may run past the target, covering taker and transfer
+    ///   fees; what settlement leaves over is returned pro-rata
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn contribute<'info>(
+        ctx: Context<'_, '_, '_, 'info, Contribute<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+        let crowdfill = &ctx.accounts.crowdfill;
+        require!(
+            !crowdfill.settled && Clock::get()?.unix_timestamp < crowdfill.expires_at,
+            EscrowError::CrowdfillClosed
+        );
+
+        let vault_before = ctx.accounts.crowdfill_vault.amount;
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.contributor_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.crowdfill_vault.to_account_info(),
+                    authority: ctx.accounts.contributor.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            amount,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        // Only what reaches the vault after transfer fees is credited
+        ctx.accounts.crowdfill_vault.reload()?;
+        let added = ctx
+            .accounts
+            .crowdfill_vault
+            .amount
+            .checked_sub(vault_before)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        let contribution = &mut ctx.accounts.contribution;
+        let crowdfill = &mut ctx.accounts.crowdfill;
+        if contribution.amount == 0 {
+            contribution.crowdfill = crowdfill.key();
+            contribution.contributor = ctx.accounts.contributor.key();
+            contribution.bump = ctx.bumps.contribution;
+            crowdfill.contributors = crowdfill
+                .contributors
+                .checked_add(1)
+                .ok_or(EscrowError::MathOverflow)?;
+        }
+        contribution.amount = contribution
+            .amount
+            .checked_add(added)
+            .ok_or(EscrowError::MathOverflow)?;
+        crowdfill.raised = crowdfill
+            .raised
+            .checked_add(added)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!(
+            "Crowdfill {} raised {} of {}",
+            crowdfill.key(),
+            crowdfill.raised,
+            crowdfill.target
+        );
+
+        Ok(())
+    }
+
+    /// Settle a crowdfill that raised its target, taking the whole escrow
+    /// into the crowdfill's offer vault for contributors to claim
+    /// - Callable by anyone
+    /// - Remaining accounts supply creator royalty accounts as laid out in
+    ///   `Fill`, then transfer-hook accounts
+    pub fn settle_crowdfill<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleCrowdfill<'info>>,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        let crowdfill = &accounts.crowdfill;
+        require!(
+            !crowdfill.settled && Clock::get()?.unix_timestamp < crowdfill.expires_at,
+            EscrowError::CrowdfillClosed
+        );
+        require!(
+            crowdfill.raised >= crowdfill.target,
+            EscrowError::CrowdfillNotRaised
+        );
+        let mut escrow = accounts.escrow.load_mut()?;
+        let fill_amount = escrow.offer_amount;
+        escrow.set_locked_by(None);
+        drop(escrow);
+
+        let escrow_key = accounts.escrow.key();
+        let crowdfill_key = crowdfill.key();
+        let crowdfill_seeds = &[b"crowdfill", escrow_key.as_ref(), &[crowdfill.bump]];
+        let payer_seeds = &[&crowdfill_seeds[..]];
+        let crowdfill_info = accounts.crowdfill.to_account_info();
+
+        let event = Fill {
+            escrow: &accounts.escrow,
+            vault: &accounts.vault,
+            seller: accounts.seller.to_account_info(),
+            seller_request_token: accounts
+                .seller_request_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            proceeds_recipient_token: None,
+            proceeds_vault: None,
+            market: accounts.market.as_deref(),
+            market_fee_token: accounts.market_fee_token.as_deref(),
+            pair_fees: accounts
+                .pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .crowdfill_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.seller_index.to_account_info(),
+            seller_reputation: accounts.seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            taker_fee_vault: accounts.taker_fee_vault.as_deref(),
+            referrer_token: None,
+            offer_metadata: accounts.offer_metadata.as_deref(),
+            buyer_allowlist: accounts.buyer_allowlist.as_deref(),
+            gate_token: None,
+            gate_metadata: None,
+            discount_token: None,
+            discount_metadata: None,
+            permission_approval: None,
+            fill_commitment: None,
+            taker: crowdfill_key,
+            payer_token: Some(accounts.crowdfill_vault.to_account_info()),
+            payer_authority: crowdfill_info,
+            offer_destination: accounts.crowdfill_offer_vault.to_account_info(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            // Crowdfills always fill in full
+            offer_mint_limits: None,
+            request_mint_limits: None,
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(&mut accounts.pair_index, fill_amount, payer_seeds)?;
+
+        accounts.crowdfill_vault.reload()?;
+        accounts.crowdfill_offer_vault.reload()?;
+        let crowdfill = &mut accounts.crowdfill;
+        crowdfill.offer_amount = accounts.crowdfill_offer_vault.amount;
+        crowdfill.leftover = accounts.crowdfill_vault.amount;
+        crowdfill.settled = true;
+
+        msg!(
+            "Crowdfill {} settled for {} tokens",
+            crowdfill_key,
+            event.request_amount
+        );
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Claim a contribution's pro-rata share of a settled crowdfill's offer
+    /// tokens and leftover request tokens
+    /// - The last claim takes whatever rounding left in the vaults
+    /// - Remaining accounts supply transfer-hook accounts
+    pub fn claim_crowdfill<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimCrowdfill<'info>>,
+    ) -> Result<()> {
+        let crowdfill = &ctx.accounts.crowdfill;
+        require!(crowdfill.settled, EscrowError::CrowdfillNotSettled);
+        let contribution = ctx.accounts.contribution.amount;
+        let (offer_share, leftover_share) = if crowdfill.contributors == 1 {
+            (
+                ctx.accounts.crowdfill_offer_vault.amount,
+                ctx.accounts.crowdfill_vault.amount,
+            )
+        } else {
+            (
+                crowdfill.share(crowdfill.offer_amount, contribution)?,
+                crowdfill.share(crowdfill.leftover, contribution)?,
+            )
+        };
+
+        let escrow_key = crowdfill.escrow;
+        let crowdfill_seeds = &[b"crowdfill", escrow_key.as_ref(), &[crowdfill.bump]];
+        let signer_seeds = &[&crowdfill_seeds[..]];
+        let crowdfill_info = crowdfill.to_account_info();
+
+        if offer_share > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.crowdfill_offer_vault.to_account_info(),
+                        mint: ctx.accounts.offer_mint.to_account_info(),
+                        to: ctx.accounts.contributor_offer_token.to_account_info(),
+                        authority: crowdfill_info.clone(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                offer_share,
+                ctx.accounts.offer_mint.decimals,
+            )?;
+        }
+        if leftover_share > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.crowdfill_vault.to_account_info(),
+                        mint: ctx.accounts.request_mint.to_account_info(),
+                        to: ctx.accounts.contributor_request_token.to_account_info(),
+                        authority: crowdfill_info,
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                leftover_share,
+                ctx.accounts.request_mint.decimals,
+            )?;
+        }
+        ctx.accounts.crowdfill.contributors = ctx
+            .accounts
+            .crowdfill
+            .contributors
+            .checked_sub(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!(
+            "Claimed {} offer tokens and {} leftover tokens from crowdfill {}",
+            offer_share,
+            leftover_share,
+            ctx.accounts.crowdfill.key()
+        );
+
+        Ok(())
+    }
+
+    /// Take back a contribution to a crowdfill left unsettled at its expiry
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn reclaim_contribution<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReclaimContribution<'info>>,
+    ) -> Result<()> {
+        let crowdfill = &ctx.accounts.crowdfill;
+        require!(!crowdfill.settled, EscrowError::CrowdfillSettled);
+        require!(
+            Clock::get()?.unix_timestamp >= crowdfill.expires_at,
+            EscrowError::CrowdfillOpen
+        );
+        let amount = ctx.accounts.contribution.amount;
+
+        let escrow_key = crowdfill.escrow;
+        let crowdfill_seeds = &[b"crowdfill", escrow_key.as_ref(), &[crowdfill.bump]];
+        let signer_seeds = &[&crowdfill_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.crowdfill_vault.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.contributor_request_token.to_account_info(),
+                    authority: crowdfill.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            amount,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        let crowdfill = &mut ctx.accounts.crowdfill;
+        crowdfill.raised = crowdfill
+            .raised
+            .checked_sub(amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        crowdfill.contributors = crowdfill
+            .contributors
+            .checked_sub(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!(
+            "Reclaimed {} tokens from crowdfill {}",
+            amount,
+            crowdfill.key()
+        );
+
+        Ok(())
+    }
+
+    /// Close a crowdfill with no contributions outstanding, unlocking its
+    /// escrow if it never settled
+    pub fn close_crowdfill(ctx: Context<CloseCrowdfill>) -> Result<()> {
+        let crowdfill = &ctx.accounts.crowdfill;
+        require!(
+            crowdfill.contributors == 0,
+            EscrowError::CrowdfillHasContributions
+        );
+        if !crowdfill.settled {
+            ctx.accounts
+                .escrow
+                .as_ref()
+                .ok_or(EscrowError::MissingEscrowAccount)?
+                .load_mut()?
+                .set_locked_by(None);
+        }
+
+        let escrow_key = crowdfill.escrow;
+        let crowdfill_seeds = &[b"crowdfill", escrow_key.as_ref(), &[crowdfill.bump]];
+        let signer_seeds = &[&crowdfill_seeds[..]];
+        let crowdfill_info = crowdfill.to_account_info();
+        close_token_account(
+            ctx.accounts.request_token_program.to_account_info(),
+            ctx.accounts.crowdfill_vault.to_account_info(),
+            ctx.accounts.request_mint.to_account_info(),
+            ctx.accounts.seller.to_account_info(),
+            crowdfill_info.clone(),
+            signer_seeds,
+        )?;
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.crowdfill_offer_vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            ctx.accounts.seller.to_account_info(),
+            crowdfill_info,
+            signer_seeds,
+        )?;
+
+        msg!("Crowdfill {} closed", crowdfill.key());
+
+        Ok(())
+    }
+
+    /// Require an arbitrated, held settlement for an escrow, or return it
+    /// to atomic fills with `None`
+    /// - Buyers take the escrow with `hold_escrow`, and payment is released
+    ///   to the seller once the dispute window passes undisputed, both
+    ///   parties approve it early, or the arbiter rules for the seller
+    /// - In two-of-three mode, two of the seller, buyer and arbiter must
+    ///   instead approve the same outcome with `approve_settlement`
+    pub fn set_arbitration(
+        ctx: Context<UpdateEscrow>,
+        arbitration: Option<Arbitration>,
+    ) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(escrow.hashlock().is_none(), EscrowError::Hashlocked);
+        if let Some(Arbitration {
+            mode: ReleaseMode::DisputeWindow { seconds },
+            ..
+        }) = &arbitration
+        {
+            require!(*seconds > 0, EscrowError::InvalidDisputeWindow);
+        }
+
+        escrow.set_arbitration(arbitration);
+
+        msg!(
+            "Escrow {} arbitration {}",
+            ctx.accounts.escrow.key(),
+            if arbitration.is_some() {
+                "set"
+            } else {
+                "cleared"
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Take an escrow off the market for `duration` seconds, e.g. while
+    /// completing KYC or a fiat payment
+    /// - Locks `deposit` lamports; during the hold only the buyer can take
+    ///   the escrow
+    /// - The buyer gets the deposit back by passing the reservation to
+    ///   `accept_escrow`; `close_reservation` settles it otherwise
+    pub fn reserve_escrow(ctx: Context<ReserveEscrow>, deposit: u64, duration: i64) -> Result<()> {
+        require!(
+            deposit > 0 && (1..=MAX_RESERVATION_DURATION).contains(&duration),
+            EscrowError::InvalidReservation
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let buyer_key = ctx.accounts.buyer.key();
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(buyer_key, designated_buyer, EscrowError::NotDesignatedBuyer);
+        }
+        // Options hold the escrow through `buy_option` instead
+        require!(
+            escrow.option_premium().is_none(),
+            EscrowError::InvalidReservation
+        );
+        escrow.check_reservation(&buyer_key, now)?;
+        let expires_at = now.checked_add(duration).ok_or(EscrowError::MathOverflow)?;
+        escrow.set_reservation(Some((buyer_key, expires_at)));
+        let seller = escrow.seller;
+        drop(escrow);
+
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.buyer.to_account_info(),
+                    to: ctx.accounts.reservation.to_account_info(),
+                },
+            ),
+            deposit,
+        )?;
+        let reservation = &mut ctx.accounts.reservation;
+        reservation.escrow = ctx.accounts.escrow.key();
+        reservation.seller = seller;
+        reservation.buyer = buyer_key;
+        reservation.deposit = deposit;
+        reservation.expires_at = expires_at;
+        reservation.bump = ctx.bumps.reservation;
+
+        msg!(
+            "Escrow {} reserved by {} until {}",
+            reservation.escrow,
+            buyer_key,
+            expires_at
+        );
+
+        Ok(())
+    }
+
+    /// Settle a reservation the buyer did not redeem through
+    /// `accept_escrow`
+    /// - Once the hold lapses the deposit is forfeited to the seller
+    /// - Before then, it is refunded if the escrow is no longer open
+    /// - Permissionless; the account's rent returns to the buyer
+    pub fn close_reservation(ctx: Context<CloseReservation>) -> Result<()> {
+        let reservation = &ctx.accounts.reservation;
+        let lapsed = Clock::get()?.unix_timestamp >= reservation.expires_at;
+        if lapsed {
+            let reservation_info = reservation.to_account_info();
+            let mut reservation_lamports = reservation_info.try_borrow_mut_lamports()?;
+            **reservation_lamports = reservation_lamports
+                .checked_sub(reservation.deposit)
+                .ok_or(EscrowError::MathOverflow)?;
+            let mut seller_lamports = ctx.accounts.seller.try_borrow_mut_lamports()?;
+            **seller_lamports = seller_lamports
+                .checked_add(reservation.deposit)
+                .ok_or(EscrowError::MathOverflow)?;
+        } else {
+            require!(
+                !Escrow::is_open(&ctx.accounts.escrow)?,
+                EscrowError::ReservationActive
+            );
+        }
+
+        msg!(
+            "Reservation of escrow {} closed, deposit {}",
+            reservation.escrow,
+            if lapsed { "forfeited" } else { "refunded" }
+        );
+
+        Ok(())
+    }
+
+    /// Make buyers commit to their fills ahead of settling them, or let
+    /// the escrow be taken directly again with `None`
+    /// - A buyer commits with `commit_fill`, reveals the fill size with
+    ///   `reveal_fill`, and settles through `accept_escrow` once `delay`
+    ///   seconds have passed since committing
+    /// - Keeps bots from sniping a listing the instant it appears, since
+    ///   the earliest commitments come due first
+    pub fn set_reveal_delay(ctx: Context<UpdateEscrow>, delay: Option<i64>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        if let Some(delay) = delay {
+            require!(
+                (1..=MAX_REVEAL_DELAY).contains(&delay),
+                EscrowError::InvalidAmount
+            );
+        }
+
+        escrow.set_reveal_delay(delay);
+
+        msg!(
+            "Escrow {} reveal delay {}",
+            ctx.accounts.escrow.key(),
+            delay.unwrap_or_default()
+        );
+
+        Ok(())
+    }
+
+    /// Commit to filling an escrow that settles by commit and reveal
+    /// - `commitment` is `sha256(fill_amount as u64 LE || salt)`
+    pub fn commit_fill(ctx: Context<CommitFill>, commitment: [u8; 32]) -> Result<()> {
+        require!(
+            ctx.accounts.escrow.load()?.reveal_delay().is_some(),
+            EscrowError::InvalidFillCommitment
+        );
+
+        let fill_commitment = &mut ctx.accounts.fill_commitment;
+        fill_commitment.escrow = ctx.accounts.escrow.key();
+        fill_commitment.buyer = ctx.accounts.buyer.key();
+        fill_commitment.commitment = commitment;
+        fill_commitment.committed_at = Clock::get()?.unix_timestamp;
+        fill_commitment.fill_amount = None;
+        fill_commitment.bump = ctx.bumps.fill_commitment;
+
+        msg!(
+            "Fill committed on escrow {} by {}",
+            fill_commitment.escrow,
+            fill_commitment.buyer
+        );
+
+        Ok(())
+    }
+
+    /// Open a fill commitment ahead of settling it with `accept_escrow`
+    pub fn reveal_fill(ctx: Context<RevealFill>, fill_amount: u64, salt: [u8; 32]) -> Result<()> {
+        let fill_commitment = &mut ctx.accounts.fill_commitment;
+        require!(
+            fill_commitment.fill_amount.is_none(),
+            EscrowError::InvalidReveal
+        );
+        require!(
+            hashv(&[&fill_amount.to_le_bytes(), &salt]).to_bytes() == fill_commitment.commitment,
+            EscrowError::InvalidReveal
+        );
+        fill_commitment.fill_amount = Some(fill_amount);
+
+        msg!(
+            "Fill of {} revealed on escrow {}",
+            fill_amount,
+            fill_commitment.escrow
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw an unused fill commitment, returning its rent
+    pub fn close_fill_commitment(ctx: Context<CloseFillCommitment>) -> Result<()> {
+        msg!(
+            "Fill commitment on escrow {} closed",
+            ctx.accounts.fill_commitment.escrow
+        );
+
+        Ok(())
+    }
+
+    /// Sell an escrow as a covered option, or stop selling it with `None`
+    /// - A buyer pays `premium` request tokens for the exclusive right to
+    ///   take the escrow at its price until it expires
+    /// - Unexercised, the escrow expires back to the seller, who keeps the
+    ///   premium
+    pub fn set_option_premium(ctx: Context<UpdateEscrow>, premium: Option<u64>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(!escrow.committed(now), EscrowError::EscrowCommitted);
+        require!(premium != Some(0), EscrowError::InvalidOption);
+        // A lapsed reservation must not pass for a purchased option
+        escrow.check_reservation(&Pubkey::default(), now)?;
+        escrow.set_reservation(None);
+        escrow.set_option_premium(premium);
+
+        msg!(
+            "Escrow {} option premium: {:?}",
+            ctx.accounts.escrow.key(),
+            premium
+        );
+
+        Ok(())
+    }
+
+    /// Buy the option on an escrow, paying its premium to the seller
+    /// - Until the escrow expires only the buyer can take it, at its
+    ///   current price, through `accept_escrow`; the seller can neither
+    ///   cancel nor re-price it
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn buy_option<'info>(ctx: Context<'_, '_, '_, 'info, BuyOption<'info>>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let buyer_key = ctx.accounts.buyer.key();
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        let premium = escrow.option_premium().ok_or(EscrowError::NotAnOption)?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(!escrow.committed(now), EscrowError::EscrowCommitted);
+        let expires_at = escrow
+            .expires_at()
+            .filter(|expires_at| now < *expires_at)
+            .ok_or(EscrowError::InvalidOption)?;
+        // The seller could otherwise drop the holder from the allowlist
+        require!(
+            escrow.buyer_allowlist().is_none(),
+            EscrowError::InvalidOption
+        );
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(buyer_key, designated_buyer, EscrowError::NotDesignatedBuyer);
+        }
+        escrow.check_reservation(&buyer_key, now)?;
+        escrow.set_reservation(Some((buyer_key, expires_at)));
+        drop(escrow);
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.request_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.buyer_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.seller_request_token.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            premium,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        msg!(
+            "Option on escrow {} bought by {} until {}",
+            ctx.accounts.escrow.key(),
+            buyer_key,
+            expires_at
+        );
+
+        Ok(())
+    }
+
+    /// Route the escrow's proceeds to `recipient`'s request-mint token
+    /// account instead of the seller's, or back to the seller with `None`
+    /// - Lets a seller signing with a hot key keep proceeds in cold storage
+    /// - Fills of such an escrow must pay in tokens, to the recipient's
+    ///   account passed as `proceeds_recipient_token`
+    pub fn set_proceeds_recipient(
+        ctx: Context<UpdateEscrow>,
+        recipient: Option<Pubkey>,
+    ) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            recipient != Some(Pubkey::default()),
+            EscrowError::InvalidRecipient
+        );
+        escrow.set_proceeds_recipient(recipient);
+
+        msg!(
+            "Escrow {} proceeds recipient: {:?}",
+            ctx.accounts.escrow.key(),
+            recipient
+        );
+
+        Ok(())
+    }
+
+    /// Offer the escrow as collateral for a loan, or withdraw the offer
+    /// with `None`
+    /// - A lender disburses `request_amount` of the request mint as
+    ///   principal through `fund_loan`
+    /// - The seller owes `repayment_amount` within `duration` seconds of
+    ///   funding, or the lender can liquidate the collateral
+    pub fn set_loan_terms(ctx: Context<UpdateEscrow>, terms: Option<LoanTerms>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::InvalidLoanTerms
+        );
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        if let Some(terms) = &terms {
+            require!(
+                terms.repayment_amount >= escrow.request_amount && terms.duration > 0,
+                EscrowError::InvalidLoanTerms
+            );
+        }
+        escrow.set_loan_terms(terms);
+
+        msg!(
+            "Escrow {} loan terms: {:?}",
+            ctx.accounts.escrow.key(),
+            terms
+        );
+
+        Ok(())
+    }
+
+    /// Lend against an escrow's collateral, disbursing its `request_amount`
+    /// to the seller
+    /// - The collateral stays in the vault until the seller repays or the
+    ///   loan falls due
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn fund_loan<'info>(ctx: Context<'_, '_, '_, 'info, FundLoan<'info>>) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        let now = Clock::get()?.unix_timestamp;
+        let lender_key = ctx.accounts.lender.key();
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        let terms = escrow.loan_terms().ok_or(EscrowError::NotALoan)?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.market().is_none()
+                && escrow.receipt_mint().is_none()
+                && escrow.locked_by().is_none()
+                && escrow.hashlock().is_none()
+                && escrow.delegated_source().is_none(),
+            EscrowError::InvalidLoanTerms
+        );
+        require!(
+            terms.repayment_amount >= escrow.request_amount,
+            EscrowError::InvalidLoanTerms
+        );
+        if let Some(starts_at) = escrow.starts_at() {
+            require!(now >= starts_at, EscrowError::EscrowNotStarted);
+        }
+        if let Some(expires_at) = escrow.expires_at() {
+            require!(now < expires_at, EscrowError::EscrowExpired);
+        }
+        if let Some(condition) = &escrow.condition() {
+            require!(condition.met, EscrowError::ConditionNotMet);
+        }
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(
+                lender_key,
+                designated_buyer,
+                EscrowError::NotDesignatedBuyer
+            );
+        }
+        escrow.check_reservation(&lender_key, now)?;
+        escrow.set_reservation(None);
+        escrow.set_status(EscrowStatus::Lent);
+        let principal = escrow.request_amount;
+        let due_at = now
+            .checked_add(terms.duration)
+            .ok_or(EscrowError::InvalidLoanTerms)?;
+        let (offer_mint_key, request_mint_key) = (escrow.offer_mint, escrow.request_mint);
+        drop(escrow);
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.request_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.lender_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.seller_request_token.to_account_info(),
+                    authority: ctx.accounts.lender.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            principal,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        let loan = &mut ctx.accounts.loan;
+        loan.escrow = ctx.accounts.escrow.key();
+        loan.borrower = ctx.accounts.seller.key();
+        loan.lender = lender_key;
+        loan.principal = principal;
+        loan.repayment_amount = terms.repayment_amount;
+        loan.due_at = due_at;
+        loan.bump = ctx.bumps.loan;
+
+        ctx.accounts
+            .pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.deregister(&ctx.accounts.escrow.key())?;
+        }
+
+        msg!(
+            "Loan against escrow {} funded by {}, due at {}",
+            ctx.accounts.escrow.key(),
+            lender_key,
+            due_at
+        );
+
+        Ok(())
+    }
+
+    /// Repay a loan before it falls due, returning the collateral to the
+    /// seller and closing the escrow
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint, then the offer mint
+    pub fn repay_loan<'info>(ctx: Context<'_, '_, '_, 'info, RepayLoan<'info>>) -> Result<()> {
+        let escrow = *ctx.accounts.escrow.load()?;
+        let loan = &ctx.accounts.loan;
+        require!(
+            Clock::get()?.unix_timestamp < loan.due_at,
+            EscrowError::LoanOverdue
+        );
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.request_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.seller_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.lender_request_token.to_account_info(),
+                    authority: ctx.accounts.seller.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            loan.repayment_amount,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow.escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.seller_offer_token.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            escrow.offer_amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            rent_destination.clone(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_status(EscrowStatus::Cancelled);
+        if !escrow.keep_record() {
+            close_escrow(
+                &ctx.accounts.escrow,
+                ctx.accounts.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
+
+        msg!("Loan against escrow {} repaid", ctx.accounts.escrow.key());
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Cancelled,
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            buyer: None,
+            offer_amount: escrow.offer_amount,
+            request_amount: escrow.request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// Release an overdue loan's collateral to its lender, closing the
+    /// escrow
+    /// - Permissionless once the loan falls due, so keepers can liquidate
+    ///   on the lender's behalf
+    /// - Remaining accounts supply transfer-hook accounts for the offer
+    ///   mint
+    pub fn liquidate_loan<'info>(
+        ctx: Context<'_, '_, '_, 'info, LiquidateLoan<'info>>,
+    ) -> Result<()> {
+        let escrow = *ctx.accounts.escrow.load()?;
+        require!(
+            Clock::get()?.unix_timestamp >= ctx.accounts.loan.due_at,
+            EscrowError::LoanNotDue
+        );
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow.escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.lender_offer_token.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            escrow.offer_amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            rent_destination.clone(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_status(EscrowStatus::Filled);
+        if !escrow.keep_record() {
+            close_escrow(
+                &ctx.accounts.escrow,
+                ctx.accounts.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
+
+        msg!(
+            "Loan against escrow {} liquidated to {}",
+            ctx.accounts.escrow.key(),
+            ctx.accounts.lender.key()
+        );
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Filled,
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            buyer: Some(ctx.accounts.lender.key()),
+            offer_amount: escrow.offer_amount,
+            request_amount: ctx.accounts.loan.principal,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// Let a buyer take the whole escrow on installments, or withdraw the
+    /// option with `None`
+    /// - The buyer pays `request_amount` in `installments` equal parts,
+    ///   one every `interval` seconds, through `start_payment_plan` and
+    ///   `pay_installment`
+    /// - A missed installment lets the seller reclaim the unreleased offer
+    ///   and keep `forfeit_bps` of the payments held against it
+    pub fn set_installment_terms(
+        ctx: Context<UpdateEscrow>,
+        terms: Option<InstallmentTerms>,
+    ) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::InvalidInstallmentTerms
+        );
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        if let Some(terms) = &terms {
+            require!(
+                terms.installments >= 2
+                    && terms.interval > 0
+                    && terms.forfeit_bps as u64 <= BPS_DENOMINATOR,
+                EscrowError::InvalidInstallmentTerms
+            );
+        }
+        escrow.set_installment_terms(terms);
+
+        msg!(
+            "Escrow {} installment terms: {:?}",
+            ctx.accounts.escrow.key(),
+            terms
+        );
+
+        Ok(())
+    }
+
+    /// Take the whole escrow on its installment terms, paying the first
+    /// installment
+    /// - Later installments fall due every `interval` seconds from now
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint, then the offer mint
+    pub fn start_payment_plan<'info>(
+        ctx: Context<'_, '_, '_, 'info, StartPaymentPlan<'info>>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        let now = Clock::get()?.unix_timestamp;
+        let buyer_key = ctx.accounts.buyer.key();
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        let terms = escrow
+            .installment_terms()
+            .ok_or(EscrowError::NoInstallmentTerms)?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.market().is_none()
+                && escrow.receipt_mint().is_none()
+                && escrow.locked_by().is_none()
+                && escrow.hashlock().is_none()
+                && escrow.delegated_source().is_none()
+                && !escrow.has_extra_payments()
+                && escrow.oracle_pricing().is_none()
+                && !is_native_mint(&escrow.offer_mint)
+                && !is_native_mint(&escrow.request_mint),
+            EscrowError::InvalidInstallmentTerms
+        );
+        if let Some(starts_at) = escrow.starts_at() {
+            require!(now >= starts_at, EscrowError::EscrowNotStarted);
+        }
+        if let Some(expires_at) = escrow.expires_at() {
+            require!(now < expires_at, EscrowError::EscrowExpired);
+        }
+        if let Some(condition) = &escrow.condition() {
+            require!(condition.met, EscrowError::ConditionNotMet);
+        }
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(buyer_key, designated_buyer, EscrowError::NotDesignatedBuyer);
+        }
+        escrow.check_reservation(&buyer_key, now)?;
+        escrow.set_reservation(None);
+        escrow.set_status(EscrowStatus::PaymentPlan);
+        let (offer_amount, request_amount) = (escrow.offer_amount, escrow.request_amount);
+        let (offer_mint_key, request_mint_key) = (escrow.offer_mint, escrow.request_mint);
+        let market_index = escrow.market_index();
+        drop(escrow);
+
+        let plan = &mut ctx.accounts.payment_plan;
+        plan.escrow = ctx.accounts.escrow.key();
+        plan.buyer = buyer_key;
+        plan.vault = ctx.accounts.plan_vault.key();
+        plan.offer_amount = offer_amount;
+        plan.request_amount = request_amount;
+        plan.installments = terms.installments;
+        plan.installments_paid = 0;
+        plan.interval = terms.interval;
+        plan.started_at = now;
+        plan.release_at_end = terms.release_at_end;
+        plan.forfeit_bps = terms.forfeit_bps;
+        plan.offer_released = 0;
+        plan.bump = ctx.bumps.payment_plan;
+        plan.vault_bump = ctx.bumps.plan_vault;
+
+        ctx.accounts
+            .pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.deregister(&ctx.accounts.escrow.key())?;
+        }
+        MarketIndex::unlink(
+            ctx.accounts.market_index.as_deref_mut(),
+            market_index,
+            &ctx.accounts.escrow.key(),
+        )?;
+
+        let accounts = &mut *ctx.accounts;
+        InstallmentPayment {
+            plan: &mut accounts.payment_plan,
+            escrow: &accounts.escrow,
+            buyer: accounts.buyer.to_account_info(),
+            seller: accounts.seller.to_account_info(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            vault: accounts.vault.to_account_info(),
+            plan_vault: accounts.plan_vault.to_account_info(),
+            buyer_offer_token: accounts.buyer_offer_token.to_account_info(),
+            buyer_request_token: accounts.buyer_request_token.to_account_info(),
+            seller_request_token: accounts.seller_request_token.to_account_info(),
+            // At least two installments, so the plan never completes here
+            rent_destination: accounts.seller.to_account_info(),
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .pay(now)?;
+
+        msg!(
+            "Payment plan on escrow {} started by {} over {} installments",
+            ctx.accounts.escrow.key(),
+            buyer_key,
+            terms.installments
+        );
+
+        Ok(())
+    }
+
+    /// Pay a payment plan's next installment by its due date, releasing
+    /// the offer it pays for
+    /// - The last installment pays the seller any payments held for the
+    ///   end and closes the escrow and plan
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint, then the offer mint
+    pub fn pay_installment<'info>(
+        ctx: Context<'_, '_, '_, 'info, PayInstallment<'info>>,
+    ) -> Result<()> {
+        let escrow = *ctx.accounts.escrow.load()?;
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+        let plan = &ctx.accounts.payment_plan;
+        let paid = plan.installments_paid + 1;
+        let (buyer_key, offer_amount, request_amount) =
+            (plan.buyer, plan.offer_amount, plan.request_amount);
+
+        let accounts = &mut *ctx.accounts;
+        let completed = InstallmentPayment {
+            plan: &mut accounts.payment_plan,
+            escrow: &accounts.escrow,
+            buyer: accounts.buyer.to_account_info(),
+            seller: accounts.seller.to_account_info(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            vault: accounts.vault.to_account_info(),
+            plan_vault: accounts.plan_vault.to_account_info(),
+            buyer_offer_token: accounts.buyer_offer_token.to_account_info(),
+            buyer_request_token: accounts.buyer_request_token.to_account_info(),
+            seller_request_token: accounts.seller_request_token.to_account_info(),
+            rent_destination,
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .pay(Clock::get()?.unix_timestamp)?;
+
+        msg!(
+            "Installment {} on escrow {} paid{}",
+            paid,
+            ctx.accounts.escrow.key(),
+            if completed { ", plan complete" } else { "" }
+        );
+        if completed {
+            ActivityLog {
+                config: &ctx.accounts.config,
+                merkle_tree: ctx.accounts.activity_log.as_ref(),
+                log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+                compression_program: ctx.accounts.compression_program.as_ref(),
+            }
+            .append(&ActivityRecord {
+                kind: ActivityKind::Filled,
+                escrow: ctx.accounts.escrow.key(),
+                seller: escrow.seller,
+                buyer: Some(buyer_key),
+                offer_amount,
+                request_amount,
+                timestamp: Clock::get()?.unix_timestamp,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Reclaim an escrow whose payment plan missed an installment,
+    /// closing the escrow and plan
+    /// - The seller takes back the unreleased offer and `forfeit_bps` of
+    ///   the payments held against it; the rest returns to the buyer
+    /// - Remaining accounts supply transfer-hook accounts for the offer
+    ///   mint, then the request mint
+    pub fn reclaim_payment_plan<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReclaimPaymentPlan<'info>>,
+    ) -> Result<()> {
+        let escrow = *ctx.accounts.escrow.load()?;
+        let plan = &ctx.accounts.payment_plan;
+        require!(
+            Clock::get()?.unix_timestamp > plan.next_due_at()?,
+            EscrowError::InstallmentNotOverdue
+        );
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow.escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        if escrow.offer_amount > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.vault.to_account_info(),
+                        mint: ctx.accounts.offer_mint.to_account_info(),
+                        to: ctx.accounts.seller_offer_token.to_account_info(),
+                        authority: ctx.accounts.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                escrow.offer_amount,
+                ctx.accounts.offer_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            rent_destination.clone(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+
+        let plan_seeds = &[b"payment_plan", plan.escrow.as_ref(), &[plan.bump]];
+        let plan_signer = &[&plan_seeds[..]];
+        let held = ctx.accounts.plan_vault.amount;
+        let forfeit = bps_of(held, plan.forfeit_bps)?;
+        for (to, amount) in [
+            (ctx.accounts.seller_request_token.to_account_info(), forfeit),
+            (
+                ctx.accounts.buyer_request_token.to_account_info(),
+                held.checked_sub(forfeit).ok_or(EscrowError::MathOverflow)?,
+            ),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.plan_vault.to_account_info(),
+                        mint: ctx.accounts.request_mint.to_account_info(),
+                        to,
+                        authority: plan.to_account_info(),
+                    },
+                    plan_signer,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                amount,
+                ctx.accounts.request_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            ctx.accounts.request_token_program.to_account_info(),
+            ctx.accounts.plan_vault.to_account_info(),
+            ctx.accounts.request_mint.to_account_info(),
+            ctx.accounts.buyer.to_account_info(),
+            plan.to_account_info(),
+            plan_signer,
+        )?;
+
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_status(EscrowStatus::Cancelled);
+        if !escrow.keep_record() {
+            close_escrow(
+                &ctx.accounts.escrow,
+                ctx.accounts.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
+
+        msg!(
+            "Payment plan on escrow {} reclaimed after {} of {} installments",
+            ctx.accounts.escrow.key(),
+            plan.installments_paid,
+            plan.installments
+        );
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Cancelled,
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            buyer: None,
+            offer_amount: escrow.offer_amount,
+            request_amount: escrow.request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// Take the whole of an arbitrated escrow, holding payment until the
+    /// dispute window closes
+    /// - Locks `deposit` request tokens, at least `request_amount`, to
+    ///   cover the price and any taker fees
+    /// - The escrow is locked and no longer expires while held
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn hold_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, HoldEscrow<'info>>,
+        deposit: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        let now = Clock::get()?.unix_timestamp;
+        let buyer_key = ctx.accounts.buyer.key();
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        let arbitration = escrow.arbitration().ok_or(EscrowError::NoArbitration)?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(!escrow.has_extra_payments(), EscrowError::HasRequestLegs);
+        require!(escrow.vesting().is_none(), EscrowError::VestedEscrow);
+        require!(
+            escrow.proceeds_stream().is_none(),
+            EscrowError::StreamedEscrow
+        );
+        require!(!escrow.is_gated(), EscrowError::GatedEscrow);
+        if let Some(starts_at) = escrow.starts_at() {
+            require!(now >= starts_at, EscrowError::EscrowNotStarted);
+        }
+        if let Some(expires_at) = escrow.expires_at() {
+            require!(now < expires_at, EscrowError::EscrowExpired);
+        }
+        if let Some(condition) = &escrow.condition() {
+            require!(condition.met, EscrowError::ConditionNotMet);
+        }
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(buyer_key, designated_buyer, EscrowError::NotDesignatedBuyer);
+        }
+        escrow.check_reservation(&buyer_key, now)?;
+        if let Some(allowlist_key) = escrow.buyer_allowlist() {
+            let allowlist = ctx
+                .accounts
+                .buyer_allowlist
+                .as_ref()
+                .ok_or(EscrowError::MissingAllowlistAccount)?;
+            require_keys_eq!(
+                allowlist.key(),
+                allowlist_key,
+                EscrowError::InvalidAllowlist
+            );
+            require!(
+                allowlist.buyers.contains(&buyer_key),
+                EscrowError::BuyerNotAllowed
+            );
+        }
+        require!(deposit >= escrow.request_amount, EscrowError::InvalidAmount);
+        escrow.set_locked_by(Some(crate::ID));
+        escrow.set_expires_at(None);
+
+        let settlement = &mut ctx.accounts.settlement;
+        settlement.escrow = ctx.accounts.escrow.key();
+        settlement.buyer = buyer_key;
+        settlement.release_after = match arbitration.mode {
+            ReleaseMode::DisputeWindow { seconds } => {
+                Some(now.checked_add(seconds).ok_or(EscrowError::MathOverflow)?)
+            }
+            ReleaseMode::TwoOfThree => None,
+        };
+        settlement.status = SettlementStatus::Held;
+        settlement.release_approvals = 0;
+        settlement.refund_approvals = 0;
+        settlement.bump = ctx.bumps.settlement;
+        settlement.vault_bump = ctx.bumps.settlement_vault;
+        settlement.record(buyer_key, SettlementAction::Held, deposit, now);
+        drop(escrow);
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.buyer_request_token.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.settlement_vault.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            deposit,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        msg!("Escrow {} held for {}", settlement.escrow, buyer_key);
+
+        Ok(())
+    }
+
+    /// Dispute a held settlement before its window closes
+    /// - Either the seller or the buyer may raise it
+    /// - Holding, disputes, approvals and rulings are kept in the
+    ///   settlement's `history` for the arbiter to review
+    pub fn raise_dispute(ctx: Context<RaiseDispute>) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let settlement = &mut ctx.accounts.settlement;
+        require!(
+            authority == ctx.accounts.escrow.load()?.seller || authority == settlement.buyer,
+            EscrowError::Unauthorized
+        );
+        require!(
+            settlement.status == SettlementStatus::Held,
+            EscrowError::InvalidSettlementStatus
+        );
+        // Two-of-three settlements have no window to dispute
+        let release_after = settlement
+            .release_after
+            .ok_or(EscrowError::InvalidSettlementStatus)?;
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < release_after, EscrowError::DisputeWindowClosed);
+        settlement.status = SettlementStatus::Disputed;
+        settlement.record(authority, SettlementAction::Disputed, 0, now);
+
+        msg!("Settlement of escrow {} disputed", settlement.escrow);
+
+        Ok(())
+    }
+
+    /// Rule on a disputed settlement as the escrow's arbiter
+    /// - `release_to_seller` lets the settlement be released to the seller;
+    ///   otherwise the buyer's deposit can be refunded
+    pub fn resolve_dispute(ctx: Context<ResolveDispute>, release_to_seller: bool) -> Result<()> {
+        let arbitration = ctx
+            .accounts
+            .escrow
+            .load()?
+            .arbitration()
+            .ok_or(EscrowError::NoArbitration)?;
+        require_keys_eq!(
+            ctx.accounts.arbiter.key(),
+            arbitration.arbiter,
+            EscrowError::Unauthorized
+        );
+        let settlement = &mut ctx.accounts.settlement;
+        require!(
+            settlement.status == SettlementStatus::Disputed,
+            EscrowError::InvalidSettlementStatus
+        );
+        let (status, action) = if release_to_seller {
+            (
+                SettlementStatus::AwardedToSeller,
+                SettlementAction::ResolvedForSeller,
+            )
+        } else {
+            (
+                SettlementStatus::AwardedToBuyer,
+                SettlementAction::ResolvedForBuyer,
+            )
+        };
+        settlement.status = status;
+        settlement.record(
+            ctx.accounts.arbiter.key(),
+            action,
+            0,
+            Clock::get()?.unix_timestamp,
+        );
+        if !release_to_seller {
+            Reputation::record(&ctx.accounts.seller_reputation, |reputation| {
+                reputation.disputes_lost = reputation.disputes_lost.saturating_add(1)
+            })?;
+        }
+
+        msg!(
+            "Dispute over escrow {} resolved for the {}",
+            settlement.escrow,
+            if release_to_seller { "seller" } else { "buyer" }
+        );
+
+        Ok(())
+    }
+
+    /// Approve releasing a held settlement to the seller, or refunding the
+    /// buyer
+    /// - Signed by the seller, the buyer or, in two-of-three mode, the
+    ///   arbiter; a later approval replaces the signer's earlier one
+    /// - The second matching approval decides the settlement, so in
+    ///   dispute-window mode both parties agreeing skips the window
+    pub fn approve_settlement(
+        ctx: Context<ApproveSettlement>,
+        release_to_seller: bool,
+    ) -> Result<()> {
+        let arbitration = ctx
+            .accounts
+            .escrow
+            .load()?
+            .arbitration()
+            .ok_or(EscrowError::NoArbitration)?;
+        let authority = ctx.accounts.authority.key();
+        let settlement = &mut ctx.accounts.settlement;
+        let parties = [
+            ctx.accounts.escrow.load()?.seller,
+            settlement.buyer,
+            arbitration.arbiter,
+        ];
+        // The arbiter of a dispute window rules through `resolve_dispute`
+        let approvers = match arbitration.mode {
+            ReleaseMode::TwoOfThree => &parties[..],
+            ReleaseMode::DisputeWindow { .. } => &parties[..2],
+        };
+        let party = approvers
+            .iter()
+            .position(|party| *party == authority)
+            .ok_or(EscrowError::Unauthorized)?;
+        require!(
+            settlement.status == SettlementStatus::Held,
+            EscrowError::InvalidSettlementStatus
+        );
+
+        let flag = 1u8 << party;
+        let action = if release_to_seller {
+            settlement.release_approvals |= flag;
+            settlement.refund_approvals &= !flag;
+            SettlementAction::ApprovedRelease
+        } else {
+            settlement.refund_approvals |= flag;
+            settlement.release_approvals &= !flag;
+            SettlementAction::ApprovedRefund
+        };
+        settlement.record(authority, action, 0, Clock::get()?.unix_timestamp);
+        if settlement.release_approvals.count_ones() >= 2 {
+            settlement.status = SettlementStatus::AwardedToSeller;
+        } else if settlement.refund_approvals.count_ones() >= 2 {
+            settlement.status = SettlementStatus::AwardedToBuyer;
+        }
+
+        msg!(
+            "Settlement of escrow {}: {} release, {} refund approvals",
+            settlement.escrow,
+            settlement.release_approvals.count_ones(),
+            settlement.refund_approvals.count_ones()
+        );
+
+        Ok(())
+    }
+
+    /// Complete a held settlement once its window closes undisputed or it
+    /// is decided for the seller
+    /// - Pays from the settlement vault and returns the rest of the deposit
+    ///   to the buyer; callable by anyone
+    /// - Remaining accounts supply creator royalty accounts as laid out in
+    ///   `Fill`, then transfer-hook accounts
+    pub fn release_settlement<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseSettlement<'info>>,
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        let settlement = &accounts.settlement;
+        require!(
+            settlement.releasable(Clock::get()?.unix_timestamp),
+            EscrowError::InvalidSettlementStatus
+        );
+        let fill_amount = accounts.escrow.load()?.offer_amount;
+        let escrow_key = accounts.escrow.key();
+        let settlement_bump = settlement.bump;
+        accounts.escrow.load_mut()?.set_locked_by(None);
+
+        let settlement_seeds = &[b"settlement", escrow_key.as_ref(), &[settlement_bump]];
+        let payer_seeds = &[&settlement_seeds[..]];
+        let settlement_info = accounts.settlement.to_account_info();
+
+        let event = Fill {
+            escrow: &accounts.escrow,
+            vault: &accounts.vault,
+            seller: accounts.seller.to_account_info(),
+            seller_request_token: accounts
+                .seller_request_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            proceeds_recipient_token: None,
+            proceeds_vault: None,
+            market: accounts.market.as_deref(),
+            market_fee_token: accounts.market_fee_token.as_deref(),
+            pair_fees: accounts
+                .pair_fees
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_stats: accounts
+                .seller_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            taker_stats: accounts
+                .buyer_stats
+                .as_ref()
+                .map(|info| info.to_account_info()),
+            seller_index: accounts.seller_index.to_account_info(),
+            seller_reputation: accounts.seller_reputation.to_account_info(),
+            rent_recipient: accounts
+                .rent_recipient
+                .as_ref()
+                .map(|payer| payer.to_account_info()),
+            stats: accounts.stats.to_account_info(),
+            pair_stats: accounts.pair_stats.to_account_info(),
+            config: &accounts.config,
+            activity_log: accounts.activity_log.as_ref(),
+            log_wrapper: accounts.log_wrapper.as_ref(),
+            compression_program: accounts.compression_program.as_ref(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            taker_fee_vault: accounts.taker_fee_vault.as_deref(),
+            referrer_token: None,
+            offer_metadata: accounts.offer_metadata.as_deref(),
+            buyer_allowlist: accounts.buyer_allowlist.as_deref(),
+            gate_token: None,
+            gate_metadata: None,
+            discount_token: None,
+            discount_metadata: None,
+            permission_approval: None,
+            fill_commitment: None,
+            taker: accounts.buyer.key(),
+            payer_token: Some(accounts.settlement_vault.to_account_info()),
+            payer_authority: settlement_info.clone(),
+            offer_destination: accounts.buyer_offer_token.to_account_info(),
+            offer_mint: &accounts.offer_mint,
+            request_mint: &accounts.request_mint,
+            // Held settlements always fill in full
+            offer_mint_limits: None,
+            request_mint_limits: None,
+            offer_token_program: accounts.token_program.to_account_info(),
+            request_token_program: accounts.request_token_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .settle(&mut accounts.pair_index, fill_amount, payer_seeds)?;
+
+        // Return the rest of the deposit, then close the settlement vault
+        accounts.settlement_vault.reload()?;
+        let leftover = accounts.settlement_vault.amount;
+        if leftover > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.settlement_vault.to_account_info(),
+                        mint: accounts.request_mint.to_account_info(),
+                        to: accounts.buyer_request_token.to_account_info(),
+                        authority: settlement_info.clone(),
+                    },
+                    payer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                leftover,
+                accounts.request_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            accounts.request_token_program.to_account_info(),
+            accounts.settlement_vault.to_account_info(),
+            accounts.request_mint.to_account_info(),
+            accounts.buyer.to_account_info(),
+            settlement_info,
+            payer_seeds,
+        )?;
+
+        msg!("Held settlement of escrow {} released", escrow_key);
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Vest an escrow's offer to its buyers instead of delivering it on
+    /// accept, or deliver it immediately again with `None`
+    /// - Each fill vests linearly over `duration_seconds`, with nothing
+    ///   claimable before `cliff_seconds`
+    pub fn set_vesting(ctx: Context<UpdateEscrow>, vesting: Option<VestingSchedule>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        if let Some(schedule) = &vesting {
+            require!(
+                schedule.duration_seconds > 0
+                    && (0..=schedule.duration_seconds).contains(&schedule.cliff_seconds),
+                EscrowError::InvalidVestingSchedule
+            );
+            // Native offers are unwrapped on delivery
+            require!(
+                !is_native_mint(&escrow.offer_mint),
+                EscrowError::InvalidVestingSchedule
+            );
+        }
+
+        escrow.set_vesting(vesting);
+
+        msg!(
+            "Escrow {} vesting {}",
+            ctx.accounts.escrow.key(),
+            if vesting.is_some() { "set" } else { "cleared" }
+        );
+
+        Ok(())
+    }
+
+    /// Stream each fill's proceeds to the seller over `seconds` instead of
+    /// paying them out on accept, or pay them out again with `None`
+    /// - Proceeds accrue linearly from the fill and are withdrawn with
+    ///   `withdraw_stream`; extra request legs are still paid out directly
+    pub fn set_proceeds_stream(ctx: Context<UpdateEscrow>, seconds: Option<i64>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            seconds.is_none_or(|seconds| seconds > 0),
+            EscrowError::InvalidStreamDuration
+        );
+        escrow.set_proceeds_stream(seconds);
+
+        msg!(
+            "Escrow {} proceeds stream: {:?} seconds",
+            ctx.accounts.escrow.key(),
+            seconds
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw the streamed part of a fill's proceeds to the seller
+    /// - Closes the stream once everything is withdrawn, returning its
+    ///   rent to the buyer who funded it
+    pub fn withdraw_stream<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawStream<'info>>,
+    ) -> Result<()> {
+        let stream = &ctx.accounts.stream;
+        let withdrawable = stream
+            .streamed(Clock::get()?.unix_timestamp)?
+            .checked_sub(stream.withdrawn_amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(withdrawable > 0, EscrowError::NothingStreamed);
+
+        let escrow_key = stream.escrow;
+        let nonce = stream.nonce.to_le_bytes();
+        let stream_seeds = &[
+            b"stream",
+            escrow_key.as_ref(),
+            nonce.as_ref(),
+            &[stream.bump],
+        ];
+        let signer_seeds = &[&stream_seeds[..]];
+        let stream_info = stream.to_account_info();
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.stream_vault.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.seller_request_token.to_account_info(),
+                    authority: stream_info.clone(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            withdrawable,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        let stream = &mut ctx.accounts.stream;
+        stream.withdrawn_amount = stream
+            .withdrawn_amount
+            .checked_add(withdrawable)
+            .ok_or(EscrowError::MathOverflow)?;
+        msg!(
+            "Withdrew {} of {} streamed tokens",
+            stream.withdrawn_amount,
+            stream.total_amount
+        );
+
+        if stream.withdrawn_amount == stream.total_amount {
+            close_token_account(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.stream_vault.to_account_info(),
+                ctx.accounts.request_mint.to_account_info(),
+                ctx.accounts.buyer.to_account_info(),
+                stream_info,
+                signer_seeds,
+            )?;
+            stream.close(ctx.accounts.buyer.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Claim the vested part of a fill's offer tokens
+    /// - Closes the vesting once everything is claimed
+    /// - Remaining accounts supply transfer-hook accounts for the offer mint
+    pub fn claim_vested<'info>(ctx: Context<'_, '_, '_, 'info, ClaimVested<'info>>) -> Result<()> {
+        let vesting = &ctx.accounts.vesting;
+        let claimable = vesting
+            .vested(Clock::get()?.unix_timestamp)?
+            .checked_sub(vesting.claimed_amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(claimable > 0, EscrowError::NothingVested);
+
+        let escrow_key = vesting.escrow;
+        let beneficiary_key = vesting.beneficiary;
+        let nonce = vesting.nonce.to_le_bytes();
+        let vesting_seeds = &[
+            b"vesting",
+            escrow_key.as_ref(),
+            beneficiary_key.as_ref(),
+            nonce.as_ref(),
+            &[vesting.bump],
+        ];
+        let signer_seeds = &[&vesting_seeds[..]];
+        let vesting_info = vesting.to_account_info();
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vesting_vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.beneficiary_token.to_account_info(),
+                    authority: vesting_info.clone(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            claimable,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.claimed_amount = vesting
+            .claimed_amount
+            .checked_add(claimable)
+            .ok_or(EscrowError::MathOverflow)?;
+        msg!(
+            "Claimed {} of {} vested tokens",
+            vesting.claimed_amount,
+            vesting.total_amount
+        );
+
+        if vesting.claimed_amount == vesting.total_amount {
+            close_token_account(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.vesting_vault.to_account_info(),
+                ctx.accounts.offer_mint.to_account_info(),
+                ctx.accounts.beneficiary.to_account_info(),
+                vesting_info,
+                signer_seeds,
+            )?;
+            vesting.close(ctx.accounts.beneficiary.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Refund the buyer of a settlement decided in their favour and reopen
+    /// the escrow to its seller
+    /// - Remaining accounts supply transfer-hook accounts for the request
+    ///   mint
+    pub fn refund_settlement<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundSettlement<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.settlement.status == SettlementStatus::AwardedToBuyer,
+            EscrowError::InvalidSettlementStatus
+        );
+        let escrow_key = ctx.accounts.escrow.key();
+        let settlement_seeds = &[
+            b"settlement",
+            escrow_key.as_ref(),
+            &[ctx.accounts.settlement.bump],
+        ];
+        let signer_seeds = &[&settlement_seeds[..]];
+        let settlement_info = ctx.accounts.settlement.to_account_info();
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.settlement_vault.to_account_info(),
+                    mint: ctx.accounts.request_mint.to_account_info(),
+                    to: ctx.accounts.buyer_request_token.to_account_info(),
+                    authority: settlement_info.clone(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            ctx.accounts.settlement_vault.amount,
+            ctx.accounts.request_mint.decimals,
+        )?;
+
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.settlement_vault.to_account_info(),
+            ctx.accounts.request_mint.to_account_info(),
+            ctx.accounts.buyer.to_account_info(),
+            settlement_info,
+            signer_seeds,
+        )?;
+
+        ctx.accounts.escrow.load_mut()?.set_locked_by(None);
+
+        msg!("Held settlement of escrow {} refunded", escrow_key);
+
+        Ok(())
+    }
+
+    /// Mint a receipt token representing the seller's escrow position
+    /// - One receipt per escrow, minted to the seller's associated token account
+    /// - Metadata lists the escrow PDA as its verified creator
+    pub fn mint_receipt(
+        ctx: Context<MintReceipt>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptAlreadyMinted
+        );
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        // Extra request legs are paid to the seller, not the receipt holder
+        require!(!escrow.has_extra_payments(), EscrowError::HasRequestLegs);
+        require!(
+            escrow.proceeds_stream().is_none(),
+            EscrowError::StreamedEscrow
+        );
+
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
+
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    to: ctx.accounts.seller_receipt_token.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            1,
+        )?;
+
+        create_metadata_accounts_v3(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                CreateMetadataAccountsV3 {
+                    metadata: ctx.accounts.receipt_metadata.to_account_info(),
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    mint_authority: ctx.accounts.escrow.to_account_info(),
+                    payer: ctx.accounts.seller.to_account_info(),
+                    update_authority: ctx.accounts.escrow.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            DataV2 {
+                name,
+                symbol,
+                uri,
+                seller_fee_basis_points: 0,
+                creators: Some(vec![Creator {
+                    address: ctx.accounts.escrow.key(),
+                    verified: true,
+                    share: 100,
+                }]),
+                collection: None,
+                uses: None,
+            },
+            false,
+            true,
+            None,
+        )?;
+
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        escrow.set_receipt_mint(Some(ctx.accounts.receipt_mint.key()));
+        escrow.proceeds_bump = ctx.bumps.proceeds_vault;
+
+        msg!("Receipt minted for escrow {}", ctx.accounts.escrow.key());
+
+        Ok(())
+    }
+
+    /// Redeem a position receipt
+    /// - Any holder may burn the receipt to take over the position
+    /// - Unfilled offer tokens and accumulated proceeds go to the holder
+    /// - Closes the escrow accounts, returning rent to the seller
+    pub fn redeem_receipt<'info>(
+        ctx: Context<'_, '_, '_, 'info, RedeemReceipt<'info>>,
+    ) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        let (seller_key, offer_amount) = (escrow.seller, escrow.offer_amount);
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
+
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    from: ctx.accounts.holder_receipt_token.to_account_info(),
+                    authority: ctx.accounts.holder.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        // Unfilled position: return the offer tokens to the holder
+        if offer_amount > 0 {
+            let vault = ctx
+                .accounts
+                .vault
+                .as_ref()
+                .ok_or(EscrowError::MissingVaultAccount)?;
+
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: vault.to_account_info(),
+                        mint: ctx.accounts.offer_mint.to_account_info(),
+                        to: ctx.accounts.holder_offer_token.to_account_info(),
+                        authority: ctx.accounts.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                offer_amount,
+                ctx.accounts.offer_mint.decimals,
+            )?;
+
+            close_token_account(
+                ctx.accounts.token_program.to_account_info(),
+                vault.to_account_info(),
+                ctx.accounts.offer_mint.to_account_info(),
+                rent_destination.clone(),
+                ctx.accounts.escrow.to_account_info(),
+                signer_seeds,
+            )?;
+
+            ctx.accounts
+                .pair_index
+                .record_close(offer_mint_key, request_mint_key);
+            SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        }
+
+        // Sweep any proceeds collected from fills
+        let proceeds = ctx.accounts.proceeds_vault.amount;
+        if proceeds > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.request_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.proceeds_vault.to_account_info(),
+                        mint: ctx.accounts.request_mint.to_account_info(),
+                        to: ctx.accounts.holder_request_token.to_account_info(),
+                        authority: ctx.accounts.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                proceeds,
+                ctx.accounts.request_mint.decimals,
+            )?;
+        }
+
+        close_token_account(
+            ctx.accounts.request_token_program.to_account_info(),
+            ctx.accounts.proceeds_vault.to_account_info(),
+            ctx.accounts.request_mint.to_account_info(),
+            ctx.accounts.seller.to_account_info(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow,
+            ctx.accounts.seller.to_account_info(),
+            rent_destination,
+        )?;
+
+        msg!(
+            "Receipt redeemed: {} offer tokens and {} proceeds to holder",
+            offer_amount,
+            proceeds
+        );
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Redeemed,
+            escrow: ctx.accounts.escrow.key(),
+            seller: seller_key,
+            buyer: Some(ctx.accounts.holder.key()),
+            offer_amount,
+            request_amount: proceeds,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// Lock an escrow position as collateral for an external program
+    /// - Called via CPI by a program whitelisted on the escrow's market
+    /// - The program signs with its `escrow_locker` PDA; the seller consents
+    /// - While locked the position cannot be cancelled, redeemed or updated
+    pub fn lock_escrow(ctx: Context<LockEscrow>) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require_keys_eq!(
+            ctx.accounts.market.key(),
+            escrow.market().ok_or(EscrowError::LockerNotApproved)?,
+            EscrowError::InvalidMarket
+        );
+        drop(escrow);
+
+        let locker_program = ctx.accounts.locker_program.key();
+        require!(
+            ctx.accounts
+                .market
+                .approved_lockers
+                .contains(&locker_program),
+            EscrowError::LockerNotApproved
+        );
+        check_locker_authority(&ctx.accounts.locker_authority.key(), &locker_program)?;
+
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_locked_by(Some(locker_program));
+
+        msg!(
+            "Escrow {} locked by {}",
+            ctx.accounts.escrow.key(),
+            locker_program
+        );
+
+        Ok(())
+    }
+
+    /// Release a collateral lock
+    /// - Only the program that placed the lock can release it
+    pub fn unlock_escrow(ctx: Context<UnlockEscrow>) -> Result<()> {
+        let locker_program = ctx.accounts.locker_program.key();
+        require!(
+            ctx.accounts.escrow.load()?.locked_by() == Some(locker_program),
+            EscrowError::Unauthorized
+        );
+        check_locker_authority(&ctx.accounts.locker_authority.key(), &locker_program)?;
+
+        ctx.accounts.escrow.load_mut()?.set_locked_by(None);
+
+        msg!("Escrow {} unlocked", ctx.accounts.escrow.key());
+
+        Ok(())
+    }
+
+    /// Approve a buyer for the seller's gated escrows
+    /// - Creates the seller's allowlist on first use
+    pub fn add_allowed_buyer(ctx: Context<AddAllowedBuyer>, buyer: Pubkey) -> Result<()> {
+        let allowlist = &mut ctx.accounts.buyer_allowlist;
+        allowlist.seller = ctx.accounts.seller.key();
+        allowlist.bump = ctx.bumps.buyer_allowlist;
+
+        if !allowlist.buyers.contains(&buyer) {
+            require!(
+                allowlist.buyers.len() < MAX_BUYER_ALLOWLIST_LEN,
+                EscrowError::AllowlistFull
+            );
+            allowlist.buyers.push(buyer);
+        }
+
+        msg!("Buyer {} added to allowlist", buyer);
+
+        Ok(())
+    }
+
+    /// Revoke a buyer's approval
+    /// - Takes effect immediately for every escrow gated on the allowlist
+    pub fn remove_allowed_buyer(ctx: Context<RemoveAllowedBuyer>, buyer: Pubkey) -> Result<()> {
+        ctx.accounts
+            .buyer_allowlist
+            .buyers
+            .retain(|allowed| *allowed != buyer);
+
+        msg!("Buyer {} removed from allowlist", buyer);
+
+        Ok(())
+    }
+
+    /// Authorize an operator to cancel, update and top up the seller's
+    /// escrows
+    /// - Replaces any previous operator; proceeds and refunds still go to
+    ///   the seller
+    pub fn set_seller_operator(ctx: Context<SetSellerOperator>, operator: Pubkey) -> Result<()> {
+        let seller_operator = &mut ctx.accounts.seller_operator;
+        seller_operator.seller = ctx.accounts.seller.key();
+        seller_operator.operator = operator;
+        seller_operator.bump = ctx.bumps.seller_operator;
+
+        msg!("Operator {} set", operator);
+
+        Ok(())
+    }
+
+    /// Revoke the seller's operator, returning the account's rent
+    pub fn remove_seller_operator(ctx: Context<RemoveSellerOperator>) -> Result<()> {
+        msg!("Operator {} removed", ctx.accounts.seller_operator.operator);
+
+        Ok(())
+    }
+
+    /// Let `session_key` act for the owner until `expires_at`, at most
+    /// `MAX_SESSION_DURATION` away
+    /// - `permissions` are `SESSION_*` flags: accepting escrows as the
+    ///   owner's beneficiary, spending at most `spend_cap` request tokens,
+    ///   and cancelling the owner's escrows
+    /// - To accept, the owner approves the session account as delegate of
+    ///   their request token account
+    pub fn create_session(
+        ctx: Context<CreateSession>,
+        session_key: Pubkey,
+        expires_at: i64,
+        permissions: u8,
+        spend_cap: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            expires_at > now && expires_at - now <= MAX_SESSION_DURATION,
+            EscrowError::InvalidSession
+        );
+        require!(
+            permissions != 0 && permissions & !(SESSION_ACCEPT | SESSION_CANCEL) == 0,
+            EscrowError::InvalidSession
+        );
+
+        let session = &mut ctx.accounts.session;
+        session.owner = ctx.accounts.owner.key();
+        session.session_key = session_key;
+        session.expires_at = expires_at;
+        session.permissions = permissions;
+        session.spend_remaining = spend_cap;
+        session.bump = ctx.bumps.session;
+
+        msg!("Session key {} valid until {}", session_key, expires_at);
+
+        Ok(())
+    }
+
+    /// End a session early, returning its rent to the owner
+    pub fn revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+        msg!("Session key {} revoked", ctx.accounts.session.session_key);
+
+        Ok(())
+    }
+
+    /// Gate acceptance of an escrow on an external condition
+    /// - The condition must resolve true before `deadline`, otherwise the
+    ///   escrow can be refunded by anyone
+    pub fn set_escrow_condition(
+        ctx: Context<SetEscrowCondition>,
+        kind: ConditionKind,
+        deadline: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(deadline > now, EscrowError::InvalidDeadline);
+
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(
+            escrow.condition().is_none(),
+            EscrowError::ConditionAlreadySet
+        );
+        require!(escrow.hashlock().is_none(), EscrowError::Hashlocked);
+        escrow.set_condition(Some(Condition {
+            kind,
+            deadline,
+            set_at: now,
+            met: false,
+        }));
+
+        msg!(
+            "Escrow {} gated on condition until {}",
+            ctx.accounts.escrow.key(),
+            deadline
+        );
+
+        Ok(())
+    }
+
+    /// Resolve an escrow's condition as met
+    /// - Price conditions are permissionless and read a Pyth price update
+    ///   published between the condition being set and its deadline
+    /// - Attested conditions must be signed by their resolver
+    pub fn resolve_condition(ctx: Context<ResolveCondition>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        let mut condition = escrow.condition().ok_or(EscrowError::NoCondition)?;
+        require!(!condition.met, EscrowError::ConditionAlreadyMet);
+        require!(
+            Clock::get()?.unix_timestamp <= condition.deadline,
+            EscrowError::ConditionExpired
+        );
+
+        match condition.kind {
+            ConditionKind::PriceAtLeast {
+                feed_id,
+                threshold,
+                exponent,
+            }
+            | ConditionKind::PriceAtMost {
+                feed_id,
+                threshold,
+                exponent,
+            } => {
+                let price_update = ctx
+                    .accounts
+                    .price_update
+                    .as_ref()
+                    .ok_or(EscrowError::MissingOracleAccount)?;
+                let price = PythPrice::load(price_update, &feed_id)?;
+                require!(
+                    price.publish_time >= condition.set_at
+                        && price.publish_time <= condition.deadline,
+                    EscrowError::StaleOracle
+                );
+
+                let ordering = price.compare(threshold, exponent)?;
+                let met = match condition.kind {
+                    ConditionKind::PriceAtLeast { .. } => ordering.is_ge(),
+                    _ => ordering.is_le(),
+                };
+                require!(met, EscrowError::ConditionNotMet);
+            }
+            ConditionKind::Attested { resolver } => {
+                let signer = ctx
+                    .accounts
+                    .resolver
+                    .as_ref()
+                    .ok_or(EscrowError::Unauthorized)?;
+                require_keys_eq!(signer.key(), resolver, EscrowError::Unauthorized);
+            }
+        }
+
+        condition.met = true;
+        escrow.set_condition(Some(condition));
+
+        msg!("Condition met for escrow {}", ctx.accounts.escrow.key());
+
+        Ok(())
+    }
+
+    /// Refund an escrow whose condition was not met by its deadline
+    /// - Permissionless; tokens always go back to the seller, and rent to
+    ///   its rent recipient
+    pub fn refund_unmet_condition<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundEscrow<'info>>,
+    ) -> Result<()> {
+        let condition = ctx
+            .accounts
+            .escrow
+            .load()?
+            .condition()
+            .ok_or(EscrowError::NoCondition)?;
+        require!(!condition.met, EscrowError::ConditionAlreadyMet);
+        require!(
+            Clock::get()?.unix_timestamp > condition.deadline,
+            EscrowError::ConditionNotExpired
+        );
+
+        ctx.accounts
+            .refund(ctx.remaining_accounts, EscrowStatus::Expired)?;
+
+        msg!("Condition unmet, escrow refunded to seller");
+
+        Ok(())
+    }
+
+    /// Lock an escrow as a hashed timelock for a cross-chain atomic swap
+    /// - Until the escrow expires, only the preimage of `hashlock` can
+    ///   release the offer, and only to the designated buyer; the price is
+    ///   settled on the counterparty chain
+    /// - The seller can neither cancel, withdraw nor update the escrow
+    ///   until then, after which anyone can expire it back to the seller
+    pub fn set_hashlock(ctx: Context<UpdateEscrow>, hashlock: [u8; 32]) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        // A delegated offer could be revoked from under the buyer
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+        require!(
+            escrow.hashlock().is_none()
+                && escrow.condition().is_none()
+                && escrow.arbitration().is_none(),
+            EscrowError::InvalidHashlock
+        );
+        require!(
+            hashlock != [0; 32]
+                && escrow.designated_buyer().is_some()
+                && escrow
+                    .expires_at()
+                    .is_some_and(|expires_at| now < expires_at),
+            EscrowError::InvalidHashlock
+        );
+        escrow.set_hashlock(Some(hashlock));
+
+        msg!("Escrow {} hashlocked", ctx.accounts.escrow.key());
+
+        Ok(())
+    }
+
+    /// Release a hashlocked escrow's offer to its designated buyer
+    /// - Permissionless: anyone holding the preimage can claim on the
+    ///   buyer's behalf before the escrow expires
+    /// - The preimage is published in the `HashlockClaimed` event, for the
+    ///   seller to claim the counterparty chain's side of the swap
+    pub fn claim_hashlock<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimHashlock<'info>>,
+        preimage: [u8; 32],
+    ) -> Result<()> {
+        let escrow = *ctx.accounts.escrow.load()?;
+        let hashlock = escrow.hashlock().ok_or(EscrowError::InvalidHashlock)?;
+        require!(
+            escrow.hashlock_active(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowExpired
+        );
+        require!(
+            hash(&preimage).to_bytes() == hashlock,
+            EscrowError::InvalidPreimage
+        );
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+
+        let seller_key = escrow.seller;
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow.escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.buyer_offer_token.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            escrow.offer_amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            rent_destination.clone(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+
+        ctx.accounts
+            .pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        Stats::record(&ctx.accounts.stats, |stats| {
+            stats.escrows_filled = stats.escrows_filled.saturating_add(1)
+        })?;
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.deregister(&ctx.accounts.escrow.key())?;
+        }
+
+        let mut filled = ctx.accounts.escrow.load_mut()?;
+        filled.record_fill();
+        filled.set_status(EscrowStatus::Filled);
+        drop(filled);
+        if !escrow.keep_record() {
+            close_escrow(
+                &ctx.accounts.escrow,
+                ctx.accounts.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
+
+        msg!("Hashlocked escrow claimed by its buyer");
+        let event = HashlockClaimed {
+            escrow: ctx.accounts.escrow.key(),
+            seller: seller_key,
+            buyer: ctx.accounts.buyer.key(),
+            offer_mint: offer_mint_key,
+            offer_amount: escrow.offer_amount,
+            preimage,
+        };
+        emit!(event);
+        emit_cpi!(event);
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Filled,
+            escrow: event.escrow,
+            seller: seller_key,
+            buyer: Some(event.buyer),
+            offer_amount: escrow.offer_amount,
+            request_amount: escrow.request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// Let a payment attested over Wormhole settle the escrow, or settle
+    /// on Solana only with `None`
+    /// - `chain` must have a foreign emitter trusted by the config
+    ///   authority; the seller trusts it to collect their price there
+    pub fn set_wormhole_settlement(ctx: Context<UpdateEscrow>, chain: Option<u16>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(chain != Some(0), EscrowError::NotWormholeSettled);
+        if chain.is_some() {
+            // Wormhole settlement has no taker on this chain to check these
+            // against, so they only fill through `accept_escrow`
+            require!(
+                !escrow.is_gated() && escrow.buyer_allowlist().is_none(),
+                EscrowError::GatedEscrow
+            );
+            require!(
+                escrow.reveal_delay().is_none(),
+                EscrowError::MissingFillCommitment
+            );
+        }
+        escrow.set_wormhole_chain(chain);
+
+        msg!(
+            "Escrow {} Wormhole settlement: {:?}",
+            ctx.accounts.escrow.key(),
+            chain
+        );
+
+        Ok(())
+    }
+
+    /// Release an escrow's whole offer for a payment made on another chain
+    /// - Permissionless: anyone may relay the core bridge's posted VAA,
+    ///   emitted by the trusted emitter of the escrow's Wormhole chain
+    /// - The VAA's payload names the escrow, the Solana recipient, and the
+    ///   offer and request amounts, which must cover the escrow's price
+    /// - Each message settles once, tracked by `received_message`
+    /// - Escrows under a market, receipt, lock, hashlock, delegation, extra
+    ///   request legs, oracle pricing, vesting, streaming or arbitration
+    ///   cannot settle over Wormhole, nor can gated, allowlisted or
+    ///   commit-reveal escrows
+    /// - A reservation or bought option holds the escrow for its holder as
+    ///   the recipient
+    pub fn accept_wormhole_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptWormholeEscrow<'info>>,
+        emitter_chain: u16,
+        sequence: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        let escrow = *ctx.accounts.escrow.load()?;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            escrow.wormhole_chain() == Some(emitter_chain),
+            EscrowError::NotWormholeSettled
+        );
+        require!(
+            escrow.market().is_none()
+                && escrow.receipt_mint().is_none()
+                && escrow.locked_by().is_none()
+                && escrow.hashlock().is_none()
+                && escrow.delegated_source().is_none()
+                && !escrow.has_extra_payments()
+                && escrow.oracle_pricing().is_none()
+                && escrow.vesting().is_none()
+                && escrow.proceeds_stream().is_none()
+                && escrow.arbitration().is_none(),
+            EscrowError::NotWormholeSettled
+        );
+        require!(
+            !escrow.is_gated() && escrow.buyer_allowlist().is_none(),
+            EscrowError::GatedEscrow
+        );
+        require!(
+            escrow.reveal_delay().is_none(),
+            EscrowError::MissingFillCommitment
+        );
+        if let Some(starts_at) = escrow.starts_at() {
+            require!(now >= starts_at, EscrowError::EscrowNotStarted);
+        }
+        if let Some(expires_at) = escrow.expires_at() {
+            require!(now < expires_at, EscrowError::EscrowExpired);
+        }
+        if let Some(condition) = &escrow.condition() {
+            require!(condition.met, EscrowError::ConditionNotMet);
+        }
+        escrow.check_reservation(&ctx.accounts.recipient.key(), now)?;
+
+        let payment =
+            WormholePayment::load(&ctx.accounts.posted_vaa, &ctx.accounts.foreign_emitter)?;
+        require!(
+            payment.sequence == sequence
+                && payment.escrow == ctx.accounts.escrow.key()
+                && payment.recipient == ctx.accounts.recipient.key()
+                && payment.offer_amount == escrow.offer_amount
+                && payment.request_amount >= escrow.request_amount,
+            EscrowError::InvalidWormholePayment
+        );
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(
+                payment.recipient,
+                designated_buyer,
+                EscrowError::NotDesignatedBuyer
+            );
+        }
+        let received = &mut ctx.accounts.received_message;
+        received.escrow = payment.escrow;
+        received.bump = ctx.bumps.received_message;
+
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+        let seller_key = escrow.seller;
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow.escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.recipient_offer_token.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            escrow.offer_amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            rent_destination.clone(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+
+        ctx.accounts
+            .pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+        Stats::record(&ctx.accounts.stats, |stats| {
+            stats.fills = stats.fills.saturating_add(1);
+            stats.escrows_filled = stats.escrows_filled.saturating_add(1);
+        })?;
+        if let Some(registry_page) = &mut ctx.accounts.registry_page {
+            registry_page.deregister(&ctx.accounts.escrow.key())?;
+        }
+
+        let mut filled = ctx.accounts.escrow.load_mut()?;
+        filled.record_fill();
+        filled.set_status(EscrowStatus::Filled);
+        drop(filled);
+        if !escrow.keep_record() {
+            close_escrow(
+                &ctx.accounts.escrow,
+                ctx.accounts.seller.to_account_info(),
+                rent_destination,
+            )?;
+        }
+
+        msg!("Escrow accepted over Wormhole from chain {}", emitter_chain);
+        let event = WormholeEscrowAccepted {
+            escrow: ctx.accounts.escrow.key(),
+            seller: seller_key,
+            recipient: payment.recipient,
+            offer_mint: offer_mint_key,
+            offer_amount: escrow.offer_amount,
+            request_amount: payment.request_amount,
+            emitter_chain,
+            sequence,
+        };
+        emit!(event);
+        emit_cpi!(event);
+        ActivityLog {
+            config: &ctx.accounts.config,
+            merkle_tree: ctx.accounts.activity_log.as_ref(),
+            log_wrapper: ctx.accounts.log_wrapper.as_ref(),
+            compression_program: ctx.accounts.compression_program.as_ref(),
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Filled,
+            escrow: event.escrow,
+            seller: seller_key,
+            buyer: Some(event.recipient),
+            offer_amount: escrow.offer_amount,
+            request_amount: payment.request_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    /// Cancel any open escrow as the config authority, for incident
+    /// response such as a compromised mint
+    /// - Tokens always go back to the seller, and rent to its rent recipient
+    /// - Locked and receipted escrows must first be released by their
+    ///   locker or receipt holder
+    pub fn admin_cancel_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundEscrow<'info>>,
+    ) -> Result<()> {
+        let authority = ctx
+            .accounts
+            .authority
+            .as_ref()
+            .ok_or(EscrowError::Unauthorized)?
+            .key();
+        require_keys_eq!(
+            authority,
+            ctx.accounts.config.authority,
+            EscrowError::Unauthorized
+        );
+        let escrow = ctx.accounts.escrow.load()?;
+        // Taken before the refund closes the escrow
+        let event = AdminCancelled {
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            offer_mint: escrow.offer_mint,
+            request_mint: escrow.request_mint,
+            offer_amount: escrow.offer_amount,
+            authority,
+            label: escrow.label(),
+        };
+        drop(escrow);
+
+        ctx.accounts
+            .refund(ctx.remaining_accounts, EscrowStatus::Cancelled)?;
+
+        msg!("Escrow cancelled by the config authority, refunded to seller");
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// `release_settlement` for automation threads, which call on a
+    /// schedule rather than once the settlement is due
+    /// - Succeeds without effect until the settlement can be released
+    pub fn tick_settlement<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseSettlement<'info>>,
+    ) -> Result<()> {
+        if !ctx
+            .accounts
+            .settlement
+            .releasable(Clock::get()?.unix_timestamp)
+        {
+            msg!("Settlement not yet releasable");
+            return Ok(());
+        }
+        release_settlement(ctx)
+    }
+
+    /// Refund an escrow past its expiry
+    /// - Permissionless; tokens always go back to the seller, and rent to
+    ///   its rent recipient
+    /// - Any crank tip goes back to the seller; only `crank_expired` pays
+    ///   it out
+    pub fn expire_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundEscrow<'info>>,
+    ) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        let expires_at = escrow.expires_at().ok_or(EscrowError::EscrowNotExpired)?;
+        require!(
+            Clock::get()?.unix_timestamp >= expires_at,
+            EscrowError::EscrowNotExpired
+        );
+        // Taken before the refund closes the escrow
+        let event = EscrowExpired {
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            offer_mint: escrow.offer_mint,
+            request_mint: escrow.request_mint,
+            offer_amount: escrow.offer_amount,
+            label: escrow.label(),
+        };
+        drop(escrow);
+
+        ctx.accounts
+            .refund(ctx.remaining_accounts, EscrowStatus::Expired)?;
+
+        msg!("Escrow expired, refunded to seller");
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Refund an escrow past its expiry and pay the caller the configured
+    /// crank bounty out of the escrow's rent, plus the tip its seller
+    /// deposited at creation
+    /// - Permissionless; the tokens go back to the seller and the rest of
+    ///   the rent to its rent recipient
+    pub fn crank_expired<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundEscrow<'info>>,
+    ) -> Result<()> {
+        let escrow = ctx.accounts.escrow.load()?;
+        let expires_at = escrow.expires_at().ok_or(EscrowError::EscrowNotExpired)?;
+        require!(
+            Clock::get()?.unix_timestamp >= expires_at,
+            EscrowError::EscrowNotExpired
+        );
+        // Taken before the refund closes the escrow
+        let event = EscrowExpired {
+            escrow: ctx.accounts.escrow.key(),
+            seller: escrow.seller,
+            offer_mint: escrow.offer_mint,
+            request_mint: escrow.request_mint,
+            offer_amount: escrow.offer_amount,
+            label: escrow.label(),
+        };
+        let keep_record = escrow.keep_record();
+        let bond_lamports = escrow.cancellation_bond().map_or(0, |bond| bond.lamports);
+        let tip = escrow.crank_tip();
+        drop(escrow);
+        let cranker = ctx
+            .accounts
+            .cranker
+            .as_ref()
+            .ok_or(EscrowError::Unauthorized)?
+            .to_account_info();
+
+        // The escrow is closed by the refund, so the bounty
+        // is carved out of its lamports first; a kept record must stay
+        // rent-exempt, and the seller's bond is never paid out
+        let escrow_info = ctx.accounts.escrow.to_account_info();
+        let reserved = if keep_record {
+            Rent::get()?.minimum_balance(escrow_info.data_len())
+        } else {
+            0
+        }
+        .checked_add(bond_lamports)
+        .and_then(|reserved| reserved.checked_add(tip))
+        .ok_or(EscrowError::MathOverflow)?;
+        let bounty = ctx
+            .accounts
+            .config
+            .crank_bounty_lamports
+            .min(escrow_info.lamports().saturating_sub(reserved))
+            .checked_add(tip)
+            .ok_or(EscrowError::MathOverflow)?;
+        {
+            let mut escrow_lamports = escrow_info.try_borrow_mut_lamports()?;
+            **escrow_lamports = escrow_lamports
+                .checked_sub(bounty)
+                .ok_or(EscrowError::MathOverflow)?;
+            let mut cranker_lamports = cranker.try_borrow_mut_lamports()?;
+            **cranker_lamports = cranker_lamports
+                .checked_add(bounty)
+                .ok_or(EscrowError::MathOverflow)?;
+        }
+        if tip > 0 {
+            ctx.accounts.escrow.load_mut()?.set_crank_tip(0);
+        }
+
+        ctx.accounts
+            .refund(ctx.remaining_accounts, EscrowStatus::Expired)?;
+
+        msg!("Expired escrow cranked, {} lamport bounty paid", bounty);
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Run whatever is due on an escrow, for automation threads that call
+    /// on a schedule rather than once an action is due
+    /// - Expires an escrow past its expiry, paying a signing `cranker` the
+    ///   crank bounty as `crank_expired` does
+    /// - Succeeds without effect while nothing is due, so scheduled calls
+    ///   never fail
+    pub fn tick<'info>(ctx: Context<'_, '_, '_, 'info, RefundEscrow<'info>>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let expired = ctx
+            .accounts
+            .escrow
+            .load()?
+            .expires_at()
+            .is_some_and(|expires_at| now >= expires_at);
+        if !expired {
+            msg!("Nothing due on escrow {}", ctx.accounts.escrow.key());
+            return Ok(());
+        }
+        if ctx.accounts.cranker.is_some() {
+            crank_expired(ctx)
+        } else {
+            expire_escrow(ctx)
+        }
+    }
+
+    /// Hand the rent an escrow returns on close to another account, such
+    /// as a keeper cleaning it up
+    /// - Signed by the current rent recipient: the creation payer, or the
+    ///   seller of an escrow created before rent recipients were stored
+    pub fn set_rent_recipient(ctx: Context<SetRentRecipient>, recipient: Pubkey) -> Result<()> {
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_rent_recipient(recipient);
+
+        msg!(
+            "Escrow {} rent now returns to {}",
+            ctx.accounts.escrow.key(),
+            recipient
+        );
+
+        Ok(())
+    }
+
+    /// Close a settled escrow kept as a record, returning its rent to its
+    /// rent recipient
+    pub fn close_record(ctx: Context<CloseRecord>) -> Result<()> {
+        let rent_destination = ctx
+            .accounts
+            .escrow
+            .load()?
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+        close_escrow(
+            &ctx.accounts.escrow,
+            ctx.accounts.seller.to_account_info(),
+            rent_destination,
+        )?;
+        msg!("Escrow record {} closed", ctx.accounts.escrow.key());
+
+        Ok(())
+    }
+
+    /// Return the contents of a vault stranded without its escrow to the
+    /// seller the escrow's address was derived from
+    /// - Signed by the seller or the config authority; the escrow address
+    ///   must hold no live escrow, having been closed or overwritten, so
+    ///   vaults of escrows that still exist go through their usual paths
+    /// - The vault must be the escrow's offer-mint vault at either its
+    ///   associated or its pre-version-12 PDA address; the tokens go to
+    ///   the seller's token account and the vault's rent to the seller
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn recover_vault<'info>(
+        ctx: Context<'_, '_, '_, 'info, RecoverVault<'info>>,
+        request_mint: Pubkey,
+        escrow_id: u64,
+    ) -> Result<()> {
+        let accounts = &ctx.accounts;
+        let seller_key = accounts.seller.key();
+        require!(
+            accounts.authority.key() == seller_key
+                || accounts.authority.key() == accounts.config.authority,
+            EscrowError::Unauthorized
+        );
+        let escrow_info = accounts.escrow.to_account_info();
+        let live = escrow_info.owner == &crate::ID
+            && escrow_info
+                .try_borrow_data()?
+                .get(..8)
+                .is_some_and(|discriminator| discriminator == Escrow::DISCRIMINATOR);
+        require!(!live, EscrowError::VaultNotStranded);
+
+        let escrow_key = escrow_info.key();
+        let offer_mint_key = accounts.offer_mint.key();
+        let vault_key = accounts.vault.key();
+        require!(
+            vault_key
+                == get_associated_token_address_with_program_id(
+                    &escrow_key,
+                    &offer_mint_key,
+                    &accounts.token_program.key(),
+                )
+                || vault_key
+                    == Pubkey::find_program_address(&[b"vault", escrow_key.as_ref()], &crate::ID).0,
+            EscrowError::InvalidVault
+        );
+
+        let escrow_id = escrow_id.to_le_bytes();
+        let escrow_seeds = &[
+            b"escrow",
+            seller_key.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint.as_ref(),
+            escrow_id.as_ref(),
+            &[ctx.bumps.escrow],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        // Native SOL unwraps when the vault is closed to the seller
+        let amount = accounts.vault.amount;
+        if amount > 0 && !is_native_mint(&offer_mint_key) {
+            let seller_offer_token = accounts
+                .seller_offer_token
+                .as_ref()
+                .ok_or(EscrowError::MissingOfferTokenAccount)?;
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.vault.to_account_info(),
+                        mint: accounts.offer_mint.to_account_info(),
+                        to: seller_offer_token.to_account_info(),
+                        authority: escrow_info.clone(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                amount,
+                accounts.offer_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            accounts.token_program.to_account_info(),
+            accounts.vault.to_account_info(),
+            accounts.offer_mint.to_account_info(),
+            accounts.seller.to_account_info(),
+            escrow_info,
+            signer_seeds,
+        )?;
+
+        msg!(
+            "Stranded vault {} recovered, {} tokens returned to seller {}",
+            vault_key,
+            amount,
+            seller_key
+        );
+
+        Ok(())
+    }
+
+    /// Mark an escrow whose vault was frozen by the offer mint's freeze
+    /// authority as stuck
+    /// - Halts fills; once the vault is thawed the seller cancels it as
+    ///   usual, recovering the offer and both accounts' rent
+    pub fn mark_escrow_stuck(ctx: Context<MarkEscrowStuck>) -> Result<()> {
+        ctx.accounts
+            .escrow
+            .load_mut()?
+            .set_status(EscrowStatus::Stuck);
+
+        msg!("Escrow {} marked stuck", ctx.accounts.escrow.key());
+
+        Ok(())
+    }
+
+    /// Export a live escrow to a new deployment of this program
+    /// - Gated on the program's upgrade authority
+    /// - Moves the vault into a holding account owned by the target
+    ///   program and records the escrow's state for `import_escrow`
+    pub fn export_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExportEscrow<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.target_program.executable,
+            EscrowError::InvalidMigrationTarget
+        );
+        require_keys_neq!(
+            ctx.accounts.target_program.key(),
+            crate::ID,
+            EscrowError::InvalidMigrationTarget
+        );
+
+        let escrow = ctx.accounts.escrow.load()?;
+        // Receipts and locks are bound to this program's escrow PDA
+        require!(
+            escrow.receipt_mint().is_none(),
+            EscrowError::ReceiptOutstanding
+        );
+        require!(escrow.locked_by().is_none(), EscrowError::EscrowLocked);
+        require!(
+            !escrow.committed(Clock::get()?.unix_timestamp),
+            EscrowError::EscrowCommitted
+        );
+        require!(
+            escrow.delegated_source().is_none(),
+            EscrowError::DelegatedEscrow
+        );
+        let rent_destination = escrow
+            .rent_destination(&ctx.accounts.seller, ctx.accounts.rent_recipient.as_deref())?;
+
+        let record = &mut ctx.accounts.migration_record;
+        record.escrow = ctx.accounts.escrow.key();
+        record.target_program = ctx.accounts.target_program.key();
+        record.state = *escrow;
+        record.bump = ctx.bumps.migration_record;
+
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_bump = escrow.escrow_bump;
+        drop(escrow);
+
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.holding.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            ctx.accounts.vault.amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            rent_destination.clone(),
+            ctx.accounts.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+        close_escrow(
+            &ctx.accounts.escrow,
+            ctx.accounts.seller.to_account_info(),
+            rent_destination,
+        )?;
+
+        ctx.accounts
+            .pair_index
+            .record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&ctx.accounts.seller_index)?;
+
+        msg!(
+            "Escrow {} exported to {}",
+            record.escrow,
+            record.target_program
+        );
+
+        Ok(())
+    }
+
+    /// Import an escrow exported by a previous deployment
+    /// - Gated on the program's upgrade authority
+    /// - Recreates the escrow from the source program's migration record
+    ///   and moves the holding account into a fresh vault
+    pub fn import_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, ImportEscrow<'info>>,
+        escrow_id: u64,
+    ) -> Result<()> {
+        let record = MigrationRecord::load(
+            &ctx.accounts.migration_record,
+            &ctx.accounts.source_program.key(),
+        )?;
+        require_keys_eq!(
+            record.target_program,
+            crate::ID,
+            EscrowError::InvalidMigrationRecord
+        );
+        require_keys_eq!(
+            record.escrow,
+            ctx.accounts.source_escrow.key(),
+            EscrowError::InvalidMigrationRecord
+        );
+        let state = record.state;
+        require_keys_eq!(
+            state.seller,
+            ctx.accounts.seller.key(),
+            EscrowError::InvalidMigrationRecord
+        );
+        require_keys_eq!(
+            state.offer_mint,
+            ctx.accounts.offer_mint.key(),
+            EscrowError::InvalidMigrationRecord
+        );
+        require_keys_eq!(
+            state.request_mint,
+            ctx.accounts.request_mint.key(),
+            EscrowError::InvalidMigrationRecord
+        );
+        require_eq!(
+            state.escrow_id,
+            escrow_id,
+            EscrowError::InvalidMigrationRecord
+        );
+        // Listings reference markets of the source deployment
+        if let Some(market) = state.market() {
+            let market_info = ctx
+                .accounts
+                .market
+                .as_ref()
+                .ok_or(EscrowError::MissingMarketAccount)?;
+            require_keys_eq!(market_info.key(), market, EscrowError::InvalidMarket);
+        }
+
+        let source_escrow_key = ctx.accounts.source_escrow.key();
+        let authority_seeds = &[
+            b"import",
+            source_escrow_key.as_ref(),
+            &[ctx.bumps.import_authority],
+        ];
+        let signer_seeds = &[&authority_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.holding.to_account_info(),
+                    mint: ctx.accounts.offer_mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.import_authority.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            ctx.accounts.holding.amount,
+            ctx.accounts.offer_mint.decimals,
+        )?;
+
+        // Exports carry no receipt or lock, so the state moves as is apart
+        // from the escrow bump and vault under this program; transfer fees
+        // on the way over come out of the offer
+        ctx.accounts.vault.reload()?;
+        let mut escrow = ctx.accounts.escrow.load_init()?;
+        *escrow = state;
+        escrow.escrow_bump = ctx.bumps.escrow;
+        escrow.set_ata_vault();
+        escrow.set_status(EscrowStatus::Open);
+        escrow.offer_amount = ctx.accounts.vault.amount;
+        escrow.version = ESCROW_VERSION;
+
+        ctx.accounts
+            .pair_index
+            .record_open(escrow.offer_mint, escrow.request_mint);
+        drop(escrow);
+        let seller_index = &mut ctx.accounts.seller_index;
+        seller_index.seller = ctx.accounts.seller.key();
+        seller_index.bump = ctx.bumps.seller_index;
+        seller_index.record_open(escrow_id)?;
+
+        // Closing the holding account makes the record single-use
+        close_token_account(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.holding.to_account_info(),
+            ctx.accounts.offer_mint.to_account_info(),
+            ctx.accounts.admin.to_account_info(),
+            ctx.accounts.import_authority.to_account_info(),
+            signer_seeds,
+        )?;
+
+        msg!(
+            "Escrow {} imported from {}",
+            ctx.accounts.escrow.key(),
+            source_escrow_key
+        );
+
+        Ok(())
+    }
+
+    /// Upgrade an escrow written by an older build to the current layout
+    /// - Grows the account to the current size, with appended fields read
+    ///   as unset; the payer covers the extra rent
+    /// - Permissionless, and works on settled records too
+    /// - Escrows listed before their listing time was recorded are stamped
+    ///   with the migration time, which the minimum listing duration then
+    ///   counts from
+    pub fn migrate_escrow(ctx: Context<MigrateEscrow>) -> Result<()> {
+        let mut escrow = ctx.accounts.escrow.load_mut()?;
+        require!(escrow.version < ESCROW_VERSION, EscrowError::EscrowUpToDate);
+        let from_version = escrow.version;
+        escrow.version = ESCROW_VERSION;
+        if escrow.listed_at().is_none() {
+            escrow.set_listed_at(Clock::get()?.unix_timestamp);
+        }
+
+        msg!(
+            "Escrow {} migrated from version {} to {}",
+            ctx.accounts.escrow.key(),
+            from_version,
+            ESCROW_VERSION
+        );
+
+        Ok(())
+    }
+
+    /// Escrow a compressed NFT
+    /// - Transfers the leaf to the escrow PDA through Bubblegum
+    /// - `root` is the tree's current root; remaining accounts supply the
+    ///   leaf's merkle proof
+    pub fn create_cnft_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateCnftEscrow<'info>>,
+        leaf: CnftLeaf,
+        root: [u8; 32],
+        request_amount: u64,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        ctx.accounts.config.check_expiry(expires_at)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, &ctx.accounts.config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+
+        let escrow = &mut ctx.accounts.cnft_escrow;
+        escrow.seller = ctx.accounts.seller.key();
+        escrow.merkle_tree = ctx.accounts.merkle_tree.key();
+        escrow.leaf = leaf;
+        escrow.request_mint = ctx.accounts.request_mint.key();
+        escrow.request_amount = request_amount;
+        escrow.expires_at = expires_at;
+        escrow.bump = ctx.bumps.cnft_escrow;
+
+        BubblegumTransfer {
+            tree_authority: ctx.accounts.tree_authority.to_account_info(),
+            leaf_owner: ctx.accounts.seller.to_account_info(),
+            leaf_delegate: ctx.accounts.leaf_delegate.to_account_info(),
+            new_leaf_owner: ctx.accounts.cnft_escrow.to_account_info(),
+            merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+            log_wrapper: ctx.accounts.log_wrapper.to_account_info(),
+            compression_program: ctx.accounts.compression_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            bubblegum_program: ctx.accounts.bubblegum_program.to_account_info(),
+            proof: ctx.remaining_accounts,
+        }
+        .transfer(&leaf, root, &[])?;
+
+        msg!(
+            "Compressed NFT {} of tree {} escrowed for {} tokens",
+            leaf.nonce,
+            ctx.accounts.merkle_tree.key(),
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Buy an escrowed compressed NFT
+    /// - Pays the seller, less the protocol fee, and delivers the leaf to
+    ///   the buyer
+    /// - Remaining accounts supply the leaf's merkle proof, so hooked
+    ///   request mints are not supported
+    pub fn accept_cnft_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptCnftEscrow<'info>>,
+        root: [u8; 32],
+    ) -> Result<()> {
+        let accounts = &ctx.accounts;
+        require!(!accounts.config.paused, EscrowError::ProtocolPaused);
+        let escrow = &accounts.cnft_escrow;
+        if let Some(expires_at) = escrow.expires_at {
+            require!(
+                Clock::get()?.unix_timestamp < expires_at,
+                EscrowError::EscrowExpired
+            );
+        }
+
+        let request_amount = escrow.request_amount;
+        ListingPayment {
+            config: &accounts.config,
+            buyer: accounts.buyer.to_account_info(),
+            buyer_request_token: accounts.buyer_request_token.to_account_info(),
+            seller_request_token: accounts.seller_request_token.to_account_info(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            request_mint: &accounts.request_mint,
+            token_program: accounts.token_program.to_account_info(),
+        }
+        .pay(request_amount)?;
+
+        let merkle_tree_key = escrow.merkle_tree;
+        let nonce = escrow.leaf.nonce.to_le_bytes();
+        let escrow_seeds = &[
+            b"cnft_escrow",
+            merkle_tree_key.as_ref(),
+            nonce.as_ref(),
+            &[escrow.bump],
+        ];
+        BubblegumTransfer {
+            tree_authority: accounts.tree_authority.to_account_info(),
+            leaf_owner: escrow.to_account_info(),
+            leaf_delegate: escrow.to_account_info(),
+            new_leaf_owner: accounts.buyer.to_account_info(),
+            merkle_tree: accounts.merkle_tree.to_account_info(),
+            log_wrapper: accounts.log_wrapper.to_account_info(),
+            compression_program: accounts.compression_program.to_account_info(),
+            system_program: accounts.system_program.to_account_info(),
+            bubblegum_program: accounts.bubblegum_program.to_account_info(),
+            proof: ctx.remaining_accounts,
+        }
+        .transfer(&escrow.leaf, root, &[&escrow_seeds[..]])?;
+
+        msg!(
+            "Compressed NFT {} sold to {} for {} tokens",
+            escrow.leaf.nonce,
+            accounts.buyer.key(),
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Cancel a compressed NFT escrow and return the leaf to the seller
+    /// - Remaining accounts supply the leaf's merkle proof
+    pub fn cancel_cnft_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelCnftEscrow<'info>>,
+        root: [u8; 32],
+    ) -> Result<()> {
+        let escrow = &ctx.accounts.cnft_escrow;
+        let merkle_tree_key = escrow.merkle_tree;
+        let nonce = escrow.leaf.nonce.to_le_bytes();
+        let escrow_seeds = &[
+            b"cnft_escrow",
+            merkle_tree_key.as_ref(),
+            nonce.as_ref(),
+            &[escrow.bump],
+        ];
+        BubblegumTransfer {
+            tree_authority: ctx.accounts.tree_authority.to_account_info(),
+            leaf_owner: escrow.to_account_info(),
+            leaf_delegate: escrow.to_account_info(),
+            new_leaf_owner: ctx.accounts.seller.to_account_info(),
+            merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+            log_wrapper: ctx.accounts.log_wrapper.to_account_info(),
+            compression_program: ctx.accounts.compression_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            bubblegum_program: ctx.accounts.bubblegum_program.to_account_info(),
+            proof: ctx.remaining_accounts,
+        }
+        .transfer(&escrow.leaf, root, &[&escrow_seeds[..]])?;
+
+        msg!("Compressed NFT {} returned to seller", escrow.leaf.nonce);
+
+        Ok(())
+    }
+
+    /// Open an empty offer basket; fill it with `add_basket_leg`
+    pub fn create_basket_escrow(
+        ctx: Context<CreateBasketEscrow>,
+        basket_id: u64,
+        request_amount: u64,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(request_amount > 0, EscrowError::InvalidAmount);
+        ctx.accounts.config.check_expiry(expires_at)?;
+        MintPolicy::check_listing(&ctx.accounts.request_mint_policy, &ctx.accounts.config)?;
+        SellerBan::check_listing(&ctx.accounts.seller_ban)?;
+
+        let basket = &mut ctx.accounts.basket;
+        basket.seller = ctx.accounts.seller.key();
+        basket.basket_id = basket_id;
+        basket.legs = Vec::new();
+        basket.request_mint = ctx.accounts.request_mint.key();
+        basket.request_amount = request_amount;
+        basket.expires_at = expires_at;
+        basket.bump = ctx.bumps.basket;
+
+        msg!(
+            "Basket {} opened for {} tokens",
+            basket.key(),
+            request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Add one mint to an offer basket
+    /// - Locks `amount` of the mint in a vault derived from the basket
+    /// - Remaining accounts supply transfer-hook accounts for hooked mints
+    pub fn add_basket_leg<'info>(
+        ctx: Context<'_, '_, '_, 'info, AddBasketLeg<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProtocolPaused);
+        require!(amount > 0, EscrowError::InvalidAmount);
+        require!(
+            ctx.accounts.basket.legs.len() < MAX_BASKET_LEGS,
+            EscrowError::BasketFull
+        );
+        MintPolicy::check_listing(&ctx.accounts.mint_policy, &ctx.accounts.config)?;
+
+        Deposit {
+            seller: ctx.accounts.seller.to_account_info(),
+            seller_offer_token: ctx
+                .accounts
+                .seller_token
+                .as_ref()
+                .map(|token| token.to_account_info()),
+            vault: ctx.accounts.vault.to_account_info(),
+            offer_mint: &ctx.accounts.mint,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            remaining_accounts: ctx.remaining_accounts,
+        }
+        .deposit(amount)?;
+
+        // Only what reaches the vault after transfer fees is on offer
+        ctx.accounts.vault.reload()?;
+        let amount = ctx.accounts.vault.amount;
+        require!(amount > 0, EscrowError::TransferFeeTooHigh);
+        ctx.accounts.basket.legs.push(BasketLeg {
+            mint: ctx.accounts.mint.key(),
+            amount,
+            vault_bump: ctx.bumps.vault,
+        });
+
+        msg!(
+            "Basket {} now offers {} of {}",
+            ctx.accounts.basket.key(),
+            amount,
+            ctx.accounts.mint.key()
+        );
+
+        Ok(())
+    }
+
+    /// Buy every leg of an offer basket at once
+    /// - Pays the seller, less the protocol fee
+    /// - Remaining accounts supply, per leg in order, the leg's mint, its
+    ///   vault, the buyer's token account and the mint's token program
+    pub fn accept_basket_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcceptBasketEscrow<'info>>,
+    ) -> Result<()> {
+        let accounts = &ctx.accounts;
+        require!(!accounts.config.paused, EscrowError::ProtocolPaused);
+        let basket = &accounts.basket;
+        require!(!basket.legs.is_empty(), EscrowError::EmptyBasket);
+        if let Some(expires_at) = basket.expires_at {
+            require!(
+                Clock::get()?.unix_timestamp < expires_at,
+                EscrowError::EscrowExpired
+            );
+        }
+
+        ListingPayment {
+            config: &accounts.config,
+            buyer: accounts.buyer.to_account_info(),
+            buyer_request_token: accounts.buyer_request_token.to_account_info(),
+            seller_request_token: accounts.seller_request_token.to_account_info(),
+            fee_vault: accounts.fee_vault.as_deref(),
+            request_mint: &accounts.request_mint,
+            token_program: accounts.token_program.to_account_info(),
+        }
+        .pay(basket.request_amount)?;
+
+        basket.release(
+            basket.to_account_info(),
+            &accounts.buyer.key(),
+            accounts.seller.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        msg!(
+            "Basket {} sold to {} for {} tokens",
+            basket.key(),
+            accounts.buyer.key(),
+            basket.request_amount
+        );
+
+        Ok(())
+    }
+
+    /// Cancel an offer basket and return every leg to the seller
+    /// - Remaining accounts supply, per leg in order, the leg's mint, its
+    ///   vault, the seller's token account and the mint's token program
+    pub fn cancel_basket_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelBasketEscrow<'info>>,
+    ) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        basket.release(
+            basket.to_account_info(),
+            &ctx.accounts.seller.key(),
+            ctx.accounts.seller.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        msg!("Basket {} cancelled", basket.key());
+
+        Ok(())
+    }
+
+    /// Create the global protocol config
+    /// - Gated on the program's upgrade authority, who becomes the config
+    ///   authority
+    pub fn initialize_config(ctx: Context<InitializeConfig>, params: ConfigParams) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.authority = ctx.accounts.admin.key();
+        config.bump = ctx.bumps.config;
+        config.apply(params)?;
+
+        msg!("Config initialized, authority {}", config.authority);
+
+        Ok(())
+    }
+
+    /// Replace the protocol settings
+    /// - Gated on the config authority, who may also hand over the role
+    /// - A new `authority`, such as a DAO's governance PDA, only takes over
+    ///   once it signs `accept_authority`; passing the current authority
+    ///   withdraws a pending handover
+    /// - While `config_delay_seconds` is set, `params` are queued for
+    ///   `execute_pending_config` once the delay passes, replacing any
+    ///   update already queued
+    pub fn update_config(
+        ctx: Context<UpdateConfig>,
+        authority: Pubkey,
+        params: ConfigParams,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.pending_authority = (authority != config.authority).then_some(authority);
+        if config.config_delay_seconds == 0 {
+            config.apply(params)?;
+            msg!(
+                "Config updated, authority {}, pending {:?}",
+                config.authority,
+                config.pending_authority
+            );
+            return Ok(());
+        }
+
+        params.validate()?;
+        config.pending_config = Some(params);
+        config.pending_config_at = Clock::get()?
+            .unix_timestamp
+            .checked_add(config.config_delay_seconds)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!(
+            "Config update queued until {}, authority {}, pending {:?}",
+            config.pending_config_at,
+            config.authority,
+            config.pending_authority
+        );
+
+        Ok(())
+    }
+
+    /// Create the program's fee vault for `mint`, where protocol fees paid
+    /// in it accrue until claimed
+    /// - Permissionless; the payer funds the vault's rent
+    pub fn initialize_fee_vault(ctx: Context<InitializeFeeVault>) -> Result<()> {
+        msg!("Fee vault opened for mint {}", ctx.accounts.mint.key());
+        Ok(())
+    }
+
+    /// Sweep a fee vault's accrued protocol fees to the treasury
+    /// - Gated on the config authority
+    /// - Burns the config's `fee_burn_bps` share of them first
+    /// - Remaining accounts supply transfer-hook accounts for the mint
+    pub fn claim_fees<'info>(ctx: Context<'_, '_, '_, 'info, ClaimFees<'info>>) -> Result<()> {
+        let collected = ctx.accounts.fee_vault.amount;
+        require!(collected > 0, EscrowError::NothingToClaim);
+
+        let config_seeds: &[&[u8]] = &[b"config", &[ctx.accounts.config.bump]];
+        let burned = bps_of(collected, ctx.accounts.config.fee_burn_bps)?;
+        if burned > 0 {
+            burn(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.mint.to_account_info(),
+                        from: ctx.accounts.fee_vault.to_account_info(),
+                        authority: ctx.accounts.config.to_account_info(),
+                    },
+                    &[config_seeds],
+                ),
+                burned,
+            )?;
+        }
+
+        let amount = collected
+            .checked_sub(burned)
+            .ok_or(EscrowError::MathOverflow)?;
+        if amount > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.fee_vault.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.treasury_token.to_account_info(),
+                        authority: ctx.accounts.config.to_account_info(),
+                    },
+                    &[config_seeds],
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+                amount,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
+
+        emit!(FeesClaimed {
+            mint: ctx.accounts.mint.key(),
+            amount,
+            burned,
+        });
+        msg!(
+            "Claimed {} fees in mint {} to the treasury, burned {}",
+            amount,
+            ctx.accounts.mint.key(),
+            burned
+        );
+
+        Ok(())
+    }
+
+    /// Open an insurance pool for escrows requesting `mint`
+    /// - Gated on the config authority
+    /// - `claims_authority`, an arbiter or governance signer, rules on
+    ///   claims against it
+    pub fn create_insurance_pool(
+        ctx: Context<CreateInsurancePool>,
+        claims_authority: Pubkey,
+        premium_bps: u16,
+    ) -> Result<()> {
+        require!(
+            (1..=MAX_INSURANCE_PREMIUM_BPS).contains(&premium_bps),
+            EscrowError::InvalidAmount
+        );
+        let pool = &mut ctx.accounts.pool;
+        pool.mint = ctx.accounts.mint.key();
+        pool.claims_authority = claims_authority;
+        pool.premium_bps = premium_bps;
+        pool.bump = ctx.bumps.pool;
+        pool.vault_bump = ctx.bumps.pool_vault;
+
+        msg!("Insurance pool opened for mint {}", pool.mint);
+
+        Ok(())
+    }
+
+    /// Cover an open escrow up to its request amount by paying the pool's
+    /// premium on it
+    /// - Opt-in; the seller or the protocol pays
+    /// - Remaining accounts supply transfer-hook accounts for the mint
+    pub fn insure_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, InsureEscrow<'info>>,
+    ) -> Result<()> {
+        let covered_amount = ctx.accounts.escrow.load()?.request_amount;
+        let premium = bps_of(covered_amount, ctx.accounts.pool.premium_bps)?;
+        require!(premium > 0, EscrowError::InvalidAmount);
+
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.payer_token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.pool_vault.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            premium,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let coverage = &mut ctx.accounts.coverage;
+        coverage.escrow = ctx.accounts.escrow.key();
+        coverage.pool = ctx.accounts.pool.key();
+        coverage.covered_amount = covered_amount;
+        coverage.claimed = 0;
+        coverage.bump = ctx.bumps.coverage;
+
+        msg!(
+            "Escrow {} covered for {}, premium {}",
+            coverage.escrow,
+            covered_amount,
+            premium
+        );
+
+        Ok(())
+    }
+
+    /// Compensate a counterparty harmed by a covered escrow's failure,
+    /// such as a frozen mint or a dispute ruled in their favour
+    /// - Gated on the pool's claims authority
+    /// - Claims against one escrow never exceed its coverage
+    /// - Remaining accounts supply transfer-hook accounts for the mint
+    pub fn pay_insurance_claim<'info>(
+        ctx: Context<'_, '_, '_, 'info, PayInsuranceClaim<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+        let coverage = &mut ctx.accounts.coverage;
+        coverage.claimed = coverage
+            .claimed
+            .checked_add(amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(
+            coverage.claimed <= coverage.covered_amount,
+            EscrowError::CoverageExceeded
+        );
+
+        let mint_key = ctx.accounts.mint.key();
+        let pool_seeds: &[&[u8]] = &[
+            b"insurance_pool",
+            mint_key.as_ref(),
+            &[ctx.accounts.pool.bump],
+        ];
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.claimant_token.to_account_info(),
+                    authority: ctx.accounts.pool.to_account_info(),
+                },
+                &[pool_seeds],
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        msg!(
+            "Insurance claim of {} paid against escrow {}",
+            amount,
+            ctx.accounts.coverage.escrow
+        );
+
+        let event = InsuranceClaimPaid {
+            escrow: ctx.accounts.coverage.escrow,
+            mint: mint_key,
+            claimant: ctx.accounts.claimant_token.owner,
+            amount,
+        };
+        emit!(event);
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Apply the settings queued by `update_config` once their delay has
+    /// passed
+    /// - Permissionless
+    pub fn execute_pending_config(ctx: Context<ExecutePendingConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let params = config
+            .pending_config
+            .take()
+            .ok_or(EscrowError::NoPendingConfig)?;
+        require!(
+            Clock::get()?.unix_timestamp >= config.pending_config_at,
+            EscrowError::ConfigTimelocked
+        );
+        config.apply(params)?;
+        config.pending_config_at = 0;
+
+        msg!("Queued config update executed");
+
+        Ok(())
+    }
+
+    /// Drop the settings queued by `update_config`
+    pub fn cancel_pending_config(ctx: Context<UpdateConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(
+            config.pending_config.is_some(),
+            EscrowError::NoPendingConfig
+        );
+        config.pending_config = None;
+        config.pending_config_at = 0;
+
+        msg!("Queued config update cancelled");
+
+        Ok(())
+    }
+
+    /// Take over the config authority handed over by `update_config`
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.authority = ctx.accounts.new_authority.key();
+        config.pending_authority = None;
+
+        msg!("Config authority accepted by {}", config.authority);
+
+        Ok(())
+    }
+
+    /// Start logging escrow lifecycle events to a fresh compressed merkle
+    /// tree, replacing any tree logged to so far
+    /// - The tree account is allocated beforehand for `max_depth` and
+    ///   `max_buffer_size` and owned by the account compression program;
+    ///   the config becomes its authority
+    /// - Every create, fill, cancellation, expiry and receipt redemption
+    ///   then logs its
+    ///   `ActivityRecord` through the noop program and appends the
+    ///   record's hash as a leaf, so history survives pruned RPC logs
+    pub fn initialize_activity_log(
+        ctx: Context<InitializeActivityLog>,
+        max_depth: u32,
+        max_buffer_size: u32,
+    ) -> Result<()> {
+        let accounts = &ctx.accounts;
+        let mut data = COMPRESSION_INIT_TREE_DISCRIMINATOR.to_vec();
+        (max_depth, max_buffer_size).serialize(&mut data)?;
+        invoke_signed(
+            &Instruction {
+                program_id: accounts.compression_program.key(),
+                accounts: vec![
+                    AccountMeta::new(accounts.merkle_tree.key(), false),
+                    AccountMeta::new_readonly(accounts.config.key(), true),
+                    AccountMeta::new_readonly(accounts.log_wrapper.key(), false),
+                ],
+                data,
+            },
+            &[
+                accounts.merkle_tree.to_account_info(),
+                accounts.config.to_account_info(),
+                accounts.log_wrapper.to_account_info(),
+            ],
+            &[&[b"config", &[accounts.config.bump]]],
+        )?;
+        ctx.accounts.config.activity_log = Some(ctx.accounts.merkle_tree.key());
+
+        msg!(
+            "Activity log started in tree {}",
+            ctx.accounts.merkle_tree.key()
+        );
+
+        Ok(())
+    }
+
+    /// Stop logging escrow lifecycle events; the tree keeps its history
+    pub fn stop_activity_log(ctx: Context<UpdateConfig>) -> Result<()> {
+        ctx.accounts.config.activity_log = None;
+
+        msg!("Activity log stopped");
+
+        Ok(())
+    }
+
+    /// Set the programs, such as flash lenders, whose instructions may not
+    /// share a transaction with oracle-priced fills or auction settlements
+    /// - The Pyth and Switchboard programs are always refused alongside
+    ///   oracle-priced fills, whose price must be posted beforehand
+    pub fn set_guarded_programs(ctx: Context<UpdateConfig>, programs: Vec<Pubkey>) -> Result<()> {
+        require!(
+            programs.len() <= MAX_GUARDED_PROGRAMS,
+            EscrowError::TooManyGuardedPrograms
+        );
+        ctx.accounts.config.guarded_programs = programs;
+
+        msg!(
+            "Guarded programs updated: {}",
+            ctx.accounts.config.guarded_programs.len()
+        );
+
+        Ok(())
+    }
+
+    /// Lock stake-mint tokens towards a protocol fee tier
+    /// - Remaining accounts supply transfer-hook accounts for the stake mint
+    pub fn stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, StakeTokens<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+
+        let vault_before = ctx.accounts.stake_vault.amount;
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.owner_stake_token.to_account_info(),
+                    mint: ctx.accounts.stake_mint.to_account_info(),
+                    to: ctx.accounts.stake_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            amount,
+            ctx.accounts.stake_mint.decimals,
+        )?;
+
+        // Only what reaches the vault after transfer fees is staked
+        ctx.accounts.stake_vault.reload()?;
+        let added = ctx
+            .accounts
+            .stake_vault
+            .amount
+            .checked_sub(vault_before)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        let stake = &mut ctx.accounts.stake;
+        stake.owner = ctx.accounts.owner.key();
+        stake.mint = ctx.accounts.stake_mint.key();
+        stake.amount = stake
+            .amount
+            .checked_add(added)
+            .ok_or(EscrowError::MathOverflow)?;
+        stake.unlocks_at = Clock::get()?
+            .unix_timestamp
+            .checked_add(ctx.accounts.config.stake_lock_seconds)
+            .ok_or(EscrowError::MathOverflow)?;
+        stake.bump = ctx.bumps.stake;
+        stake.vault_bump = ctx.bumps.stake_vault;
+
+        msg!(
+            "Staked {} tokens, locked until {}",
+            stake.amount,
+            stake.unlocks_at
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw staked tokens once the stake unlocks
+    /// - Remaining accounts supply transfer-hook accounts for the stake mint
+    pub fn unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, UnstakeTokens<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let stake = &ctx.accounts.stake;
+        require!(
+            amount > 0 && amount <= stake.amount,
+            EscrowError::InvalidAmount
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= stake.unlocks_at,
+            EscrowError::StakeLocked
+        );
+
+        let owner_key = stake.owner;
+        let stake_seeds = &[b"stake", owner_key.as_ref(), &[stake.bump]];
+        let signer_seeds = &[&stake_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.stake_vault.to_account_info(),
+                    mint: ctx.accounts.stake_mint.to_account_info(),
+                    to: ctx.accounts.owner_stake_token.to_account_info(),
+                    authority: stake.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            amount,
+            ctx.accounts.stake_mint.decimals,
+        )?;
+
+        let stake = &mut ctx.accounts.stake;
+        stake.amount = stake
+            .amount
+            .checked_sub(amount)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!("Unstaked {} tokens, {} remain", amount, stake.amount);
+
+        Ok(())
+    }
+
+    /// Approve or ban a mint for listing
+    /// - Banned mints can never be listed; while the config requires mint
+    ///   approval, only approved mints can
+    pub fn set_mint_policy(ctx: Context<SetMintPolicy>, status: MintStatus) -> Result<()> {
+        let policy = &mut ctx.accounts.mint_policy;
+        policy.mint = ctx.accounts.mint.key();
+        policy.status = status;
+        policy.bump = ctx.bumps.mint_policy;
+
+        msg!("Mint {} policy set", policy.mint);
+
+        Ok(())
+    }
+
+    /// Drop a mint's policy, returning it to the default treatment
+    pub fn remove_mint_policy(ctx: Context<RemoveMintPolicy>) -> Result<()> {
+        msg!("Mint {} policy removed", ctx.accounts.mint_policy.mint);
+
+        Ok(())
+    }
+
+    /// Bar a seller from listing new escrows; their open escrows are
+    /// unaffected
+    pub fn ban_seller(ctx: Context<BanSeller>) -> Result<()> {
+        let ban = &mut ctx.accounts.seller_ban;
+        ban.seller = ctx.accounts.seller.key();
+        ban.bump = ctx.bumps.seller_ban;
+
+        msg!("Seller {} banned", ban.seller);
+
+        Ok(())
+    }
+
+    /// Lift a seller's ban
+    pub fn unban_seller(ctx: Context<UnbanSeller>) -> Result<()> {
+        msg!("Seller {} unbanned", ctx.accounts.seller_ban.seller);
+
+        Ok(())
+    }
+
+    /// Trust `address` to attest payments made on Wormhole chain `chain`,
+    /// replacing any emitter trusted before
+    pub fn set_foreign_emitter(
+        ctx: Context<SetForeignEmitter>,
+        chain: u16,
+        address: [u8; 32],
+    ) -> Result<()> {
+        require!(chain != 0, EscrowError::UntrustedEmitter);
+        let emitter = &mut ctx.accounts.foreign_emitter;
+        emitter.chain = chain;
+        emitter.address = address;
+        emitter.bump = ctx.bumps.foreign_emitter;
+
+        msg!("Foreign emitter for chain {} set", chain);
+
+        Ok(())
+    }
+
+    /// Set size limits for escrows involving a mint
+    /// - Open offers of the mint must stay within
+    ///   [`min_offer_amount`, `max_offer_amount`]
+    /// - Escrows requesting the mint must ask for at least `min_notional`
+    pub fn set_mint_limits(
+        ctx: Context<SetMintLimits>,
+        min_offer_amount: u64,
+        max_offer_amount: Option<u64>,
+        min_notional: u64,
+    ) -> Result<()> {
+        if let Some(max_offer_amount) = max_offer_amount {
+            require!(
+                max_offer_amount >= min_offer_amount,
+                EscrowError::InvalidAmount
+            );
+        }
+
+        let limits = &mut ctx.accounts.mint_limits;
+        limits.mint = ctx.accounts.mint.key();
+        limits.min_offer_amount = min_offer_amount;
+        limits.max_offer_amount = max_offer_amount;
+        limits.min_notional = min_notional;
+        limits.bump = ctx.bumps.mint_limits;
+
+        msg!("Mint {} limits set", limits.mint);
+
+        Ok(())
+    }
+
+    /// Drop a mint's size limits
+    pub fn remove_mint_limits(ctx: Context<RemoveMintLimits>) -> Result<()> {
+        msg!("Mint {} limits removed", ctx.accounts.mint_limits.mint);
+
+        Ok(())
+    }
+
+    /// Halt new escrows and fills; cancels and refunds keep working so
+    /// sellers can always recover their funds
+    pub fn pause(ctx: Context<UpdateConfig>) -> Result<()> {
+        ctx.accounts.config.paused = true;
+
+        msg!("Protocol paused");
+
+        Ok(())
+    }
+
+    /// Resume normal operation after a pause
+    pub fn unpause(ctx: Context<UpdateConfig>) -> Result<()> {
+        ctx.accounts.config.paused = false;
+
+        msg!("Protocol unpaused");
+
+        Ok(())
+    }
+
+    /// Create the global pair index
+    /// - Permissionless; only needs to be called once per deployment
+    pub fn initialize_pair_index(ctx: Context<InitializePairIndex>) -> Result<()> {
+        let pair_index = &mut ctx.accounts.pair_index;
+        pair_index.pairs = Vec::new();
+        pair_index.bump = ctx.bumps.pair_index;
+
+        msg!("Pair index initialized");
+
+        Ok(())
+    }
+
+    /// Create the fill statistics account of a mint pair
+    /// - Permissionless; fills of the pair skip updating it until it
+    ///   exists
+    pub fn initialize_pair_stats(ctx: Context<InitializePairStats>) -> Result<()> {
+        let pair_stats = &mut ctx.accounts.pair_stats;
+        pair_stats.offer_mint = ctx.accounts.offer_mint.key();
+        pair_stats.request_mint = ctx.accounts.request_mint.key();
+        pair_stats.bump = ctx.bumps.pair_stats;
+
+        msg!(
+            "Pair stats initialized for {} / {}",
+            pair_stats.offer_mint,
+            pair_stats.request_mint
+        );
+
+        Ok(())
+    }
+
+    /// Create a seller's reputation account
+    /// - Permissionless; fills, cancellations and lost disputes are
+    ///   recorded on it from then on
+    pub fn initialize_reputation(ctx: Context<InitializeReputation>) -> Result<()> {
+        let reputation = &mut ctx.accounts.reputation;
+        reputation.seller = ctx.accounts.seller.key();
+        reputation.bump = ctx.bumps.reputation;
+
+        msg!("Reputation initialized for seller {}", reputation.seller);
+
+        Ok(())
+    }
+
+    /// Create the protocol-wide stats account
+    /// - Escrow instructions skip updating it until it exists
+    pub fn initialize_stats(ctx: Context<InitializeStats>) -> Result<()> {
+        let stats = &mut ctx.accounts.stats;
+        stats.bump = ctx.bumps.stats;
+
+        msg!("Protocol stats initialized");
+
+        Ok(())
+    }
+
+    /// Create the registry of open escrows frontends can enumerate instead
+    /// of scanning program accounts
+    /// - Escrows are listed on `RegistryPage`s added with
+    ///   `add_registry_page`
+    pub fn initialize_offer_registry(ctx: Context<InitializeOfferRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.offer_registry;
+        registry.page_count = 0;
+        registry.bump = ctx.bumps.offer_registry;
+
+        msg!("Offer registry initialized");
+
+        Ok(())
+    }
+
+    /// Append a page to the offer registry
+    /// - Permissionless; the payer covers the page's rent
+    pub fn add_registry_page(ctx: Context<AddRegistryPage>) -> Result<()> {
+        let registry = &mut ctx.accounts.offer_registry;
+        let page = &mut ctx.accounts.registry_page;
+        page.index = registry.page_count;
+        page.escrows = Vec::new();
+        page.bump = ctx.bumps.registry_page;
+        registry.page_count = registry
+            .page_count
+            .checked_add(1)
+            .ok_or(EscrowError::MathOverflow)?;
+
+        msg!("Registry page {} added", page.index);
+
+        Ok(())
+    }
+
+    /// Drop escrows that are no longer open from a registry page
+    /// - Permissionless; remaining accounts supply the listed escrows to
+    ///   check, and escrows whose account was closed are dropped too
+    /// - Covers escrows settled through paths that take no registry page
+    pub fn prune_registry<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PruneRegistry<'info>>,
+    ) -> Result<()> {
+        let page = &mut ctx.accounts.registry_page;
+        let listed = page.escrows.len();
+        for info in ctx.remaining_accounts {
+            if !page.escrows.contains(info.key) {
+                continue;
+            }
+            let open = AccountLoader::<Escrow>::try_from(info)
+                .and_then(|escrow| Ok(escrow.load()?.status() == EscrowStatus::Open))
+                .unwrap_or(false);
+            if !open {
+                page.deregister(info.key)?;
+            }
+        }
+
+        msg!(
+            "Pruned {} escrows from registry page {}",
+            listed - page.escrows.len(),
+            page.index
+        );
+
+        Ok(())
+    }
+
+    /// Add a page to a mint pair's market index, so sellers can link the
+    /// pair's escrows into it and clients walk them page by page
+    /// - Permissionless; the payer covers the page's rent
+    /// - Pages are numbered from zero, and each past the first needs the
+    ///   one before it, so clients stop at the first missing page
+    pub fn initialize_market_index(ctx: Context<InitializeMarketIndex>, page: u32) -> Result<()> {
+        require!(
+            page == 0 || ctx.accounts.previous_page.is_some(),
+            EscrowError::InvalidMarketIndex
+        );
+        let market_index = &mut ctx.accounts.market_index;
+        market_index.offer_mint = ctx.accounts.offer_mint.key();
+        market_index.request_mint = ctx.accounts.request_mint.key();
+        market_index.page = page;
+        market_index.escrows = Vec::new();
+        market_index.bump = ctx.bumps.market_index;
+
+        msg!(
+            "Market index page {} added for {} / {}",
+            page,
+            market_index.offer_mint,
+            market_index.request_mint
+        );
+
+        Ok(())
+    }
+
+    /// Drop escrows that are no longer open from a market index page
+    /// - Permissionless; remaining accounts supply the linked escrows to
+    ///   check, as in `prune_registry`
+    pub fn prune_market_index<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PruneMarketIndex<'info>>,
+    ) -> Result<()> {
+        let market_index = &mut ctx.accounts.market_index;
+        let linked = market_index.escrows.len();
+        for info in ctx.remaining_accounts {
+            let open = AccountLoader::<Escrow>::try_from(info)
+                .and_then(|escrow| Ok(escrow.load()?.status() == EscrowStatus::Open))
+                .unwrap_or(false);
+            if !open {
+                market_index.escrows.retain(|escrow| escrow != info.key);
+            }
+        }
+
+        msg!(
+            "Pruned {} escrows from market index page {}",
+            linked - market_index.escrows.len(),
+            market_index.page
+        );
+
+        Ok(())
+    }
+
+    /// Create a market that escrows can be listed under
+    /// - The signer becomes the market operator
+    /// - Fees collected on fills are paid to `fee_receiver`
+    pub fn create_market(
+        ctx: Context<CreateMarket>,
+        branding_id: [u8; 32],
+        fee_receiver: Pubkey,
+        fee_bps: u16,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_MARKET_FEE_BPS, EscrowError::InvalidFee);
+
+        let market = &mut ctx.accounts.market;
+        market.operator = ctx.accounts.operator.key();
+        market.fee_receiver = fee_receiver;
+        market.fee_bps = fee_bps;
+        market.branding_id = branding_id;
+        market.allowed_mints = Vec::new();
+        market.allowed_sellers = Vec::new();
+        market.approved_lockers = Vec::new();
+        market.bump = ctx.bumps.market;
+
+        msg!("Market created: {} (fee {} bps)", market.key(), fee_bps);
+
+        Ok(())
+    }
+
+    /// Update the fee settings of a market
+    /// - Only the market operator can call this
+    pub fn update_market(
+        ctx: Context<UpdateMarket>,
+        fee_receiver: Pubkey,
+        fee_bps: u16,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_MARKET_FEE_BPS, EscrowError::InvalidFee);
+
+        let market = &mut ctx.accounts.market;
+        market.fee_receiver = fee_receiver;
+        market.fee_bps = fee_bps;
+
+        msg!("Market updated: {} (fee {} bps)", market.key(), fee_bps);
+
+        Ok(())
+    }
+
+    /// Set maker/taker fees for one mint pair of a market
+    /// - A negative maker fee is a rebate funded from the taker fee
+    pub fn set_pair_fees(
+        ctx: Context<SetPairFees>,
+        maker_fee_bps: i16,
+        taker_fee_bps: u16,
+    ) -> Result<()> {
+        require!(
+            taker_fee_bps <= MAX_MARKET_FEE_BPS
+                && maker_fee_bps <= MAX_MARKET_FEE_BPS as i16
+                && maker_fee_bps >= -(taker_fee_bps as i16),
+            EscrowError::InvalidFee
+        );
+
+        let pair_fees = &mut ctx.accounts.pair_fees;
+        pair_fees.market = ctx.accounts.market.key();
+        pair_fees.offer_mint = ctx.accounts.offer_mint.key();
+        pair_fees.request_mint = ctx.accounts.request_mint.key();
+        pair_fees.maker_fee_bps = maker_fee_bps;
+        pair_fees.taker_fee_bps = taker_fee_bps;
+        pair_fees.bump = ctx.bumps.pair_fees;
+
+        msg!(
+            "Pair fees set: maker {} bps, taker {} bps",
+            maker_fee_bps,
+            taker_fee_bps
+        );
+
+        Ok(())
+    }
+
+    /// Set the volume tier discounts for a market pair
+    /// - Tiers are keyed by the trader's trailing volume in the request mint
+    pub fn set_volume_tiers(ctx: Context<SetVolumeTiers>, tiers: Vec<VolumeTier>) -> Result<()> {
+        require!(tiers.len() <= MAX_VOLUME_TIERS, EscrowError::InvalidFee);
+        require!(
+            tiers
+                .iter()
+                .all(|tier| tier.discount_bps as u64 <= BPS_DENOMINATOR),
+            EscrowError::InvalidFee
+        );
+
+        ctx.accounts.pair_fees.volume_tiers = tiers;
+
+        msg!("Volume tiers updated for {}", ctx.accounts.pair_fees.key());
+
+        Ok(())
+    }
+
+    /// Start tracking settled volume for a trader in one quote mint
+    /// - Permissionless; anyone may pay to open a trader's stats
+    pub fn initialize_trader_stats(ctx: Context<InitializeTraderStats>) -> Result<()> {
+        let stats = &mut ctx.accounts.trader_stats;
+        stats.trader = ctx.accounts.trader.key();
+        stats.mint = ctx.accounts.mint.key();
+        stats.window_start = Clock::get()?.unix_timestamp;
+        stats.bump = ctx.bumps.trader_stats;
+
+        msg!("Trader stats initialized for {}", stats.trader);
+
+        Ok(())
+    }
+
+    /// Replace one of the market's allowlists
+    /// - An empty list means the market is open for that dimension
+    pub fn set_market_allowlist(
+        ctx: Context<UpdateMarket>,
+        kind: MarketAllowlist,
+        entries: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            entries.len() <= MAX_MARKET_ALLOWLIST_LEN,
+            EscrowError::AllowlistFull
+        );
+
+        let market = &mut ctx.accounts.market;
+        match kind {
+            MarketAllowlist::Mints => market.allowed_mints = entries,
+            MarketAllowlist::Sellers => market.allowed_sellers = entries,
+            MarketAllowlist::Lockers => market.approved_lockers = entries,
+        }
+
+        msg!("Market {} allowlist updated", market.key());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,1305 @@
+//! Deposit and fill flows shared by the instructions that settle escrows
+
+use crate::*;
+
+/// Accounts for a Bubblegum `transfer` of one compressed NFT leaf
+pub struct BubblegumTransfer<'a, 'info> {
+    pub tree_authority: AccountInfo<'info>,
+    pub leaf_owner: AccountInfo<'info>,
+    pub leaf_delegate: AccountInfo<'info>,
+    pub new_leaf_owner: AccountInfo<'info>,
+    pub merkle_tree: AccountInfo<'info>,
+    pub log_wrapper: AccountInfo<'info>,
+    pub compression_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub bubblegum_program: AccountInfo<'info>,
+    /// Merkle proof nodes, leaf to root
+    pub proof: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> BubblegumTransfer<'a, 'info> {
+    /// Move `leaf` to `new_leaf_owner` against the tree's current `root`;
+    /// the owner signs directly or through `signer_seeds`
+    pub fn transfer(
+        self,
+        leaf: &CnftLeaf,
+        root: [u8; 32],
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let mut accounts = vec![
+            AccountMeta::new_readonly(self.tree_authority.key(), false),
+            AccountMeta::new_readonly(self.leaf_owner.key(), true),
+            AccountMeta::new_readonly(self.leaf_delegate.key(), false),
+            AccountMeta::new_readonly(self.new_leaf_owner.key(), false),
+            AccountMeta::new(self.merkle_tree.key(), false),
+            AccountMeta::new_readonly(self.log_wrapper.key(), false),
+            AccountMeta::new_readonly(self.compression_program.key(), false),
+            AccountMeta::new_readonly(self.system_program.key(), false),
+        ];
+        accounts.extend(
+            self.proof
+                .iter()
+                .map(|node| AccountMeta::new_readonly(node.key(), false)),
+        );
+
+        let mut data = BUBBLEGUM_TRANSFER_DISCRIMINATOR.to_vec();
+        (
+            root,
+            leaf.data_hash,
+            leaf.creator_hash,
+            leaf.nonce,
+            leaf.index,
+        )
+            .serialize(&mut data)?;
+
+        let mut infos = vec![
+            self.tree_authority,
+            self.leaf_owner,
+            self.leaf_delegate,
+            self.new_leaf_owner,
+            self.merkle_tree,
+            self.log_wrapper,
+            self.compression_program,
+            self.system_program,
+        ];
+        infos.extend_from_slice(self.proof);
+
+        invoke_signed(
+            &Instruction {
+                program_id: self.bubblegum_program.key(),
+                accounts,
+                data,
+            },
+            &infos,
+            signer_seeds,
+        )
+        .map_err(Into::into)
+    }
+}
+
+/// Accounts for appending to the config's compressed activity log
+pub struct ActivityLog<'a, 'info> {
+    pub config: &'a Account<'info, Config>,
+    pub merkle_tree: Option<&'a UncheckedAccount<'info>>,
+    pub log_wrapper: Option<&'a UncheckedAccount<'info>>,
+    pub compression_program: Option<&'a UncheckedAccount<'info>>,
+}
+
+impl<'a, 'info> ActivityLog<'a, 'info> {
+    /// Log `record` through the noop program, where indexers rebuild the
+    /// history from, and append its hash to the tree as the next leaf
+    /// - Does nothing while the config keeps no activity log
+    pub fn append(self, record: &ActivityRecord) -> Result<()> {
+        let Some(activity_log) = self.config.activity_log else {
+            return Ok(());
+        };
+        let (Some(merkle_tree), Some(log_wrapper), Some(compression_program)) =
+            (self.merkle_tree, self.log_wrapper, self.compression_program)
+        else {
+            return err!(EscrowError::MissingActivityLogAccount);
+        };
+        require_keys_eq!(
+            merkle_tree.key(),
+            activity_log,
+            EscrowError::InvalidActivityLog
+        );
+
+        let mut record_data = Vec::new();
+        record.serialize(&mut record_data)?;
+        invoke(
+            &Instruction {
+                program_id: log_wrapper.key(),
+                accounts: vec![],
+                data: record_data.clone(),
+            },
+            &[log_wrapper.to_account_info()],
+        )?;
+
+        let mut data = COMPRESSION_APPEND_DISCRIMINATOR.to_vec();
+        hash(&record_data).to_bytes().serialize(&mut data)?;
+        invoke_signed(
+            &Instruction {
+                program_id: compression_program.key(),
+                accounts: vec![
+                    AccountMeta::new(merkle_tree.key(), false),
+                    AccountMeta::new_readonly(self.config.key(), true),
+                    AccountMeta::new_readonly(log_wrapper.key(), false),
+                ],
+                data,
+            },
+            &[
+                merkle_tree.to_account_info(),
+                self.config.to_account_info(),
+                log_wrapper.to_account_info(),
+            ],
+            &[&[b"config", &[self.config.bump]]],
+        )
+        .map_err(Into::into)
+    }
+}
+
+/// Accounts for paying a payment plan's next installment
+pub struct InstallmentPayment<'a, 'info> {
+    pub plan: &'a mut Account<'info, PaymentPlan>,
+    pub escrow: &'a AccountLoader<'info, Escrow>,
+    pub buyer: AccountInfo<'info>,
+    pub seller: AccountInfo<'info>,
+    pub offer_mint: &'a InterfaceAccount<'info, Mint>,
+    pub request_mint: &'a InterfaceAccount<'info, Mint>,
+    pub vault: AccountInfo<'info>,
+    pub plan_vault: AccountInfo<'info>,
+    pub buyer_offer_token: AccountInfo<'info>,
+    pub buyer_request_token: AccountInfo<'info>,
+    pub seller_request_token: AccountInfo<'info>,
+    /// Receives the escrow's rent once the plan completes
+    pub rent_destination: AccountInfo<'info>,
+    pub offer_token_program: AccountInfo<'info>,
+    pub request_token_program: AccountInfo<'info>,
+    /// Transfer-hook accounts for the request mint, then the offer mint
+    pub remaining_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> InstallmentPayment<'a, 'info> {
+    /// Take the next installment from the buyer by `now` and release the
+    /// offer it pays for; the last one also pays the seller any payments
+    /// held for the end and closes the escrow, its vault and the plan.
+    /// Returns whether the plan completed
+    pub fn pay(self, now: i64) -> Result<bool> {
+        let plan = self.plan;
+        require!(now <= plan.next_due_at()?, EscrowError::InstallmentOverdue);
+        let amount = plan.next_installment()?;
+        let paid = plan.installments_paid + 1;
+
+        // Payments against offer released only at the end wait in the
+        // plan's vault; the rest go straight to the seller
+        let destination = if plan.release_at_end {
+            self.plan_vault.clone()
+        } else {
+            self.seller_request_token.clone()
+        };
+        transfer_checked_with_hook(
+            CpiContext::new(
+                self.request_token_program.clone(),
+                TransferChecked {
+                    from: self.buyer_request_token,
+                    mint: self.request_mint.to_account_info(),
+                    to: destination,
+                    authority: self.buyer.clone(),
+                },
+            )
+            .with_remaining_accounts(self.remaining_accounts.to_vec()),
+            amount,
+            self.request_mint.decimals,
+        )?;
+
+        let escrow = *self.escrow.load()?;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let listed_by = escrow.listed_by();
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            escrow.offer_mint.as_ref(),
+            escrow.request_mint.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow.escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        let release = plan
+            .offer_releasable(paid)?
+            .checked_sub(plan.offer_released)
+            .ok_or(EscrowError::MathOverflow)?;
+        if release > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    self.offer_token_program.clone(),
+                    TransferChecked {
+                        from: self.vault.clone(),
+                        mint: self.offer_mint.to_account_info(),
+                        to: self.buyer_offer_token,
+                        authority: self.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(self.remaining_accounts.to_vec()),
+                release,
+                self.offer_mint.decimals,
+            )?;
+        }
+        plan.installments_paid = paid;
+        plan.offer_released = plan
+            .offer_released
+            .checked_add(release)
+            .ok_or(EscrowError::MathOverflow)?;
+        let mut filled = self.escrow.load_mut()?;
+        filled.offer_amount = escrow
+            .offer_amount
+            .checked_sub(release)
+            .ok_or(EscrowError::MathOverflow)?;
+        if release > 0 {
+            filled.record_fill();
+        }
+        drop(filled);
+        if paid < plan.installments {
+            return Ok(false);
+        }
+
+        let plan_key = plan.escrow;
+        let plan_seeds = &[b"payment_plan", plan_key.as_ref(), &[plan.bump]];
+        let held = token_balance(&self.plan_vault)?;
+        if held > 0 {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    self.request_token_program.clone(),
+                    TransferChecked {
+                        from: self.plan_vault.clone(),
+                        mint: self.request_mint.to_account_info(),
+                        to: self.seller_request_token,
+                        authority: plan.to_account_info(),
+                    },
+                    &[&plan_seeds[..]],
+                )
+                .with_remaining_accounts(self.remaining_accounts.to_vec()),
+                held,
+                self.request_mint.decimals,
+            )?;
+        }
+        close_token_account(
+            self.request_token_program,
+            self.plan_vault,
+            self.request_mint.to_account_info(),
+            self.buyer.clone(),
+            plan.to_account_info(),
+            &[&plan_seeds[..]],
+        )?;
+        close_token_account(
+            self.offer_token_program,
+            self.vault,
+            self.offer_mint.to_account_info(),
+            self.rent_destination.clone(),
+            self.escrow.to_account_info(),
+            signer_seeds,
+        )?;
+
+        self.escrow.load_mut()?.set_status(EscrowStatus::Filled);
+        if !escrow.keep_record() {
+            close_escrow(self.escrow, self.seller, self.rent_destination)?;
+        }
+        plan.close(self.buyer)?;
+
+        Ok(true)
+    }
+}
+
+/// Accounts for paying for a listing settled outside of `Fill`
+pub struct ListingPayment<'a, 'info> {
+    pub config: &'a Account<'info, Config>,
+    pub buyer: AccountInfo<'info>,
+    pub buyer_request_token: AccountInfo<'info>,
+    pub seller_request_token: AccountInfo<'info>,
+    /// Required while the protocol fee is non-zero
+    pub fee_vault: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    pub request_mint: &'a InterfaceAccount<'info, Mint>,
+    pub token_program: AccountInfo<'info>,
+}
+
+impl<'a, 'info> ListingPayment<'a, 'info> {
+    /// Pay the seller `request_amount` less the maker's protocol fee, and
+    /// the fee vault that fee plus the taker's on top; returns the protocol
+    /// fees
+    pub fn pay(self, request_amount: u64) -> Result<u64> {
+        let protocol_fee = bps_of(request_amount, self.config.protocol_fee_bps)?;
+        let protocol_taker_fee = bps_of(request_amount, self.config.protocol_taker_fee_bps)?;
+        let protocol_fees = protocol_fee
+            .checked_add(protocol_taker_fee)
+            .ok_or(EscrowError::MathOverflow)?;
+        let mut payments = vec![(
+            self.seller_request_token,
+            request_amount
+                .checked_sub(protocol_fee)
+                .ok_or(EscrowError::MathOverflow)?,
+        )];
+        if protocol_fees > 0 {
+            let fee_vault = self.fee_vault.ok_or(EscrowError::MissingTreasuryAccount)?;
+            require_keys_eq!(
+                fee_vault.owner,
+                self.config.key(),
+                EscrowError::InvalidTokenAccountOwner
+            );
+            payments.push((fee_vault.to_account_info(), protocol_fees));
+        }
+
+        let request_mint = self.request_mint.to_account_info();
+        for (to, net) in payments {
+            transfer_checked_with_hook(
+                CpiContext::new(
+                    self.token_program.clone(),
+                    TransferChecked {
+                        from: self.buyer_request_token.clone(),
+                        mint: request_mint.clone(),
+                        to,
+                        authority: self.buyer.clone(),
+                    },
+                ),
+                gross_amount(&request_mint, net)?,
+                self.request_mint.decimals,
+            )?;
+        }
+        Ok(protocol_fees)
+    }
+}
+
+/// Accounts for emptying an RFQ quote's vault
+pub struct QuoteRelease<'a, 'info> {
+    pub quote: &'a Account<'info, RfqQuote>,
+    pub quote_vault: &'a InterfaceAccount<'info, TokenAccount>,
+    pub offer_mint: &'a InterfaceAccount<'info, Mint>,
+    /// Receives the vault's rent
+    pub seller: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+impl<'a, 'info> QuoteRelease<'a, 'info> {
+    /// Move the quote's locked offer to `destination` and close its vault;
+    /// the quote itself is closed by its `close = seller` constraint
+    pub fn release(
+        self,
+        destination: AccountInfo<'info>,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let quote_seeds = &[
+            b"rfq_quote",
+            self.quote.rfq.as_ref(),
+            self.quote.seller.as_ref(),
+            &[self.quote.bump],
+        ];
+        let signer_seeds = &[&quote_seeds[..]];
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                self.token_program.clone(),
+                TransferChecked {
+                    from: self.quote_vault.to_account_info(),
+                    mint: self.offer_mint.to_account_info(),
+                    to: destination,
+                    authority: self.quote.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(remaining_accounts.to_vec()),
+            self.quote_vault.amount,
+            self.offer_mint.decimals,
+        )?;
+
+        close_token_account(
+            self.token_program,
+            self.quote_vault.to_account_info(),
+            self.offer_mint.to_account_info(),
+            self.seller,
+            self.quote.to_account_info(),
+            signer_seeds,
+        )
+    }
+}
+
+/// Accounts for moving a seller's offer tokens into an escrow vault
+pub struct Deposit<'a, 'info> {
+    pub seller: AccountInfo<'info>,
+    /// Not needed when offering native SOL
+    pub seller_offer_token: Option<AccountInfo<'info>>,
+    pub vault: AccountInfo<'info>,
+    pub offer_mint: &'a InterfaceAccount<'info, Mint>,
+    pub token_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    /// Transfer-hook accounts for the offer mint
+    pub remaining_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> Deposit<'a, 'info> {
+    /// Move `amount` offer tokens into the vault, wrapping the seller's
+    /// lamports straight into it for native SOL offers
+    pub fn deposit(self, amount: u64) -> Result<()> {
+        if is_native_mint(&self.offer_mint.key()) {
+            system_program::transfer(
+                CpiContext::new(
+                    self.system_program,
+                    system_program::Transfer {
+                        from: self.seller,
+                        to: self.vault.clone(),
+                    },
+                ),
+                amount,
+            )?;
+            return sync_native(CpiContext::new(
+                self.token_program,
+                SyncNative {
+                    account: self.vault,
+                },
+            ));
+        }
+
+        let seller_offer_token = self
+            .seller_offer_token
+            .ok_or(EscrowError::MissingOfferTokenAccount)?;
+        transfer_checked_with_hook(
+            CpiContext::new(
+                self.token_program,
+                TransferChecked {
+                    from: seller_offer_token,
+                    mint: self.offer_mint.to_account_info(),
+                    to: self.vault,
+                    authority: self.seller,
+                },
+            )
+            .with_remaining_accounts(self.remaining_accounts.to_vec()),
+            amount,
+            self.offer_mint.decimals,
+        )
+    }
+}
+
+/// Accounts taking part in a full fill of one escrow
+pub struct Fill<'a, 'info> {
+    pub escrow: &'a AccountLoader<'info, Escrow>,
+    pub vault: &'a InterfaceAccount<'info, TokenAccount>,
+    pub seller: AccountInfo<'info>,
+    /// Not needed when the taker pays in lamports
+    pub seller_request_token: Option<AccountInfo<'info>>,
+    /// Request-mint token account of the escrow's proceeds recipient;
+    /// required in place of `seller_request_token` when it has one
+    pub proceeds_recipient_token: Option<AccountInfo<'info>>,
+    /// Holds the proceeds for a receipt holder, or streams them to the
+    /// seller
+    pub proceeds_vault: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    pub market: Option<&'a Account<'info, Market>>,
+    pub market_fee_token: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    /// The market's `PairFees` address for this pair, initialized or not
+    pub pair_fees: Option<AccountInfo<'info>>,
+    /// `TraderStats` addresses of both parties, initialized or not
+    pub seller_stats: Option<AccountInfo<'info>>,
+    pub taker_stats: Option<AccountInfo<'info>>,
+    /// The seller's `SellerIndex` address, initialized or not
+    pub seller_index: AccountInfo<'info>,
+    /// The seller's `Reputation` address, initialized or not
+    pub seller_reputation: AccountInfo<'info>,
+    /// The escrow's rent recipient, needed to close it when that is not
+    /// the seller
+    pub rent_recipient: Option<AccountInfo<'info>>,
+    /// The protocol `Stats` address, initialized or not
+    pub stats: AccountInfo<'info>,
+    /// The pair's `PairStats` address, initialized or not
+    pub pair_stats: AccountInfo<'info>,
+    pub config: &'a Account<'info, Config>,
+    /// The config's activity log tree and the programs appending to it,
+    /// required while it keeps one
+    pub activity_log: Option<&'a UncheckedAccount<'info>>,
+    pub log_wrapper: Option<&'a UncheckedAccount<'info>>,
+    pub compression_program: Option<&'a UncheckedAccount<'info>>,
+    /// Program fee vault of the request mint, accruing the protocol fee
+    /// until `claim_fees`; required while the protocol fee is non-zero
+    pub fee_vault: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    /// Program fee vault of the request mint for the taker's protocol fee
+    /// while `fee_vault` holds the offer mint
+    pub taker_fee_vault: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    /// Referrer's request-mint token account; receives the configured
+    /// referral share of the protocol fee
+    pub referrer_token: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    /// Offered NFT's metadata; required when the escrow pays royalties
+    pub offer_metadata: Option<&'a Account<'info, MetadataAccount>>,
+    /// Required when the escrow is gated on a buyer allowlist
+    pub buyer_allowlist: Option<&'a Account<'info, BuyerAllowlist>>,
+    /// Taker's token account meeting the escrow's token gate, and the
+    /// metadata of its mint for collection gates
+    pub gate_token: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    pub gate_metadata: Option<&'a Account<'info, MetadataAccount>>,
+    /// Taker's NFT of the config's fee discount collection, and the
+    /// metadata of its mint, when claiming the discount
+    pub discount_token: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    pub discount_metadata: Option<&'a Account<'info, MetadataAccount>>,
+    /// Permission program's approval of the taker, when required
+    pub permission_approval: Option<AccountInfo<'info>>,
+    /// Revealed commitment to this fill, when the escrow settles by commit
+    /// and reveal
+    pub fill_commitment: Option<&'a Account<'info, FillCommitment>>,
+    /// Wallet credited with the taker side of the fill
+    pub taker: Pubkey,
+    /// Source of the request tokens and its authority; without a token
+    /// account a native SOL request is paid in lamports from the authority
+    pub payer_token: Option<AccountInfo<'info>>,
+    pub payer_authority: AccountInfo<'info>,
+    /// Receives the offer tokens released from the vault
+    pub offer_destination: AccountInfo<'info>,
+    pub offer_mint: &'a InterfaceAccount<'info, Mint>,
+    pub request_mint: &'a InterfaceAccount<'info, Mint>,
+    /// `MintLimits` addresses of both mints, initialized or not; required
+    /// for partial fills
+    pub offer_mint_limits: Option<AccountInfo<'info>>,
+    pub request_mint_limits: Option<AccountInfo<'info>>,
+    pub offer_token_program: AccountInfo<'info>,
+    pub request_token_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    /// Royalty destinations in the metadata's creator order when the
+    /// escrow pays royalties, then per extra request leg its mint, the
+    /// payer's and seller's token accounts and its token program, then
+    /// one account per configured fee split, then transfer-hook accounts
+    /// for any mint
+    pub remaining_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> Fill<'a, 'info> {
+    /// Pay the seller (net of market fees) for `fill_amount` offer tokens
+    /// and release them from the vault, closing out the escrow once fully
+    /// filled. `payer_seeds` signs for a PDA payer and is empty when the
+    /// payer authority is a transaction signer.
+    /// Returns the fill's `EscrowAccepted` event for the caller to emit.
+    pub fn settle(
+        self,
+        pair_index: &mut PairIndex,
+        fill_amount: u64,
+        payer_seeds: &[&[&[u8]]],
+    ) -> Result<EscrowAccepted> {
+        require!(!self.config.paused, EscrowError::ProtocolPaused);
+        // Work from a copy so no borrow of the escrow is held across CPIs
+        let escrow = *self.escrow.load()?;
+        require!(
+            escrow.status() == EscrowStatus::Open,
+            EscrowError::EscrowNotOpen
+        );
+        require!(escrow.offer_amount > 0, EscrowError::EscrowAlreadyFilled);
+        require!(
+            fill_amount > 0 && fill_amount <= escrow.offer_amount,
+            EscrowError::InvalidAmount
+        );
+        // A hashed timelock settles its price off-chain, through the preimage
+        require!(escrow.hashlock().is_none(), EscrowError::Hashlocked);
+        // A locked position may only fill if the proceeds stay in escrow
+        require!(
+            escrow.locked_by().is_none() || escrow.receipt_mint().is_some(),
+            EscrowError::EscrowLocked
+        );
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        // Every leg of the fill reuses the mints' fees for this epoch
+        let offer_fee = epoch_transfer_fee(&self.offer_mint.to_account_info(), clock.epoch)?;
+        let request_fee = epoch_transfer_fee(&self.request_mint.to_account_info(), clock.epoch)?;
+        if let Some(starts_at) = escrow.starts_at() {
+            require!(now >= starts_at, EscrowError::EscrowNotStarted);
+        }
+        if let Some(expires_at) = escrow.expires_at() {
+            require!(now < expires_at, EscrowError::EscrowExpired);
+        }
+        if let Some(condition) = &escrow.condition() {
+            require!(condition.met, EscrowError::ConditionNotMet);
+        }
+        if let Some(designated_buyer) = escrow.designated_buyer() {
+            require_keys_eq!(
+                self.taker,
+                designated_buyer,
+                EscrowError::NotDesignatedBuyer
+            );
+        }
+        escrow.check_reservation(&self.taker, now)?;
+        // Committed fills settle in commitment order: each waits out the
+        // delay from its own commitment, so the earliest comes due first
+        if let Some(delay) = escrow.reveal_delay() {
+            let commitment = self
+                .fill_commitment
+                .ok_or(EscrowError::MissingFillCommitment)?;
+            require!(
+                commitment.escrow == self.escrow.key()
+                    && commitment.fill_amount == Some(fill_amount),
+                EscrowError::InvalidFillCommitment
+            );
+            require!(
+                now >= commitment.committed_at.saturating_add(delay),
+                EscrowError::CommitmentTooRecent
+            );
+        }
+        if let Some(gate) = escrow.token_gate() {
+            let gate_token = self.gate_token.ok_or(EscrowError::MissingGateAccount)?;
+            require_keys_eq!(
+                gate_token.owner,
+                self.taker,
+                EscrowError::InvalidTokenAccountOwner
+            );
+            gate.check(gate_token, self.gate_metadata.map(|metadata| &**metadata))?;
+        }
+        if let Some(permission) = escrow.permission() {
+            let approval = self
+                .permission_approval
+                .as_ref()
+                .ok_or(EscrowError::InvalidPermissionAccount)?;
+            permission.check(approval, &self.taker)?;
+        }
+        if let Some(allowlist_key) = escrow.buyer_allowlist() {
+            let allowlist = self
+                .buyer_allowlist
+                .ok_or(EscrowError::MissingAllowlistAccount)?;
+            require_keys_eq!(
+                allowlist.key(),
+                allowlist_key,
+                EscrowError::InvalidAllowlist
+            );
+            require!(
+                allowlist.buyers.contains(&self.taker),
+                EscrowError::BuyerNotAllowed
+            );
+        }
+        if self.payer_token.is_none() {
+            require!(
+                is_native_mint(&escrow.request_mint),
+                EscrowError::MissingRequestTokenAccount
+            );
+        }
+        let fully_filled = fill_amount == escrow.offer_amount;
+        let remaining_offer_amount = escrow
+            .offer_amount
+            .checked_sub(fill_amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(
+            fully_filled || !escrow.has_extra_payments(),
+            EscrowError::HasRequestLegs
+        );
+        let request_amount = escrow.fill_cost(fill_amount)?;
+
+        // Create signer seeds for the escrow PDA
+        let seller_key = escrow.seller;
+        let listed_by = escrow.listed_by();
+        let offer_mint_key = escrow.offer_mint;
+        let request_mint_key = escrow.request_mint;
+        let escrow_id = escrow.escrow_id.to_le_bytes();
+        let escrow_bump = escrow.escrow_bump;
+
+        let escrow_seeds = &[
+            b"escrow",
+            listed_by.as_ref(),
+            offer_mint_key.as_ref(),
+            request_mint_key.as_ref(),
+            escrow_id.as_ref(),
+            &[escrow_bump],
+        ];
+        let signer_seeds = &[&escrow_seeds[..]];
+
+        // Route market fees (if any): the maker fee comes out of the seller's
+        // proceeds (negative = rebate), the taker fee is paid on top
+        let mut fees = match escrow.market() {
+            Some(market_key) => {
+                let market = self.market.ok_or(EscrowError::MissingMarketAccount)?;
+                require_keys_eq!(market.key(), market_key, EscrowError::InvalidMarket);
+                let pair_fees = self
+                    .pair_fees
+                    .as_ref()
+                    .ok_or(EscrowError::MissingMarketAccount)?;
+                let seller_stats = self
+                    .seller_stats
+                    .as_ref()
+                    .ok_or(EscrowError::MissingMarketAccount)?;
+                let taker_stats = self
+                    .taker_stats
+                    .as_ref()
+                    .ok_or(EscrowError::MissingMarketAccount)?;
+
+                // Tiers use the volume traded before this fill
+                let mut seller_record =
+                    TraderStats::load(seller_stats, &seller_key, &request_mint_key)?;
+                let mut taker_record =
+                    TraderStats::load(taker_stats, &self.taker, &request_mint_key)?;
+                let seller_volume = seller_record.as_ref().map_or(0, |s| s.volume(now));
+                let taker_volume = taker_record.as_ref().map_or(0, |s| s.volume(now));
+
+                let fees = market
+                    .fee_schedule(&market_key, pair_fees, &offer_mint_key, &request_mint_key)?
+                    .discounted(seller_volume, taker_volume)
+                    .apply(request_amount)?;
+
+                // Self-trades never count towards volume tiers
+                if self.taker != seller_key {
+                    for (record, info) in [
+                        (&mut seller_record, seller_stats),
+                        (&mut taker_record, taker_stats),
+                    ] {
+                        if let Some(record) = record {
+                            record.record_volume(request_amount, now)?;
+                            record.store(info)?;
+                        }
+                    }
+                }
+                // The taker covers request-mint transfer fees on every leg
+                let collected = fees.collected()?;
+                if collected > 0 {
+                    let fee_token = self
+                        .market_fee_token
+                        .ok_or(EscrowError::MissingMarketAccount)?;
+                    require_keys_eq!(fee_token.mint, request_mint_key, EscrowError::InvalidMint);
+                    require_keys_eq!(
+                        fee_token.owner,
+                        market.fee_receiver,
+                        EscrowError::InvalidTokenAccountOwner
+                    );
+
+                    self.pay(
+                        fee_token.to_account_info(),
+                        collected,
+                        request_fee.as_ref(),
+                        payer_seeds,
+                    )?;
+                }
+                fees
+            }
+            None => MarketFees::default(),
+        };
+
+        // The maker's protocol fee comes out of the seller's proceeds, or out
+        // of the offer the vault releases when the config takes fees in the
+        // offer mint, less the discount the seller's stake earned when they
+        // listed
+        let fee_on_offer = self.config.fee_currency == FeeCurrency::Offer;
+        let (fee_base, fee_mint_key) = if fee_on_offer {
+            (fill_amount, offer_mint_key)
+        } else {
+            (request_amount, request_mint_key)
+        };
+        let protocol_fee = bps_of(
+            fee_base,
+            discount_rate(
+                self.config.protocol_fee_bps,
+                escrow.protocol_fee_discount_bps(),
+            ),
+        )?;
+        // The taker's protocol fee is always paid on top of the request
+        // tokens, straight to the treasury
+        let protocol_taker_fee = bps_of(
+            request_amount,
+            discount_rate(self.config.protocol_taker_fee_bps, self.taker_discount()?),
+        )?;
+        let mut offer_fee_payments = Vec::new();
+        // A referrer takes its share of the maker's fee before the treasury
+        if let Some(referrer_token) = self.referrer_token {
+            require_keys_eq!(referrer_token.mint, fee_mint_key, EscrowError::InvalidMint);
+            fees.referral_fee = bps_of(protocol_fee, self.config.referral_share_bps)?;
+            if fees.referral_fee > 0 && fee_on_offer {
+                offer_fee_payments.push((referrer_token.to_account_info(), fees.referral_fee));
+            } else if fees.referral_fee > 0 {
+                self.pay(
+                    referrer_token.to_account_info(),
+                    fees.referral_fee,
+                    request_fee.as_ref(),
+                    payer_seeds,
+                )?;
+            }
+        }
+        // Configured splits share out the rest before the treasury, paid
+        // once their accounts are located after the request legs
+        let (split_payments, treasury_fee) = self.config.split_fee(
+            protocol_fee
+                .checked_sub(fees.referral_fee)
+                .ok_or(EscrowError::MathOverflow)?,
+        )?;
+        if treasury_fee > 0 {
+            let fee_vault = self.fee_vault.ok_or(EscrowError::MissingTreasuryAccount)?;
+            require_keys_eq!(fee_vault.mint, fee_mint_key, EscrowError::InvalidMint);
+            require_keys_eq!(
+                fee_vault.owner,
+                self.config.key(),
+                EscrowError::InvalidTokenAccountOwner
+            );
+            if fee_on_offer {
+                offer_fee_payments.push((fee_vault.to_account_info(), treasury_fee));
+            } else {
+                self.pay(
+                    fee_vault.to_account_info(),
+                    treasury_fee,
+                    request_fee.as_ref(),
+                    payer_seeds,
+                )?;
+            }
+        }
+        if protocol_taker_fee > 0 {
+            let taker_fee_vault = if fee_on_offer {
+                self.taker_fee_vault
+            } else {
+                self.fee_vault
+            }
+            .ok_or(EscrowError::MissingTreasuryAccount)?;
+            require_keys_eq!(
+                taker_fee_vault.mint,
+                request_mint_key,
+                EscrowError::InvalidMint
+            );
+            require_keys_eq!(
+                taker_fee_vault.owner,
+                self.config.key(),
+                EscrowError::InvalidTokenAccountOwner
+            );
+            self.pay(
+                taker_fee_vault.to_account_info(),
+                protocol_taker_fee,
+                request_fee.as_ref(),
+                payer_seeds,
+            )?;
+            fees.protocol_taker_fee = protocol_taker_fee;
+        }
+        let (offer_protocol_fee, delivered_amount) = if fee_on_offer {
+            (
+                protocol_fee,
+                fill_amount
+                    .checked_sub(protocol_fee)
+                    .ok_or(EscrowError::MathOverflow)?,
+            )
+        } else {
+            fees.protocol_fee = protocol_fee;
+            (0, fill_amount)
+        };
+
+        // Creator royalties also come out of the seller's proceeds
+        let mut extra_accounts = self.remaining_accounts;
+        if escrow.pay_royalties() {
+            let metadata = self
+                .offer_metadata
+                .ok_or(EscrowError::MissingMetadataAccount)?;
+            let royalty = bps_of(request_amount, metadata.seller_fee_basis_points)?;
+            let creators = metadata.creators.as_deref().unwrap_or_default();
+            require!(
+                self.remaining_accounts.len() >= creators.len(),
+                EscrowError::MissingCreatorAccount
+            );
+            for (creator, destination) in creators.iter().zip(self.remaining_accounts) {
+                let share = royalty
+                    .checked_mul(creator.share as u64)
+                    .ok_or(EscrowError::MathOverflow)?
+                    / 100;
+                if share == 0 {
+                    continue;
+                }
+                // Lamport payments go to the creator's wallet
+                if self.payer_token.is_none() {
+                    require_keys_eq!(
+                        destination.key(),
+                        creator.address,
+                        EscrowError::InvalidCreatorAccount
+                    );
+                } else {
+                    require_keys_eq!(
+                        *destination.owner,
+                        self.request_token_program.key(),
+                        EscrowError::InvalidCreatorAccount
+                    );
+                    let token = TokenAccount::try_deserialize(&mut &destination.data.borrow()[..])?;
+                    require_keys_eq!(token.mint, request_mint_key, EscrowError::InvalidMint);
+                    require_keys_eq!(
+                        token.owner,
+                        creator.address,
+                        EscrowError::InvalidCreatorAccount
+                    );
+                }
+                self.pay(
+                    destination.clone(),
+                    share,
+                    request_fee.as_ref(),
+                    payer_seeds,
+                )?;
+                fees.royalty = fees
+                    .royalty
+                    .checked_add(share)
+                    .ok_or(EscrowError::MathOverflow)?;
+            }
+            extra_accounts = &extra_accounts[creators.len()..];
+        }
+
+        // Extra request legs go to the seller in full
+        let leg_accounts = extra_accounts
+            .get(..escrow.request_legs().len().saturating_mul(4))
+            .ok_or(EscrowError::MissingRequestLegAccount)?;
+        for (leg, accounts) in escrow.request_legs().iter().zip(leg_accounts.chunks(4)) {
+            let [mint, payer_token, seller_token, token_program] = accounts else {
+                unreachable!()
+            };
+            require_keys_eq!(mint.key(), leg.mint, EscrowError::InvalidMint);
+            require_keys_eq!(
+                *mint.owner,
+                token_program.key(),
+                EscrowError::InvalidRequestLegAccount
+            );
+            require_keys_eq!(
+                *seller_token.owner,
+                token_program.key(),
+                EscrowError::InvalidRequestLegAccount
+            );
+            let seller_leg_token =
+                TokenAccount::try_deserialize(&mut &seller_token.data.borrow()[..])?;
+            require_keys_eq!(seller_leg_token.mint, leg.mint, EscrowError::InvalidMint);
+            require_keys_eq!(
+                seller_leg_token.owner,
+                seller_key,
+                EscrowError::InvalidTokenAccountOwner
+            );
+            let decimals = Mint::try_deserialize(&mut &mint.data.borrow()[..])?.decimals;
+
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: payer_token.clone(),
+                        mint: mint.clone(),
+                        to: seller_token.clone(),
+                        authority: self.payer_authority.clone(),
+                    },
+                    payer_seeds,
+                )
+                .with_remaining_accounts(self.remaining_accounts.to_vec()),
+                gross_amount(mint, leg.amount)?,
+                decimals,
+            )?;
+        }
+
+        // Lamports requested on top go to the seller's wallet in full
+        if escrow.request_lamports() > 0 {
+            system_program::transfer(
+                CpiContext::new_with_signer(
+                    self.system_program.clone(),
+                    system_program::Transfer {
+                        from: self.payer_authority.clone(),
+                        to: self.seller.clone(),
+                    },
+                    payer_seeds,
+                ),
+                escrow.request_lamports(),
+            )?;
+        }
+
+        // One account per fee split follows the request legs: the
+        // recipient's token account of the fee's mint, or its wallet when
+        // paid in lamports
+        let split_accounts = extra_accounts
+            .get(leg_accounts.len()..)
+            .and_then(|accounts| accounts.get(..split_payments.len()))
+            .ok_or(EscrowError::MissingFeeSplitAccount)?;
+        for ((recipient, amount), destination) in split_payments.into_iter().zip(split_accounts) {
+            if !fee_on_offer && self.payer_token.is_none() {
+                require_keys_eq!(
+                    destination.key(),
+                    recipient,
+                    EscrowError::InvalidTokenAccountOwner
+                );
+            } else {
+                let token = TokenAccount::try_deserialize(&mut &destination.data.borrow()[..])?;
+                require_keys_eq!(token.mint, fee_mint_key, EscrowError::InvalidMint);
+                require_keys_eq!(
+                    token.owner,
+                    recipient,
+                    EscrowError::InvalidTokenAccountOwner
+                );
+            }
+            if amount == 0 {
+                continue;
+            }
+            if fee_on_offer {
+                offer_fee_payments.push((destination.clone(), amount));
+            } else {
+                self.pay(
+                    destination.clone(),
+                    amount,
+                    request_fee.as_ref(),
+                    payer_seeds,
+                )?;
+            }
+        }
+
+        // Proceeds of a receipted position are held for the receipt holder
+        let proceeds_destination = match escrow.receipt_mint() {
+            Some(_) => self
+                .proceeds_vault
+                .ok_or(EscrowError::MissingReceiptAccount)?
+                .to_account_info(),
+            // Streamed proceeds accrue to the seller from their stream vault
+            None if escrow.proceeds_stream().is_some() => self
+                .proceeds_vault
+                .ok_or(EscrowError::InvalidStreamAccount)?
+                .to_account_info(),
+            // Proceeds routed away from the seller arrive as tokens only
+            None if escrow.proceeds_recipient().is_some() => {
+                let recipient_token = self
+                    .proceeds_recipient_token
+                    .clone()
+                    .ok_or(EscrowError::MissingProceedsRecipient)?;
+                require!(
+                    self.payer_token.is_some(),
+                    EscrowError::MissingRequestTokenAccount
+                );
+                let token = TokenAccount::try_deserialize(&mut &recipient_token.data.borrow()[..])?;
+                require_keys_eq!(token.mint, request_mint_key, EscrowError::InvalidMint);
+                require_keys_eq!(
+                    token.owner,
+                    escrow.proceeds_recipient().unwrap_or_default(),
+                    EscrowError::InvalidTokenAccountOwner
+                );
+                recipient_token
+            }
+            // Lamport payments go straight to the seller's wallet
+            None if self.payer_token.is_none() => self.seller.clone(),
+            None => self
+                .seller_request_token
+                .clone()
+                .ok_or(EscrowError::MissingRequestTokenAccount)?,
+        };
+
+        // A delegated escrow pulls the offer straight from the seller, whose
+        // balance or approval may have lapsed since listing
+        if escrow.delegated_source().is_some() {
+            require!(
+                self.vault.delegate == COption::Some(self.escrow.key())
+                    && self.vault.delegated_amount >= fill_amount
+                    && self.vault.amount >= fill_amount,
+                EscrowError::DelegationLapsed
+            );
+        } else {
+            require!(
+                self.vault.amount >= escrow.offer_amount,
+                EscrowError::VaultShortfall
+            );
+        }
+
+        // The taker never receives less than nothing for their payment
+        require!(
+            fee_of(offer_fee.as_ref(), delivered_amount)? < delivered_amount,
+            EscrowError::TransferFeeTooHigh
+        );
+
+        // Transfer request tokens (net of the maker fee) to the seller
+        self.pay(
+            proceeds_destination,
+            fees.seller_proceeds(request_amount)?,
+            request_fee.as_ref(),
+            payer_seeds,
+        )?;
+
+        // Fees taken in the offer mint leave the vault ahead of the taker's
+        // share
+        for (destination, amount) in offer_fee_payments {
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    self.offer_token_program.clone(),
+                    TransferChecked {
+                        from: self.vault.to_account_info(),
+                        mint: self.offer_mint.to_account_info(),
+                        to: destination,
+                        authority: self.escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                )
+                .with_remaining_accounts(self.remaining_accounts.to_vec()),
+                amount,
+                self.offer_mint.decimals,
+            )?;
+        }
+
+        // Transfer offer tokens from vault to the taker
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                self.offer_token_program.clone(),
+                TransferChecked {
+                    from: self.vault.to_account_info(),
+                    mint: self.offer_mint.to_account_info(),
+                    to: self.offer_destination.clone(),
+                    authority: self.escrow.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(self.remaining_accounts.to_vec()),
+            delivered_amount,
+            self.offer_mint.decimals,
+        )?;
+        // Whatever stays listed must still be backed once the fill is out
+        if escrow.delegated_source().is_none() {
+            require!(
+                token_balance(&self.vault.to_account_info())? >= remaining_offer_amount,
+                EscrowError::VaultShortfall
+            );
+        }
+
+        let event = EscrowAccepted {
+            escrow: self.escrow.key(),
+            seller: seller_key,
+            buyer: self.taker,
+            offer_mint: offer_mint_key,
+            request_mint: request_mint_key,
+            offer_amount: fill_amount,
+            request_amount,
+            maker_fee: fees.maker_fee,
+            taker_fee: fees.taker_fee,
+            protocol_fee: fees.protocol_fee,
+            offer_protocol_fee,
+            protocol_taker_fee,
+            referrer: self.referrer_token.map(|token| token.owner),
+            referral_fee: fees.referral_fee,
+            royalty: fees.royalty,
+            remaining_offer_amount,
+            offer_ui_amount: ui_amount(
+                &self.offer_mint.to_account_info(),
+                fill_amount,
+                self.offer_mint.decimals,
+            )?,
+            request_ui_amount: ui_amount(
+                &self.request_mint.to_account_info(),
+                request_amount,
+                self.request_mint.decimals,
+            )?,
+            label: escrow.label(),
+        };
+        ActivityLog {
+            config: self.config,
+            merkle_tree: self.activity_log,
+            log_wrapper: self.log_wrapper,
+            compression_program: self.compression_program,
+        }
+        .append(&ActivityRecord {
+            kind: ActivityKind::Filled,
+            escrow: event.escrow,
+            seller: event.seller,
+            buyer: Some(event.buyer),
+            offer_amount: fill_amount,
+            request_amount,
+            timestamp: now,
+        })?;
+        Stats::record(&self.stats, |stats| {
+            stats.fills = stats.fills.saturating_add(1);
+            stats.volume = stats.volume.saturating_add(request_amount as u128);
+            if fully_filled {
+                stats.escrows_filled = stats.escrows_filled.saturating_add(1);
+            }
+        })?;
+        PairStats::record(&self.pair_stats, fill_amount, request_amount, now)?;
+        Reputation::record(&self.seller_reputation, |reputation| {
+            reputation.completed_trades = reputation.completed_trades.saturating_add(1);
+            reputation.total_volume = reputation
+                .total_volume
+                .saturating_add(request_amount as u128);
+        })?;
+
+        self.escrow.load_mut()?.record_fill();
+
+        if !fully_filled {
+            let mut escrow = self.escrow.load_mut()?;
+            escrow.offer_amount = remaining_offer_amount;
+            escrow.request_amount = match escrow.unit_price() {
+                Some(unit_price) => unit_price.cost(escrow.offer_amount)?,
+                None => escrow
+                    .request_amount
+                    .checked_sub(request_amount)
+                    .ok_or(EscrowError::MathOverflow)?,
+            };
+            // The remainder must still be a valid listing
+            if let (Some(offer_limits), Some(request_limits)) =
+                (&self.offer_mint_limits, &self.request_mint_limits)
+            {
+                MintLimits::check(
+                    offer_limits,
+                    request_limits,
+                    escrow.offer_amount,
+                    escrow.request_amount,
+                )?;
+            }
+            return Ok(event);
+        }
+
+        // A recurring escrow stays open, empty, until its seller replenishes it
+        if escrow.recurring().is_some() {
+            let mut escrow = self.escrow.load_mut()?;
+            escrow.offer_amount = 0;
+            escrow.request_amount = 0;
+            return Ok(event);
+        }
+
+        // Close the vault token account and return rent to its recipient;
+        // a delegated escrow's vault is the seller's own account
+        let rent_destination =
+            escrow.rent_destination(&self.seller, self.rent_recipient.as_ref())?;
+        if escrow.delegated_source().is_none() {
+            close_token_account(
+                self.offer_token_program.clone(),
+                self.vault.to_account_info(),
+                self.offer_mint.to_account_info(),
+                rent_destination.clone(),
+                self.escrow.to_account_info(),
+                signer_seeds,
+            )?;
+        }
+
+        pair_index.record_close(offer_mint_key, request_mint_key);
+        SellerIndex::record_close(&self.seller_index)?;
+        {
+            let mut escrow = self.escrow.load_mut()?;
+            escrow.set_status(EscrowStatus::Filled);
+            if escrow.receipt_mint().is_some() {
+                escrow.offer_amount = 0;
+                escrow.request_amount = 0;
+            }
+        }
+
+        // A receipted escrow stays open until its holder redeems the
+        // proceeds, and a kept record until the seller closes it
+        if escrow.receipt_mint().is_none() && !escrow.keep_record() {
+            close_escrow(self.escrow, self.seller, rent_destination)?;
+        }
+
+        Ok(event)
+    }
+
+    /// Discount on the taker fee earned by an NFT of the config's fee
+    /// discount collection the taker holds, if they pass one
+    fn taker_discount(&self) -> Result<u16> {
+        self.config.taker_discount(
+            &self.taker,
+            self.discount_token.map(|token| &**token),
+            self.discount_metadata.map(|metadata| &**metadata),
+        )
+    }
+
+    /// Pay `net` of the request mint from the payer to `to`. Token payments
+    /// are grossed up so `net` arrives after transfer fees; lamport payments
+    /// are synced when they land in a wrapped SOL account.
+    fn pay(
+        &self,
+        to: AccountInfo<'info>,
+        net: u64,
+        request_fee: Option<&TransferFee>,
+        payer_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let Some(payer_token) = &self.payer_token else {
+            system_program::transfer(
+                CpiContext::new_with_signer(
+                    self.system_program.clone(),
+                    system_program::Transfer {
+                        from: self.payer_authority.clone(),
+                        to: to.clone(),
+                    },
+                    payer_seeds,
+                ),
+                net,
+            )?;
+            if to.owner == self.request_token_program.key {
+                sync_native(CpiContext::new(
+                    self.request_token_program.clone(),
+                    SyncNative { account: to },
+                ))?;
+            }
+            return Ok(());
+        };
+
+        transfer_checked_with_hook(
+            CpiContext::new_with_signer(
+                self.request_token_program.clone(),
+                TransferChecked {
+                    from: payer_token.clone(),
+                    mint: self.request_mint.to_account_info(),
+                    to,
+                    authority: self.payer_authority.clone(),
+                },
+                payer_seeds,
+            )
+            .with_remaining_accounts(self.remaining_accounts.to_vec()),
+            gross_up(request_fee, net)?,
+            self.request_mint.decimals,
+        )
+    }
+}
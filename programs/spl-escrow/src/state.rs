@@ -0,0 +1,3177 @@
+//! Program-owned account types and the data they embed
+
+use crate::*;
+
+/// Protocol-wide settings
+#[account]
+#[derive(InitSpace)]
+pub struct Config {
+    pub authority: Pubkey,
+    /// Maker fee taken from the seller's side of every fill
+    pub protocol_fee_bps: u16,
+    /// Wallet whose request-mint token accounts receive the protocol fee
+    pub treasury: Pubkey,
+    /// Longest an escrow may stay listed, in seconds; escrows must carry
+    /// an expiry while this is set
+    pub max_expiry_duration: Option<i64>,
+    /// Only mints with an approved policy may be listed while set
+    pub require_mint_approval: bool,
+    /// Blocks new escrows and fills while set
+    pub paused: bool,
+    pub bump: u8,
+    /// Paid to whoever cranks an expired escrow, out of its rent
+    pub crank_bounty_lamports: u64,
+    /// Share of the protocol fee paid to a fill's referrer
+    pub referral_share_bps: u16,
+    /// Most escrows one seller may have open at once, counted on their
+    /// seller index
+    pub max_open_escrows_per_seller: Option<u32>,
+    /// Refuses offers of mints with a permanent delegate while set;
+    /// otherwise they list with a `PermanentDelegateListed` warning
+    pub reject_permanent_delegate: bool,
+    /// Refuses offers of mints with a freeze authority while set
+    pub reject_freezable_mints: bool,
+    /// Mint sellers stake for protocol fee discounts, if any
+    pub stake_mint: Option<Pubkey>,
+    /// How long each stake stays locked after it is added to
+    pub stake_lock_seconds: i64,
+    #[max_len(MAX_STAKE_TIERS)]
+    pub stake_tiers: Vec<StakeTier>,
+    /// Authority the role is being handed to, until it accepts
+    pub pending_authority: Option<Pubkey>,
+    /// Notice given before settings updates take effect, in seconds
+    pub config_delay_seconds: i64,
+    /// Settings queued by `update_config`, and when they may be executed
+    pub pending_config: Option<ConfigParams>,
+    pub pending_config_at: i64,
+    /// Share of claimed protocol fees burned instead of reaching the
+    /// treasury
+    pub fee_burn_bps: u16,
+    /// Programs whose instructions may not share a transaction with an
+    /// oracle-priced fill or an auction settlement
+    #[max_len(MAX_GUARDED_PROGRAMS)]
+    pub guarded_programs: Vec<Pubkey>,
+    /// Leg of each fill the maker's protocol fee is taken from
+    pub fee_currency: FeeCurrency,
+    /// Recipients sharing the treasury's part of the protocol fee
+    #[max_len(MAX_FEE_SPLITS)]
+    pub fee_splits: Vec<FeeSplit>,
+    /// Compressed merkle tree escrow lifecycle events are appended to, if
+    /// any
+    pub activity_log: Option<Pubkey>,
+    /// Taker fee charged on top of the buyer's payment for every fill,
+    /// alongside the maker's `protocol_fee_bps`
+    pub protocol_taker_fee_bps: u16,
+    /// Collection whose holders get `fee_discount_bps` off the taker fee,
+    /// if any
+    pub fee_discount_collection: Option<Pubkey>,
+    pub fee_discount_bps: u16,
+    /// Seconds a partly filled escrow must stay listed before its seller
+    /// may cancel it, or 0 for no minimum
+    pub min_listing_duration: i64,
+    /// Tip a seller deposits on each expiring escrow they create, paid on
+    /// top of the crank bounty to whoever cranks it once expired
+    pub crank_tip_lamports: u64,
+}
+
+/// Leg of a fill the protocol fee is taken from
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum FeeCurrency {
+    /// Out of the seller's request-mint proceeds
+    Request,
+    /// Out of the offer released to the taker
+    Offer,
+}
+
+/// Settings the config authority controls
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct ConfigParams {
+    pub protocol_fee_bps: u16,
+    pub treasury: Pubkey,
+    pub max_expiry_duration: Option<i64>,
+    pub require_mint_approval: bool,
+    pub crank_bounty_lamports: u64,
+    pub referral_share_bps: u16,
+    pub max_open_escrows_per_seller: Option<u32>,
+    pub reject_permanent_delegate: bool,
+    pub reject_freezable_mints: bool,
+    /// Notice given before later settings updates take effect; 0 applies
+    /// them immediately
+    pub config_delay_seconds: i64,
+    pub fee_burn_bps: u16,
+    /// Taker fee charged on top of the buyer's payment, alongside the
+    /// maker's `protocol_fee_bps`
+    pub protocol_taker_fee_bps: u16,
+    /// Mint sellers stake for protocol fee discounts, or `None` to disable
+    /// staking; sellers list at the tier their stake reaches when they
+    /// create the escrow
+    pub stake_mint: Option<Pubkey>,
+    pub stake_lock_seconds: i64,
+    #[max_len(MAX_STAKE_TIERS)]
+    pub stake_tiers: Vec<StakeTier>,
+    /// Fee vaults and referrer accounts must be of the maker fee's mint
+    pub fee_currency: FeeCurrency,
+    /// Fills pass each recipient's account of the fee's mint in this
+    /// order, after their request-leg accounts
+    #[max_len(MAX_FEE_SPLITS)]
+    pub fee_splits: Vec<FeeSplit>,
+    pub fee_discount_collection: Option<Pubkey>,
+    pub fee_discount_bps: u16,
+    /// Unfilled escrows, stuck escrows and expiry are unaffected
+    pub min_listing_duration: i64,
+    /// Escrows created without an expiry carry no tip
+    pub crank_tip_lamports: u64,
+}
+
+impl ConfigParams {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.protocol_fee_bps <= MAX_PROTOCOL_FEE_BPS,
+            EscrowError::InvalidFee
+        );
+        require!(
+            self.referral_share_bps as u64 <= BPS_DENOMINATOR,
+            EscrowError::InvalidFee
+        );
+        if let Some(duration) = self.max_expiry_duration {
+            require!(duration > 0, EscrowError::InvalidDeadline);
+        }
+        if let Some(max_open) = self.max_open_escrows_per_seller {
+            require!(max_open > 0, EscrowError::TooManyOpenEscrows);
+        }
+        require!(
+            self.fee_burn_bps as u64 <= BPS_DENOMINATOR,
+            EscrowError::InvalidFee
+        );
+        require!(self.config_delay_seconds >= 0, EscrowError::InvalidDeadline);
+        require!(
+            self.protocol_taker_fee_bps <= MAX_PROTOCOL_FEE_BPS,
+            EscrowError::InvalidFee
+        );
+        require!(
+            self.stake_tiers.len() <= MAX_STAKE_TIERS
+                && self
+                    .stake_tiers
+                    .iter()
+                    .all(|tier| tier.discount_bps as u64 <= BPS_DENOMINATOR),
+            EscrowError::InvalidFee
+        );
+        require!(self.stake_lock_seconds >= 0, EscrowError::InvalidDeadline);
+        let split_bps = self
+            .fee_splits
+            .iter()
+            .map(|split| split.bps as u64)
+            .sum::<u64>();
+        require!(
+            self.fee_splits.len() <= MAX_FEE_SPLITS && split_bps <= BPS_DENOMINATOR,
+            EscrowError::InvalidFeeSplit
+        );
+        require!(
+            self.fee_discount_bps as u64 <= BPS_DENOMINATOR,
+            EscrowError::InvalidFee
+        );
+        require!(self.min_listing_duration >= 0, EscrowError::InvalidDeadline);
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Validate and store `params`
+    pub fn apply(&mut self, params: ConfigParams) -> Result<()> {
+        params.validate()?;
+
+        self.protocol_fee_bps = params.protocol_fee_bps;
+        self.treasury = params.treasury;
+        self.max_expiry_duration = params.max_expiry_duration;
+        self.require_mint_approval = params.require_mint_approval;
+        self.crank_bounty_lamports = params.crank_bounty_lamports;
+        self.referral_share_bps = params.referral_share_bps;
+        self.max_open_escrows_per_seller = params.max_open_escrows_per_seller;
+        self.reject_permanent_delegate = params.reject_permanent_delegate;
+        self.reject_freezable_mints = params.reject_freezable_mints;
+        self.config_delay_seconds = params.config_delay_seconds;
+        self.fee_burn_bps = params.fee_burn_bps;
+        self.protocol_taker_fee_bps = params.protocol_taker_fee_bps;
+        self.stake_mint = params.stake_mint;
+        self.stake_lock_seconds = params.stake_lock_seconds;
+        self.stake_tiers = params.stake_tiers;
+        self.fee_currency = params.fee_currency;
+        self.fee_splits = params.fee_splits;
+        self.fee_discount_collection = params.fee_discount_collection;
+        self.fee_discount_bps = params.fee_discount_bps;
+        self.min_listing_duration = params.min_listing_duration;
+        self.crank_tip_lamports = params.crank_tip_lamports;
+        Ok(())
+    }
+
+    /// Protocol fee discount earned by `stake`, in basis points of the fee
+    pub fn stake_discount(&self, stake: &Stake) -> u16 {
+        if self.stake_mint != Some(stake.mint) {
+            return 0;
+        }
+        self.stake_tiers
+            .iter()
+            .filter(|tier| stake.amount >= tier.min_staked)
+            .map(|tier| tier.discount_bps)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Taker fee discount earned by `taker` holding `token`, an NFT of the
+    /// fee discount collection, in basis points of the fee; 0 without one
+    pub fn taker_discount(
+        &self,
+        taker: &Pubkey,
+        token: Option<&TokenAccount>,
+        metadata: Option<&MetadataAccount>,
+    ) -> Result<u16> {
+        let (Some(collection), Some(token)) = (self.fee_discount_collection, token) else {
+            return Ok(0);
+        };
+        require_keys_eq!(token.owner, *taker, EscrowError::InvalidTokenAccountOwner);
+        TokenGate::Collection { collection }.check(token, metadata)?;
+        Ok(self.fee_discount_bps)
+    }
+
+    /// Share `fee` out among the fee splits, in order, returning each
+    /// recipient's cut and what is left for the treasury
+    pub fn split_fee(&self, fee: u64) -> Result<(Vec<(Pubkey, u64)>, u64)> {
+        let mut treasury_fee = fee;
+        let mut splits = Vec::with_capacity(self.fee_splits.len());
+        for split in &self.fee_splits {
+            let amount = bps_of(fee, split.bps)?;
+            treasury_fee = treasury_fee
+                .checked_sub(amount)
+                .ok_or(EscrowError::MathOverflow)?;
+            splits.push((split.recipient, amount));
+        }
+        Ok((splits, treasury_fee))
+    }
+
+    /// Check a seller's open escrow count is within the per-seller limit
+    pub fn check_open_escrows(&self, open_escrows: u32) -> Result<()> {
+        if let Some(max_open) = self.max_open_escrows_per_seller {
+            require!(open_escrows <= max_open, EscrowError::TooManyOpenEscrows);
+        }
+        Ok(())
+    }
+
+    /// Check an escrow expiry is in the future and within the maximum
+    /// listing duration
+    pub fn check_expiry(&self, expires_at: Option<i64>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        if let Some(expires_at) = expires_at {
+            require!(expires_at > now, EscrowError::InvalidDeadline);
+        }
+        if let Some(max_duration) = self.max_expiry_duration {
+            let expires_at = expires_at.ok_or(EscrowError::ExpiryTooLong)?;
+            require!(expires_at - now <= max_duration, EscrowError::ExpiryTooLong);
+        }
+        Ok(())
+    }
+}
+
+/// One recipient's cut of the protocol fee, such as a frontend or creator
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct FeeSplit {
+    /// Wallet whose token account of the fee's mint receives the cut
+    pub recipient: Pubkey,
+    /// Share of the protocol fee, after any referral, in basis points
+    pub bps: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct StakeTier {
+    /// Staked amount (stake-mint base units) needed for this tier
+    pub min_staked: u64,
+    /// Share of the protocol fee waived, in basis points of the fee
+    pub discount_bps: u16,
+}
+
+/// Listing status the config authority assigns to a mint
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum MintStatus {
+    Approved,
+    Banned,
+}
+
+/// Config authority's policy for one mint
+#[account]
+#[derive(InitSpace)]
+pub struct MintPolicy {
+    pub mint: Pubkey,
+    pub status: MintStatus,
+    pub bump: u8,
+}
+
+impl MintPolicy {
+    /// Check that the mint whose policy address is `info` may be listed
+    pub fn check_listing(info: &AccountInfo, config: &Config) -> Result<()> {
+        match load_if_initialized::<Self>(info)?.map(|policy| policy.status) {
+            Some(MintStatus::Banned) => err!(EscrowError::MintBanned),
+            Some(MintStatus::Approved) => Ok(()),
+            None if config.require_mint_approval => err!(EscrowError::MintNotApproved),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Config authority's ban on one seller listing escrows
+#[account]
+#[derive(InitSpace)]
+pub struct SellerBan {
+    pub seller: Pubkey,
+    pub bump: u8,
+}
+
+impl SellerBan {
+    /// Check that the seller whose ban address is `info` may list
+    pub fn check_listing(info: &AccountInfo) -> Result<()> {
+        if let Some(ban) = load_if_initialized::<Self>(info)? {
+            emit!(ListingBlocked { seller: ban.seller });
+            return err!(EscrowError::SellerBanned);
+        }
+        Ok(())
+    }
+}
+
+/// Config authority's size limits for escrows involving one mint
+#[account]
+#[derive(InitSpace)]
+pub struct MintLimits {
+    pub mint: Pubkey,
+    /// Smallest open offer of this mint, in base units
+    pub min_offer_amount: u64,
+    /// Largest open offer of this mint, if capped
+    pub max_offer_amount: Option<u64>,
+    /// Smallest amount of this mint an escrow may request
+    pub min_notional: u64,
+    pub bump: u8,
+}
+
+impl MintLimits {
+    /// Check an escrow's open amounts against the limits at the offer and
+    /// request mints' limits addresses
+    pub fn check(
+        offer_limits: &AccountInfo,
+        request_limits: &AccountInfo,
+        offer_amount: u64,
+        request_amount: u64,
+    ) -> Result<()> {
+        if let Some(limits) = load_if_initialized::<Self>(offer_limits)? {
+            require!(
+                offer_amount >= limits.min_offer_amount,
+                EscrowError::OfferBelowMinimum
+            );
+            if let Some(max_offer_amount) = limits.max_offer_amount {
+                require!(
+                    offer_amount <= max_offer_amount,
+                    EscrowError::OfferAboveMaximum
+                );
+            }
+        }
+        if let Some(limits) = load_if_initialized::<Self>(request_limits)? {
+            require!(
+                request_amount >= limits.min_notional,
+                EscrowError::NotionalBelowMinimum
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Load the account at an address already known to be correct; returns
+/// `None` if the account was never initialized
+pub(crate) fn load_if_initialized<T: AccountDeserialize>(info: &AccountInfo) -> Result<Option<T>> {
+    if info.owner != &crate::ID || info.data_is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(T::try_deserialize(&mut &info.data.borrow()[..])?))
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Market {
+    pub operator: Pubkey,
+    pub fee_receiver: Pubkey,
+    pub fee_bps: u16,
+    /// Opaque identifier the operator's frontend uses for branding
+    pub branding_id: [u8; 32],
+    /// Mints that may be listed; empty means any mint
+    #[max_len(MAX_MARKET_ALLOWLIST_LEN)]
+    pub allowed_mints: Vec<Pubkey>,
+    /// Sellers that may list; empty means anyone
+    #[max_len(MAX_MARKET_ALLOWLIST_LEN)]
+    pub allowed_sellers: Vec<Pubkey>,
+    /// Programs allowed to lock escrows as collateral
+    #[max_len(MAX_MARKET_ALLOWLIST_LEN)]
+    pub approved_lockers: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl Market {
+    /// Check that a new listing satisfies the market's allowlists
+    pub fn check_listing(
+        &self,
+        seller: &Pubkey,
+        offer_mint: &Pubkey,
+        request_mint: &Pubkey,
+    ) -> Result<()> {
+        if !self.allowed_sellers.is_empty() {
+            require!(
+                self.allowed_sellers.contains(seller),
+                EscrowError::SellerNotAllowed
+            );
+        }
+        if !self.allowed_mints.is_empty() {
+            require!(
+                self.allowed_mints.contains(offer_mint)
+                    && self.allowed_mints.contains(request_mint),
+                EscrowError::MintNotAllowed
+            );
+        }
+        Ok(())
+    }
+
+    /// Resolve the fee rates for a pair
+    /// - `pair_fees` must be the pair's `PairFees` address; when it has not
+    ///   been initialized the market's flat fee is charged to the maker
+    pub fn fee_schedule(
+        &self,
+        market_key: &Pubkey,
+        pair_fees: &AccountInfo,
+        offer_mint: &Pubkey,
+        request_mint: &Pubkey,
+    ) -> Result<FeeSchedule> {
+        let (expected, _) = Pubkey::find_program_address(
+            &[
+                b"pair_fees",
+                market_key.as_ref(),
+                offer_mint.as_ref(),
+                request_mint.as_ref(),
+            ],
+            &crate::ID,
+        );
+        require_keys_eq!(pair_fees.key(), expected, EscrowError::InvalidPairFees);
+
+        if pair_fees.owner != &crate::ID || pair_fees.data_is_empty() {
+            return Ok(FeeSchedule {
+                maker_fee_bps: self.fee_bps as i16,
+                taker_fee_bps: 0,
+                volume_tiers: Vec::new(),
+            });
+        }
+        let pair_fees = PairFees::try_deserialize(&mut &pair_fees.data.borrow()[..])?;
+        Ok(FeeSchedule {
+            maker_fee_bps: pair_fees.maker_fee_bps,
+            taker_fee_bps: pair_fees.taker_fee_bps,
+            volume_tiers: pair_fees.volume_tiers,
+        })
+    }
+}
+
+/// Maker/taker fee rates of a market pair
+#[account]
+#[derive(InitSpace)]
+pub struct PairFees {
+    pub market: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Charged to the escrow creator; negative values are rebates
+    pub maker_fee_bps: i16,
+    /// Charged to the acceptor on top of the request amount
+    pub taker_fee_bps: u16,
+    /// Fee discounts by trailing volume, ascending by `min_volume`
+    #[max_len(MAX_VOLUME_TIERS)]
+    pub volume_tiers: Vec<VolumeTier>,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct VolumeTier {
+    /// Trailing volume (request-mint base units) needed for this tier
+    pub min_volume: u64,
+    /// Share of the fee waived, in basis points of the fee
+    pub discount_bps: u16,
+}
+
+#[derive(Clone)]
+pub struct FeeSchedule {
+    pub maker_fee_bps: i16,
+    pub taker_fee_bps: u16,
+    pub volume_tiers: Vec<VolumeTier>,
+}
+
+impl FeeSchedule {
+    /// Apply each party's volume tier discount
+    /// - Maker rebates are never scaled
+    pub fn discounted(mut self, maker_volume: u64, taker_volume: u64) -> Self {
+        if self.maker_fee_bps > 0 {
+            let discount = self.discount_for(maker_volume);
+            self.maker_fee_bps = discount_rate(self.maker_fee_bps as u16, discount) as i16;
+        }
+        let discount = self.discount_for(taker_volume);
+        self.taker_fee_bps = discount_rate(self.taker_fee_bps, discount);
+        self
+    }
+
+    fn discount_for(&self, volume: u64) -> u16 {
+        self.volume_tiers
+            .iter()
+            .filter(|tier| volume >= tier.min_volume)
+            .map(|tier| tier.discount_bps)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Compute the fees owed on a request-token amount, rounded down
+    pub fn apply(&self, amount: u64) -> Result<MarketFees> {
+        let maker = bps_of(amount, self.maker_fee_bps.unsigned_abs())?;
+        let maker_fee = if self.maker_fee_bps < 0 {
+            -(maker as i64)
+        } else {
+            maker as i64
+        };
+        Ok(MarketFees {
+            maker_fee,
+            taker_fee: bps_of(amount, self.taker_fee_bps)?,
+            protocol_fee: 0,
+            protocol_taker_fee: 0,
+            referral_fee: 0,
+            royalty: 0,
+        })
+    }
+}
+
+/// Market fees charged on one fill
+#[derive(Clone, Copy, Default)]
+pub struct MarketFees {
+    /// Deducted from the seller's proceeds; negative values are rebates
+    pub maker_fee: i64,
+    /// Paid by the taker on top of the request amount
+    pub taker_fee: u64,
+    /// Paid to the protocol treasury out of the seller's proceeds
+    pub protocol_fee: u64,
+    /// Paid to the protocol treasury by the taker on top of the request
+    /// amount
+    pub protocol_taker_fee: u64,
+    /// Part of the protocol fees paid to the taker's referrer instead
+    pub referral_fee: u64,
+    /// Paid to the offered NFT's creators out of the seller's proceeds
+    pub royalty: u64,
+}
+
+impl MarketFees {
+    /// Request tokens the taker pays for a fill of `request_amount`, fees
+    /// included
+    pub fn taker_cost(&self, request_amount: u64) -> Result<u64> {
+        request_amount
+            .checked_add(self.taker_fee)
+            .and_then(|cost| cost.checked_add(self.protocol_taker_fee))
+            .ok_or(error!(EscrowError::MathOverflow))
+    }
+
+    /// Net amount owed to the fee receiver
+    pub fn collected(&self) -> Result<u64> {
+        let collected = (self.taker_fee as i64)
+            .checked_add(self.maker_fee)
+            .ok_or(EscrowError::MathOverflow)?;
+        u64::try_from(collected).map_err(|_| error!(EscrowError::InvalidFee))
+    }
+
+    /// Amount the seller receives for a fill of `request_amount`
+    pub fn seller_proceeds(&self, request_amount: u64) -> Result<u64> {
+        let proceeds = (request_amount as i64)
+            .checked_sub(self.maker_fee)
+            .and_then(|proceeds| proceeds.checked_sub(self.protocol_fee as i64))
+            .and_then(|proceeds| proceeds.checked_sub(self.royalty as i64))
+            .ok_or(EscrowError::MathOverflow)?;
+        u64::try_from(proceeds).map_err(|_| error!(EscrowError::MathOverflow))
+    }
+}
+
+/// Price of a fill as `accept_escrow` would settle it now, returned by
+/// `get_quote`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quote {
+    /// Offer tokens released from the vault
+    pub fill_amount: u64,
+    /// Offer tokens the buyer receives after the offer mint's transfer fee
+    pub offer_out: u64,
+    /// Escrow price of the fill, before market fees
+    pub request_amount: u64,
+    /// Deducted from the seller's proceeds; negative values are rebates
+    pub maker_fee: i64,
+    /// Paid by the buyer on top of `request_amount`
+    pub taker_fee: u64,
+    /// Paid to the protocol out of the seller's proceeds, referral share
+    /// included
+    pub protocol_fee: u64,
+    /// Paid to the protocol out of the offer instead, when the config
+    /// takes fees in the offer mint
+    pub offer_protocol_fee: u64,
+    /// Paid to the protocol by the buyer on top of `request_amount`
+    pub protocol_taker_fee: u64,
+    /// Paid to the offered NFT's creators out of the seller's proceeds
+    pub royalty: u64,
+    /// `request_amount` plus the buyer's fees in the request mint: the
+    /// bound to pass as `max_request_amount`, before request-mint transfer
+    /// fees
+    pub total_cost: u64,
+    /// Request tokens the seller is credited
+    pub seller_proceeds: u64,
+    /// Lamports the buyer pays the seller on top of the request tokens
+    pub request_lamports: u64,
+}
+
+/// Snapshot of an escrow's lifecycle, returned by `get_escrow_state`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct EscrowState {
+    pub status: EscrowStatus,
+    pub offer_amount: u64,
+    /// Listed price of the remaining offer; oracle-priced escrows re-quote
+    /// it on every fill
+    pub request_amount: u64,
+    pub starts_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    /// Past its expiry, whether or not anyone has expired it yet
+    pub expired: bool,
+    /// Open, started, unexpired and not yet emptied
+    pub fillable: bool,
+}
+
+/// Reduce a fee rate by `discount_bps` of itself
+pub fn discount_rate(rate_bps: u16, discount_bps: u16) -> u16 {
+    let kept = BPS_DENOMINATOR.saturating_sub(discount_bps as u64);
+    (rate_bps as u64 * kept / BPS_DENOMINATOR) as u16
+}
+
+/// Smallest amount that still leaves `net` after `bps_of` it is taken
+pub fn gross_of_bps(net: u64, bps: u16) -> Result<u64> {
+    let kept = BPS_DENOMINATOR.saturating_sub(bps as u64) as u128;
+    require!(kept > 0, EscrowError::InvalidFee);
+    let value = (net as u128)
+        .checked_mul(BPS_DENOMINATOR as u128)
+        .ok_or(EscrowError::MathOverflow)?
+        .div_ceil(kept);
+    u64::try_from(value).map_err(|_| error!(EscrowError::MathOverflow))
+}
+
+/// `amount * bps / 10_000`, rounded down
+pub fn bps_of(amount: u64, bps: u16) -> Result<u64> {
+    let value = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(EscrowError::MathOverflow)?
+        / BPS_DENOMINATOR as u128;
+    u64::try_from(value).map_err(|_| error!(EscrowError::MathOverflow))
+}
+
+/// Directory of mint pairs that currently have open escrows
+#[account]
+#[derive(InitSpace)]
+pub struct PairIndex {
+    #[max_len(MAX_INDEXED_PAIRS)]
+    pub pairs: Vec<PairEntry>,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct PairEntry {
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    pub open_escrows: u32,
+}
+
+impl PairIndex {
+    /// Count a newly opened escrow for the pair
+    /// - Once the index is full, new pairs are left unindexed rather than
+    ///   blocking escrow creation
+    pub fn record_open(&mut self, offer_mint: Pubkey, request_mint: Pubkey) {
+        if let Some(entry) = self.find_mut(&offer_mint, &request_mint) {
+            entry.open_escrows = entry.open_escrows.saturating_add(1);
+        } else if self.pairs.len() < MAX_INDEXED_PAIRS {
+            self.pairs.push(PairEntry {
+                offer_mint,
+                request_mint,
+                open_escrows: 1,
+            });
+        } else {
+            msg!("Pair index full; pair not indexed");
+        }
+    }
+
+    /// Count a closed escrow, dropping the pair once nothing is open
+    pub fn record_close(&mut self, offer_mint: Pubkey, request_mint: Pubkey) {
+        if let Some(position) = self
+            .pairs
+            .iter()
+            .position(|entry| entry.offer_mint == offer_mint && entry.request_mint == request_mint)
+        {
+            let entry = &mut self.pairs[position];
+            entry.open_escrows = entry.open_escrows.saturating_sub(1);
+            if entry.open_escrows == 0 {
+                self.pairs.swap_remove(position);
+            }
+        }
+    }
+
+    fn find_mut(&mut self, offer_mint: &Pubkey, request_mint: &Pubkey) -> Option<&mut PairEntry> {
+        self.pairs
+            .iter_mut()
+            .find(|entry| entry.offer_mint == *offer_mint && entry.request_mint == *request_mint)
+    }
+}
+
+/// Directory of open escrows, split over `RegistryPage`s numbered from
+/// zero so it can grow past one account's size
+#[account]
+#[derive(InitSpace)]
+pub struct OfferRegistry {
+    pub page_count: u32,
+    pub bump: u8,
+}
+
+/// One page of the offer registry
+#[account]
+#[derive(InitSpace)]
+pub struct RegistryPage {
+    pub index: u32,
+    #[max_len(MAX_REGISTRY_PAGE_LEN)]
+    pub escrows: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl RegistryPage {
+    /// List an open escrow on the page
+    pub fn register(&mut self, escrow: Pubkey) -> Result<()> {
+        require!(
+            self.escrows.len() < MAX_REGISTRY_PAGE_LEN,
+            EscrowError::RegistryPageFull
+        );
+        self.escrows.push(escrow);
+        Ok(())
+    }
+
+    /// Remove an escrow listed on the page
+    pub fn deregister(&mut self, escrow: &Pubkey) -> Result<()> {
+        let position = self
+            .escrows
+            .iter()
+            .position(|listed| listed == escrow)
+            .ok_or(EscrowError::EscrowNotRegistered)?;
+        self.escrows.swap_remove(position);
+        Ok(())
+    }
+}
+
+/// One page of a mint pair's index of open escrows, numbered from zero,
+/// so clients can walk a pair's offers without scanning the program
+#[account]
+#[derive(InitSpace)]
+pub struct MarketIndex {
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    pub page: u32,
+    #[max_len(MAX_MARKET_INDEX_LEN)]
+    pub escrows: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl MarketIndex {
+    /// Link an open escrow into the page
+    pub fn link(&mut self, escrow: Pubkey) -> Result<()> {
+        require!(
+            self.escrows.len() < MAX_MARKET_INDEX_LEN,
+            EscrowError::MarketIndexFull
+        );
+        self.escrows.push(escrow);
+        Ok(())
+    }
+
+    /// Unlink `escrow` from the page it was `linked` into, `index` being
+    /// the page passed to the instruction closing it; an escrow already
+    /// pruned from the page is left as is
+    pub fn unlink(
+        index: Option<&mut Account<Self>>,
+        linked: Option<Pubkey>,
+        escrow: &Pubkey,
+    ) -> Result<()> {
+        let Some(linked) = linked else {
+            return Ok(());
+        };
+        let index = index.ok_or(EscrowError::MissingMarketIndexAccount)?;
+        require_keys_eq!(index.key(), linked, EscrowError::InvalidMarketIndex);
+        if let Some(position) = index.escrows.iter().position(|listed| listed == escrow) {
+            index.escrows.swap_remove(position);
+        }
+        Ok(())
+    }
+}
+
+/// Protocol-wide activity totals
+#[account]
+#[derive(InitSpace)]
+pub struct Stats {
+    pub escrows_created: u64,
+    pub escrows_filled: u64,
+    pub escrows_cancelled: u64,
+    pub escrows_expired: u64,
+    /// Fills of any size, partial or full
+    pub fills: u64,
+    /// Request tokens paid across all fills, in raw units of each mint
+    pub volume: u128,
+    pub bump: u8,
+}
+
+impl Stats {
+    /// Apply `update` to the stats at `info`, if they were initialized
+    pub fn record(info: &AccountInfo, update: impl FnOnce(&mut Self)) -> Result<()> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(());
+        }
+        let mut stats = Self::try_deserialize(&mut &info.data.borrow()[..])?;
+        update(&mut stats);
+        stats.try_serialize(&mut &mut info.data.borrow_mut()[..])
+    }
+}
+
+/// One seller's track record, kept by the program for frontends ranking
+/// counterparties
+#[account]
+#[derive(InitSpace)]
+pub struct Reputation {
+    pub seller: Pubkey,
+    /// Fills of the seller's escrows, partial or full
+    pub completed_trades: u64,
+    /// Escrows the seller cancelled while open
+    pub cancellations: u64,
+    /// Disputes the escrow's arbiter resolved for the buyer
+    pub disputes_lost: u64,
+    /// Request tokens the seller was paid, in raw units of each mint
+    pub total_volume: u128,
+    pub bump: u8,
+}
+
+impl Reputation {
+    /// Apply `update` to the reputation at `info`, if it was initialized
+    pub fn record(info: &AccountInfo, update: impl FnOnce(&mut Self)) -> Result<()> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(());
+        }
+        let mut reputation = Self::try_deserialize(&mut &info.data.borrow()[..])?;
+        update(&mut reputation);
+        reputation.try_serialize(&mut &mut info.data.borrow_mut()[..])
+    }
+}
+
+/// Fill count, volume and last price of one mint pair, as an on-chain
+/// reference for integrators
+#[account]
+#[derive(InitSpace)]
+pub struct PairStats {
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    pub fills: u64,
+    pub offer_volume: u128,
+    pub request_volume: u128,
+    /// Last fill's price as the amounts exchanged
+    pub last_offer_amount: u64,
+    pub last_request_amount: u64,
+    pub last_fill_at: i64,
+    pub bump: u8,
+}
+
+impl PairStats {
+    /// Record a fill against the stats at `info`, if they were initialized
+    pub fn record(
+        info: &AccountInfo,
+        offer_amount: u64,
+        request_amount: u64,
+        now: i64,
+    ) -> Result<()> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(());
+        }
+        let mut stats = Self::try_deserialize(&mut &info.data.borrow()[..])?;
+        stats.fills = stats.fills.saturating_add(1);
+        stats.offer_volume = stats.offer_volume.saturating_add(offer_amount as u128);
+        stats.request_volume = stats.request_volume.saturating_add(request_amount as u128);
+        stats.last_offer_amount = offer_amount;
+        stats.last_request_amount = request_amount;
+        stats.last_fill_at = now;
+        stats.try_serialize(&mut &mut info.data.borrow_mut()[..])
+    }
+}
+
+/// Hot key a seller authorizes to manage their escrows
+#[account]
+#[derive(InitSpace)]
+pub struct SellerOperator {
+    pub seller: Pubkey,
+    pub operator: Pubkey,
+    pub bump: u8,
+}
+
+impl SellerOperator {
+    /// Whether `authority` may manage the escrows of `seller`: the seller
+    /// themselves, or the operator they set
+    pub fn authorizes(authority: &Pubkey, seller: &Pubkey, operator: Option<Pubkey>) -> bool {
+        authority == seller || operator == Some(*authority)
+    }
+}
+
+/// Short-lived key its owner lets accept and cancel escrows for them
+/// without signing each action. Accepting pays from the owner's request
+/// token account, which must approve this account as its delegate
+#[account]
+#[derive(InitSpace)]
+pub struct SessionAuthority {
+    pub owner: Pubkey,
+    pub session_key: Pubkey,
+    pub expires_at: i64,
+    /// `SESSION_*` flags of the actions the key may take
+    pub permissions: u8,
+    /// Request tokens the key may still spend accepting escrows, fees
+    /// included
+    pub spend_remaining: u64,
+    pub bump: u8,
+}
+
+impl SessionAuthority {
+    /// Whether `signer` may take `permission` actions for `owner` through
+    /// this session, until it expires
+    pub fn authorizes(&self, signer: &Pubkey, owner: &Pubkey, permission: u8) -> Result<bool> {
+        Ok(self.session_key == *signer
+            && self.owner == *owner
+            && self.permissions & permission != 0
+            && Clock::get()?.unix_timestamp < self.expires_at)
+    }
+}
+
+/// Per-seller escrow counter and tally of open escrows
+#[account]
+#[derive(InitSpace)]
+pub struct SellerIndex {
+    pub seller: Pubkey,
+    /// Past every escrow id the seller has used, so it is always free for
+    /// the seller's next escrow
+    pub next_escrow_id: u64,
+    pub open_escrows: u32,
+    pub bump: u8,
+}
+
+impl SellerIndex {
+    /// Count a newly opened escrow under `escrow_id`
+    pub fn record_open(&mut self, escrow_id: u64) -> Result<()> {
+        self.open_escrows = self
+            .open_escrows
+            .checked_add(1)
+            .ok_or(EscrowError::MathOverflow)?;
+        let next_escrow_id = escrow_id.checked_add(1).ok_or(EscrowError::MathOverflow)?;
+        self.next_escrow_id = self.next_escrow_id.max(next_escrow_id);
+        Ok(())
+    }
+
+    /// Count a closed escrow against the index at `info`; sellers whose
+    /// escrows predate the index may have none to update
+    pub fn record_close(info: &AccountInfo) -> Result<()> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(());
+        }
+        let mut index = Self::try_deserialize(&mut &info.data.borrow()[..])?;
+        index.open_escrows = index.open_escrows.saturating_sub(1);
+        index.try_serialize(&mut &mut info.data.borrow_mut()[..])
+    }
+}
+
+/// Tokens a wallet has locked for protocol fee discounts
+#[account]
+#[derive(InitSpace)]
+pub struct Stake {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    /// Staked so far, net of transfer fees
+    pub amount: u64,
+    pub unlocks_at: i64,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+/// Settled volume of one wallet in one quote (request) mint
+#[account]
+#[derive(InitSpace)]
+pub struct TraderStats {
+    pub trader: Pubkey,
+    pub mint: Pubkey,
+    pub window_start: i64,
+    pub window_volume: u64,
+    pub previous_window_volume: u64,
+    pub lifetime_volume: u64,
+    pub bump: u8,
+}
+
+impl TraderStats {
+    /// Load the stats at `info`, which must be the trader's stats address
+    /// for `mint`; returns `None` if the account was never initialized
+    pub fn load(info: &AccountInfo, trader: &Pubkey, mint: &Pubkey) -> Result<Option<Self>> {
+        let (expected, _) = Pubkey::find_program_address(
+            &[b"trader_stats", trader.as_ref(), mint.as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(info.key(), expected, EscrowError::InvalidTraderStats);
+
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::try_deserialize(&mut &info.data.borrow()[..])?))
+    }
+
+    pub fn store(&self, info: &AccountInfo) -> Result<()> {
+        self.try_serialize(&mut &mut info.data.borrow_mut()[..])
+    }
+
+    /// Trailing volume: the current window plus the previous one
+    pub fn volume(&self, now: i64) -> u64 {
+        match (now - self.window_start) / VOLUME_WINDOW_SECONDS {
+            0 => self
+                .window_volume
+                .saturating_add(self.previous_window_volume),
+            1 => self.window_volume,
+            _ => 0,
+        }
+    }
+
+    pub fn record_volume(&mut self, amount: u64, now: i64) -> Result<()> {
+        let elapsed_windows = (now - self.window_start) / VOLUME_WINDOW_SECONDS;
+        if elapsed_windows > 0 {
+            self.previous_window_volume = if elapsed_windows == 1 {
+                self.window_volume
+            } else {
+                0
+            };
+            self.window_volume = 0;
+            self.window_start += elapsed_windows * VOLUME_WINDOW_SECONDS;
+        }
+        self.window_volume = self
+            .window_volume
+            .checked_add(amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        self.lifetime_volume = self
+            .lifetime_volume
+            .checked_add(amount)
+            .ok_or(EscrowError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MarketAllowlist {
+    Mints,
+    Sellers,
+    Lockers,
+}
+
+/// An escrow's state, laid out for zero-copy access: optional values are
+/// stored flattened behind accessors, with zero meaning unset
+#[account(zero_copy)]
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct Escrow {
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Seller-chosen id distinguishing concurrent escrows on the same pair
+    pub escrow_id: u64,
+    pub offer_amount: u64,
+    pub request_amount: u64,
+    /// Unix timestamp after which the escrow can no longer be filled
+    expires_at: i64,
+    /// Unix timestamp before which the escrow cannot be filled
+    starts_at: i64,
+    condition_threshold: i64,
+    condition_deadline: i64,
+    condition_set_at: i64,
+    oracle_usd_price: u64,
+    /// Price fills per unit of offer instead of pro rata; unset while
+    /// `unit` is zero
+    unit_price: UnitPrice,
+    dispute_window: i64,
+    /// Vest the offer to buyers instead of delivering it on accept; unset
+    /// while `duration_seconds` is zero
+    vesting: VestingSchedule,
+    /// Only counterparty allowed to fill a private escrow
+    designated_buyer: Pubkey,
+    /// Seller's buyer allowlist the escrow is gated on
+    buyer_allowlist: Pubkey,
+    /// Market the escrow was listed under, if any
+    market: Pubkey,
+    /// Mint of the position receipt, once minted
+    receipt_mint: Pubkey,
+    /// External program holding the position as collateral
+    locked_by: Pubkey,
+    /// Verified Metaplex collection of the offered NFT
+    collection: Pubkey,
+    /// Price feed or resolver of the fill condition
+    condition_key: [u8; 32],
+    /// Feed quoting `request_amount` at fill time
+    oracle_feed: [u8; 32],
+    /// Arbiter of held settlements
+    arbiter: Pubkey,
+    /// Mints requested in full on top of the request mint
+    request_legs: [RequestLeg; MAX_REQUEST_LEGS],
+    condition_exponent: i32,
+    oracle_usd_exponent: i32,
+    oracle_max_age: u32,
+    oracle_max_confidence_bps: u16,
+    pub escrow_bump: u8,
+    pub vault_bump: u8,
+    pub proceeds_bump: u8,
+    /// Offers a single non-fungible token
+    nft: u8,
+    /// Pay the offered NFT's creators their royalty on fill
+    pay_royalties: u8,
+    request_leg_count: u8,
+    condition_kind: u8,
+    condition_met: u8,
+    oracle_kind: u8,
+    release_mode: u8,
+    status: u8,
+    /// Keep the escrow as an on-chain record once settled, until the
+    /// seller reclaims its rent with `close_record`
+    keep_record: u8,
+    /// Layout the account was last written with; zero for escrows created
+    /// before versioning. Later fields are only ever appended, so
+    /// `migrate_escrow` upgrades an account by growing it
+    pub version: u8,
+    _padding: [u8; 5],
+    /// Seller's UTF-8 label for reconciling the escrow off-chain,
+    /// zero-padded (version 2)
+    label: [u8; MAX_LABEL_LEN],
+    /// JSON document describing the deal terms, zero-padded (version 3)
+    metadata_uri: [u8; MAX_METADATA_URI_LEN],
+    /// Lamports the seller bonded against cancelling early, held on the
+    /// escrow account on top of its rent (version 4)
+    bond_lamports: u64,
+    bond_locked_until: i64,
+    bond_penalty_bps: u16,
+    _bond_padding: [u8; 6],
+    /// Buyer holding the escrow off the market until `reserved_until`
+    /// (version 5)
+    reserved_by: Pubkey,
+    reserved_until: i64,
+    /// Collection or mint a buyer must hold to take the escrow (version 6)
+    gate_key: Pubkey,
+    gate_min_amount: u64,
+    gate_kind: u8,
+    _gate_padding: [u8; 7],
+    /// External program approving buyers, and the scope of its approvals
+    /// (version 7)
+    permission_program: Pubkey,
+    permission_account: Pubkey,
+    /// Size and price a recurring escrow is replenished to (version 8)
+    recurring_offer_amount: u64,
+    recurring_request_amount: u64,
+    /// Seconds each fill's proceeds stream to the seller over, or 0 to pay
+    /// them out on accept (version 9)
+    stream_seconds: i64,
+    /// Share of the protocol fee waived for the seller's stake when they
+    /// listed (version 10)
+    protocol_fee_discount_bps: u16,
+    _discount_padding: [u8; 6],
+    /// Account the escrow and vault rent returns to on close, when not the
+    /// seller; the creation payer unless it handed the rent on (version 11)
+    rent_recipient: Pubkey,
+    /// Whether the vault is the escrow's associated token account of the
+    /// offer mint rather than a `["vault", escrow]` PDA (version 12)
+    ata_vault: u8,
+    _vault_padding: [u8; 7],
+    /// Seller token account the offer is pulled from at accept, for an
+    /// escrow listed by delegation rather than custody (version 13)
+    delegated_source: Pubkey,
+    /// SHA-256 hash whose preimage releases the offer to the designated
+    /// buyer, for an escrow locked as a hashed timelock (version 14)
+    hashlock: [u8; 32],
+    /// Wormhole chain whose trusted emitter may attest payment for the
+    /// escrow, or 0 to settle on Solana only (version 15)
+    wormhole_chain: u16,
+    _wormhole_padding: [u8; 6],
+    /// Request tokens a buyer pays the seller for the exclusive right to
+    /// take the escrow until it expires, or 0 when not sold as an option
+    /// (version 16)
+    option_premium: u64,
+    /// Terms on which the offer secures a loan of `request_amount`, with
+    /// a zero duration when not offered as a loan (version 17)
+    loan_repayment: u64,
+    loan_duration: i64,
+    /// Wallet whose request-mint token account receives the proceeds in
+    /// place of the seller's, or default for the seller (version 18)
+    proceeds_recipient: Pubkey,
+    /// Bounds on the request amount the oracle quotes for the offer listed
+    /// when the pricing was set, which also scales a total USD price to
+    /// what remains of it (version 19)
+    oracle_min_request: u64,
+    oracle_max_request: u64,
+    oracle_listed_offer: u64,
+    oracle_usd_total: u8,
+    /// Settle against the feed's exponential moving average rather than
+    /// its latest price
+    oracle_ema: u8,
+    _oracle_padding: [u8; 6],
+    /// Seconds a buyer's fill commitment must age before it settles, or 0
+    /// when the escrow can be taken without committing first (version 20)
+    reveal_delay: i64,
+    /// Unix timestamp the escrow was listed at, from which clones take
+    /// its listing duration (version 21)
+    listed_at: i64,
+    /// Market index page the escrow is linked into, if any (version 22)
+    market_index: Pubkey,
+    /// Installment terms a buyer may take the whole escrow on, or a zero
+    /// count when it sells outright (version 23)
+    installment_interval: i64,
+    installment_count: u8,
+    installment_release_at_end: u8,
+    installment_forfeit_bps: u16,
+    _installment_padding: [u8; 4],
+    /// Lamports paid to the seller on top of the request tokens, or 0
+    /// (version 24)
+    request_lamports: u64,
+    /// Fills taken from the escrow so far (version 25)
+    fill_count: u64,
+    /// Seller the escrow's address was derived from, once ownership has
+    /// been transferred away from it (version 26)
+    listed_by: Pubkey,
+    /// Wallet the seller has offered the escrow's ownership to, until it
+    /// accepts
+    pending_seller: Pubkey,
+    /// Lamports the seller deposited for whoever cranks the escrow once it
+    /// expires, held on the escrow account on top of its rent (version 27)
+    crank_tip: u64,
+}
+
+const CONDITION_PRICE_AT_LEAST: u8 = 1;
+const CONDITION_PRICE_AT_MOST: u8 = 2;
+const CONDITION_ATTESTED: u8 = 3;
+const ORACLE_PYTH: u8 = 1;
+const ORACLE_SWITCHBOARD: u8 = 2;
+const GATE_COLLECTION: u8 = 1;
+const GATE_MINT: u8 = 2;
+const RELEASE_DISPUTE_WINDOW: u8 = 1;
+const RELEASE_TWO_OF_THREE: u8 = 2;
+
+/// Read an optional key stored as the default key when unset
+fn optional_key(key: Pubkey) -> Option<Pubkey> {
+    (key != Pubkey::default()).then_some(key)
+}
+
+/// Read an optional timestamp stored as zero when unset
+fn optional_timestamp(timestamp: i64) -> Option<i64> {
+    (timestamp != 0).then_some(timestamp)
+}
+
+impl Escrow {
+    pub fn expires_at(&self) -> Option<i64> {
+        optional_timestamp(self.expires_at)
+    }
+
+    pub fn set_expires_at(&mut self, expires_at: Option<i64>) {
+        self.expires_at = expires_at.unwrap_or_default();
+    }
+
+    pub fn listed_at(&self) -> Option<i64> {
+        optional_timestamp(self.listed_at)
+    }
+
+    pub fn set_listed_at(&mut self, listed_at: i64) {
+        self.listed_at = listed_at;
+    }
+
+    pub fn market_index(&self) -> Option<Pubkey> {
+        optional_key(self.market_index)
+    }
+
+    pub fn set_market_index(&mut self, market_index: Option<Pubkey>) {
+        self.market_index = market_index.unwrap_or_default();
+    }
+
+    pub fn starts_at(&self) -> Option<i64> {
+        optional_timestamp(self.starts_at)
+    }
+
+    pub fn set_starts_at(&mut self, starts_at: Option<i64>) {
+        self.starts_at = starts_at.unwrap_or_default();
+    }
+
+    pub fn designated_buyer(&self) -> Option<Pubkey> {
+        optional_key(self.designated_buyer)
+    }
+
+    pub fn set_designated_buyer(&mut self, designated_buyer: Option<Pubkey>) {
+        self.designated_buyer = designated_buyer.unwrap_or_default();
+    }
+
+    pub fn token_gate(&self) -> Option<TokenGate> {
+        match self.gate_kind {
+            GATE_COLLECTION => Some(TokenGate::Collection {
+                collection: self.gate_key,
+            }),
+            GATE_MINT => Some(TokenGate::Mint {
+                mint: self.gate_key,
+                min_amount: self.gate_min_amount,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn set_token_gate(&mut self, gate: Option<TokenGate>) {
+        (self.gate_kind, self.gate_key, self.gate_min_amount) = match gate {
+            Some(TokenGate::Collection { collection }) => (GATE_COLLECTION, collection, 0),
+            Some(TokenGate::Mint { mint, min_amount }) => (GATE_MINT, mint, min_amount),
+            None => (0, Pubkey::default(), 0),
+        };
+    }
+
+    pub fn permission(&self) -> Option<Permission> {
+        optional_key(self.permission_program).map(|program| Permission {
+            program,
+            account: self.permission_account,
+        })
+    }
+
+    pub fn set_permission(&mut self, permission: Option<Permission>) {
+        let permission = permission.unwrap_or_default();
+        self.permission_program = permission.program;
+        self.permission_account = permission.account;
+    }
+
+    pub fn recurring(&self) -> Option<RecurringTarget> {
+        (self.recurring_offer_amount != 0).then_some(RecurringTarget {
+            offer_amount: self.recurring_offer_amount,
+            request_amount: self.recurring_request_amount,
+        })
+    }
+
+    pub fn set_recurring(&mut self, target: Option<RecurringTarget>) {
+        let target = target.unwrap_or_default();
+        self.recurring_offer_amount = target.offer_amount;
+        self.recurring_request_amount = target.request_amount;
+    }
+
+    /// Whether only `accept_escrow` can check who takes the escrow
+    pub fn is_gated(&self) -> bool {
+        self.token_gate().is_some() || self.permission().is_some()
+    }
+
+    /// Buyer holding the escrow and when their hold lapses
+    pub fn reservation(&self) -> Option<(Pubkey, i64)> {
+        optional_key(self.reserved_by).map(|buyer| (buyer, self.reserved_until))
+    }
+
+    pub fn set_reservation(&mut self, reservation: Option<(Pubkey, i64)>) {
+        let (buyer, until) = reservation.unwrap_or_default();
+        self.reserved_by = buyer;
+        self.reserved_until = until;
+    }
+
+    /// Only the holder of a live reservation may take the escrow
+    pub fn check_reservation(&self, taker: &Pubkey, now: i64) -> Result<()> {
+        if let Some((buyer, until)) = self.reservation() {
+            require!(now >= until || *taker == buyer, EscrowError::EscrowReserved);
+        }
+        Ok(())
+    }
+
+    /// Whether `info` holds an open escrow; a closed account holds none
+    pub fn is_open(info: &AccountInfo) -> Result<bool> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(false);
+        }
+        let data = info.try_borrow_data()?;
+        require!(
+            data.starts_with(&Escrow::DISCRIMINATOR),
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+        Ok(Escrow::deserialize(&mut &data[8..])?.status() == EscrowStatus::Open)
+    }
+
+    pub fn buyer_allowlist(&self) -> Option<Pubkey> {
+        optional_key(self.buyer_allowlist)
+    }
+
+    pub fn set_buyer_allowlist(&mut self, buyer_allowlist: Option<Pubkey>) {
+        self.buyer_allowlist = buyer_allowlist.unwrap_or_default();
+    }
+
+    pub fn market(&self) -> Option<Pubkey> {
+        optional_key(self.market)
+    }
+
+    pub fn set_market(&mut self, market: Option<Pubkey>) {
+        self.market = market.unwrap_or_default();
+    }
+
+    pub fn receipt_mint(&self) -> Option<Pubkey> {
+        optional_key(self.receipt_mint)
+    }
+
+    pub fn set_receipt_mint(&mut self, receipt_mint: Option<Pubkey>) {
+        self.receipt_mint = receipt_mint.unwrap_or_default();
+    }
+
+    pub fn locked_by(&self) -> Option<Pubkey> {
+        optional_key(self.locked_by)
+    }
+
+    pub fn set_locked_by(&mut self, locked_by: Option<Pubkey>) {
+        self.locked_by = locked_by.unwrap_or_default();
+    }
+
+    pub fn collection(&self) -> Option<Pubkey> {
+        optional_key(self.collection)
+    }
+
+    pub fn set_collection(&mut self, collection: Option<Pubkey>) {
+        self.collection = collection.unwrap_or_default();
+    }
+
+    pub fn nft(&self) -> bool {
+        self.nft != 0
+    }
+
+    pub fn set_nft(&mut self, nft: bool) {
+        self.nft = nft.into();
+    }
+
+    pub fn pay_royalties(&self) -> bool {
+        self.pay_royalties != 0
+    }
+
+    pub fn set_pay_royalties(&mut self, pay_royalties: bool) {
+        self.pay_royalties = pay_royalties.into();
+    }
+
+    pub fn keep_record(&self) -> bool {
+        self.keep_record != 0
+    }
+
+    pub fn set_keep_record(&mut self, keep_record: bool) {
+        self.keep_record = keep_record.into();
+    }
+
+    pub fn label(&self) -> String {
+        let len = self
+            .label
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        String::from_utf8_lossy(&self.label[..len]).into_owned()
+    }
+
+    /// Replace the label; at most `MAX_LABEL_LEN` bytes
+    pub fn set_label(&mut self, label: &str) {
+        self.label = [0; MAX_LABEL_LEN];
+        self.label[..label.len()].copy_from_slice(label.as_bytes());
+    }
+
+    pub fn metadata_uri(&self) -> Option<String> {
+        let len = self.metadata_uri.iter().rposition(|&byte| byte != 0)? + 1;
+        Some(String::from_utf8_lossy(&self.metadata_uri[..len]).into_owned())
+    }
+
+    /// Replace the metadata URI; at most `MAX_METADATA_URI_LEN` bytes
+    pub fn set_metadata_uri(&mut self, metadata_uri: Option<&str>) {
+        let metadata_uri = metadata_uri.unwrap_or_default();
+        self.metadata_uri = [0; MAX_METADATA_URI_LEN];
+        self.metadata_uri[..metadata_uri.len()].copy_from_slice(metadata_uri.as_bytes());
+    }
+
+    pub fn request_legs(&self) -> &[RequestLeg] {
+        &self.request_legs[..self.request_leg_count as usize]
+    }
+
+    /// Replace the extra request legs; at most `MAX_REQUEST_LEGS`
+    pub fn set_request_legs(&mut self, legs: &[RequestLeg]) {
+        self.request_legs = [RequestLeg::default(); MAX_REQUEST_LEGS];
+        self.request_legs[..legs.len()].copy_from_slice(legs);
+        self.request_leg_count = legs.len() as u8;
+    }
+
+    pub fn request_lamports(&self) -> u64 {
+        self.request_lamports
+    }
+
+    pub fn set_request_lamports(&mut self, lamports: u64) {
+        self.request_lamports = lamports;
+    }
+
+    /// Seller the escrow's address is derived from, which stays put as
+    /// ownership changes hands
+    pub fn listed_by(&self) -> Pubkey {
+        optional_key(self.listed_by).unwrap_or(self.seller)
+    }
+
+    pub fn pending_seller(&self) -> Option<Pubkey> {
+        optional_key(self.pending_seller)
+    }
+
+    pub fn set_pending_seller(&mut self, seller: Option<Pubkey>) {
+        self.pending_seller = seller.unwrap_or_default();
+    }
+
+    /// Hand the escrow to `new_seller`, keeping its address; rent the
+    /// outgoing seller would have received follows the escrow, while a
+    /// sponsor keeps theirs
+    pub fn transfer_ownership(&mut self, new_seller: Pubkey) {
+        self.listed_by = self.listed_by();
+        if self.rent_recipient() == self.seller {
+            self.rent_recipient = Pubkey::default();
+        }
+        self.seller = new_seller;
+        self.pending_seller = Pubkey::default();
+        self.set_proceeds_recipient(None);
+    }
+
+    pub fn fill_count(&self) -> u64 {
+        self.fill_count
+    }
+
+    pub fn record_fill(&mut self) {
+        self.fill_count = self.fill_count.saturating_add(1);
+    }
+
+    pub fn crank_tip(&self) -> u64 {
+        self.crank_tip
+    }
+
+    pub fn set_crank_tip(&mut self, lamports: u64) {
+        self.crank_tip = lamports;
+    }
+
+    /// Whether a partly filled escrow has been listed too briefly to
+    /// cancel under `min_listing_duration`; unfilled escrows may always be
+    /// withdrawn
+    /// - An escrow without a listing time counts as too early until
+    ///   `migrate_escrow` stamps one
+    pub fn cancel_too_early(&self, min_listing_duration: i64, now: i64) -> bool {
+        if self.fill_count == 0 || min_listing_duration <= 0 {
+            return false;
+        }
+        match self.listed_at() {
+            Some(listed_at) => now < listed_at.saturating_add(min_listing_duration),
+            None => true,
+        }
+    }
+
+    /// Whether buyers pay anything beyond the request mint, which only
+    /// whole fills settle
+    pub fn has_extra_payments(&self) -> bool {
+        !self.request_legs().is_empty() || self.request_lamports != 0
+    }
+
+    /// External condition that must resolve before the escrow can fill
+    pub fn condition(&self) -> Option<Condition> {
+        let kind = match self.condition_kind {
+            CONDITION_PRICE_AT_LEAST => ConditionKind::PriceAtLeast {
+                feed_id: self.condition_key,
+                threshold: self.condition_threshold,
+                exponent: self.condition_exponent,
+            },
+            CONDITION_PRICE_AT_MOST => ConditionKind::PriceAtMost {
+                feed_id: self.condition_key,
+                threshold: self.condition_threshold,
+                exponent: self.condition_exponent,
+            },
+            CONDITION_ATTESTED => ConditionKind::Attested {
+                resolver: Pubkey::new_from_array(self.condition_key),
+            },
+            _ => return None,
+        };
+        Some(Condition {
+            kind,
+            deadline: self.condition_deadline,
+            set_at: self.condition_set_at,
+            met: self.condition_met != 0,
+        })
+    }
+
+    pub fn set_condition(&mut self, condition: Option<Condition>) {
+        let Some(condition) = condition else {
+            self.condition_kind = 0;
+            return;
+        };
+        (self.condition_kind, self.condition_key) = match condition.kind {
+            ConditionKind::PriceAtLeast {
+                feed_id,
+                threshold,
+                exponent,
+            } => {
+                self.condition_threshold = threshold;
+                self.condition_exponent = exponent;
+                (CONDITION_PRICE_AT_LEAST, feed_id)
+            }
+            ConditionKind::PriceAtMost {
+                feed_id,
+                threshold,
+                exponent,
+            } => {
+                self.condition_threshold = threshold;
+                self.condition_exponent = exponent;
+                (CONDITION_PRICE_AT_MOST, feed_id)
+            }
+            ConditionKind::Attested { resolver } => (CONDITION_ATTESTED, resolver.to_bytes()),
+        };
+        self.condition_deadline = condition.deadline;
+        self.condition_set_at = condition.set_at;
+        self.condition_met = condition.met.into();
+    }
+
+    /// Quote `request_amount` from an oracle at fill time
+    pub fn oracle_pricing(&self) -> Option<OraclePricing> {
+        let oracle = match self.oracle_kind {
+            ORACLE_PYTH => OracleKind::Pyth {
+                feed_id: self.oracle_feed,
+            },
+            ORACLE_SWITCHBOARD => OracleKind::Switchboard {
+                feed: Pubkey::new_from_array(self.oracle_feed),
+            },
+            _ => return None,
+        };
+        Some(OraclePricing {
+            oracle,
+            usd_price: self.oracle_usd_price,
+            usd_exponent: self.oracle_usd_exponent,
+            max_age: self.oracle_max_age,
+            max_confidence_bps: self.oracle_max_confidence_bps,
+            usd_total: self.oracle_usd_total != 0,
+            min_request_amount: self.oracle_min_request,
+            max_request_amount: self.oracle_max_request,
+            use_ema: self.oracle_ema != 0,
+        })
+    }
+
+    /// Offer amount an oracle price's total and bounds refer to
+    pub fn oracle_listed_offer(&self) -> u64 {
+        self.oracle_listed_offer
+    }
+
+    pub fn set_oracle_pricing(&mut self, pricing: Option<OraclePricing>) {
+        let Some(pricing) = pricing else {
+            self.oracle_kind = 0;
+            return;
+        };
+        (self.oracle_kind, self.oracle_feed) = match pricing.oracle {
+            OracleKind::Pyth { feed_id } => (ORACLE_PYTH, feed_id),
+            OracleKind::Switchboard { feed } => (ORACLE_SWITCHBOARD, feed.to_bytes()),
+        };
+        self.oracle_usd_price = pricing.usd_price;
+        self.oracle_usd_exponent = pricing.usd_exponent;
+        self.oracle_max_age = pricing.max_age;
+        self.oracle_max_confidence_bps = pricing.max_confidence_bps;
+        self.oracle_usd_total = pricing.usd_total.into();
+        self.oracle_min_request = pricing.min_request_amount;
+        self.oracle_max_request = pricing.max_request_amount;
+        self.oracle_ema = pricing.use_ema.into();
+        self.oracle_listed_offer = self.offer_amount;
+    }
+
+    pub fn unit_price(&self) -> Option<UnitPrice> {
+        (self.unit_price.unit != 0).then_some(self.unit_price)
+    }
+
+    pub fn set_unit_price(&mut self, unit_price: Option<UnitPrice>) {
+        self.unit_price = unit_price.unwrap_or_default();
+    }
+
+    /// Settle through a held, disputable settlement
+    pub fn arbitration(&self) -> Option<Arbitration> {
+        let mode = match self.release_mode {
+            RELEASE_DISPUTE_WINDOW => ReleaseMode::DisputeWindow {
+                seconds: self.dispute_window,
+            },
+            RELEASE_TWO_OF_THREE => ReleaseMode::TwoOfThree,
+            _ => return None,
+        };
+        Some(Arbitration {
+            arbiter: self.arbiter,
+            mode,
+        })
+    }
+
+    pub fn set_arbitration(&mut self, arbitration: Option<Arbitration>) {
+        let Some(arbitration) = arbitration else {
+            self.release_mode = 0;
+            return;
+        };
+        self.arbiter = arbitration.arbiter;
+        self.release_mode = match arbitration.mode {
+            ReleaseMode::DisputeWindow { seconds } => {
+                self.dispute_window = seconds;
+                RELEASE_DISPUTE_WINDOW
+            }
+            ReleaseMode::TwoOfThree => RELEASE_TWO_OF_THREE,
+        };
+    }
+
+    pub fn vesting(&self) -> Option<VestingSchedule> {
+        (self.vesting.duration_seconds != 0).then_some(self.vesting)
+    }
+
+    pub fn set_vesting(&mut self, vesting: Option<VestingSchedule>) {
+        self.vesting = vesting.unwrap_or_default();
+    }
+
+    pub fn protocol_fee_discount_bps(&self) -> u16 {
+        self.protocol_fee_discount_bps
+    }
+
+    pub fn set_protocol_fee_discount_bps(&mut self, discount_bps: u16) {
+        self.protocol_fee_discount_bps = discount_bps;
+    }
+
+    /// Address of the escrow's offer vault under `token_program`: the
+    /// escrow's associated token account, or for escrows created before
+    /// version 12 its `["vault", escrow]` PDA. A delegated escrow's vault
+    /// is the seller's own token account
+    pub fn vault(&self, escrow: &Pubkey, token_program: &Pubkey) -> Pubkey {
+        if let Some(source) = self.delegated_source() {
+            source
+        } else if self.ata_vault != 0 {
+            get_associated_token_address_with_program_id(escrow, &self.offer_mint, token_program)
+        } else {
+            Pubkey::create_program_address(
+                &[b"vault", escrow.as_ref(), &[self.vault_bump]],
+                &crate::ID,
+            )
+            .unwrap_or_default()
+        }
+    }
+
+    pub fn set_ata_vault(&mut self) {
+        self.ata_vault = 1;
+    }
+
+    pub fn delegated_source(&self) -> Option<Pubkey> {
+        optional_key(self.delegated_source)
+    }
+
+    pub fn set_delegated_source(&mut self, source: Option<Pubkey>) {
+        self.delegated_source = source.unwrap_or_default();
+    }
+
+    pub fn hashlock(&self) -> Option<[u8; 32]> {
+        (self.hashlock != [0; 32]).then_some(self.hashlock)
+    }
+
+    pub fn set_hashlock(&mut self, hashlock: Option<[u8; 32]>) {
+        self.hashlock = hashlock.unwrap_or_default();
+    }
+
+    pub fn wormhole_chain(&self) -> Option<u16> {
+        (self.wormhole_chain != 0).then_some(self.wormhole_chain)
+    }
+
+    pub fn set_wormhole_chain(&mut self, chain: Option<u16>) {
+        self.wormhole_chain = chain.unwrap_or_default();
+    }
+
+    pub fn option_premium(&self) -> Option<u64> {
+        (self.option_premium != 0).then_some(self.option_premium)
+    }
+
+    pub fn set_option_premium(&mut self, premium: Option<u64>) {
+        self.option_premium = premium.unwrap_or_default();
+    }
+
+    pub fn loan_terms(&self) -> Option<LoanTerms> {
+        (self.loan_duration != 0).then_some(LoanTerms {
+            repayment_amount: self.loan_repayment,
+            duration: self.loan_duration,
+        })
+    }
+
+    pub fn set_loan_terms(&mut self, terms: Option<LoanTerms>) {
+        let terms = terms.unwrap_or_default();
+        self.loan_repayment = terms.repayment_amount;
+        self.loan_duration = terms.duration;
+    }
+
+    pub fn installment_terms(&self) -> Option<InstallmentTerms> {
+        (self.installment_count != 0).then_some(InstallmentTerms {
+            installments: self.installment_count,
+            interval: self.installment_interval,
+            release_at_end: self.installment_release_at_end != 0,
+            forfeit_bps: self.installment_forfeit_bps,
+        })
+    }
+
+    pub fn set_installment_terms(&mut self, terms: Option<InstallmentTerms>) {
+        let terms = terms.unwrap_or_default();
+        self.installment_count = terms.installments;
+        self.installment_interval = terms.interval;
+        self.installment_release_at_end = terms.release_at_end as u8;
+        self.installment_forfeit_bps = terms.forfeit_bps;
+    }
+
+    pub fn reveal_delay(&self) -> Option<i64> {
+        (self.reveal_delay != 0).then_some(self.reveal_delay)
+    }
+
+    pub fn set_reveal_delay(&mut self, delay: Option<i64>) {
+        self.reveal_delay = delay.unwrap_or_default();
+    }
+
+    pub fn proceeds_recipient(&self) -> Option<Pubkey> {
+        optional_key(self.proceeds_recipient)
+    }
+
+    pub fn set_proceeds_recipient(&mut self, recipient: Option<Pubkey>) {
+        self.proceeds_recipient = recipient.unwrap_or_default();
+    }
+
+    /// Whether the seller has committed the unexpired escrow to a
+    /// counterparty, through a hashlock or a purchased option; until it
+    /// expires the seller can neither pull nor re-price the offer
+    pub fn committed(&self, now: i64) -> bool {
+        let option_held = self.option_premium().is_some()
+            && self.reservation().is_some_and(|(_, until)| now < until);
+        self.expires_at().is_some_and(|expires_at| now < expires_at)
+            && (self.hashlock().is_some() || option_held)
+    }
+
+    /// Whether an auction, crowdfill or held settlement has taken the
+    /// escrow off the open book, locking it to this program
+    pub fn in_settlement(&self) -> bool {
+        self.locked_by() == Some(crate::ID)
+    }
+
+    /// Whether the escrow is hashlocked and its timelock has yet to run
+    /// out; until it does, only the preimage can release the offer
+    pub fn hashlock_active(&self, now: i64) -> bool {
+        self.hashlock().is_some() && self.expires_at().is_some_and(|expires_at| now < expires_at)
+    }
+
+    /// Account the escrow's rent returns to; escrows created before
+    /// version 11 return it to their seller
+    pub fn rent_recipient(&self) -> Pubkey {
+        optional_key(self.rent_recipient).unwrap_or(self.seller)
+    }
+
+    pub fn set_rent_recipient(&mut self, rent_recipient: Pubkey) {
+        self.rent_recipient = rent_recipient;
+    }
+
+    /// Destination of the escrow's rent on close: the seller, or another
+    /// rent recipient, which must then be passed as `rent_recipient`
+    pub fn rent_destination<'info>(
+        &self,
+        seller: &AccountInfo<'info>,
+        rent_recipient: Option<&AccountInfo<'info>>,
+    ) -> Result<AccountInfo<'info>> {
+        if self.rent_recipient() == self.seller {
+            return Ok(seller.clone());
+        }
+        let rent_recipient = rent_recipient.ok_or(EscrowError::InvalidRentRecipient)?;
+        require_keys_eq!(
+            rent_recipient.key(),
+            self.rent_recipient(),
+            EscrowError::InvalidRentRecipient
+        );
+        Ok(rent_recipient.clone())
+    }
+
+    pub fn proceeds_stream(&self) -> Option<i64> {
+        (self.stream_seconds != 0).then_some(self.stream_seconds)
+    }
+
+    pub fn set_proceeds_stream(&mut self, seconds: Option<i64>) {
+        self.stream_seconds = seconds.unwrap_or_default();
+    }
+
+    pub fn cancellation_bond(&self) -> Option<CancellationBond> {
+        (self.bond_lamports != 0).then_some(CancellationBond {
+            lamports: self.bond_lamports,
+            locked_until: self.bond_locked_until,
+            penalty_bps: self.bond_penalty_bps,
+        })
+    }
+
+    pub fn set_cancellation_bond(&mut self, bond: Option<CancellationBond>) {
+        let bond = bond.unwrap_or_default();
+        self.bond_lamports = bond.lamports;
+        self.bond_locked_until = bond.locked_until;
+        self.bond_penalty_bps = bond.penalty_bps;
+    }
+
+    pub fn status(&self) -> EscrowStatus {
+        match self.status {
+            1 => EscrowStatus::Filled,
+            2 => EscrowStatus::Cancelled,
+            3 => EscrowStatus::Expired,
+            4 => EscrowStatus::Stuck,
+            5 => EscrowStatus::Lent,
+            6 => EscrowStatus::PaymentPlan,
+            _ => EscrowStatus::Open,
+        }
+    }
+
+    pub fn set_status(&mut self, status: EscrowStatus) {
+        self.status = status as u8;
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum EscrowStatus {
+    Open,
+    Filled,
+    Cancelled,
+    Expired,
+    /// Vault frozen by the offer mint's freeze authority; fills are halted
+    /// and the seller cancels once it is thawed
+    Stuck,
+    /// Collateral for an outstanding loan, returned to the seller on
+    /// repayment or released to the lender on liquidation
+    Lent,
+    /// Taken whole by a buyer paying in installments, who receives the
+    /// offer as their payment plan releases it
+    PaymentPlan,
+}
+
+/// Vesting applied to each fill, relative to the fill
+#[zero_copy]
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Default)]
+pub struct VestingSchedule {
+    pub cliff_seconds: i64,
+    pub duration_seconds: i64,
+}
+
+/// What a buyer must hold to take a token-gated escrow
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub enum TokenGate {
+    /// An NFT of this verified Metaplex collection
+    Collection { collection: Pubkey },
+    /// At least `min_amount` of this mint
+    Mint { mint: Pubkey, min_amount: u64 },
+}
+
+impl TokenGate {
+    /// Check that the buyer's `token` account meets the gate; collection
+    /// gates also take the metadata of the token's mint
+    pub fn check(&self, token: &TokenAccount, metadata: Option<&MetadataAccount>) -> Result<()> {
+        match *self {
+            TokenGate::Collection { collection } => {
+                let metadata = metadata.ok_or(EscrowError::MissingGateAccount)?;
+                require_keys_eq!(metadata.mint, token.mint, EscrowError::InvalidMetadata);
+                require!(
+                    token.amount > 0
+                        && metadata
+                            .collection
+                            .as_ref()
+                            .is_some_and(|member| member.verified && member.key == collection),
+                    EscrowError::GateNotMet
+                );
+            }
+            TokenGate::Mint { mint, min_amount } => {
+                require_keys_eq!(token.mint, mint, EscrowError::InvalidMint);
+                require!(token.amount >= min_amount, EscrowError::GateNotMet);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// External program whose approval a buyer needs to take an escrow
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct Permission {
+    pub program: Pubkey,
+    /// Scope of approvals within the program, e.g. one KYC list
+    pub account: Pubkey,
+}
+
+impl Permission {
+    /// Check that `approval` is the program's approval of `buyer`
+    pub fn check(&self, approval: &AccountInfo, buyer: &Pubkey) -> Result<()> {
+        let (expected, _) = Pubkey::find_program_address(
+            &[b"approval", self.account.as_ref(), buyer.as_ref()],
+            &self.program,
+        );
+        require_keys_eq!(
+            approval.key(),
+            expected,
+            EscrowError::InvalidPermissionAccount
+        );
+        require!(
+            approval.owner == &self.program && !approval.data_is_empty(),
+            EscrowError::BuyerNotApproved
+        );
+        Ok(())
+    }
+}
+
+/// Listing a recurring escrow is replenished to after fills
+#[derive(Clone, Copy, Default)]
+pub struct RecurringTarget {
+    pub offer_amount: u64,
+    pub request_amount: u64,
+}
+
+/// Terms of a loan secured by an escrow's offer; the principal is the
+/// escrow's `request_amount`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct LoanTerms {
+    /// Request tokens the borrower repays the lender
+    pub repayment_amount: u64,
+    /// Seconds from funding until the loan is due
+    pub duration: i64,
+}
+
+/// Terms on which a buyer may take the whole escrow, paying its
+/// `request_amount` in equal installments
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct InstallmentTerms {
+    /// Number of installments, the first paid as the plan starts
+    pub installments: u8,
+    /// Seconds between installments falling due
+    pub interval: i64,
+    /// Release the whole offer with the last installment instead of a
+    /// share with each
+    pub release_at_end: bool,
+    /// Share of the payments held against unreleased offer the seller
+    /// keeps when an installment is missed
+    pub forfeit_bps: u16,
+}
+
+/// Lamports a seller locks against cancelling their escrow early
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct CancellationBond {
+    pub lamports: u64,
+    /// Cancelling before this time forfeits `penalty_bps` of the bond
+    pub locked_until: i64,
+    pub penalty_bps: u16,
+}
+
+impl CancellationBond {
+    /// Share of the bond forfeited by cancelling at `now`
+    pub fn penalty(&self, now: i64) -> Result<u64> {
+        if now >= self.locked_until {
+            return Ok(0);
+        }
+        bps_of(self.lamports, self.penalty_bps)
+    }
+}
+
+/// Arbiter and release mode for an escrow's held settlements
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct Arbitration {
+    /// Rules on disputes, or acts as the neutral third approver
+    pub arbiter: Pubkey,
+    pub mode: ReleaseMode,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub enum ReleaseMode {
+    /// Released after `seconds` unless disputed to the arbiter
+    DisputeWindow { seconds: i64 },
+    /// Released or refunded on two approvals of seller, buyer and arbiter
+    TwoOfThree,
+}
+
+/// Limit-order price for an escrow
+#[zero_copy]
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Default)]
+pub struct UnitPrice {
+    /// Request tokens per `unit` offer tokens
+    pub price: u64,
+    pub unit: u64,
+    /// Smallest fill accepted short of the whole remainder
+    pub min_fill_amount: u64,
+}
+
+impl UnitPrice {
+    /// Request tokens owed for `offer_amount` offer tokens, rounded up in
+    /// the seller's favour
+    pub fn cost(&self, offer_amount: u64) -> Result<u64> {
+        let cost = (offer_amount as u128)
+            .checked_mul(self.price as u128)
+            .map(|product| product.div_ceil(self.unit as u128))
+            .ok_or(EscrowError::MathOverflow)?;
+        u64::try_from(cost).map_err(|_| error!(EscrowError::MathOverflow))
+    }
+}
+
+/// One extra mint an escrow requests
+#[zero_copy]
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Default)]
+pub struct RequestLeg {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl Escrow {
+    /// Request tokens owed for `fill_amount` offer tokens, rounded up in
+    /// the seller's favour; a full fill costs exactly `request_amount`
+    pub fn fill_cost(&self, fill_amount: u64) -> Result<u64> {
+        if let Some(unit_price) = self.unit_price() {
+            require!(
+                fill_amount >= unit_price.min_fill_amount.min(self.offer_amount),
+                EscrowError::FillBelowMinimum
+            );
+            let cost = unit_price.cost(fill_amount)?;
+            require!(cost > 0, EscrowError::InvalidAmount);
+            return Ok(cost);
+        }
+        if fill_amount == self.offer_amount {
+            return Ok(self.request_amount);
+        }
+        let cost = (fill_amount as u128)
+            .checked_mul(self.request_amount as u128)
+            .and_then(|product| product.checked_add(self.offer_amount as u128 - 1))
+            .map(|product| product / self.offer_amount as u128)
+            .ok_or(EscrowError::MathOverflow)?;
+        // The remainder must still be worth something to fill later
+        require!(
+            cost < self.request_amount as u128,
+            EscrowError::InvalidAmount
+        );
+        Ok(cost as u64)
+    }
+
+    /// Request amount keeping the current price for `offer_amount` offer
+    /// tokens, rounded up in the seller's favour
+    pub fn scaled_request(&self, offer_amount: u64) -> Result<u64> {
+        if let Some(unit_price) = self.unit_price() {
+            return unit_price.cost(offer_amount);
+        }
+        let scaled = (offer_amount as u128)
+            .checked_mul(self.request_amount as u128)
+            .and_then(|product| product.checked_add(self.offer_amount as u128 - 1))
+            .map(|product| product / self.offer_amount as u128)
+            .ok_or(EscrowError::MathOverflow)?;
+        u64::try_from(scaled).map_err(|_| error!(EscrowError::MathOverflow))
+    }
+}
+
+/// Counterparties a seller has approved for gated escrows
+#[account]
+#[derive(InitSpace)]
+pub struct BuyerAllowlist {
+    pub seller: Pubkey,
+    #[max_len(MAX_BUYER_ALLOWLIST_LEN)]
+    pub buyers: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+/// A buyer's proposed price for the whole of an escrow, backed by a
+/// deposit in its own vault
+#[account]
+#[derive(InitSpace)]
+pub struct CounterOffer {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub request_mint: Pubkey,
+    /// Escrow offer the counter was made against
+    pub offer_amount: u64,
+    /// Proposed price for `offer_amount`
+    pub request_amount: u64,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+/// A buyer's standing order for offer-mint tokens, paid for from request
+/// tokens locked in its own vault; the bid side of the escrow market
+#[account]
+#[derive(InitSpace)]
+pub struct BidEscrow {
+    pub buyer: Pubkey,
+    /// Mint the buyer wants to receive
+    pub offer_mint: Pubkey,
+    /// Mint locked to pay for it
+    pub request_mint: Pubkey,
+    pub bid_id: u64,
+    /// Offer tokens still wanted
+    pub offer_amount: u64,
+    /// Request tokens still locked to pay for `offer_amount`
+    pub request_amount: u64,
+    pub expires_at: Option<i64>,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+/// A buyer's request for quotes on `offer_amount` of the offer mint,
+/// answered by sellers until `deadline`
+#[account]
+#[derive(InitSpace)]
+pub struct Rfq {
+    pub buyer: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    pub rfq_id: u64,
+    /// Offer tokens wanted, which every quote locks in full
+    pub offer_amount: u64,
+    /// Last moment quotes may be submitted or accepted
+    pub deadline: i64,
+    /// Quote the buyer took, once they have
+    pub accepted_quote: Option<Pubkey>,
+    /// Quotes not yet accepted or released
+    pub quote_count: u32,
+    pub bump: u8,
+}
+
+impl Rfq {
+    /// Whether quotes may still be submitted or accepted
+    pub fn is_open(&self, now: i64) -> bool {
+        self.accepted_quote.is_none() && now <= self.deadline
+    }
+}
+
+/// A seller's price for an RFQ, backed by its offer tokens in the quote's
+/// own vault
+#[account]
+#[derive(InitSpace)]
+pub struct RfqQuote {
+    pub rfq: Pubkey,
+    pub seller: Pubkey,
+    /// Price asked for the RFQ's `offer_amount`
+    pub request_amount: u64,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+/// Sealed-bid auction for the whole of an escrow
+#[account]
+#[derive(InitSpace)]
+pub struct Auction {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub request_mint: Pubkey,
+    /// Lowest price a revealed bid may name
+    pub reserve_price: u64,
+    pub commit_ends_at: i64,
+    pub reveal_ends_at: i64,
+    /// Bids committed so far
+    pub bid_count: u32,
+    /// Best revealed bid so far
+    pub highest_bid: u64,
+    pub highest_bidder: Option<Pubkey>,
+    pub bump: u8,
+}
+
+/// One bidder's sealed bid, backed by a deposit in its own vault
+#[account]
+#[derive(InitSpace)]
+pub struct Bid {
+    pub auction: Pubkey,
+    pub bidder: Pubkey,
+    /// `sha256(price as u64 LE || salt)`
+    pub commitment: [u8; 32],
+    /// Set once revealed
+    pub price: Option<u64>,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+/// Joint fill of the whole of an escrow, raising its request amount from
+/// several contributors into one vault
+#[account]
+#[derive(InitSpace)]
+pub struct Crowdfill {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub offer_mint: Pubkey,
+    pub request_mint: Pubkey,
+    /// Request tokens to raise before the escrow can be settled
+    pub target: u64,
+    /// Contributed so far, net of transfer fees
+    pub raised: u64,
+    /// Contributions not yet claimed or reclaimed
+    pub contributors: u32,
+    /// Contributions close at this time, and are reclaimable after it
+    /// unless the target was raised
+    pub expires_at: i64,
+    /// Offer tokens bought and request tokens left over once settled,
+    /// shared among contributors pro-rata
+    pub offer_amount: u64,
+    pub leftover: u64,
+    pub settled: bool,
+    pub bump: u8,
+    pub vault_bump: u8,
+    pub offer_vault_bump: u8,
+}
+
+impl Crowdfill {
+    /// A contribution's pro-rata share of `total`
+    pub fn share(&self, total: u64, contribution: u64) -> Result<u64> {
+        let share = (total as u128)
+            .checked_mul(contribution as u128)
+            .ok_or(EscrowError::MathOverflow)?
+            / self.raised as u128;
+        u64::try_from(share).map_err(|_| error!(EscrowError::MathOverflow))
+    }
+}
+
+/// One contributor's share of a crowdfill
+#[account]
+#[derive(InitSpace)]
+pub struct Contribution {
+    pub crowdfill: Pubkey,
+    pub contributor: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+/// Lamport deposit behind a buyer's hold on an escrow, refunded when the
+/// buyer takes the escrow or it closes during the hold, and forfeited to
+/// the seller once the hold lapses
+#[account]
+#[derive(InitSpace)]
+pub struct Reservation {
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub deposit: u64,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+/// Buyer's sealed commitment to fill an escrow that settles by commit and
+/// reveal
+#[account]
+#[derive(InitSpace)]
+pub struct FillCommitment {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    /// `sha256(fill_amount as u64 LE || salt)`
+    pub commitment: [u8; 32],
+    pub committed_at: i64,
+    /// Set once revealed
+    pub fill_amount: Option<u64>,
+    pub bump: u8,
+}
+
+/// Opt-in insurance for escrows requesting one mint, paying claims out of
+/// the premiums collected in its vault
+#[account]
+#[derive(InitSpace)]
+pub struct InsurancePool {
+    pub mint: Pubkey,
+    /// Arbiter or governance signer ruling on claims
+    pub claims_authority: Pubkey,
+    /// Premium on an escrow's request amount for covering it
+    pub premium_bps: u16,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+/// Coverage an escrow bought from its request mint's insurance pool
+#[account]
+#[derive(InitSpace)]
+pub struct Coverage {
+    pub escrow: Pubkey,
+    pub pool: Pubkey,
+    /// Most the pool pays out on claims against the escrow
+    pub covered_amount: u64,
+    pub claimed: u64,
+    pub bump: u8,
+}
+
+/// Escrow lifecycle event whose Borsh encoding is logged through the noop
+/// program and whose hash is appended to the config's activity log
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ActivityRecord {
+    pub kind: ActivityKind,
+    pub escrow: Pubkey,
+    pub seller: Pubkey,
+    /// Buyer of a fill, or the holder redeeming a receipt
+    pub buyer: Option<Pubkey>,
+    /// Offer listed, filled, or returned to the seller or receipt holder
+    pub offer_amount: u64,
+    /// Request paid on a fill, proceeds swept to a receipt holder,
+    /// otherwise the escrow's asking price
+    pub request_amount: u64,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityKind {
+    Created,
+    Filled,
+    Cancelled,
+    Expired,
+    /// A receipt holder took over the position, closing the escrow
+    Redeemed,
+}
+
+/// Loan funded against an escrow's offer as collateral
+#[account]
+#[derive(InitSpace)]
+pub struct Loan {
+    pub escrow: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub principal: u64,
+    pub repayment_amount: u64,
+    /// Repayment is accepted until this time, and liquidation after it
+    pub due_at: i64,
+    pub bump: u8,
+}
+
+/// A buyer's installment purchase of a whole escrow
+#[account]
+#[derive(InitSpace)]
+pub struct PaymentPlan {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    /// Holds payments against offer not yet released to the buyer
+    pub vault: Pubkey,
+    pub offer_amount: u64,
+    pub request_amount: u64,
+    pub installments: u8,
+    pub installments_paid: u8,
+    pub interval: i64,
+    pub started_at: i64,
+    pub release_at_end: bool,
+    pub forfeit_bps: u16,
+    /// Offer released to the buyer so far
+    pub offer_released: u64,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+impl PaymentPlan {
+    /// When the next installment falls due; the first is paid on start
+    pub fn next_due_at(&self) -> Result<i64> {
+        (self.installments_paid as i64)
+            .checked_mul(self.interval)
+            .and_then(|offset| self.started_at.checked_add(offset))
+            .ok_or(error!(EscrowError::MathOverflow))
+    }
+
+    /// Request tokens owed for the next installment, the last taking
+    /// whatever equal shares leave over
+    pub fn next_installment(&self) -> Result<u64> {
+        let paid = self.installments_paid as u128;
+        let owed = |installments: u128| {
+            (self.request_amount as u128)
+                .checked_mul(installments)
+                .map(|total| total / self.installments as u128)
+                .ok_or(error!(EscrowError::MathOverflow))
+        };
+        Ok((owed(paid + 1)? - owed(paid)?) as u64)
+    }
+
+    /// Offer due to the buyer once `paid` installments are in
+    pub fn offer_releasable(&self, paid: u8) -> Result<u64> {
+        if self.release_at_end {
+            return Ok(if paid == self.installments {
+                self.offer_amount
+            } else {
+                0
+            });
+        }
+        (self.offer_amount as u128)
+            .checked_mul(paid as u128)
+            .map(|released| (released / self.installments as u128) as u64)
+            .ok_or(error!(EscrowError::MathOverflow))
+    }
+}
+
+/// Payment for a held escrow, released once its dispute window closes or
+/// its outcome is decided
+#[account]
+#[derive(InitSpace)]
+pub struct Settlement {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    /// Unix timestamp from which an undisputed settlement can be released;
+    /// `None` for two-of-three settlements
+    pub release_after: Option<i64>,
+    pub status: SettlementStatus,
+    /// Two-of-three approvals as bit flags: seller, buyer, arbiter
+    pub release_approvals: u8,
+    pub refund_approvals: u8,
+    pub bump: u8,
+    pub vault_bump: u8,
+    /// Last actions taken on the settlement, oldest first, so its arbiter
+    /// can rule from on-chain state alone
+    #[max_len(SETTLEMENT_HISTORY_LEN)]
+    pub history: Vec<SettlementEntry>,
+}
+
+impl Settlement {
+    /// Append an action to the history, dropping the oldest once it is
+    /// full
+    pub fn record(&mut self, actor: Pubkey, action: SettlementAction, amount: u64, now: i64) {
+        if self.history.len() >= SETTLEMENT_HISTORY_LEN {
+            self.history.remove(0);
+        }
+        self.history.push(SettlementEntry {
+            actor,
+            action,
+            amount,
+            timestamp: now,
+        });
+    }
+
+    /// Whether `release_settlement` can pay the seller at `now`: the
+    /// window closed undisputed, or the dispute went the seller's way
+    pub fn releasable(&self, now: i64) -> bool {
+        match self.status {
+            SettlementStatus::Held => self
+                .release_after
+                .is_some_and(|release_after| now >= release_after),
+            SettlementStatus::AwardedToSeller => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum SettlementStatus {
+    Held,
+    Disputed,
+    AwardedToSeller,
+    AwardedToBuyer,
+}
+
+/// One action in a settlement's history
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct SettlementEntry {
+    pub actor: Pubkey,
+    pub action: SettlementAction,
+    /// Request tokens the action moved, or 0
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum SettlementAction {
+    Held,
+    Disputed,
+    ApprovedRelease,
+    ApprovedRefund,
+    ResolvedForSeller,
+    ResolvedForBuyer,
+}
+
+/// Offer tokens from one fill, vesting to its buyer
+#[account]
+#[derive(InitSpace)]
+pub struct Vesting {
+    pub beneficiary: Pubkey,
+    pub escrow: Pubkey,
+    /// Escrow's offer amount before the fill, distinguishing the buyer's
+    /// fills of one escrow
+    pub nonce: u64,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub total_amount: u64,
+    pub claimed_amount: u64,
+    pub start_at: i64,
+    pub cliff_at: i64,
+    pub end_at: i64,
+    pub bump: u8,
+}
+
+impl Vesting {
+    /// Amount vested by `now`: nothing before the cliff, then linear from
+    /// the start until the end
+    pub fn vested(&self, now: i64) -> Result<u64> {
+        if now < self.cliff_at {
+            return Ok(0);
+        }
+        if now >= self.end_at {
+            return Ok(self.total_amount);
+        }
+        let vested = (self.total_amount as u128)
+            .checked_mul((now - self.start_at) as u128)
+            .map(|product| product / (self.end_at - self.start_at) as u128)
+            .ok_or(EscrowError::MathOverflow)?;
+        Ok(vested as u64)
+    }
+}
+
+/// Request tokens from one fill, streaming to the escrow's seller
+#[account]
+#[derive(InitSpace)]
+pub struct Stream {
+    pub seller: Pubkey,
+    /// Paid the stream's rent, returned once it is fully withdrawn
+    pub buyer: Pubkey,
+    pub escrow: Pubkey,
+    /// Escrow's offer amount before the fill, distinguishing its fills
+    pub nonce: u64,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub total_amount: u64,
+    pub withdrawn_amount: u64,
+    pub start_at: i64,
+    pub end_at: i64,
+    pub bump: u8,
+}
+
+impl Stream {
+    /// Amount streamed by `now`, linear from the start until the end
+    pub fn streamed(&self, now: i64) -> Result<u64> {
+        if now >= self.end_at {
+            return Ok(self.total_amount);
+        }
+        let streamed = (self.total_amount as u128)
+            .checked_mul(now.saturating_sub(self.start_at).max(0) as u128)
+            .map(|product| product / (self.end_at - self.start_at) as u128)
+            .ok_or(EscrowError::MathOverflow)?;
+        Ok(streamed as u64)
+    }
+}
+
+/// Escrow state handed from one deployment to another
+#[account]
+#[derive(InitSpace)]
+pub struct MigrationRecord {
+    /// Escrow address under the exporting program
+    pub escrow: Pubkey,
+    /// Program expected to import the escrow
+    pub target_program: Pubkey,
+    /// Escrow state at the time of export
+    pub state: Escrow,
+    pub bump: u8,
+}
+
+impl MigrationRecord {
+    /// Load a record written by `source_program`, which is a different
+    /// deployment of this program and so cannot be typed as `Account`
+    pub fn load(info: &AccountInfo, source_program: &Pubkey) -> Result<Self> {
+        require_keys_eq!(
+            *info.owner,
+            *source_program,
+            EscrowError::InvalidMigrationRecord
+        );
+        let data = info.try_borrow_data()?;
+        Self::try_deserialize(&mut &data[..])
+            .map_err(|_| error!(EscrowError::InvalidMigrationRecord))
+    }
+}
+
+/// Several mints offered together for one request, released atomically
+#[account]
+#[derive(InitSpace)]
+pub struct BasketEscrow {
+    pub seller: Pubkey,
+    /// Seller-chosen id distinguishing concurrent baskets
+    pub basket_id: u64,
+    #[max_len(MAX_BASKET_LEGS)]
+    pub legs: Vec<BasketLeg>,
+    pub request_mint: Pubkey,
+    pub request_amount: u64,
+    /// Unix timestamp after which the basket can no longer be filled
+    pub expires_at: Option<i64>,
+    pub bump: u8,
+}
+
+/// One mint of an offer basket, held in its own vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct BasketLeg {
+    pub mint: Pubkey,
+    /// Net of any transfer fee on the deposit
+    pub amount: u64,
+    pub vault_bump: u8,
+}
+
+impl BasketEscrow {
+    /// Send every leg to `recipient`'s token accounts and close the vaults
+    /// to `rent_destination`. `accounts` holds, per leg in order, the mint,
+    /// its vault, the recipient's token account and the token program.
+    pub fn release<'info>(
+        &self,
+        basket: AccountInfo<'info>,
+        recipient: &Pubkey,
+        rent_destination: AccountInfo<'info>,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(
+            accounts.len() == self.legs.len() * 4,
+            EscrowError::MissingBasketAccount
+        );
+        let basket_key = basket.key();
+        let basket_id = self.basket_id.to_le_bytes();
+        let basket_seeds = &[
+            b"basket",
+            self.seller.as_ref(),
+            basket_id.as_ref(),
+            &[self.bump],
+        ];
+        let signer_seeds = &[&basket_seeds[..]];
+
+        for (leg, leg_accounts) in self.legs.iter().zip(accounts.chunks(4)) {
+            let [mint, vault, destination, token_program] = leg_accounts else {
+                unreachable!()
+            };
+            require_keys_eq!(mint.key(), leg.mint, EscrowError::InvalidMint);
+            require_keys_eq!(
+                *mint.owner,
+                token_program.key(),
+                EscrowError::InvalidBasketAccount
+            );
+            let expected_vault = Pubkey::create_program_address(
+                &[
+                    b"basket_vault",
+                    basket_key.as_ref(),
+                    leg.mint.as_ref(),
+                    &[leg.vault_bump],
+                ],
+                &crate::ID,
+            )
+            .map_err(|_| error!(EscrowError::InvalidBasketAccount))?;
+            require_keys_eq!(
+                vault.key(),
+                expected_vault,
+                EscrowError::InvalidBasketAccount
+            );
+            require_keys_eq!(
+                *destination.owner,
+                token_program.key(),
+                EscrowError::InvalidBasketAccount
+            );
+            let destination_token =
+                TokenAccount::try_deserialize(&mut &destination.data.borrow()[..])?;
+            require_keys_eq!(destination_token.mint, leg.mint, EscrowError::InvalidMint);
+            require_keys_eq!(
+                destination_token.owner,
+                *recipient,
+                EscrowError::InvalidTokenAccountOwner
+            );
+            let decimals = Mint::try_deserialize(&mut &mint.data.borrow()[..])?.decimals;
+
+            transfer_checked_with_hook(
+                CpiContext::new_with_signer(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: vault.clone(),
+                        mint: mint.clone(),
+                        to: destination.clone(),
+                        authority: basket.clone(),
+                    },
+                    signer_seeds,
+                ),
+                leg.amount,
+                decimals,
+            )?;
+            close_token_account(
+                token_program.clone(),
+                vault.clone(),
+                mint.clone(),
+                rent_destination.clone(),
+                basket.clone(),
+                signer_seeds,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for listing a single NFT
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct NftListing {
+    /// Pay the creators in the NFT's metadata their royalty on fill
+    pub pay_royalties: bool,
+}
+
+/// Compressed NFT held by an escrow PDA as the leaf owner and delegate
+#[account]
+#[derive(InitSpace)]
+pub struct CnftEscrow {
+    pub seller: Pubkey,
+    pub merkle_tree: Pubkey,
+    pub leaf: CnftLeaf,
+    pub request_mint: Pubkey,
+    pub request_amount: u64,
+    /// Unix timestamp after which the escrow can no longer be filled
+    pub expires_at: Option<i64>,
+    pub bump: u8,
+}
+
+/// Fields identifying a compressed NFT's leaf to Bubblegum
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct CnftLeaf {
+    pub data_hash: [u8; 32],
+    pub creator_hash: [u8; 32],
+    pub nonce: u64,
+    pub index: u32,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct Condition {
+    pub kind: ConditionKind,
+    pub deadline: i64,
+    pub set_at: i64,
+    pub met: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub enum ConditionKind {
+    /// Pyth price >= threshold * 10^exponent
+    PriceAtLeast {
+        feed_id: [u8; 32],
+        threshold: i64,
+        exponent: i32,
+    },
+    /// Pyth price <= threshold * 10^exponent
+    PriceAtMost {
+        feed_id: [u8; 32],
+        threshold: i64,
+        exponent: i32,
+    },
+    /// A designated resolver attests that the condition holds
+    Attested { resolver: Pubkey },
+}
+
+/// USD price for an escrow's offer, paid in the request mint at its oracle
+/// price
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct OraclePricing {
+    /// Feed pricing the request mint in USD
+    pub oracle: OracleKind,
+    /// USD per whole offer token: `usd_price * 10^usd_exponent`
+    pub usd_price: u64,
+    pub usd_exponent: i32,
+    /// Oldest price update accepted, in seconds
+    pub max_age: u32,
+    /// Widest confidence interval accepted, relative to the price
+    pub max_confidence_bps: u16,
+    /// Whether `usd_price` is the total for the offer listed when the
+    /// pricing is set, rather than per whole offer token
+    pub usd_total: bool,
+    /// Request amounts the listed offer may be quoted at, scaled to what
+    /// remains of it; zero leaves a side unbounded
+    pub min_request_amount: u64,
+    pub max_request_amount: u64,
+    /// Quote off the feed's time-weighted EMA price, which a single slot
+    /// cannot move; Pyth feeds only
+    pub use_ema: bool,
+}
+
+impl OraclePricing {
+    /// Price the escrow settles against from the feed's account
+    pub fn load(&self, info: &AccountInfo) -> Result<OraclePrice> {
+        self.oracle.load(info, self.use_ema)
+    }
+
+    /// Request tokens owed for `offer_amount` of the `listed_offer_amount`
+    /// offer tokens priced at `price`, rounded up in the seller's favour
+    pub fn quote(
+        &self,
+        price: &OraclePrice,
+        offer_amount: u64,
+        listed_offer_amount: u64,
+        offer_decimals: u8,
+        request_decimals: u8,
+    ) -> Result<u64> {
+        require!(
+            Clock::get()?.unix_timestamp - price.publish_time <= self.max_age as i64,
+            EscrowError::StaleOracle
+        );
+        require!(price.price > 0, EscrowError::InvalidOracle);
+        let price_mantissa = price.price as u128;
+        require!(
+            (price.conf as u128) * 10_000 <= price_mantissa * self.max_confidence_bps as u128,
+            EscrowError::OracleConfidenceTooWide
+        );
+
+        // offer_amount * usd_price * 10^(usd_exp + request_dec - offer_dec - price_exp) / price,
+        // or for a total price
+        // offer_amount * usd_price * 10^(usd_exp + request_dec - price_exp) / (price * listed)
+        let (offer_exponent, listed) = if self.usd_total {
+            (0, listed_offer_amount as u128)
+        } else {
+            (offer_decimals as i64, 1)
+        };
+        let exponent = self.usd_exponent as i64 + request_decimals as i64
+            - offer_exponent
+            - price.exponent as i64;
+        let scale = 10u128
+            .checked_pow(exponent.unsigned_abs() as u32)
+            .ok_or(EscrowError::MathOverflow)?;
+        let value = (offer_amount as u128)
+            .checked_mul(self.usd_price as u128)
+            .ok_or(EscrowError::MathOverflow)?;
+        let (numerator, denominator) = if exponent >= 0 {
+            (value.checked_mul(scale), price_mantissa.checked_mul(listed))
+        } else {
+            (
+                Some(value),
+                price_mantissa
+                    .checked_mul(scale)
+                    .and_then(|denominator| denominator.checked_mul(listed)),
+            )
+        };
+        let amount = numerator
+            .zip(denominator)
+            .filter(|(_, denominator)| *denominator > 0)
+            .map(|(numerator, denominator)| numerator.div_ceil(denominator))
+            .ok_or(EscrowError::MathOverflow)?;
+        require!(amount > 0, EscrowError::InvalidAmount);
+
+        // A quote outside the seller's bounds means the feed has failed
+        // rather than the market moved
+        let remaining = offer_amount as u128;
+        let listed = (listed_offer_amount as u128).max(1);
+        require!(
+            amount >= (self.min_request_amount as u128 * remaining).div_ceil(listed),
+            EscrowError::OracleQuoteOutOfBounds
+        );
+        require!(
+            self.max_request_amount == 0
+                || amount <= self.max_request_amount as u128 * remaining / listed,
+            EscrowError::OracleQuoteOutOfBounds
+        );
+        u64::try_from(amount).map_err(|_| error!(EscrowError::MathOverflow))
+    }
+}
+
+/// Oracle backend a feed is read from
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub enum OracleKind {
+    /// Pyth pull feed, read from any `PriceUpdateV2` account for the feed
+    Pyth { feed_id: [u8; 32] },
+    /// Switchboard On-Demand pull feed account
+    Switchboard { feed: Pubkey },
+}
+
+impl OracleKind {
+    /// Latest price, or with `ema` the feed's EMA price and confidence,
+    /// which only Pyth publishes
+    pub fn load(&self, info: &AccountInfo, ema: bool) -> Result<OraclePrice> {
+        match self {
+            Self::Pyth { feed_id } => {
+                let price = PythPrice::load(info, feed_id)?;
+                let (value, conf) = if ema {
+                    (price.ema_price, price.ema_conf)
+                } else {
+                    (price.price, price.conf)
+                };
+                Ok(OraclePrice {
+                    price: value,
+                    conf,
+                    exponent: price.exponent,
+                    publish_time: price.publish_time,
+                })
+            }
+            Self::Switchboard { feed } => {
+                require!(!ema, EscrowError::InvalidOracle);
+                require_keys_eq!(info.key(), *feed, EscrowError::InvalidOracle);
+                SwitchboardFeed::load(info)
+            }
+        }
+    }
+}
+
+/// Price read from either oracle backend
+pub struct OraclePrice {
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+}
+
+/// Reader for Switchboard On-Demand `PullFeedAccountData` accounts
+pub struct SwitchboardFeed;
+
+impl SwitchboardFeed {
+    const DISCRIMINATOR: [u8; 8] = [196, 27, 108, 196, 10, 215, 219, 40];
+    /// Offsets into the zero-copy account, discriminator included
+    const LAST_UPDATE_TIMESTAMP_OFFSET: usize = 2216;
+    const RESULT_VALUE_OFFSET: usize = 2264;
+    const RESULT_STD_DEV_OFFSET: usize = 2280;
+    /// Results are fixed-point with 18 decimals; keep 9 so they fit Pyth's
+    /// 64-bit mantissa
+    const PRECISION_TRIM: i128 = 1_000_000_000;
+    const EXPONENT: i32 = -9;
+
+    pub fn load(info: &AccountInfo) -> Result<OraclePrice> {
+        require_keys_eq!(
+            *info.owner,
+            SWITCHBOARD_ON_DEMAND_PROGRAM_ID,
+            EscrowError::InvalidOracle
+        );
+        let data = info.try_borrow_data()?;
+        require!(
+            data.len() >= Self::RESULT_STD_DEV_OFFSET + 16 && data[..8] == Self::DISCRIMINATOR,
+            EscrowError::InvalidOracle
+        );
+        let read_i128 =
+            |offset: usize| i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap());
+        let publish_time = i64::from_le_bytes(
+            data[Self::LAST_UPDATE_TIMESTAMP_OFFSET..Self::LAST_UPDATE_TIMESTAMP_OFFSET + 8]
+                .try_into()
+                .unwrap(),
+        );
+        let price = i64::try_from(read_i128(Self::RESULT_VALUE_OFFSET) / Self::PRECISION_TRIM)
+            .map_err(|_| error!(EscrowError::InvalidOracle))?;
+        let conf = u64::try_from(read_i128(Self::RESULT_STD_DEV_OFFSET) / Self::PRECISION_TRIM)
+            .map_err(|_| error!(EscrowError::InvalidOracle))?;
+
+        Ok(OraclePrice {
+            price,
+            conf,
+            exponent: Self::EXPONENT,
+            publish_time,
+        })
+    }
+}
+
+/// Emitter trusted to attest payments made on one Wormhole chain
+#[account]
+#[derive(InitSpace)]
+pub struct ForeignEmitter {
+    pub chain: u16,
+    /// Emitter address on `chain`, left-padded to 32 bytes
+    pub address: [u8; 32],
+    pub bump: u8,
+}
+
+/// Marks a Wormhole message as consumed, so each payment settles once
+#[account]
+#[derive(InitSpace)]
+pub struct ReceivedMessage {
+    pub escrow: Pubkey,
+    pub bump: u8,
+}
+
+/// Payment for an escrow attested by a foreign emitter, read from a
+/// Wormhole core bridge `PostedVAA` account
+pub struct WormholePayment {
+    pub sequence: u64,
+    pub escrow: Pubkey,
+    /// Solana wallet the offer is released to
+    pub recipient: Pubkey,
+    pub offer_amount: u64,
+    pub request_amount: u64,
+}
+
+/// Borsh layout of the core bridge's `PostedVAA` account after its magic
+#[derive(AnchorDeserialize)]
+struct PostedVaaV1 {
+    _vaa_version: u8,
+    _consistency_level: u8,
+    _vaa_time: u32,
+    _vaa_signature_account: Pubkey,
+    _submission_time: u32,
+    _nonce: u32,
+    sequence: u64,
+    emitter_chain: u16,
+    emitter_address: [u8; 32],
+    payload: Vec<u8>,
+}
+
+impl WormholePayment {
+    const MAGIC: &'static [u8] = b"vaa";
+    /// Escrow, recipient, then big-endian offer and request amounts
+    const PAYLOAD_LEN: usize = 80;
+
+    /// Load a VAA the core bridge verified, emitted by `emitter`
+    pub fn load(info: &AccountInfo, emitter: &ForeignEmitter) -> Result<Self> {
+        require_keys_eq!(
+            *info.owner,
+            WORMHOLE_CORE_BRIDGE_PROGRAM_ID,
+            EscrowError::InvalidVaa
+        );
+        let data = info.try_borrow_data()?;
+        require!(data.starts_with(Self::MAGIC), EscrowError::InvalidVaa);
+        let vaa = PostedVaaV1::deserialize(&mut &data[Self::MAGIC.len()..])
+            .map_err(|_| error!(EscrowError::InvalidVaa))?;
+        require!(
+            vaa.emitter_chain == emitter.chain && vaa.emitter_address == emitter.address,
+            EscrowError::UntrustedEmitter
+        );
+
+        let payload = vaa.payload.as_slice();
+        require!(
+            payload.len() == Self::PAYLOAD_LEN,
+            EscrowError::InvalidWormholePayment
+        );
+        let word =
+            |range: std::ops::Range<usize>| -> [u8; 8] { payload[range].try_into().unwrap() };
+        Ok(Self {
+            sequence: vaa.sequence,
+            escrow: Pubkey::try_from(&payload[..32]).unwrap(),
+            recipient: Pubkey::try_from(&payload[32..64]).unwrap(),
+            offer_amount: u64::from_be_bytes(word(64..72)),
+            request_amount: u64::from_be_bytes(word(72..80)),
+        })
+    }
+}
+
+/// Price read from a Pyth `PriceUpdateV2` account
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+    pub ema_price: i64,
+    pub ema_conf: u64,
+}
+
+/// Borsh layout of the Pyth receiver's `PriceUpdateV2` account
+#[derive(AnchorDeserialize)]
+struct PriceUpdateV2 {
+    _write_authority: Pubkey,
+    verification_level: PythVerificationLevel,
+    feed_id: [u8; 32],
+    price: i64,
+    conf: u64,
+    exponent: i32,
+    publish_time: i64,
+    _prev_publish_time: i64,
+    ema_price: i64,
+    ema_conf: u64,
+    _posted_slot: u64,
+}
+
+#[derive(AnchorDeserialize, PartialEq, Eq)]
+enum PythVerificationLevel {
+    Partial { _num_signatures: u8 },
+    Full,
+}
+
+impl PythPrice {
+    const DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+    /// Load a fully verified price update for `feed_id`
+    pub fn load(info: &AccountInfo, feed_id: &[u8; 32]) -> Result<Self> {
+        require_keys_eq!(
+            *info.owner,
+            PYTH_RECEIVER_PROGRAM_ID,
+            EscrowError::InvalidOracle
+        );
+        let data = info.try_borrow_data()?;
+        require!(
+            data.len() > 8 && data[..8] == Self::DISCRIMINATOR,
+            EscrowError::InvalidOracle
+        );
+        let update = PriceUpdateV2::deserialize(&mut &data[8..])
+            .map_err(|_| error!(EscrowError::InvalidOracle))?;
+        require!(
+            update.verification_level == PythVerificationLevel::Full,
+            EscrowError::InvalidOracle
+        );
+        require!(update.feed_id == *feed_id, EscrowError::InvalidOracle);
+
+        Ok(Self {
+            price: update.price,
+            conf: update.conf,
+            exponent: update.exponent,
+            publish_time: update.publish_time,
+            ema_price: update.ema_price,
+            ema_conf: update.ema_conf,
+        })
+    }
+
+    /// Compare the price against `value * 10^exponent`
+    pub fn compare(&self, value: i64, exponent: i32) -> Result<std::cmp::Ordering> {
+        let common = self.exponent.min(exponent);
+        let scale = |mantissa: i64, from: i32| -> Result<i128> {
+            10i128
+                .checked_pow((from - common) as u32)
+                .and_then(|factor| factor.checked_mul(mantissa as i128))
+                .ok_or(error!(EscrowError::MathOverflow))
+        };
+        Ok(scale(self.price, self.exponent)?.cmp(&scale(value, exponent)?))
+    }
+}
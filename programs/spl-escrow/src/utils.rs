@@ -0,0 +1,261 @@
+//! Token helpers shared by the instruction handlers
+
+use crate::*;
+
+/// Check that `authority` is the locker PDA of `locker_program`
+pub(crate) fn check_locker_authority(authority: &Pubkey, locker_program: &Pubkey) -> Result<()> {
+    let (expected, _) = Pubkey::find_program_address(&[LOCKER_AUTHORITY_SEED], locker_program);
+    require_keys_eq!(*authority, expected, EscrowError::Unauthorized);
+    Ok(())
+}
+
+/// Log `memo` through the SPL Memo program, so that accounting systems
+/// watching the transaction's token accounts can match its transfers to it
+pub fn log_memo(memo_program: Option<&AccountInfo>, memo: &str) -> Result<()> {
+    require!(memo.len() <= MAX_MEMO_LEN, EscrowError::MemoTooLong);
+    let memo_program = memo_program.ok_or(EscrowError::MissingMemoProgram)?;
+    invoke(
+        &Instruction {
+            program_id: memo_program.key(),
+            accounts: vec![],
+            data: memo.as_bytes().to_vec(),
+        },
+        std::slice::from_ref(memo_program),
+    )?;
+    Ok(())
+}
+
+/// Whether `mint` is wrapped SOL under either token program
+pub fn is_native_mint(mint: &Pubkey) -> bool {
+    *mint == spl_token::native_mint::ID || *mint == spl_token_2022::native_mint::ID
+}
+
+/// Transfer fee `mint` withholds on a transfer of `amount` this epoch;
+/// zero for mints without the Token-2022 transfer fee extension
+pub fn transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != Token2022::id() {
+        return Ok(0);
+    }
+    fee_of(
+        epoch_transfer_fee(mint, Clock::get()?.epoch)?.as_ref(),
+        amount,
+    )
+}
+
+/// Amount to send so that `net` arrives after `mint`'s transfer fee
+pub fn gross_amount(mint: &AccountInfo, net: u64) -> Result<u64> {
+    if *mint.owner != Token2022::id() || net == 0 {
+        return Ok(net);
+    }
+    gross_up(epoch_transfer_fee(mint, Clock::get()?.epoch)?.as_ref(), net)
+}
+
+/// Transfer fee `mint` charges in `epoch`; `None` for mints without the
+/// Token-2022 transfer fee extension. Lets a caller making several
+/// transfers of one mint unpack it once.
+pub fn epoch_transfer_fee(mint: &AccountInfo, epoch: u64) -> Result<Option<TransferFee>> {
+    if *mint.owner != Token2022::id() {
+        return Ok(None);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    Ok(state
+        .get_extension::<TransferFeeConfig>()
+        .ok()
+        .map(|config| *config.get_epoch_fee(epoch)))
+}
+
+/// Fee withheld on a transfer of `amount` under `fee`
+pub fn fee_of(fee: Option<&TransferFee>, amount: u64) -> Result<u64> {
+    fee.map_or(Ok(0), |fee| {
+        fee.calculate_fee(amount)
+            .ok_or(error!(EscrowError::MathOverflow))
+    })
+}
+
+/// Amount to send so that `net` arrives after `fee`
+pub fn gross_up(fee: Option<&TransferFee>, net: u64) -> Result<u64> {
+    match fee {
+        Some(fee) if net > 0 => fee
+            .calculate_pre_fee_amount(net)
+            .ok_or(error!(EscrowError::TransferFeeTooHigh)),
+        _ => Ok(net),
+    }
+}
+
+/// Permanent delegate of `mint`, if it is a Token-2022 mint with the
+/// extension
+pub fn permanent_delegate(mint: &AccountInfo) -> Result<Option<Pubkey>> {
+    if *mint.owner != Token2022::id() {
+        return Ok(None);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    Ok(get_permanent_delegate(&state))
+}
+
+/// Interest-bearing config of `mint`, if it is a Token-2022 mint with the
+/// extension
+fn interest_bearing_config(mint: &AccountInfo) -> Result<Option<InterestBearingConfig>> {
+    if *mint.owner != Token2022::id() {
+        return Ok(None);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    Ok(state.get_extension::<InterestBearingConfig>().ok().copied())
+}
+
+/// UI value of `amount` of `mint` right now; interest-bearing mints scale
+/// their raw amounts by the interest accrued so far
+pub fn ui_amount(mint: &AccountInfo, amount: u64, decimals: u8) -> Result<String> {
+    match interest_bearing_config(mint)? {
+        Some(config) => config
+            .amount_to_ui_amount(amount, decimals, Clock::get()?.unix_timestamp)
+            .ok_or(error!(EscrowError::MathOverflow)),
+        None => Ok(spl_token_2022::amount_to_ui_amount_string_trimmed(
+            amount, decimals,
+        )),
+    }
+}
+
+/// Raw amount of `mint` worth `ui_amount` right now; the inverse of
+/// [`ui_amount`]
+pub fn raw_amount(mint: &AccountInfo, ui_amount: &str, decimals: u8) -> Result<u64> {
+    match interest_bearing_config(mint)? {
+        Some(config) => config
+            .try_ui_amount_into_amount(ui_amount, decimals, Clock::get()?.unix_timestamp)
+            .map_err(|_| error!(EscrowError::InvalidUiAmount)),
+        None => spl_token_2022::try_ui_amount_into_amount(ui_amount.to_string(), decimals)
+            .map_err(|_| error!(EscrowError::InvalidUiAmount)),
+    }
+}
+
+/// `transfer_checked` that resolves the mint's transfer-hook accounts from
+/// the context's remaining accounts; a plain transfer for unhooked mints
+pub fn transfer_checked_with_hook<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>,
+    amount: u64,
+    decimals: u8,
+) -> Result<()> {
+    spl_token_2022::onchain::invoke_transfer_checked(
+        ctx.program.key,
+        ctx.accounts.from,
+        ctx.accounts.mint,
+        ctx.accounts.to,
+        ctx.accounts.authority,
+        &ctx.remaining_accounts,
+        amount,
+        decimals,
+        ctx.signer_seeds,
+    )
+    .map_err(Into::into)
+}
+
+/// Close `escrow`, returning its rent to `rent_recipient`; when that is
+/// not the seller, lamports beyond the rent, such as a cancellation bond,
+/// still go to the seller
+pub fn close_escrow<'info>(
+    escrow: &AccountLoader<'info, Escrow>,
+    seller: AccountInfo<'info>,
+    rent_recipient: AccountInfo<'info>,
+) -> Result<()> {
+    if rent_recipient.key() != seller.key() {
+        let bond = escrow
+            .load()?
+            .cancellation_bond()
+            .map_or(0, |bond| bond.lamports);
+        let info = escrow.to_account_info();
+        let rent = Rent::get()?.minimum_balance(info.data_len());
+        let surplus = info.lamports().saturating_sub(rent).max(bond);
+        **info.try_borrow_mut_lamports()? -= surplus;
+        **seller.try_borrow_mut_lamports()? += surplus;
+    }
+    escrow.close(rent_recipient)
+}
+
+/// Refuse a transaction that also targets `escrow` from another of our
+/// instructions, or that calls one of `guarded_programs`, or an oracle
+/// program when `oracle_priced`
+pub fn check_transaction_guard(
+    instructions: &AccountInfo,
+    escrow: &Pubkey,
+    guarded_programs: &[Pubkey],
+    oracle_priced: bool,
+) -> Result<()> {
+    let current = load_current_index_checked(instructions)? as usize;
+    let mut index = 0;
+    while let Ok(instruction) = load_instruction_at_checked(index, instructions) {
+        if index != current {
+            let program_id = instruction.program_id;
+            require!(
+                !guarded_programs.contains(&program_id)
+                    && !(oracle_priced
+                        && (program_id == PYTH_RECEIVER_PROGRAM_ID
+                            || program_id == SWITCHBOARD_ON_DEMAND_PROGRAM_ID)),
+                EscrowError::GuardedInstruction
+            );
+            require!(
+                program_id != crate::ID
+                    || instruction
+                        .accounts
+                        .iter()
+                        .all(|meta| meta.pubkey != *escrow),
+                EscrowError::DuplicateEscrowInstruction
+            );
+        }
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Current balance of a token account under either token program, read
+/// straight from its data so a borrowed account can be re-checked after a
+/// transfer
+pub fn token_balance(account: &AccountInfo) -> Result<u64> {
+    let data = account.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?;
+    Ok(state.base.amount)
+}
+
+/// Close a token account owned by one of our PDAs, first harvesting any
+/// transfer fees withheld in it, which would otherwise block the close
+pub fn close_token_account<'info>(
+    token_program: AccountInfo<'info>,
+    account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let withheld = if *account.owner == Token2022::id() {
+        let data = account.try_borrow_data()?;
+        let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?;
+        state
+            .get_extension::<TransferFeeAmount>()
+            .map_or(0, |fees| u64::from(fees.withheld_amount))
+    } else {
+        0
+    };
+    if withheld > 0 {
+        harvest_withheld_tokens_to_mint(
+            CpiContext::new(
+                token_program.clone(),
+                HarvestWithheldTokensToMint {
+                    token_program_id: token_program.clone(),
+                    mint,
+                },
+            ),
+            vec![account.clone()],
+        )?;
+    }
+
+    close_account(CpiContext::new_with_signer(
+        token_program,
+        CloseAccount {
+            account,
+            destination,
+            authority,
+        },
+        signer_seeds,
+    ))
+}
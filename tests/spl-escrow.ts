@@ -1,23 +1,45 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { SplEscrow } from "../target/types/spl_escrow";
+import { MockLocker } from "../target/types/mock_locker";
+import { MockTransferHook } from "../target/types/mock_transfer_hook";
 import {
   createMint,
   createAccount,
   mintTo,
+  approve,
+  revoke,
   getAccount,
+  getMint,
   TOKEN_PROGRAM_ID,
   getAssociatedTokenAddress,
+  getAssociatedTokenAddressSync,
   createAssociatedTokenAccount,
+  createAssociatedTokenAccountInstruction,
+  transfer,
+  TOKEN_2022_PROGRAM_ID,
+  ExtensionType,
+  getMintLen,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializePermanentDelegateInstruction,
+  createInitializeTransferHookInstruction,
+  freezeAccount,
+  thawAccount,
+  burn,
+  NATIVE_MINT,
 } from "@solana/spl-token";
 import { expect } from "chai";
 import { PublicKey, Keypair, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { createHash } from "crypto";
 
 describe("spl-escrow", () => {
   const provider = anchor.AnchorProvider.env();
   anchor.setProvider(provider);
 
   const program = anchor.workspace.SplEscrow as Program<SplEscrow>;
+  const mockLocker = anchor.workspace.MockLocker as Program<MockLocker>;
+  const mockTransferHook = anchor.workspace.MockTransferHook as Program<MockTransferHook>;
   const connection = provider.connection;
 
   // Test accounts
@@ -36,6 +58,11 @@ describe("spl-escrow", () => {
   const INITIAL_SELLER_BALANCE = 10000;
   const INITIAL_BUYER_BALANCE = 10000;
 
+  const MEMO_PROGRAM_ID = new PublicKey("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+  // Compute units a plain accept may use, well under the 200k default
+  const ACCEPT_COMPUTE_BUDGET = 150_000;
+
   // Helper function to airdrop SOL
   async function airdrop(pubkey: PublicKey, amount: number = 2 * LAMPORTS_PER_SOL) {
     const sig = await connection.requestAirdrop(pubkey, amount);
@@ -46,7 +73,8 @@ describe("spl-escrow", () => {
   function deriveEscrowPDA(
     sellerPubkey: PublicKey,
     offerMintPubkey: PublicKey,
-    requestMintPubkey: PublicKey
+    requestMintPubkey: PublicKey,
+    escrowId: number = 0
   ): [PublicKey, number] {
     return PublicKey.findProgramAddressSync(
       [
@@ -54,17 +82,100 @@ describe("spl-escrow", () => {
         sellerPubkey.toBuffer(),
         offerMintPubkey.toBuffer(),
         requestMintPubkey.toBuffer(),
+        new anchor.BN(escrowId).toArrayLike(Buffer, "le", 8),
       ],
       program.programId
     );
   }
 
-  // Helper to derive vault PDA
-  function deriveVaultPDA(escrowPubkey: PublicKey): [PublicKey, number] {
-    return PublicKey.findProgramAddressSync(
-      [Buffer.from("vault"), escrowPubkey.toBuffer()],
+  // Global pair index shared by every escrow
+  const [pairIndexPDA] = PublicKey.findProgramAddressSync(
+    [Buffer.from("pair_index")],
+    program.programId
+  );
+
+  // Global protocol config, initialized by the upgrade authority
+  const [configPDA] = PublicKey.findProgramAddressSync(
+    [Buffer.from("config")],
+    program.programId
+  );
+  const [programDataPDA] = PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+  );
+
+  before(async () => {
+    if ((await connection.getAccountInfo(pairIndexPDA)) === null) {
+      await program.methods
+        .initializePairIndex()
+        .accounts({
+          payer: provider.wallet.publicKey,
+          pairIndex: pairIndexPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+    }
+    if ((await connection.getAccountInfo(configPDA)) === null) {
+      await program.methods
+        .initializeConfig({
+          protocolFeeBps: 0,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 0,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 0,
+          stakeMint: null,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [],
+          feeCurrency: { request: {} },
+          feeSplits: [],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({
+          admin: provider.wallet.publicKey,
+          program: program.programId,
+          programData: programDataPDA,
+          config: configPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+    }
+  });
+
+  // Helper to derive an escrow's vault: its associated token account of the
+  // offer mint, under whichever token program owns the mint
+  async function deriveVault(escrowPubkey: PublicKey, offerMint: PublicKey): Promise<PublicKey> {
+    const mintAccount = await connection.getAccountInfo(offerMint);
+    return getAssociatedTokenAddressSync(offerMint, escrowPubkey, true, mintAccount.owner);
+  }
+
+  // Helper to open the program's fee vault for a mint
+  async function initializeFeeVault(mint: PublicKey): Promise<PublicKey> {
+    const [feeVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("fee_vault"), mint.toBuffer()],
       program.programId
     );
+    await program.methods
+      .initializeFeeVault()
+      .accounts({
+        payer: provider.wallet.publicKey,
+        config: configPDA,
+        mint,
+        feeVault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    return feeVault;
   }
 
   // Setup for each test: create fresh accounts and mints
@@ -150,20 +261,32 @@ describe("spl-escrow", () => {
 
     it("Creates an escrow successfully", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
       // Get initial balance
       const initialSellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
 
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
@@ -187,20 +310,95 @@ describe("spl-escrow", () => {
       expect(Number(initialSellerBalance) - Number(finalSellerBalance)).to.equal(OFFER_AMOUNT);
     });
 
+    it("Clones an escrow's terms at a new size", async () => {
+      const [templatePDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 1);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: templatePDA,
+          vault: await deriveVault(templatePDA, offerMint),
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .cloneEscrow(new anchor.BN(1), new anchor.BN(OFFER_AMOUNT / 2))
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          template: templatePDA,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrowAccount = await program.account.escrow.fetch(escrowPDA);
+      expect(escrowAccount.offerAmount.toNumber()).to.equal(OFFER_AMOUNT / 2);
+      expect(escrowAccount.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT / 2);
+      expect(Number((await getAccount(connection, vaultPDA)).amount)).to.equal(OFFER_AMOUNT / 2);
+    });
+
     it("Fails to create escrow with zero offer amount", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
       try {
         await program.methods
-          .createEscrow(new anchor.BN(0), new anchor.BN(REQUEST_AMOUNT))
+          .createEscrow(new anchor.BN(0), new anchor.BN(0), new anchor.BN(REQUEST_AMOUNT), null, null, null)
           .accounts({
             seller: seller.publicKey,
+            payer: seller.publicKey,
             offerMint: offerMint,
             requestMint: requestMint,
             sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
             escrow: escrowPDA,
             vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: anchor.web3.SystemProgram.programId,
           })
@@ -214,18 +412,30 @@ describe("spl-escrow", () => {
 
     it("Fails to create escrow with zero request amount", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
       try {
         await program.methods
-          .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(0))
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(0), null, null, null)
           .accounts({
             seller: seller.publicKey,
+            payer: seller.publicKey,
             offerMint: offerMint,
             requestMint: requestMint,
             sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
             escrow: escrowPDA,
             vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
             tokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: anchor.web3.SystemProgram.programId,
           })
@@ -236,6 +446,49 @@ describe("spl-escrow", () => {
         expect(err.error.errorCode.code).to.equal("InvalidAmount");
       }
     });
+
+    it("Emits an EscrowCreated event", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      const signature = await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc({ commitment: "confirmed" });
+
+      const tx = await connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+      const events = [...parser.parseLogs(tx.meta.logMessages)];
+      const created = events.find((event) => event.name.toLowerCase() === "escrowcreated");
+      expect(created.data.escrow.toString()).to.equal(escrowPDA.toString());
+      expect(created.data.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
+      expect(created.data.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT);
+    });
   });
 
   describe("accept_escrow", () => {
@@ -245,18 +498,30 @@ describe("spl-escrow", () => {
 
     it("Accepts an escrow successfully", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
       // Create escrow first
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
@@ -270,18 +535,59 @@ describe("spl-escrow", () => {
 
       // Accept the escrow
       await program.methods
-        .acceptEscrow()
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
         .accounts({
           buyer: buyer.publicKey,
+          payer: buyer.publicKey,
           seller: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           escrow: escrowPDA,
+          rentRecipient: null,
           vault: vaultPDA,
           buyerRequestToken: buyerRequestToken,
           buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
           sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
           tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
           associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
@@ -317,426 +623,14302 @@ describe("spl-escrow", () => {
       }
     });
 
-    it("Fails if buyer has insufficient request tokens", async () => {
+    it("Logs a memo with the fill through the SPL Memo program", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
-      // Create escrow with request amount larger than buyer's balance
-      const largeRequestAmount = INITIAL_BUYER_BALANCE + 1000;
+      // Create escrow first
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(largeRequestAmount))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc({ commitment: "confirmed" });
+
+      const tx = await connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      expect(tx.meta.logMessages.join("\n")).to.include('Memo (len 10): "invoice-42"');
+    });
+
+
+      const signature = await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, "invoice-42")
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
           escrow: escrowPDA,
+          rentRecipient: null,
           vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: MEMO_PROGRAM_ID,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc({ commitment: "confirmed" });
+
+      const tx = await connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      expect(tx.meta.logMessages.join("\n")).to.include('Memo (len 10): "invoice-42"');
+    });
+
+    it("Pulls a delegated listing's offer from the seller at accept", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const initialSellerOfferBalance = (await getAccount(connection, sellerOfferToken)).amount;
+
+      await program.methods
+        .createDelegatedEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          escrow: escrowPDA,
+          pairIndex: pairIndexPDA,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      try {
-        await program.methods
-          .acceptEscrow()
+      // The seller keeps custody, with the offer delegated to the escrow
+      let sellerAccount = await getAccount(connection, sellerOfferToken);
+      expect(sellerAccount.amount).to.equal(initialSellerOfferBalance);
+      expect(sellerAccount.delegate.toBase58()).to.equal(escrowPDA.toBase58());
+
+      const accept = (amount: number) =>
+        program.methods
+          .acceptEscrow(new anchor.BN(amount), null, null, null, null, null)
           .accounts({
             buyer: buyer.publicKey,
+            payer: buyer.publicKey,
             seller: seller.publicKey,
             offerMint: offerMint,
             requestMint: requestMint,
             escrow: escrowPDA,
-            vault: vaultPDA,
+            rentRecipient: null,
+            vault: sellerOfferToken,
             buyerRequestToken: buyerRequestToken,
             buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
             sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
             tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
             associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
             systemProgram: anchor.web3.SystemProgram.programId,
           })
           .signers([buyer])
           .rpc();
-        expect.fail("Should have thrown an error");
+
+      await accept(OFFER_AMOUNT / 2);
+      sellerAccount = await getAccount(connection, sellerOfferToken);
+      expect(Number(initialSellerOfferBalance - sellerAccount.amount)).to.equal(OFFER_AMOUNT / 2);
+
+      // Revoking the approval fails later fills cleanly
+      await revoke(connection, seller, sellerOfferToken, seller);
+      try {
+        await accept(OFFER_AMOUNT / 2);
+        expect.fail("Should have thrown an error - delegation revoked");
       } catch (err: any) {
-        // Token program will throw insufficient funds error
-        expect(err.message).to.include("insufficient");
+        expect(err.error.errorCode.code).to.equal("DelegationLapsed");
       }
     });
-  });
-
-  describe("cancel_escrow", () => {
-    beforeEach(async () => {
-      await setupTest();
-    });
 
-    it("Cancels an escrow successfully", async () => {
+    it("Accepts an escrow within the compute budget", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
-
-      // Get initial balance
-      const initialSellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
-      // Create escrow
+      // Create escrow first
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      // Verify tokens are in vault
-      const vaultBalance = (await getAccount(connection, vaultPDA)).amount;
-      expect(Number(vaultBalance)).to.equal(OFFER_AMOUNT);
+      // Simulate the accept to measure it without settling
+      const tx = await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .transaction();
+      tx.feePayer = buyer.publicKey;
+      const simulation = await connection.simulateTransaction(tx, [buyer]);
+      expect(simulation.value.err).to.be.null;
+      expect(simulation.value.unitsConsumed).to.be.lessThan(ACCEPT_COMPUTE_BUDGET);
+    });
+
+    it("Lets a relayer pay the fees and rent of a buyer's accept", async () => {
+      const relayer = Keypair.generate();
+      await airdrop(relayer.publicKey);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
-      // Cancel the escrow
       await program.methods
-        .cancelEscrow()
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
-          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      // Verify tokens returned to seller
-      const finalSellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
-      expect(Number(finalSellerBalance)).to.equal(Number(initialSellerBalance));
-
-      // Verify escrow account is closed
-      try {
-        await program.account.escrow.fetch(escrowPDA);
-        expect.fail("Escrow account should be closed");
-      } catch (err: any) {
-        expect(err.message).to.include("Account does not exist");
-      }
+      const buyerLamportsBefore = await connection.getBalance(buyer.publicKey);
+      const tx = await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: relayer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .transaction();
+      // The relayer is the fee payer; the buyer only signs for its tokens
+      await anchor.web3.sendAndConfirmTransaction(connection, tx, [relayer, buyer]);
 
-      // Verify vault is closed
-      try {
-        await getAccount(connection, vaultPDA);
-        expect.fail("Vault account should be closed");
-      } catch (err: any) {
-        expect(err.message).to.include("could not find account");
-      }
+      expect(await connection.getBalance(buyer.publicKey)).to.equal(buyerLamportsBefore);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
     });
 
-    it("Prevents unauthorized cancellation", async () => {
+    it("Holds a reserved escrow for its buyer until the reservation lapses", async () => {
+      const holder = Keypair.generate();
+      await airdrop(holder.publicKey);
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [reservationPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reservation"), escrowPDA.toBuffer()],
+        program.programId
+      );
 
-      // Create escrow
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      // Create a token account for buyer to receive "refund" (for test purposes)
-      const buyerOfferTokenForCancel = await createAssociatedTokenAccount(
-        connection,
-        buyer,
-        offerMint,
-        buyer.publicKey
-      ).catch(() => buyerOfferToken); // Use existing if already created
+      await program.methods
+        .reserveEscrow(new anchor.BN(LAMPORTS_PER_SOL / 100), new anchor.BN(3600))
+        .accounts({
+          buyer: holder.publicKey,
+          escrow: escrowPDA,
+          reservation: reservationPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([holder])
+        .rpc();
 
-      // Try to cancel as buyer (not seller) - should fail
       try {
         await program.methods
-          .cancelEscrow()
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
           .accounts({
-            seller: buyer.publicKey, // Wrong! Buyer trying to act as seller
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
             offerMint: offerMint,
+            requestMint: requestMint,
             escrow: escrowPDA,
+            rentRecipient: null,
             vault: vaultPDA,
-            sellerOfferToken: buyerOfferTokenForCancel,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
             tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
             systemProgram: anchor.web3.SystemProgram.programId,
           })
           .signers([buyer])
           .rpc();
-        expect.fail("Should have thrown an error");
+        expect.fail("Should have thrown an error - escrow reserved");
       } catch (err: any) {
-        // Should fail with unauthorized or constraint error
-        expect(err.toString()).to.satisfy((msg: string) => 
-          msg.includes("Unauthorized") || 
-          msg.includes("ConstraintAddress") ||
-          msg.includes("Error")
-        );
+        expect(err.error.errorCode.code).to.equal("EscrowReserved");
       }
 
-      // Verify escrow still exists
-      const escrowAccount = await program.account.escrow.fetch(escrowPDA);
-      expect(escrowAccount.seller.toString()).to.equal(seller.publicKey.toString());
-    });
-  });
-
-  describe("security tests", () => {
-    beforeEach(async () => {
-      await setupTest();
+      // The deposit stays locked while the hold is live on an open escrow
+      try {
+        await program.methods
+          .closeReservation()
+          .accounts({
+            reservation: reservationPDA,
+            escrow: escrowPDA,
+            seller: seller.publicKey,
+            buyer: holder.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have thrown an error - reservation active");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("ReservationActive");
+      }
     });
 
-    it("Prevents double-spending (escrow closed after accept)", async () => {
+    it("Only lets buyers holding the gate token accept a token-gated escrow", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
-      // Create escrow
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      // Accept the escrow
-      await program.methods
-        .acceptEscrow()
-        .accounts({
-          buyer: buyer.publicKey,
-          seller: seller.publicKey,
-          offerMint: offerMint,
-          requestMint: requestMint,
-          escrow: escrowPDA,
-          vault: vaultPDA,
-          buyerRequestToken: buyerRequestToken,
-          buyerOfferToken: buyerOfferToken,
-          sellerRequestToken: sellerRequestToken,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
-          systemProgram: anchor.web3.SystemProgram.programId,
-        })
-        .signers([buyer])
-        .rpc();
-
-      // Create another buyer to try double-spend
-      const attacker = Keypair.generate();
-      await airdrop(attacker.publicKey);
-
-      const attackerRequestToken = await createAssociatedTokenAccount(
-        connection,
-        attacker,
-        requestMint,
-        attacker.publicKey
-      );
-      const attackerOfferToken = await createAssociatedTokenAccount(
-        connection,
-        attacker,
-        offerMint,
-        attacker.publicKey
-      );
-
-      // Mint some request tokens to attacker
-      await mintTo(
-        connection,
-        buyer, // buyer is the mint authority
-        requestMint,
-        attackerRequestToken,
-        buyer,
-        REQUEST_AMOUNT
-      );
+      const gate = (minAmount: number) =>
+        program.methods
+          .setTokenGate({ mint: { mint: requestMint, minAmount: new anchor.BN(minAmount) } })
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
 
-      // Try to accept the same escrow again - should fail
-      try {
-        await program.methods
-          .acceptEscrow()
+      const accept = () =>
+        program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
           .accounts({
-            buyer: attacker.publicKey,
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
             seller: seller.publicKey,
             offerMint: offerMint,
             requestMint: requestMint,
             escrow: escrowPDA,
+            rentRecipient: null,
             vault: vaultPDA,
-            buyerRequestToken: attackerRequestToken,
-            buyerOfferToken: attackerOfferToken,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
             sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: buyerRequestToken,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
             tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
             associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
             systemProgram: anchor.web3.SystemProgram.programId,
           })
-          .signers([attacker])
+          .signers([buyer])
           .rpc();
-        expect.fail("Should have thrown an error - escrow already closed");
+
+      await gate(INITIAL_BUYER_BALANCE + 1);
+      try {
+        await accept();
+        expect.fail("Should have thrown an error - gate not met");
       } catch (err: any) {
-        // Escrow account should not exist
-        expect(err.message).to.include("Account does not exist");
+        expect(err.error.errorCode.code).to.equal("GateNotMet");
       }
+
+      await gate(INITIAL_BUYER_BALANCE);
+      await accept();
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
     });
 
-    it("Validates token mint matches escrow", async () => {
+    it("Only lets buyers approved by the permission program accept a permissioned escrow", async () => {
       const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA] = deriveVaultPDA(escrowPDA);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
 
-      // Create escrow
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
           escrow: escrowPDA,
           vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      // Create a fake mint
-      const fakeMint = await createMint(
-        connection,
-        buyer,
-        buyer.publicKey,
-        null,
-        9
+      // Any program works as the approver; an approval is a non-empty
+      // account it owns at the buyer's approval PDA
+      const permissionProgram = anchor.web3.Keypair.generate().publicKey;
+      const kycList = anchor.web3.Keypair.generate().publicKey;
+      const [approvalPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("approval"), kycList.toBuffer(), buyer.publicKey.toBuffer()],
+        permissionProgram
       );
 
-      // Try to accept with wrong mint - should fail due to address constraint
-      try {
-        await program.methods
-          .acceptEscrow()
+      const permit = (permission: { program: PublicKey; account: PublicKey } | null) =>
+        program.methods
+          .setPermission(permission)
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+
+      const accept = (permissionApproval: PublicKey | null) =>
+        program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
           .accounts({
             buyer: buyer.publicKey,
+            payer: buyer.publicKey,
             seller: seller.publicKey,
-            offerMint: fakeMint, // Wrong mint!
+            offerMint: offerMint,
             requestMint: requestMint,
             escrow: escrowPDA,
+            rentRecipient: null,
             vault: vaultPDA,
             buyerRequestToken: buyerRequestToken,
             buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
             sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: permissionApproval,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
             tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
             associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
             systemProgram: anchor.web3.SystemProgram.programId,
           })
           .signers([buyer])
           .rpc();
-        expect.fail("Should have thrown an error");
+
+      await permit({ program: permissionProgram, account: kycList });
+      try {
+        await accept(buyerRequestToken);
+        expect.fail("Should have thrown an error - not the approval account");
       } catch (err: any) {
-        expect(err.toString()).to.satisfy((msg: string) => 
-          msg.includes("InvalidMint") || 
-          msg.includes("ConstraintAddress") ||
-          msg.includes("Error")
-        );
+        expect(err.error.errorCode.code).to.equal("InvalidPermissionAccount");
+      }
+      try {
+        await accept(approvalPDA);
+        expect.fail("Should have thrown an error - buyer not approved");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("BuyerNotApproved");
       }
+
+      await permit(null);
+      await accept(null);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
     });
 
-    it("Handles multiple escrows by the same seller with different mints", async () => {
-      // Create a second pair of mints
-      const offerMint2 = await createMint(
-        connection,
-        seller,
-        seller.publicKey,
-        null,
-        9
+    it("Sweeps several escrows of a pair up to the buyer's target", async () => {
+      const [sellerIndexPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_index"), seller.publicKey.toBuffer()],
+        program.programId
       );
-      const requestMint2 = await createMint(
-        connection,
-        buyer,
-        buyer.publicKey,
-        null,
-        9
+      const [sellerReputationPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reputation"), seller.publicKey.toBuffer()],
+        program.programId
       );
+      // Two listings of the same pair at different prices
+      const listings = [
+        { escrowId: 0, requestAmount: REQUEST_AMOUNT / 2 },
+        { escrowId: 1, requestAmount: REQUEST_AMOUNT },
+      ];
+      const remainingAccounts = [];
+      for (const { escrowId, requestAmount } of listings) {
+        const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, escrowId);
+        const vaultPDA = await deriveVault(escrowPDA, offerMint);
+        await program.methods
+          .createEscrow(new anchor.BN(escrowId), new anchor.BN(OFFER_AMOUNT / 2), new anchor.BN(requestAmount), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        remainingAccounts.push(
+          { pubkey: escrowPDA, isWritable: true, isSigner: false },
+          { pubkey: vaultPDA, isWritable: true, isSigner: false },
+          { pubkey: seller.publicKey, isWritable: true, isSigner: false },
+          { pubkey: sellerRequestToken, isWritable: true, isSigner: false },
+          { pubkey: sellerIndexPDA, isWritable: true, isSigner: false },
+          { pubkey: sellerReputationPDA, isWritable: true, isSigner: false }
+        );
+      }
 
-      // Create token accounts for second pair
-      const sellerOfferToken2 = await createAssociatedTokenAccount(
-        connection,
-        seller,
-        offerMint2,
-        seller.publicKey
-      );
+      const initialBuyerOfferBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      const initialBuyerRequestBalance = (await getAccount(connection, buyerRequestToken)).amount;
 
-      // Mint tokens
-      await mintTo(
-        connection,
-        seller,
-        offerMint2,
-        sellerOfferToken2,
-        seller,
-        INITIAL_SELLER_BALANCE
+      // The cheaper listing fills in full, the other for the rest
+      const target = (OFFER_AMOUNT * 3) / 4;
+      const cost = REQUEST_AMOUNT / 2 + REQUEST_AMOUNT / 2;
+      await program.methods
+        .acceptMany(new anchor.BN(target), new anchor.BN(cost))
+        .accounts({
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          pairIndex: pairIndexPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts(remainingAccounts)
+        .signers([buyer])
+        .rpc();
+
+      const finalBuyerOfferBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      const finalBuyerRequestBalance = (await getAccount(connection, buyerRequestToken)).amount;
+      expect(Number(finalBuyerOfferBalance - initialBuyerOfferBalance)).to.equal(target);
+      expect(Number(initialBuyerRequestBalance - finalBuyerRequestBalance)).to.equal(cost);
+
+      const [firstEscrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 0);
+      const [secondEscrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 1);
+      expect(await connection.getAccountInfo(firstEscrowPDA)).to.be.null;
+      const secondEscrow = await program.account.escrow.fetch(secondEscrowPDA);
+      expect(secondEscrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT / 4);
+    });
+
+    it("Fills the cheapest of several escrows of a pair", async () => {
+      const [sellerIndexPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_index"), seller.publicKey.toBuffer()],
+        program.programId
       );
+      const [sellerReputationPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reputation"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      // The pricier listing comes first
+      const listings = [
+        { escrowId: 0, requestAmount: REQUEST_AMOUNT },
+        { escrowId: 1, requestAmount: REQUEST_AMOUNT / 2 },
+      ];
+      const remainingAccounts = [];
+      for (const { escrowId, requestAmount } of listings) {
+        const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, escrowId);
+        const vaultPDA = await deriveVault(escrowPDA, offerMint);
+        await program.methods
+          .createEscrow(new anchor.BN(escrowId), new anchor.BN(OFFER_AMOUNT / 2), new anchor.BN(requestAmount), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        remainingAccounts.push(
+          { pubkey: escrowPDA, isWritable: true, isSigner: false },
+          { pubkey: vaultPDA, isWritable: true, isSigner: false },
+          { pubkey: seller.publicKey, isWritable: true, isSigner: false },
+          { pubkey: sellerRequestToken, isWritable: true, isSigner: false },
+          { pubkey: sellerIndexPDA, isWritable: true, isSigner: false },
+          { pubkey: sellerReputationPDA, isWritable: true, isSigner: false }
+        );
+      }
 
-      // Create two different escrows
-      const [escrowPDA1] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
-      const [vaultPDA1] = deriveVaultPDA(escrowPDA1);
+      const initialBuyerRequestBalance = (await getAccount(connection, buyerRequestToken)).amount;
 
-      const [escrowPDA2] = deriveEscrowPDA(seller.publicKey, offerMint2, requestMint2);
-      const [vaultPDA2] = deriveVaultPDA(escrowPDA2);
+      const fillAmount = OFFER_AMOUNT / 4;
+      await program.methods
+        .acceptBest(new anchor.BN(fillAmount), new anchor.BN(REQUEST_AMOUNT / 4))
+        .accounts({
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          pairIndex: pairIndexPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts(remainingAccounts)
+        .signers([buyer])
+        .rpc();
 
-      // Create first escrow
+      const finalBuyerRequestBalance = (await getAccount(connection, buyerRequestToken)).amount;
+      expect(Number(initialBuyerRequestBalance - finalBuyerRequestBalance)).to.equal(REQUEST_AMOUNT / 4);
+
+      const [pricierEscrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 0);
+      const [cheaperEscrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 1);
+      const pricierEscrow = await program.account.escrow.fetch(pricierEscrowPDA);
+      expect(pricierEscrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT / 2);
+      const cheaperEscrow = await program.account.escrow.fetch(cheaperEscrowPDA);
+      expect(cheaperEscrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT / 4);
+    });
+
+    it("Lets a delegate of the buyer's request tokens accept on their behalf", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // Create escrow first
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT))
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
         .accounts({
           seller: seller.publicKey,
+          payer: seller.publicKey,
           offerMint: offerMint,
           requestMint: requestMint,
           sellerOfferToken: sellerOfferToken,
-          escrow: escrowPDA1,
-          vault: vaultPDA1,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .signers([seller])
         .rpc();
 
-      // Create second escrow
+      // The delegate spends the buyer's request tokens but holds none of its own
+      const delegate = Keypair.generate();
+      await airdrop(delegate.publicKey);
+      await approve(connection, buyer, buyerRequestToken, delegate.publicKey, buyer, REQUEST_AMOUNT);
+
+      // Get initial balances
+      const initialBuyerRequestBalance = (await getAccount(connection, buyerRequestToken)).amount;
+      const initialBuyerOfferBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      const initialSellerRequestBalance = (await getAccount(connection, sellerRequestToken)).amount;
+
+      // Accept the escrow as the delegate
       await program.methods
-        .createEscrow(new anchor.BN(OFFER_AMOUNT * 2), new anchor.BN(REQUEST_AMOUNT * 2))
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
         .accounts({
+          buyer: delegate.publicKey,
+          payer: delegate.publicKey,
           seller: seller.publicKey,
-          offerMint: offerMint2,
-          requestMint: requestMint2,
-          sellerOfferToken: sellerOfferToken2,
-          escrow: escrowPDA2,
-          vault: vaultPDA2,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: null,
+          beneficiary: buyer.publicKey,
+          beneficiaryOfferToken: buyerOfferToken,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
           tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
-        .signers([seller])
+        .signers([delegate])
         .rpc();
 
-      // Verify both escrows exist with correct data
-      const escrow1 = await program.account.escrow.fetch(escrowPDA1);
-      const escrow2 = await program.account.escrow.fetch(escrowPDA2);
+      // Verify buyer received offer tokens
+      const finalBuyerOfferBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      expect(Number(finalBuyerOfferBalance) - Number(initialBuyerOfferBalance)).to.equal(OFFER_AMOUNT);
 
-      expect(escrow1.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
-      expect(escrow2.offerAmount.toNumber()).to.equal(OFFER_AMOUNT * 2);
+      // Verify buyer paid request tokens
+      const finalBuyerRequestBalance = (await getAccount(connection, buyerRequestToken)).amount;
+      expect(Number(initialBuyerRequestBalance) - Number(finalBuyerRequestBalance)).to.equal(REQUEST_AMOUNT);
 
-      // Verify vaults have correct balances
-      const vault1Balance = (await getAccount(connection, vaultPDA1)).amount;
-      const vault2Balance = (await getAccount(connection, vaultPDA2)).amount;
+      // Verify seller received request tokens
+      const finalSellerRequestBalance = (await getAccount(connection, sellerRequestToken)).amount;
+      expect(Number(finalSellerRequestBalance) - Number(initialSellerRequestBalance)).to.equal(REQUEST_AMOUNT);
+    });
 
-      expect(Number(vault1Balance)).to.equal(OFFER_AMOUNT);
-      expect(Number(vault2Balance)).to.equal(OFFER_AMOUNT * 2);
+    it("Fails if buyer has insufficient request tokens", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // Create escrow with request amount larger than buyer's balance
+      const largeRequestAmount = INITIAL_BUYER_BALANCE + 1000;
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(largeRequestAmount), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        // Token program will throw insufficient funds error
+        expect(err.message).to.include("insufficient");
+      }
+    });
+
+    it("Rejects a fill priced above the buyer's UI amount limit", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(
+            new anchor.BN(OFFER_AMOUNT),
+            // One base unit under the price, at 9 decimals
+            "0.000000499",
+            null,
+            null,
+            null,
+            null
+          )
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - price above the UI limit");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("RequestUiAmountExceeded");
+      }
+    });
+
+    it("Rejects a fill landing after the buyer's deadline slot", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Already behind the cluster by the time the transaction lands
+      const deadline = (await connection.getSlot()) - 1;
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, new anchor.BN(deadline), null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have failed past the deadline");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("FillDeadlinePassed");
+      }
+    });
+
+    it("Rejects a fill outside the buyer's slippage bounds", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const accept = (maxRequestAmount: number | null, minOfferOut: number | null) =>
+        program.methods
+          .acceptEscrow(
+            new anchor.BN(OFFER_AMOUNT),
+            null,
+            maxRequestAmount === null ? null : new anchor.BN(maxRequestAmount),
+            minOfferOut === null ? null : new anchor.BN(minOfferOut),
+            null,
+            null
+          )
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+
+      for (const [maxRequestAmount, minOfferOut] of [
+        [REQUEST_AMOUNT - 1, null],
+        [null, OFFER_AMOUNT + 1],
+      ]) {
+        try {
+          await accept(maxRequestAmount, minOfferOut);
+          expect.fail("Should have thrown an error - fill outside the bounds");
+        } catch (err: any) {
+          expect(err.error.errorCode.code).to.equal("SlippageExceeded");
+        }
+      }
+
+      await accept(REQUEST_AMOUNT, OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Partially fills an escrow and closes it once fully filled", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const accept = (fillAmount: number) =>
+        program.methods
+          .acceptEscrow(new anchor.BN(fillAmount), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+
+      // Fill 30% of the offer
+      await accept(OFFER_AMOUNT * 0.3);
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT * 0.7);
+      expect(escrow.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT * 0.7);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT * 0.3
+      );
+
+      // Fill the remainder
+      await accept(OFFER_AMOUNT * 0.7);
+
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT
+      );
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Creates missing recipient token accounts on accept", async () => {
+      const newSeller = Keypair.generate();
+      const taker = Keypair.generate();
+      await airdrop(newSeller.publicKey);
+      await airdrop(taker.publicKey);
+
+      const newSellerOfferToken = await createAssociatedTokenAccount(connection, newSeller, offerMint, newSeller.publicKey);
+      await mintTo(connection, seller, offerMint, newSellerOfferToken, seller, OFFER_AMOUNT);
+      const takerRequestToken = await createAssociatedTokenAccount(connection, taker, requestMint, taker.publicKey);
+      await mintTo(connection, buyer, requestMint, takerRequestToken, buyer, REQUEST_AMOUNT);
+
+      // Neither recipient account exists yet
+      const takerOfferToken = await getAssociatedTokenAddress(offerMint, taker.publicKey);
+      const newSellerRequestToken = await getAssociatedTokenAddress(requestMint, newSeller.publicKey);
+
+      const [escrowPDA] = deriveEscrowPDA(newSeller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: newSeller.publicKey,
+          payer: newSeller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: newSellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([newSeller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: taker.publicKey,
+          payer: taker.publicKey,
+          seller: newSeller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: takerRequestToken,
+          buyerOfferToken: takerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: newSellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([taker])
+        .rpc();
+
+      expect(Number((await getAccount(connection, takerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, newSellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+    });
+  });
+
+  describe("cancel_escrow", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Cancels an escrow successfully", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // Get initial balance
+      const initialSellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
+
+      // Create escrow
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Verify tokens are in vault
+      const vaultBalance = (await getAccount(connection, vaultPDA)).amount;
+      expect(Number(vaultBalance)).to.equal(OFFER_AMOUNT);
+
+      // Cancel the escrow
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Verify tokens returned to seller
+      const finalSellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      expect(Number(finalSellerBalance)).to.equal(Number(initialSellerBalance));
+
+      // Verify escrow account is closed
+      try {
+        await program.account.escrow.fetch(escrowPDA);
+        expect.fail("Escrow account should be closed");
+      } catch (err: any) {
+        expect(err.message).to.include("Account does not exist");
+      }
+
+      // Verify vault is closed
+      try {
+        await getAccount(connection, vaultPDA);
+        expect.fail("Vault account should be closed");
+      } catch (err: any) {
+        expect(err.message).to.include("could not find account");
+      }
+    });
+
+    it("Returns a sponsored escrow's rent to its sponsor on cancel", async () => {
+      const sponsor = Keypair.generate();
+      await airdrop(sponsor.publicKey);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: sponsor.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller, sponsor])
+        .rpc();
+
+      const rent =
+        (await connection.getBalance(escrowPDA)) + (await connection.getBalance(vaultPDA));
+      const sponsorBalance = await connection.getBalance(sponsor.publicKey);
+      const cancel = (rentRecipient: PublicKey | null) =>
+        program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+      try {
+        await cancel(null);
+        expect.fail("Should have thrown an error - sponsor not passed");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidRentRecipient");
+      }
+
+      await cancel(sponsor.publicKey);
+
+      expect(await connection.getBalance(sponsor.publicKey)).to.equal(sponsorBalance + rent);
+    });
+
+    it("Hands an escrow's rent to a new rent recipient", async () => {
+      const keeper = Keypair.generate();
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .setRentRecipient(keeper.publicKey)
+          .accounts({ rentRecipient: keeper.publicKey, escrow: escrowPDA })
+          .signers([keeper])
+          .rpc();
+        expect.fail("Should have thrown an error - not the rent recipient");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidRentRecipient");
+      }
+
+      await program.methods
+        .setRentRecipient(keeper.publicKey)
+        .accounts({ rentRecipient: seller.publicKey, escrow: escrowPDA })
+        .signers([seller])
+        .rpc();
+
+      const rent =
+        (await connection.getBalance(escrowPDA)) + (await connection.getBalance(vaultPDA));
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: keeper.publicKey,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(await connection.getBalance(keeper.publicKey)).to.equal(rent);
+    });
+
+    it("Forfeits part of the cancellation bond when cancelling early", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const BOND = LAMPORTS_PER_SOL / 10;
+      const PENALTY_BPS = 5_000;
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      await program.methods
+        .postCancellationBond({
+          lamports: new anchor.BN(BOND),
+          lockedUntil: new anchor.BN(now + 3600),
+          penaltyBps: PENALTY_BPS,
+        })
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const cancel = (treasury: PublicKey | null) =>
+        program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: treasury && configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            treasury,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc({ commitment: "confirmed" });
+
+      // The bond cannot be dodged by leaving out the treasury
+      try {
+        await cancel(null);
+        expect.fail("Should have thrown an error - treasury missing");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MissingTreasuryAccount");
+      }
+
+      const { treasury } = await program.account.config.fetch(configPDA);
+      const signature = await cancel(treasury);
+      const tx = await connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+      const cancelled = [...parser.parseLogs(tx.meta.logMessages)].find(
+        (event) => event.name.toLowerCase() === "escrowcancelled"
+      );
+      expect(cancelled.data.bondPenalty.toNumber()).to.equal((BOND * PENALTY_BPS) / 10_000);
+    });
+
+    it("Keeps a partly filled escrow listed for the minimum duration", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT / 2), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const cancel = (config: PublicKey | null) =>
+        program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            treasury: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+      const setMinListingDuration = (seconds: number) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(seconds),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      await setMinListingDuration(3600);
+      try {
+        // The minimum cannot be dodged by leaving out the config
+        try {
+          await cancel(null);
+          expect.fail("Should have thrown an error - config missing");
+        } catch (err: any) {
+          expect(err.error.errorCode.code).to.equal("MissingConfigAccount");
+        }
+        try {
+          await cancel(configPDA);
+          expect.fail("Should have thrown an error - listing too recent");
+        } catch (err: any) {
+          expect(err.error.errorCode.code).to.equal("ListingTooRecent");
+        }
+      } finally {
+        // The config is shared by every test
+        await setMinListingDuration(0);
+      }
+
+      await cancel(configPDA);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Prevents unauthorized cancellation", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // Create escrow
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Create a token account for buyer to receive "refund" (for test purposes)
+      const buyerOfferTokenForCancel = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        offerMint,
+        buyer.publicKey
+      ).catch(() => buyerOfferToken); // Use existing if already created
+
+      // Try to cancel as buyer (not seller) - should fail
+      try {
+        await program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: buyer.publicKey,
+            seller: buyer.publicKey, // Wrong! Buyer trying to act as seller
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: buyerOfferTokenForCancel,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        // Should fail with unauthorized or constraint error
+        expect(err.toString()).to.satisfy((msg: string) => 
+          msg.includes("Unauthorized") || 
+          msg.includes("ConstraintAddress") ||
+          msg.includes("Error")
+        );
+      }
+
+      // Verify escrow still exists
+      const escrowAccount = await program.account.escrow.fetch(escrowPDA);
+      expect(escrowAccount.seller.toString()).to.equal(seller.publicKey.toString());
+    });
+
+    it("Keeps a cancelled escrow as a record until the seller closes it", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setKeepRecord(true)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // The vault is gone but the escrow remains as a cancelled record
+      const record = await program.account.escrow.fetch(escrowPDA);
+      // Zero-copy escrows store their status as a plain u8 (2 = cancelled)
+      expect(record.status).to.equal(2);
+      expect(record.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
+
+      await program.methods
+        .closeRecord()
+        .accounts({ seller: seller.publicKey, rentRecipient: null, escrow: escrowPDA })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.account.escrow.fetch(escrowPDA);
+        expect.fail("Escrow record should be closed");
+      } catch (err: any) {
+        expect(err.message).to.include("Account does not exist");
+      }
+    });
+
+    it("Refuses to close an open escrow as a record", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .closeRecord()
+          .accounts({ seller: seller.publicKey, rentRecipient: null, escrow: escrowPDA })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow still open");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowStillOpen");
+      }
+    });
+
+    it("Recovers a vault stranded without its escrow", async () => {
+      // A vault left holding tokens at the address of an escrow that no
+      // longer exists
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 7);
+      const vault = getAssociatedTokenAddressSync(offerMint, escrowPDA, true);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          createAssociatedTokenAccountInstruction(provider.wallet.publicKey, vault, escrowPDA, offerMint)
+        )
+      );
+      await transfer(connection, seller, sellerOfferToken, vault, seller, OFFER_AMOUNT);
+
+      const recover = (authority: Keypair) =>
+        program.methods
+          .recoverVault(requestMint, new anchor.BN(7))
+          .accounts({
+            authority: authority.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            vault: vault,
+            sellerOfferToken: sellerOfferToken,
+            config: configPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([authority])
+          .rpc();
+
+      try {
+        await recover(buyer);
+        expect.fail("Should have thrown an error - not the seller");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+
+      await recover(seller);
+
+      const sellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      expect(Number(sellerBalance)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(vault)).to.be.null;
+    });
+  });
+
+  describe("update_escrow", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Updates the terms of an open escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .updateEscrow(new anchor.BN(REQUEST_AMOUNT * 2), null, buyer.publicKey)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT * 2);
+      expect(escrow.designatedBuyer.toString()).to.equal(buyer.publicKey.toString());
+    });
+
+    it("Only allows the seller to update", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .updateEscrow(new anchor.BN(1), null, null)
+          .accounts({
+            authority: buyer.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - not the seller");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+    });
+
+    it("Labels an escrow and rejects labels over the limit", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const label = "OTC deal #1423 with Acme";
+      await program.methods
+        .setLabel(label)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(Buffer.from(escrow.label).toString("utf8").replace(/\0+$/, "")).to.equal(label);
+
+      try {
+        await program.methods
+          .setLabel("x".repeat(65))
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - label too long");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("LabelTooLong");
+      }
+    });
+
+    it("Lets the seller's operator relabel and cancel their escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [sellerOperatorPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_operator"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      const operator = Keypair.generate();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setSellerOperator(operator.publicKey)
+        .accounts({
+          seller: seller.publicKey,
+          sellerOperator: sellerOperatorPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setLabel("managed by desk bot")
+        .accounts({
+          authority: operator.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: sellerOperatorPDA,
+        })
+        .signers([operator])
+        .rpc();
+
+      const initialSellerOfferBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: operator.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: sellerOperatorPDA,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+
+      // The refund still goes to the seller
+      const finalSellerOfferBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      expect(Number(finalSellerOfferBalance) - Number(initialSellerOfferBalance)).to.equal(OFFER_AMOUNT);
+
+      await program.methods
+        .removeSellerOperator()
+        .accounts({ seller: seller.publicKey, sellerOperator: sellerOperatorPDA })
+        .signers([seller])
+        .rpc();
+    });
+
+    it("Points an escrow at its deal terms and clears them", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const uri = "https://example.com/deals/1423.json";
+      await program.methods
+        .setMetadataUri(uri)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      let escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(Buffer.from(escrow.metadataUri).toString("utf8").replace(/\0+$/, "")).to.equal(uri);
+
+      await program.methods
+        .setMetadataUri(null)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.metadataUri.every((byte: number) => byte === 0)).to.be.true;
+    });
+
+    it("Tops up an open escrow at the same price", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT / 2), new anchor.BN(REQUEST_AMOUNT / 2), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .topUpEscrow(new anchor.BN(OFFER_AMOUNT / 2), null)
+        .accounts({
+          authority: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          sellerOperator: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
+      expect(escrow.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, vaultPDA)).amount)).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Replenishes a recurring escrow after it is fully filled", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT / 2), new anchor.BN(REQUEST_AMOUNT / 2), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setRecurring(true)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT / 2), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // Fully filled, the escrow and its vault stay open
+      let escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(0);
+      expect(escrow.requestAmount.toNumber()).to.equal(0);
+
+      await program.methods
+        .replenish()
+        .accounts({
+          authority: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          sellerOperator: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT / 2);
+      expect(escrow.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT / 2);
+      expect(Number((await getAccount(connection, vaultPDA)).amount)).to.equal(OFFER_AMOUNT / 2);
+    });
+
+    it("Withdraws part of an open escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .withdrawFromEscrow(new anchor.BN(OFFER_AMOUNT / 4))
+        .accounts({
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal((OFFER_AMOUNT * 3) / 4);
+      expect(escrow.requestAmount.toNumber()).to.equal(Math.ceil((REQUEST_AMOUNT * 3) / 4));
+      expect(Number((await getAccount(connection, vaultPDA)).amount)).to.equal((OFFER_AMOUNT * 3) / 4);
+    });
+
+    it("Collects extra request legs on accept", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const LEG_AMOUNT = 200;
+
+      const legMint = await createMint(connection, buyer, buyer.publicKey, null, 6);
+      const buyerLegToken = await createAssociatedTokenAccount(connection, buyer, legMint, buyer.publicKey);
+      await mintTo(connection, buyer, legMint, buyerLegToken, buyer, INITIAL_BUYER_BALANCE);
+      const sellerLegToken = await createAssociatedTokenAccount(connection, seller, legMint, seller.publicKey);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setRequestLegs([{ mint: legMint, amount: new anchor.BN(LEG_AMOUNT) }])
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: legMint, isSigner: false, isWritable: false },
+          { pubkey: buyerLegToken, isSigner: false, isWritable: true },
+          { pubkey: sellerLegToken, isSigner: false, isWritable: true },
+          { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+        ])
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerLegToken)).amount)).to.equal(LEG_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Collects lamports requested on top of the request tokens", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const TIP_LAMPORTS = 0.05 * anchor.web3.LAMPORTS_PER_SOL;
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setRequestLamports(new anchor.BN(TIP_LAMPORTS))
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      const sellerLamportsBefore = await connection.getBalance(seller.publicKey);
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // The seller also gets the escrow's rent back as it closes
+      expect(await connection.getBalance(seller.publicKey)).to.be.at.least(sellerLamportsBefore + TIP_LAMPORTS);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Routes partial fills through an intermediate mint across two escrows", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // The second escrow sells a third mint for the first escrow's offer
+      const outputMint = await createMint(connection, seller, seller.publicKey, null, 6);
+      const sellerOutputToken = await createAssociatedTokenAccount(connection, seller, outputMint, seller.publicKey);
+      await mintTo(connection, seller, outputMint, sellerOutputToken, seller, OFFER_AMOUNT);
+      const buyerOutputToken = await createAssociatedTokenAccount(connection, buyer, outputMint, buyer.publicKey);
+      const [secondEscrowPDA] = deriveEscrowPDA(seller.publicKey, outputMint, offerMint);
+      const secondVaultPDA = await deriveVault(secondEscrowPDA, outputMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(OFFER_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: outputMint,
+          requestMint: offerMint,
+          sellerOfferToken: sellerOutputToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: secondEscrowPDA,
+          vault: secondVaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // 400 of the first offer costs 200 and pays for 400 of the second
+      const ROUTED = 400;
+      await program.methods
+        .routeFill(new anchor.BN(ROUTED), new anchor.BN(ROUTED))
+        .accounts({
+          taker: buyer.publicKey,
+          firstEscrow: escrowPDA,
+          firstVault: vaultPDA,
+          firstSeller: seller.publicKey,
+          firstSellerRequestToken: sellerRequestToken,
+          firstMarket: null,
+          firstMarketFeeToken: null,
+          firstFeeVault: null,
+          firstTakerFeeVault: null,
+          firstPairFees: null,
+          firstSellerStats: null,
+          firstTakerStats: null,
+          firstProceedsVault: null,
+          secondEscrow: secondEscrowPDA,
+          secondVault: secondVaultPDA,
+          secondSeller: seller.publicKey,
+          secondSellerRequestToken: sellerOfferToken,
+          secondMarket: null,
+          secondMarketFeeToken: null,
+          secondFeeVault: null,
+          secondTakerFeeVault: null,
+          secondPairFees: null,
+          secondSellerStats: null,
+          secondTakerStats: null,
+          secondProceedsVault: null,
+          inputMint: requestMint,
+          intermediateMint: offerMint,
+          outputMint: outputMint,
+          takerInputToken: buyerRequestToken,
+          takerOutputToken: buyerOutputToken,
+          takerIntermediateToken: null,
+          pairIndex: pairIndexPDA,
+          firstRentRecipient: null,
+          secondRentRecipient: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          inputTokenProgram: TOKEN_PROGRAM_ID,
+          outputTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerOutputToken)).amount)).to.equal(ROUTED);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        (ROUTED * REQUEST_AMOUNT) / OFFER_AMOUNT
+      );
+      const first = await program.account.escrow.fetch(escrowPDA);
+      const second = await program.account.escrow.fetch(secondEscrowPDA);
+      expect(first.offerAmount.toNumber()).to.equal(OFFER_AMOUNT - ROUTED);
+      expect(second.offerAmount.toNumber()).to.equal(OFFER_AMOUNT - ROUTED);
+    });
+
+    it("Settles an A to B to C chain and closes the transient account", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // The second escrow sells a third mint for less than the first escrow's offer
+      const OUTPUT_AMOUNT = 800;
+      const INTERMEDIATE_COST = 600;
+      const outputMint = await createMint(connection, seller, seller.publicKey, null, 6);
+      const sellerOutputToken = await createAssociatedTokenAccount(connection, seller, outputMint, seller.publicKey);
+      await mintTo(connection, seller, outputMint, sellerOutputToken, seller, OUTPUT_AMOUNT);
+      const buyerOutputToken = await createAssociatedTokenAccount(connection, buyer, outputMint, buyer.publicKey);
+      const [secondEscrowPDA] = deriveEscrowPDA(seller.publicKey, outputMint, offerMint);
+      const secondVaultPDA = await deriveVault(secondEscrowPDA, outputMint);
+      const [transientPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("transient"), escrowPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OUTPUT_AMOUNT),
+          new anchor.BN(INTERMEDIATE_COST),
+          null,
+          null,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: outputMint,
+          requestMint: offerMint,
+          sellerOfferToken: sellerOutputToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: secondEscrowPDA,
+          vault: secondVaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const buyerInputBefore = (await getAccount(connection, buyerRequestToken)).amount;
+      const buyerIntermediateBefore = (await getAccount(connection, buyerOfferToken)).amount;
+
+      await program.methods
+        .settleChain()
+        .accounts({
+          taker: buyer.publicKey,
+          firstEscrow: escrowPDA,
+          firstVault: vaultPDA,
+          firstSeller: seller.publicKey,
+          firstSellerRequestToken: sellerRequestToken,
+          firstMarket: null,
+          firstMarketFeeToken: null,
+          firstFeeVault: null,
+          firstTakerFeeVault: null,
+          firstPairFees: null,
+          firstSellerStats: null,
+          firstTakerStats: null,
+          firstProceedsVault: null,
+          secondEscrow: secondEscrowPDA,
+          secondVault: secondVaultPDA,
+          secondSeller: seller.publicKey,
+          secondSellerRequestToken: sellerOfferToken,
+          secondMarket: null,
+          secondMarketFeeToken: null,
+          secondFeeVault: null,
+          secondTakerFeeVault: null,
+          secondPairFees: null,
+          secondSellerStats: null,
+          secondTakerStats: null,
+          secondProceedsVault: null,
+          inputMint: requestMint,
+          intermediateMint: offerMint,
+          outputMint: outputMint,
+          takerInputToken: buyerRequestToken,
+          takerOutputToken: buyerOutputToken,
+          takerIntermediateToken: buyerOfferToken,
+          pairIndex: pairIndexPDA,
+          firstRentRecipient: null,
+          secondRentRecipient: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          inputTokenProgram: TOKEN_PROGRAM_ID,
+          outputTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // The taker paid the first leg and holds the final leg plus the surplus
+      const buyerInputAfter = (await getAccount(connection, buyerRequestToken)).amount;
+      const buyerIntermediateAfter = (await getAccount(connection, buyerOfferToken)).amount;
+      expect(Number(buyerInputBefore - buyerInputAfter)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOutputToken)).amount)).to.equal(OUTPUT_AMOUNT);
+      expect(Number(buyerIntermediateAfter - buyerIntermediateBefore)).to.equal(
+        OFFER_AMOUNT - INTERMEDIATE_COST
+      );
+
+      // Both escrows filled in full and the transient account is gone
+      expect(await connection.getAccountInfo(transientPDA)).to.be.null;
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+      expect(await connection.getAccountInfo(secondEscrowPDA)).to.be.null;
+    });
+
+    it("Requires a price update to accept an oracle-priced escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setOraclePricing({
+          oracle: { pyth: { feedId: Array(32).fill(1) } },
+          usdPrice: new anchor.BN(2),
+          usdExponent: 0,
+          maxAge: 60,
+          maxConfidenceBps: 100,
+          usdTotal: false,
+          minRequestAmount: new anchor.BN(0),
+          maxRequestAmount: new anchor.BN(0),
+          useEma: false,
+        })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have failed without a price update");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MissingOracleAccount");
+      }
+    });
+
+    it("Rejects a USD total price whose request bounds are inverted", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .setOraclePricing({
+            oracle: { pyth: { feedId: Array(32).fill(1) } },
+            usdPrice: new anchor.BN(1_000),
+            usdExponent: 0,
+            maxAge: 60,
+            maxConfidenceBps: 100,
+            usdTotal: true,
+            minRequestAmount: new anchor.BN(REQUEST_AMOUNT),
+            maxRequestAmount: new anchor.BN(REQUEST_AMOUNT / 2),
+            useEma: false,
+          })
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have rejected inverted bounds");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidAmount");
+      }
+    });
+
+    it("Only settles Pyth feeds against their EMA price", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .setOraclePricing({
+            oracle: { switchboard: { feed: Keypair.generate().publicKey } },
+            usdPrice: new anchor.BN(1_000),
+            usdExponent: 0,
+            maxAge: 60,
+            maxConfidenceBps: 100,
+            usdTotal: true,
+            minRequestAmount: new anchor.BN(0),
+            maxRequestAmount: new anchor.BN(0),
+            useEma: true,
+          })
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have rejected an EMA off a Switchboard feed");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidOracle");
+      }
+    });
+
+    it("Fills unit-priced escrows by the unit above the minimum fill", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const MIN_FILL = 200;
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // 3 request tokens per 4 offer tokens
+      await program.methods
+        .setUnitPrice({ price: new anchor.BN(3), unit: new anchor.BN(4), minFillAmount: new anchor.BN(MIN_FILL) })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      const accept = (fillAmount: number) =>
+        program.methods
+          .acceptEscrow(new anchor.BN(fillAmount), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+
+      try {
+        await accept(MIN_FILL - 1);
+        expect.fail("Should have failed below the minimum fill");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("FillBelowMinimum");
+      }
+
+      await accept(400);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(300);
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT - 400);
+      expect(escrow.requestAmount.toNumber()).to.equal(450);
+    });
+
+    it("Hands an escrow to a new seller who can then cancel it", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const newSeller = Keypair.generate();
+      await airdrop(newSeller.publicKey);
+      const newSellerOfferToken = await createAssociatedTokenAccount(
+        connection,
+        newSeller,
+        offerMint,
+        newSeller.publicKey
+      );
+      const sellerIndexPDA = (owner: PublicKey) =>
+        PublicKey.findProgramAddressSync([Buffer.from("seller_index"), owner.toBuffer()], program.programId)[0];
+      const sellerBanPDA = (owner: PublicKey) =>
+        PublicKey.findProgramAddressSync([Buffer.from("seller_ban"), owner.toBuffer()], program.programId)[0];
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .transferEscrowOwnership(newSeller.publicKey)
+        .accounts({ seller: seller.publicKey, escrow: escrowPDA })
+        .signers([seller])
+        .rpc();
+
+      // Only the proposed seller can take the escrow over
+      const acceptOwnership = (signer: Keypair) =>
+        program.methods
+          .acceptEscrowOwnership()
+          .accounts({
+            newSeller: signer.publicKey,
+            escrow: escrowPDA,
+            sellerIndex: sellerIndexPDA(seller.publicKey),
+            newSellerIndex: sellerIndexPDA(signer.publicKey),
+            config: configPDA,
+            sellerBan: sellerBanPDA(signer.publicKey),
+            market: null,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([signer])
+          .rpc();
+      try {
+        await acceptOwnership(buyer);
+        expect.fail("Should have thrown an error - not the pending seller");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("NoPendingSeller");
+      }
+
+      // A banned seller cannot take over a listing they could not create
+      await program.methods
+        .banSeller()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          seller: newSeller.publicKey,
+          sellerBan: sellerBanPDA(newSeller.publicKey),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+      try {
+        await acceptOwnership(newSeller);
+        expect.fail("Should have thrown an error - seller is banned");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("SellerBanned");
+      }
+      await program.methods
+        .unbanSeller()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          sellerBan: sellerBanPDA(newSeller.publicKey),
+        })
+        .rpc();
+      await acceptOwnership(newSeller);
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.seller.equals(newSeller.publicKey)).to.be.true;
+      expect(Number((await getAccount(connection, vaultPDA)).amount)).to.equal(OFFER_AMOUNT);
+
+      // The escrow keeps its address; the new seller now controls it
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: newSeller.publicKey,
+          seller: newSeller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: newSellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          treasury: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([newSeller])
+        .rpc();
+      expect(Number((await getAccount(connection, newSellerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("security tests", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Prevents double-spending (escrow closed after accept)", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // Create escrow
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Accept the escrow
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // Create another buyer to try double-spend
+      const attacker = Keypair.generate();
+      await airdrop(attacker.publicKey);
+
+      const attackerRequestToken = await createAssociatedTokenAccount(
+        connection,
+        attacker,
+        requestMint,
+        attacker.publicKey
+      );
+      const attackerOfferToken = await createAssociatedTokenAccount(
+        connection,
+        attacker,
+        offerMint,
+        attacker.publicKey
+      );
+
+      // Mint some request tokens to attacker
+      await mintTo(
+        connection,
+        buyer, // buyer is the mint authority
+        requestMint,
+        attackerRequestToken,
+        buyer,
+        REQUEST_AMOUNT
+      );
+
+      // Try to accept the same escrow again - should fail
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: attacker.publicKey,
+            payer: attacker.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: attackerRequestToken,
+            buyerOfferToken: attackerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([attacker])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow already closed");
+      } catch (err: any) {
+        // Escrow account should not exist
+        expect(err.message).to.include("Account does not exist");
+      }
+    });
+
+    it("Validates token mint matches escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      // Create escrow
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Create a fake mint
+      const fakeMint = await createMint(
+        connection,
+        buyer,
+        buyer.publicKey,
+        null,
+        9
+      );
+
+      // Try to accept with wrong mint - should fail due to address constraint
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: fakeMint, // Wrong mint!
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        expect(err.toString()).to.satisfy((msg: string) => 
+          msg.includes("InvalidMint") || 
+          msg.includes("ConstraintAddress") ||
+          msg.includes("Error")
+        );
+      }
+    });
+
+    it("Handles multiple escrows by the same seller with different mints", async () => {
+      // Create a second pair of mints
+      const offerMint2 = await createMint(
+        connection,
+        seller,
+        seller.publicKey,
+        null,
+        9
+      );
+      const requestMint2 = await createMint(
+        connection,
+        buyer,
+        buyer.publicKey,
+        null,
+        9
+      );
+
+      // Create token accounts for second pair
+      const sellerOfferToken2 = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        offerMint2,
+        seller.publicKey
+      );
+
+      // Mint tokens
+      await mintTo(
+        connection,
+        seller,
+        offerMint2,
+        sellerOfferToken2,
+        seller,
+        INITIAL_SELLER_BALANCE
+      );
+
+      // Create two different escrows
+      const [escrowPDA1] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA1 = await deriveVault(escrowPDA1, offerMint);
+
+      const [escrowPDA2] = deriveEscrowPDA(seller.publicKey, offerMint2, requestMint2);
+      const vaultPDA2 = await deriveVault(escrowPDA2, offerMint2);
+
+      // Create first escrow
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA1,
+          vault: vaultPDA1,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Create second escrow
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT * 2), new anchor.BN(REQUEST_AMOUNT * 2), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint2,
+          requestMint: requestMint2,
+          sellerOfferToken: sellerOfferToken2,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA2,
+          vault: vaultPDA2,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Verify both escrows exist with correct data
+      const escrow1 = await program.account.escrow.fetch(escrowPDA1);
+      const escrow2 = await program.account.escrow.fetch(escrowPDA2);
+
+      expect(escrow1.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
+      expect(escrow2.offerAmount.toNumber()).to.equal(OFFER_AMOUNT * 2);
+
+      // Verify vaults have correct balances
+      const vault1Balance = (await getAccount(connection, vaultPDA1)).amount;
+      const vault2Balance = (await getAccount(connection, vaultPDA2)).amount;
+
+      expect(Number(vault1Balance)).to.equal(OFFER_AMOUNT);
+      expect(Number(vault2Balance)).to.equal(OFFER_AMOUNT * 2);
+    });
+
+    it("Handles concurrent escrows by the same seller on the same pair", async () => {
+      const [escrowPDA1] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 1);
+      const vaultPDA1 = await deriveVault(escrowPDA1, offerMint);
+
+      const [escrowPDA2] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 2);
+      const vaultPDA2 = await deriveVault(escrowPDA2, offerMint);
+
+      for (const [escrowId, escrowPDA, vaultPDA, requestAmount] of [
+        [1, escrowPDA1, vaultPDA1, REQUEST_AMOUNT],
+        [2, escrowPDA2, vaultPDA2, REQUEST_AMOUNT * 2],
+      ] as [number, PublicKey, PublicKey, number][]) {
+        await program.methods
+          .createEscrow(new anchor.BN(escrowId), new anchor.BN(OFFER_AMOUNT), new anchor.BN(requestAmount), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+      }
+
+      const escrow1 = await program.account.escrow.fetch(escrowPDA1);
+      const escrow2 = await program.account.escrow.fetch(escrowPDA2);
+
+      expect(escrow1.escrowId.toNumber()).to.equal(1);
+      expect(escrow2.escrowId.toNumber()).to.equal(2);
+      expect(escrow2.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT * 2);
+    });
+
+    it("Rejects buyers other than the designated buyer", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const counterparty = Keypair.generate();
+
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OFFER_AMOUNT),
+          new anchor.BN(REQUEST_AMOUNT),
+          null,
+          counterparty.publicKey,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow is private");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("NotDesignatedBuyer");
+      }
+    });
+
+    it("Gates escrows on the seller's buyer allowlist", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [allowlistPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("buyer_allowlist"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+
+      // Approve someone else so the allowlist exists without the buyer
+      await program.methods
+        .addAllowedBuyer(Keypair.generate().publicKey)
+        .accounts({
+          seller: seller.publicKey,
+          buyerAllowlist: allowlistPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: allowlistPDA,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const accept = () =>
+        program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: allowlistPDA,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+
+      try {
+        await accept();
+        expect.fail("Should have thrown an error - buyer not allowlisted");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("BuyerNotAllowed");
+      }
+
+      await program.methods
+        .addAllowedBuyer(buyer.publicKey)
+        .accounts({
+          seller: seller.publicKey,
+          buyerAllowlist: allowlistPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await accept();
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("markets", () => {
+    const MARKET_FEE_BPS = 200;
+
+    function derivePairFeesPDA(marketPubkey: PublicKey): PublicKey {
+      return PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pair_fees"),
+          marketPubkey.toBuffer(),
+          offerMint.toBuffer(),
+          requestMint.toBuffer(),
+        ],
+        program.programId
+      )[0];
+    }
+
+    function deriveTraderStatsPDA(trader: PublicKey): PublicKey {
+      return PublicKey.findProgramAddressSync(
+        [Buffer.from("trader_stats"), trader.toBuffer(), requestMint.toBuffer()],
+        program.programId
+      )[0];
+    }
+
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Routes the market fee to the fee receiver on accept", async () => {
+      const operator = Keypair.generate();
+      const feeReceiver = Keypair.generate();
+      await airdrop(operator.publicKey);
+
+      const brandingId = Array.from(Buffer.alloc(32, 7));
+      const [marketPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("market"), operator.publicKey.toBuffer(), Buffer.from(brandingId)],
+        program.programId
+      );
+
+      await program.methods
+        .createMarket(brandingId, feeReceiver.publicKey, MARKET_FEE_BPS)
+        .accounts({
+          operator: operator.publicKey,
+          market: marketPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+
+      const feeReceiverToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        requestMint,
+        feeReceiver.publicKey
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: marketPDA,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrowAccount = await program.account.escrow.fetch(escrowPDA);
+      expect(escrowAccount.market.toString()).to.equal(marketPDA.toString());
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: marketPDA,
+          marketFeeToken: feeReceiverToken,
+          pairFees: derivePairFeesPDA(marketPDA),
+          sellerStats: deriveTraderStatsPDA(seller.publicKey),
+          buyerStats: deriveTraderStatsPDA(buyer.publicKey),
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const expectedFee = Math.floor((REQUEST_AMOUNT * MARKET_FEE_BPS) / 10000);
+      const feeBalance = (await getAccount(connection, feeReceiverToken)).amount;
+      expect(Number(feeBalance)).to.equal(expectedFee);
+
+      const sellerBalance = (await getAccount(connection, sellerRequestToken)).amount;
+      expect(Number(sellerBalance)).to.equal(REQUEST_AMOUNT - expectedFee);
+    });
+
+    it("Charges the taker and rebates the maker with pair fees", async () => {
+      const operator = Keypair.generate();
+      const feeReceiver = Keypair.generate();
+      await airdrop(operator.publicKey);
+
+      const brandingId = Array.from(Buffer.alloc(32, 8));
+      const [marketPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("market"), operator.publicKey.toBuffer(), Buffer.from(brandingId)],
+        program.programId
+      );
+      const pairFeesPDA = derivePairFeesPDA(marketPDA);
+
+      await program.methods
+        .createMarket(brandingId, feeReceiver.publicKey, MARKET_FEE_BPS)
+        .accounts({
+          operator: operator.publicKey,
+          market: marketPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+
+      // 1% rebate to the maker, 3% charged to the taker
+      await program.methods
+        .setPairFees(-100, 300)
+        .accounts({
+          operator: operator.publicKey,
+          market: marketPDA,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          pairFees: pairFeesPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+
+      const feeReceiverToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        requestMint,
+        feeReceiver.publicKey
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: marketPDA,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: marketPDA,
+          marketFeeToken: feeReceiverToken,
+          pairFees: pairFeesPDA,
+          sellerStats: deriveTraderStatsPDA(seller.publicKey),
+          buyerStats: deriveTraderStatsPDA(buyer.publicKey),
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const rebate = Math.floor((REQUEST_AMOUNT * 100) / 10000);
+      const takerFee = Math.floor((REQUEST_AMOUNT * 300) / 10000);
+
+      const sellerBalance = (await getAccount(connection, sellerRequestToken)).amount;
+      expect(Number(sellerBalance)).to.equal(REQUEST_AMOUNT + rebate);
+
+      const feeBalance = (await getAccount(connection, feeReceiverToken)).amount;
+      expect(Number(feeBalance)).to.equal(takerFee - rebate);
+
+      const buyerBalance = (await getAccount(connection, buyerRequestToken)).amount;
+      expect(Number(buyerBalance)).to.equal(INITIAL_BUYER_BALANCE - REQUEST_AMOUNT - takerFee);
+    });
+  });
+
+  describe("pair index", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Tracks open escrows per mint pair", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      const findPair = async () =>
+        (await program.account.pairIndex.fetch(pairIndexPDA)).pairs.find(
+          (pair) => pair.offerMint.equals(offerMint) && pair.requestMint.equals(requestMint)
+        );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect((await findPair()).openEscrows).to.equal(1);
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(await findPair()).to.be.undefined;
+    });
+
+    it("Tracks protocol-wide totals on the stats account", async () => {
+      const [statsPDA] = PublicKey.findProgramAddressSync([Buffer.from("stats")], program.programId);
+      if ((await connection.getAccountInfo(statsPDA)) === null) {
+        await program.methods
+          .initializeStats()
+          .accounts({
+            payer: provider.wallet.publicKey,
+            stats: statsPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .rpc();
+      }
+      const before = await program.account.stats.fetch(statsPDA);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Accept the escrow
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const after = await program.account.stats.fetch(statsPDA);
+      expect(after.escrowsCreated.sub(before.escrowsCreated).toNumber()).to.equal(1);
+      expect(after.escrowsFilled.sub(before.escrowsFilled).toNumber()).to.equal(1);
+      expect(after.fills.sub(before.fills).toNumber()).to.equal(1);
+      expect(after.volume.sub(before.volume).toNumber()).to.equal(REQUEST_AMOUNT);
+    });
+
+    it("Records fill count, volume and last price on the pair stats", async () => {
+      const [pairStatsPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pair_stats"), offerMint.toBuffer(), requestMint.toBuffer()],
+        program.programId
+      );
+      if ((await connection.getAccountInfo(pairStatsPDA)) === null) {
+        await program.methods
+          .initializePairStats()
+          .accounts({
+            payer: provider.wallet.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            pairStats: pairStatsPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .rpc();
+      }
+      const before = await program.account.pairStats.fetch(pairStatsPDA);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Accept the escrow
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const after = await program.account.pairStats.fetch(pairStatsPDA);
+      expect(after.fills.sub(before.fills).toNumber()).to.equal(1);
+      expect(after.offerVolume.sub(before.offerVolume).toNumber()).to.equal(OFFER_AMOUNT);
+      expect(after.requestVolume.sub(before.requestVolume).toNumber()).to.equal(REQUEST_AMOUNT);
+      expect(after.lastOfferAmount.toNumber()).to.equal(OFFER_AMOUNT);
+      expect(after.lastRequestAmount.toNumber()).to.equal(REQUEST_AMOUNT);
+    });
+
+    it("Counts a seller's escrows on their seller index", async () => {
+      const [sellerIndexPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_index"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      const escrowIds = [0, 5];
+
+      for (const escrowId of escrowIds) {
+        const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, escrowId);
+        const vaultPDA = await deriveVault(escrowPDA, offerMint);
+        await program.methods
+          .createEscrow(new anchor.BN(escrowId), new anchor.BN(OFFER_AMOUNT / 2), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+      }
+
+      let sellerIndex = await program.account.sellerIndex.fetch(sellerIndexPDA);
+      expect(sellerIndex.openEscrows).to.equal(2);
+      // The next free id is past the highest one used
+      expect(sellerIndex.nextEscrowId.toNumber()).to.equal(6);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 5);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      sellerIndex = await program.account.sellerIndex.fetch(sellerIndexPDA);
+      expect(sellerIndex.openEscrows).to.equal(1);
+      expect(sellerIndex.nextEscrowId.toNumber()).to.equal(6);
+    });
+
+    it("Records a seller's trades and cancellations on their reputation", async () => {
+      const [reputationPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reputation"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .initializeReputation()
+        .accounts({
+          payer: provider.wallet.publicKey,
+          seller: seller.publicKey,
+          reputation: reputationPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      let [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      let vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // A second listing is cancelled while open
+      [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 1);
+      vaultPDA = await deriveVault(escrowPDA, offerMint);
+      await program.methods
+        .createEscrow(new anchor.BN(1), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const reputation = await program.account.reputation.fetch(reputationPDA);
+      expect(reputation.completedTrades.toNumber()).to.equal(1);
+      expect(reputation.cancellations.toNumber()).to.equal(1);
+      expect(reputation.disputesLost.toNumber()).to.equal(0);
+      expect(reputation.totalVolume.toNumber()).to.equal(REQUEST_AMOUNT);
+    });
+
+    it("Caps the open escrows per seller", async () => {
+      const [sellerIndexPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_index"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      const { openEscrows } = await program.account.sellerIndex.fetch(sellerIndexPDA);
+
+      const setMaxOpen = (maxOpen: number | null) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: maxOpen,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      await setMaxOpen(openEscrows);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint, 7);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(7), new anchor.BN(OFFER_AMOUNT / 2), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - seller at their open escrow limit");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("TooManyOpenEscrows");
+      } finally {
+        await setMaxOpen(null);
+      }
+    });
+
+    it("Lists open escrows on the offer registry", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [offerRegistryPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("offer_registry")],
+        program.programId
+      );
+
+      if ((await connection.getAccountInfo(offerRegistryPDA)) === null) {
+        await program.methods
+          .initializeOfferRegistry()
+          .accounts({
+            payer: provider.wallet.publicKey,
+            offerRegistry: offerRegistryPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .rpc();
+      }
+      const { pageCount } = await program.account.offerRegistry.fetch(offerRegistryPDA);
+      const pageIndex = Buffer.alloc(4);
+      pageIndex.writeUInt32LE(pageCount);
+      const [registryPagePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("registry_page"), pageIndex],
+        program.programId
+      );
+      await program.methods
+        .addRegistryPage()
+        .accounts({
+          payer: provider.wallet.publicKey,
+          offerRegistry: offerRegistryPDA,
+          registryPage: registryPagePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: registryPagePDA,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      let page = await program.account.registryPage.fetch(registryPagePDA);
+      expect(page.escrows.map((key) => key.toString())).to.deep.equal([escrowPDA.toString()]);
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: registryPagePDA,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      page = await program.account.registryPage.fetch(registryPagePDA);
+      expect(page.escrows).to.be.empty;
+    });
+  });
+
+  describe("receipts", () => {
+    const TOKEN_METADATA_PROGRAM_ID = new PublicKey(
+      "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+    );
+
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Mints a receipt and burns it on cancel", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const [receiptMint] = PublicKey.findProgramAddressSync(
+        [Buffer.from("receipt"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [receiptMetadata] = PublicKey.findProgramAddressSync(
+        [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), receiptMint.toBuffer()],
+        TOKEN_METADATA_PROGRAM_ID
+      );
+      const sellerReceiptToken = await getAssociatedTokenAddress(receiptMint, seller.publicKey);
+      const [proceedsVault] = PublicKey.findProgramAddressSync(
+        [Buffer.from("proceeds"), escrowPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .mintReceipt("Escrow Receipt", "ESCROW", "https://example.com/receipt.json")
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          receiptMint: receiptMint,
+          sellerReceiptToken: sellerReceiptToken,
+          requestMint: requestMint,
+          proceedsVault: proceedsVault,
+          receiptMetadata: receiptMetadata,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerReceiptToken)).amount)).to.equal(1);
+      const escrowAccount = await program.account.escrow.fetch(escrowPDA);
+      expect(escrowAccount.receiptMint.toString()).to.equal(receiptMint.toString());
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: receiptMint,
+          sellerReceiptToken: sellerReceiptToken,
+          proceedsVault: proceedsVault,
+          requestMint: requestMint,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerReceiptToken)).amount)).to.equal(0);
+    });
+
+    it("Lets a new receipt holder redeem the proceeds of a fill", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const [receiptMint] = PublicKey.findProgramAddressSync(
+        [Buffer.from("receipt"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [receiptMetadata] = PublicKey.findProgramAddressSync(
+        [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), receiptMint.toBuffer()],
+        TOKEN_METADATA_PROGRAM_ID
+      );
+      const [proceedsVault] = PublicKey.findProgramAddressSync(
+        [Buffer.from("proceeds"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const sellerReceiptToken = await getAssociatedTokenAddress(receiptMint, seller.publicKey);
+
+      await program.methods
+        .mintReceipt("Escrow Receipt", "ESCROW", "https://example.com/receipt.json")
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          receiptMint: receiptMint,
+          sellerReceiptToken: sellerReceiptToken,
+          requestMint: requestMint,
+          proceedsVault: proceedsVault,
+          receiptMetadata: receiptMetadata,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Sell the position to a new holder
+      const holder = Keypair.generate();
+      await airdrop(holder.publicKey);
+      const holderReceiptToken = await createAssociatedTokenAccount(
+        connection,
+        holder,
+        receiptMint,
+        holder.publicKey
+      );
+      await transfer(connection, seller, sellerReceiptToken, holderReceiptToken, seller, 1);
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: proceedsVault,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, proceedsVault)).amount)).to.equal(REQUEST_AMOUNT);
+
+      const holderOfferToken = await createAssociatedTokenAccount(
+        connection,
+        holder,
+        offerMint,
+        holder.publicKey
+      );
+      const holderRequestToken = await createAssociatedTokenAccount(
+        connection,
+        holder,
+        requestMint,
+        holder.publicKey
+      );
+
+      await program.methods
+        .redeemReceipt()
+        .accounts({
+          holder: holder.publicKey,
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          receiptMint: receiptMint,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          holderReceiptToken: holderReceiptToken,
+          vault: null,
+          proceedsVault: proceedsVault,
+          holderOfferToken: holderOfferToken,
+          holderRequestToken: holderRequestToken,
+          pairIndex: pairIndexPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([holder])
+        .rpc();
+
+      expect(Number((await getAccount(connection, holderRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT
+      );
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("conditions", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Blocks accept until an attested condition is resolved", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const resolver = Keypair.generate();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const deadline = Math.floor(Date.now() / 1000) + 3600;
+      await program.methods
+        .setEscrowCondition({ attested: { resolver: resolver.publicKey } }, new anchor.BN(deadline))
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+        })
+        .signers([seller])
+        .rpc();
+
+      const accept = () =>
+        program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+
+      try {
+        await accept();
+        expect.fail("Should have failed before the condition is met");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("ConditionNotMet");
+      }
+
+      await program.methods
+        .resolveCondition()
+        .accounts({
+          escrow: escrowPDA,
+          priceUpdate: null,
+          resolver: resolver.publicKey,
+        })
+        .signers([resolver])
+        .rpc();
+
+      await accept();
+
+      const buyerOfferBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      expect(Number(buyerOfferBalance)).to.equal(OFFER_AMOUNT);
+    });
+  });
+
+  describe("hashed timelocks", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Releases a hashlocked escrow to its buyer for the preimage", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const preimage = Keypair.generate().publicKey.toBuffer();
+      const hashlock = createHash("sha256").update(preimage).digest();
+      const now = await connection.getBlockTime(await connection.getSlot());
+
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OFFER_AMOUNT),
+          new anchor.BN(REQUEST_AMOUNT),
+          new anchor.BN(now + 3600),
+          buyer.publicKey,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setHashlock([...hashlock])
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      // The seller is bound to the swap until the timelock runs out
+      try {
+        await program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow is hashlocked");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowCommitted");
+      }
+
+      // Anyone holding the preimage claims for the buyer
+      const claimer = Keypair.generate();
+      const airdrop = await connection.requestAirdrop(claimer.publicKey, LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(airdrop);
+      const claim = (secret: Buffer) =>
+        program.methods
+          .claimHashlock([...secret])
+          .accounts({
+            payer: claimer.publicKey,
+            seller: seller.publicKey,
+            buyer: buyer.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            buyerOfferToken: buyerOfferToken,
+            pairIndex: pairIndexPDA,
+            rentRecipient: null,
+            registryPage: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([claimer])
+          .rpc();
+
+      try {
+        await claim(Buffer.alloc(32, 1));
+        expect.fail("Should have thrown an error - wrong preimage");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidPreimage");
+      }
+
+      const initialBuyerBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      await claim(preimage);
+      const finalBuyerBalance = (await getAccount(connection, buyerOfferToken)).amount;
+      expect(Number(finalBuyerBalance - initialBuyerBalance)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("wormhole settlement", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Only settles over Wormhole on a VAA posted by the core bridge", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const chain = 2;
+      const sequence = new anchor.BN(7);
+      const chainSeed = Buffer.alloc(2);
+      chainSeed.writeUInt16LE(chain);
+      const [foreignEmitterPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("foreign_emitter"), chainSeed],
+        program.programId
+      );
+      const [receivedMessagePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("received_message"), chainSeed, sequence.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+
+      await program.methods
+        .setForeignEmitter(chain, [...Keypair.generate().publicKey.toBuffer()])
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          foreignEmitter: foreignEmitterPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setWormholeSettlement(chain)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      // An account the core bridge did not post carries no attestation
+      try {
+        await program.methods
+          .acceptWormholeEscrow(chain, sequence)
+          .accounts({
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            recipient: buyer.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            recipientOfferToken: buyerOfferToken,
+            foreignEmitter: foreignEmitterPDA,
+            postedVaa: configPDA,
+            receivedMessage: receivedMessagePDA,
+            pairIndex: pairIndexPDA,
+            rentRecipient: null,
+            registryPage: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - VAA not posted by the bridge");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidVaa");
+      }
+    });
+
+    it("Holds a reserved escrow for its holder over Wormhole", async () => {
+      const holder = Keypair.generate();
+      await airdrop(holder.publicKey);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [reservationPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reservation"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const chain = 2;
+      const sequence = new anchor.BN(8);
+      const chainSeed = Buffer.alloc(2);
+      chainSeed.writeUInt16LE(chain);
+      const [foreignEmitterPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("foreign_emitter"), chainSeed],
+        program.programId
+      );
+      const [receivedMessagePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("received_message"), chainSeed, sequence.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+
+      await program.methods
+        .setForeignEmitter(chain, [...Keypair.generate().publicKey.toBuffer()])
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          foreignEmitter: foreignEmitterPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setWormholeSettlement(chain)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .reserveEscrow(new anchor.BN(LAMPORTS_PER_SOL / 100), new anchor.BN(3600))
+        .accounts({
+          buyer: holder.publicKey,
+          escrow: escrowPDA,
+          reservation: reservationPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([holder])
+        .rpc();
+
+      // Only the holder may be paid out while the reservation stands
+      try {
+        await program.methods
+          .acceptWormholeEscrow(chain, sequence)
+          .accounts({
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            recipient: buyer.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            recipientOfferToken: buyerOfferToken,
+            foreignEmitter: foreignEmitterPDA,
+            postedVaa: configPDA,
+            receivedMessage: receivedMessagePDA,
+            pairIndex: pairIndexPDA,
+            rentRecipient: null,
+            registryPage: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow is reserved");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowReserved");
+      }
+    });
+
+    it("Keeps allowlisted escrows off Wormhole settlement", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [allowlistPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("buyer_allowlist"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .addAllowedBuyer(buyer.publicKey)
+        .accounts({
+          seller: seller.publicKey,
+          buyerAllowlist: allowlistPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: allowlistPDA,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Nothing on this chain checks the recipient against the allowlist
+      try {
+        await program.methods
+          .setWormholeSettlement(2)
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow is allowlisted");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("GatedEscrow");
+      }
+    });
+  });
+
+  describe("covered options", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Sells the exclusive right to take an escrow for a premium", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const premium = 50;
+      const now = await connection.getBlockTime(await connection.getSlot());
+
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OFFER_AMOUNT),
+          new anchor.BN(REQUEST_AMOUNT),
+          new anchor.BN(now + 3600),
+          null,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setOptionPremium(new anchor.BN(premium))
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .buyOption()
+        .accounts({
+          buyer: buyer.publicKey,
+          seller: seller.publicKey,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(premium);
+
+      // The seller is bound to the holder's right until expiry
+      try {
+        await program.methods
+          .updateEscrow(new anchor.BN(REQUEST_AMOUNT * 2), new anchor.BN(now + 3600), null)
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - option is held");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowCommitted");
+      }
+
+      // Nor can a top-up re-price it
+      try {
+        await program.methods
+          .topUpEscrow(new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT * 4))
+          .accounts({
+            authority: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            sellerOperator: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - option is held");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowCommitted");
+      }
+
+      // The holder exercises at the listed price
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        premium + REQUEST_AMOUNT
+      );
+    });
+  });
+
+  describe("collateralized loans", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Lends against an escrow and returns the collateral on repayment", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [loanPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("loan"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [sellerIndexPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_index"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setLoanTerms({ repaymentAmount: new anchor.BN(REQUEST_AMOUNT), duration: new anchor.BN(3600) })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .fundLoan()
+        .accounts({
+          lender: buyer.publicKey,
+          seller: seller.publicKey,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          loan: loanPDA,
+          lenderRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          pairIndex: pairIndexPDA,
+          sellerIndex: sellerIndexPDA,
+          registryPage: null,
+          config: configPDA,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+
+      // The lender cannot seize the collateral before the loan falls due
+      try {
+        await program.methods
+          .liquidateLoan()
+          .accounts({
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            lender: buyer.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            loan: loanPDA,
+            vault: vaultPDA,
+            lenderOfferToken: buyerOfferToken,
+            rentRecipient: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - loan not due");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("LoanNotDue");
+      }
+
+      const collateralBefore = Number((await getAccount(connection, sellerOfferToken)).amount);
+      await program.methods
+        .repayLoan()
+        .accounts({
+          seller: seller.publicKey,
+          lender: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          loan: loanPDA,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          sellerRequestToken: sellerRequestToken,
+          lenderRequestToken: buyerRequestToken,
+          rentRecipient: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([seller])
+        .rpc();
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(
+        collateralBefore + OFFER_AMOUNT
+      );
+      expect(await connection.getAccountInfo(loanPDA)).to.be.null;
+    });
+  });
+
+  describe("installments", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Sells an escrow over installments, releasing the offer as they are paid", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [planPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("payment_plan"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [planVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("payment_plan_vault"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [sellerIndexPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_index"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setInstallmentTerms({ installments: 2, interval: new anchor.BN(3600), releaseAtEnd: false, forfeitBps: 0 })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      const buyerOfferBefore = Number((await getAccount(connection, buyerOfferToken)).amount);
+      await program.methods
+        .startPaymentPlan()
+        .accounts({
+          buyer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          paymentPlan: planPDA,
+          planVault: planVaultPDA,
+          vault: vaultPDA,
+          buyerOfferToken: buyerOfferToken,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          pairIndex: pairIndexPDA,
+          sellerIndex: sellerIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(
+        buyerOfferBefore + OFFER_AMOUNT / 2
+      );
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT / 2);
+
+      // The seller cannot reclaim while the plan is current
+      try {
+        await program.methods
+          .reclaimPaymentPlan()
+          .accounts({
+            seller: seller.publicKey,
+            buyer: buyer.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            paymentPlan: planPDA,
+            planVault: planVaultPDA,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            sellerRequestToken: sellerRequestToken,
+            buyerRequestToken: buyerRequestToken,
+            rentRecipient: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - installment not overdue");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InstallmentNotOverdue");
+      }
+
+      await program.methods
+        .payInstallment()
+        .accounts({
+          buyer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          paymentPlan: planPDA,
+          planVault: planVaultPDA,
+          vault: vaultPDA,
+          buyerOfferToken: buyerOfferToken,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          rentRecipient: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(
+        buyerOfferBefore + OFFER_AMOUNT
+      );
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(await connection.getAccountInfo(planPDA)).to.be.null;
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("session keys", () => {
+    const SESSION_ACCEPT = 1;
+    const SESSION_CANCEL = 2;
+
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    async function createSession(owner: Keypair, permissions: number, spendCap: number) {
+      const sessionKey = Keypair.generate();
+      await airdrop(sessionKey.publicKey);
+      const [sessionPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("session"), owner.publicKey.toBuffer(), sessionKey.publicKey.toBuffer()],
+        program.programId
+      );
+      const expiresAt = Math.floor(Date.now() / 1000) + 3600;
+      await program.methods
+        .createSession(sessionKey.publicKey, new anchor.BN(expiresAt), permissions, new anchor.BN(spendCap))
+        .accounts({
+          owner: owner.publicKey,
+          session: sessionPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+      return { sessionKey, sessionPDA };
+    }
+
+    it("Lets a session key accept for its owner within the spend cap", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const { sessionKey, sessionPDA } = await createSession(buyer, SESSION_ACCEPT, REQUEST_AMOUNT);
+      // The session account spends the buyer's request tokens as their delegate
+      await approve(connection, buyer, buyerRequestToken, sessionPDA, buyer, REQUEST_AMOUNT);
+
+      const accept = (fillAmount: number) =>
+        program.methods
+          .acceptEscrow(new anchor.BN(fillAmount), null, null, null, null, null)
+          .accounts({
+            buyer: sessionKey.publicKey,
+            payer: sessionKey.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: null,
+            beneficiary: buyer.publicKey,
+            beneficiaryOfferToken: buyerOfferToken,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: sessionPDA,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([sessionKey])
+          .rpc();
+
+      await accept(OFFER_AMOUNT / 2);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT / 2);
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(
+        INITIAL_BUYER_BALANCE - REQUEST_AMOUNT / 2
+      );
+      const session = await program.account.sessionAuthority.fetch(sessionPDA);
+      expect(session.spendRemaining.toNumber()).to.equal(REQUEST_AMOUNT / 2);
+
+      // Revoking the session stops the key accepting any more
+      await program.methods
+        .revokeSession()
+        .accounts({ authority: buyer.publicKey, owner: buyer.publicKey, session: sessionPDA })
+        .signers([buyer])
+        .rpc();
+      try {
+        await accept(OFFER_AMOUNT / 2);
+        expect.fail("Should have thrown an error");
+      } catch (err) {
+        expect(err.toString()).to.include("AccountNotInitialized");
+      }
+    });
+
+    it("Lets a session key cancel its owner's escrows", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const { sessionKey, sessionPDA } = await createSession(seller, SESSION_CANCEL, 0);
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: sessionKey.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: sessionPDA,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([sessionKey])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("transaction guard", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Rejects a transaction that fills the same escrow twice", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const acceptHalf = () =>
+        program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT / 2), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .instruction();
+
+      try {
+        await anchor.web3.sendAndConfirmTransaction(
+          connection,
+          new anchor.web3.Transaction().add(await acceptHalf(), await acceptHalf()),
+          [buyer]
+        );
+        expect.fail("Should have thrown an error - escrow filled twice");
+      } catch (err: any) {
+        expect(err.logs.join("\n")).to.include("DuplicateEscrowInstruction");
+      }
+    });
+  });
+
+  describe("offer recipient", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Delivers the offer to a token account of the buyer's choosing", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const treasury = Keypair.generate();
+      const treasuryOfferToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        offerMint,
+        treasury.publicKey
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: null,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: treasuryOfferToken,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, treasuryOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("proceeds recipient", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Pays the proceeds to the seller's chosen recipient", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const treasury = Keypair.generate();
+      const treasuryRequestToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        requestMint,
+        treasury.publicKey
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setProceedsRecipient(treasury.publicKey)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: null,
+          proceedsRecipientToken: treasuryRequestToken,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, treasuryRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(0);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("commit-reveal fills", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Settles only a revealed commitment once the reveal delay has passed", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [fillCommitmentPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("fill_commitment"), escrowPDA.toBuffer(), buyer.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setRevealDelay(new anchor.BN(600))
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have required a fill commitment");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MissingFillCommitment");
+      }
+
+      const salt = Keypair.generate().publicKey.toBuffer();
+      const commitment = createHash("sha256")
+        .update(Buffer.concat([new anchor.BN(OFFER_AMOUNT).toArrayLike(Buffer, "le", 8), salt]))
+        .digest();
+      await program.methods
+        .commitFill(Array.from(commitment))
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: escrowPDA,
+          fillCommitment: fillCommitmentPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      await program.methods
+        .revealFill(new anchor.BN(OFFER_AMOUNT), Array.from(salt))
+        .accounts({ buyer: buyer.publicKey, fillCommitment: fillCommitmentPDA })
+        .signers([buyer])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: fillCommitmentPDA,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have waited out the reveal delay");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("CommitmentTooRecent");
+      }
+      expect((await program.account.fillCommitment.fetch(fillCommitmentPDA)).fillAmount.toNumber()).to.equal(
+        OFFER_AMOUNT
+      );
+    });
+  });
+
+  describe("insurance pool", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Pays claims on covered escrows up to their coverage", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [poolPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("insurance_pool"), requestMint.toBuffer()],
+        program.programId
+      );
+      const [poolVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("insurance_vault"), requestMint.toBuffer()],
+        program.programId
+      );
+      const [coveragePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("coverage"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const premium = REQUEST_AMOUNT / 100;
+      await mintTo(connection, buyer, requestMint, sellerRequestToken, buyer, premium);
+
+      await program.methods
+        .createInsurancePool(provider.wallet.publicKey, 100)
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mint: requestMint,
+          pool: poolPDA,
+          poolVault: poolVaultPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .insureEscrow()
+        .accounts({
+          payer: seller.publicKey,
+          escrow: escrowPDA,
+          mint: requestMint,
+          pool: poolPDA,
+          poolVault: poolVaultPDA,
+          payerToken: sellerRequestToken,
+          coverage: coveragePDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+      expect(Number((await getAccount(connection, poolVaultPDA)).amount)).to.equal(premium);
+
+      const claim = (amount: number) =>
+        program.methods
+          .payInsuranceClaim(new anchor.BN(amount))
+          .accounts({
+            claimsAuthority: provider.wallet.publicKey,
+            mint: requestMint,
+            pool: poolPDA,
+            poolVault: poolVaultPDA,
+            coverage: coveragePDA,
+            claimantToken: buyerRequestToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+      const buyerBalance = Number((await getAccount(connection, buyerRequestToken)).amount);
+      await claim(premium);
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(buyerBalance + premium);
+
+      try {
+        await claim(REQUEST_AMOUNT - premium + 1);
+        expect.fail("Should have capped claims at the coverage");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("CoverageExceeded");
+      }
+    });
+  });
+
+  describe("market index", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Links escrows into their pair's index until they close", async () => {
+      const derivePage = (page: number) =>
+        PublicKey.findProgramAddressSync(
+          [
+            Buffer.from("market_index"),
+            offerMint.toBuffer(),
+            requestMint.toBuffer(),
+            new anchor.BN(page).toArrayLike(Buffer, "le", 4),
+          ],
+          program.programId
+        )[0];
+      const initializePage = (page: number, previousPage: PublicKey | null) =>
+        program.methods
+          .initializeMarketIndex(page)
+          .accounts({
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            previousPage,
+            marketIndex: derivePage(page),
+          })
+          .signers([seller])
+          .rpc();
+
+      try {
+        await initializePage(1, null);
+        expect.fail("Should have required page zero first");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("InvalidMarketIndex");
+      }
+      await initializePage(0, null);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: derivePage(0),
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      let page = await program.account.marketIndex.fetch(derivePage(0));
+      expect(page.escrows.map((escrow) => escrow.toString())).to.deep.equal([escrowPDA.toString()]);
+      const escrowAccount = await program.account.escrow.fetch(escrowPDA);
+      expect(escrowAccount.marketIndex.toString()).to.equal(derivePage(0).toString());
+
+      const cancel = (marketIndex: PublicKey | null) =>
+        program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+      try {
+        await cancel(null);
+        expect.fail("Should have required the market index page");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MissingMarketIndexAccount");
+      }
+      await cancel(derivePage(0));
+
+      page = await program.account.marketIndex.fetch(derivePage(0));
+      expect(page.escrows).to.be.empty;
+    });
+  });
+
+  describe("activity log", () => {
+    const COMPRESSION_PROGRAM_ID = new PublicKey("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+    const NOOP_PROGRAM_ID = new PublicKey("noopb9bkMVfRPU8ShW8aGLs1UyUSHCLMgxDCmCdrtYe");
+
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Appends a leaf for every listing and cancellation once enabled", async () => {
+      // Header, then the depth-3 tree's sequence number, active index,
+      // buffer size, 8 change logs and rightmost path
+      const tree = Keypair.generate();
+      const treeSize = 56 + 24 + 8 * (40 + 32 * 3) + (40 + 32 * 3);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: provider.wallet.publicKey,
+            newAccountPubkey: tree.publicKey,
+            lamports: await connection.getMinimumBalanceForRentExemption(treeSize),
+            space: treeSize,
+            programId: COMPRESSION_PROGRAM_ID,
+          })
+        ),
+        [tree]
+      );
+      const sequenceNumber = async () =>
+        (await connection.getAccountInfo(tree.publicKey)).data.readBigUInt64LE(56);
+
+      await program.methods
+        .initializeActivityLog(3, 8)
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          merkleTree: tree.publicKey,
+          logWrapper: NOOP_PROGRAM_ID,
+          compressionProgram: COMPRESSION_PROGRAM_ID,
+        })
+        .rpc();
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const create = (logged: boolean) =>
+        program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: logged ? tree.publicKey : null,
+            logWrapper: logged ? NOOP_PROGRAM_ID : null,
+            compressionProgram: logged ? COMPRESSION_PROGRAM_ID : null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+      try {
+        try {
+          await create(false);
+          expect.fail("Should have required the activity log");
+        } catch (err: any) {
+          expect(err.error.errorCode.code).to.equal("MissingActivityLogAccount");
+        }
+
+        await create(true);
+        expect(await sequenceNumber()).to.equal(BigInt(1));
+
+        await program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            treasury: null,
+            activityLog: tree.publicKey,
+            logWrapper: NOOP_PROGRAM_ID,
+            compressionProgram: COMPRESSION_PROGRAM_ID,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect(await sequenceNumber()).to.equal(BigInt(2));
+      } finally {
+        await program.methods
+          .stopActivityLog()
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+    });
+  });
+
+  describe("migration", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Rejects export by anyone but the upgrade authority", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const targetProgram = TOKEN_PROGRAM_ID;
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const [programData] = PublicKey.findProgramAddressSync(
+        [program.programId.toBuffer()],
+        new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+      );
+      const [importAuthority] = PublicKey.findProgramAddressSync(
+        [Buffer.from("import"), escrowPDA.toBuffer()],
+        targetProgram
+      );
+      const [holding] = PublicKey.findProgramAddressSync(
+        [Buffer.from("migration"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [migrationRecord] = PublicKey.findProgramAddressSync(
+        [Buffer.from("migration_record"), escrowPDA.toBuffer()],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .exportEscrow()
+          .accounts({
+            admin: seller.publicKey,
+            program: program.programId,
+            programData: programData,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            targetProgram: targetProgram,
+            importAuthority: importAuthority,
+            holding: holding,
+            migrationRecord: migrationRecord,
+            pairIndex: pairIndexPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have failed for a non-admin signer");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+    });
+
+    it("Holds the offer in the escrow's associated token account", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vault = getAssociatedTokenAddressSync(offerMint, escrowPDA, true, TOKEN_PROGRAM_ID);
+
+      // Anyone can open the vault ahead of the escrow without blocking it
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          createAssociatedTokenAccountInstruction(provider.wallet.publicKey, vault, escrowPDA, offerMint)
+        )
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const vaultAccount = await getAccount(connection, vault);
+      expect(vaultAccount.owner.toBase58()).to.equal(escrowPDA.toBase58());
+      expect(Number(vaultAccount.amount)).to.equal(OFFER_AMOUNT);
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+    });
+
+    it("Stamps new escrows with the current layout version", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.version).to.equal(18);
+
+      try {
+        await program.methods
+          .migrateEscrow()
+          .accounts({
+            payer: buyer.publicKey,
+            escrow: escrowPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow already current");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowUpToDate");
+      }
+    });
+  });
+
+  describe("expiry", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Lets anyone refund an escrow after it expires", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      const expiresAt = now + 2;
+
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OFFER_AMOUNT),
+          new anchor.BN(REQUEST_AMOUNT),
+          new anchor.BN(expiresAt),
+          null,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const expire = () =>
+        program.methods
+          .expireEscrow()
+          .accounts({
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            cranker: null,
+            authority: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+      try {
+        await expire();
+        expect.fail("Should have failed before expiry");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowNotExpired");
+      }
+
+      while ((await connection.getBlockTime(await connection.getSlot())) <= expiresAt) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+
+      await expire();
+
+      const sellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      expect(Number(sellerBalance)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Ticks an escrow as a no-op until it expires, then expires it", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      const expiresAt = now + 2;
+
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OFFER_AMOUNT),
+          new anchor.BN(REQUEST_AMOUNT),
+          new anchor.BN(expiresAt),
+          null,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const tick = () =>
+        program.methods
+          .tick()
+          .accounts({
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            cranker: null,
+            authority: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+      await tick();
+      expect(await connection.getAccountInfo(escrowPDA)).to.not.be.null;
+
+      while ((await connection.getBlockTime(await connection.getSlot())) <= expiresAt) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+
+      await tick();
+
+      const sellerBalance = (await getAccount(connection, sellerOfferToken)).amount;
+      expect(Number(sellerBalance)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Rejects fills before a scheduled start", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      await program.methods
+        .setStartsAt(new anchor.BN(now + 3600))
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have failed before the start");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowNotStarted");
+      }
+    });
+
+    it("Pays the crank bounty for cleaning up an expired escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const BOUNTY = 100_000;
+      const cranker = Keypair.generate();
+      await airdrop(cranker.publicKey);
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      const expiresAt = now + 2;
+      await program.methods
+        .createEscrow(
+          new anchor.BN(0),
+          new anchor.BN(OFFER_AMOUNT),
+          new anchor.BN(REQUEST_AMOUNT),
+          new anchor.BN(expiresAt),
+          null,
+          null
+        )
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      while ((await connection.getBlockTime(await connection.getSlot())) <= expiresAt) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+
+      const setBounty = (bounty: number) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(bounty),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      await setBounty(BOUNTY);
+      const crankerBalance = await connection.getBalance(cranker.publicKey);
+      try {
+        await program.methods
+          .crankExpired()
+          .accounts({
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            cranker: cranker.publicKey,
+            authority: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([cranker])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await setBounty(0);
+      }
+
+      expect(await connection.getBalance(cranker.publicKey)).to.equal(crankerBalance + BOUNTY);
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Pays the seller's crank tip to whoever cranks the expired escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const TIP = 50_000;
+      const cranker = Keypair.generate();
+      await airdrop(cranker.publicKey);
+
+      const setTip = (tip: number) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(tip),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      const expiresAt = now + 2;
+      await setTip(TIP);
+      try {
+        await program.methods
+          .createEscrow(
+            new anchor.BN(0),
+            new anchor.BN(OFFER_AMOUNT),
+            new anchor.BN(REQUEST_AMOUNT),
+            new anchor.BN(expiresAt),
+            null,
+            null
+          )
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+      } finally {
+        // The config is shared by every test; the tip stays on the escrow
+        await setTip(0);
+      }
+
+      while ((await connection.getBlockTime(await connection.getSlot())) <= expiresAt) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+
+      const crankerBalance = await connection.getBalance(cranker.publicKey);
+      await program.methods
+        .crankExpired()
+        .accounts({
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerOfferToken,
+          pairIndex: pairIndexPDA,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          cranker: cranker.publicKey,
+          authority: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([cranker])
+        .rpc();
+
+      expect(await connection.getBalance(cranker.publicKey)).to.equal(crankerBalance + TIP);
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Lets only the config authority cancel an escrow back to the seller", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const adminCancel = (authority: Keypair | null) =>
+        program.methods
+          .adminCancelEscrow()
+          .accounts({
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            cranker: null,
+            authority: authority ? authority.publicKey : provider.wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers(authority ? [authority] : [])
+          .rpc();
+
+      try {
+        await adminCancel(buyer);
+        expect.fail("Should have thrown an error - not the config authority");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+
+      await adminCancel(null);
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+  });
+
+  describe("token-2022", () => {
+    const TRANSFER_FEE_BPS = 100;
+
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    async function createTransferFeeMint(authority: Keypair): Promise<PublicKey> {
+      const mint = Keypair.generate();
+      const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+      const lamports = await connection.getMinimumBalanceForRentExemption(mintLen);
+      const tx = new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: authority.publicKey,
+          newAccountPubkey: mint.publicKey,
+          space: mintLen,
+          lamports,
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferFeeConfigInstruction(
+          mint.publicKey,
+          authority.publicKey,
+          authority.publicKey,
+          TRANSFER_FEE_BPS,
+          BigInt(1_000_000),
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(mint.publicKey, 9, authority.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      );
+      await anchor.web3.sendAndConfirmTransaction(connection, tx, [authority, mint]);
+      return mint.publicKey;
+    }
+
+    async function createTransferHookMint(authority: Keypair): Promise<PublicKey> {
+      const mint = Keypair.generate();
+      const mintLen = getMintLen([ExtensionType.TransferHook]);
+      const lamports = await connection.getMinimumBalanceForRentExemption(mintLen);
+      const tx = new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: authority.publicKey,
+          newAccountPubkey: mint.publicKey,
+          space: mintLen,
+          lamports,
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferHookInstruction(
+          mint.publicKey,
+          authority.publicKey,
+          mockTransferHook.programId,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(mint.publicKey, 9, authority.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      );
+      await anchor.web3.sendAndConfirmTransaction(connection, tx, [authority, mint]);
+
+      await mockTransferHook.methods
+        .initializeExtraAccountMetaList()
+        .accounts({
+          payer: authority.publicKey,
+          mint: mint.publicKey,
+          extraAccountMetaList: deriveHookPDA("extra-account-metas", mint.publicKey),
+          counter: deriveHookPDA("counter", mint.publicKey),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      return mint.publicKey;
+    }
+
+    function deriveHookPDA(seed: string, mint: PublicKey): PublicKey {
+      return PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), mint.toBuffer()],
+        mockTransferHook.programId
+      )[0];
+    }
+
+    // The extra accounts a transfer of a hooked mint resolves
+    function hookAccounts(mint: PublicKey): anchor.web3.AccountMeta[] {
+      return [
+        { pubkey: mockTransferHook.programId, isSigner: false, isWritable: false },
+        { pubkey: deriveHookPDA("extra-account-metas", mint), isSigner: false, isWritable: false },
+        { pubkey: deriveHookPDA("counter", mint), isSigner: false, isWritable: true },
+      ];
+    }
+
+    it("Records the net offer and settles transfer-fee mints", async () => {
+      const feeMint = await createTransferFeeMint(seller);
+      const sellerFeeToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        feeMint,
+        seller.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const buyerFeeToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        feeMint,
+        buyer.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        seller,
+        feeMint,
+        sellerFeeToken,
+        seller,
+        INITIAL_SELLER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, feeMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, feeMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: feeMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerFeeToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // The fee on the way into the vault comes out of the offer
+      const netOffer = OFFER_AMOUNT - (OFFER_AMOUNT * TRANSFER_FEE_BPS) / 10_000;
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(netOffer);
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(netOffer), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: feeMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerFeeToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const buyerFeeBalance = (
+        await getAccount(connection, buyerFeeToken, undefined, TOKEN_2022_PROGRAM_ID)
+      ).amount;
+      expect(Number(buyerFeeBalance)).to.equal(netOffer - Math.ceil((netOffer * TRANSFER_FEE_BPS) / 10_000));
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+
+      // The vault closes even though it held withheld fees
+      expect(await connection.getAccountInfo(vaultPDA)).to.be.null;
+    });
+
+    it("Settles an SPL Token offer against a Token-2022 request", async () => {
+      const feeMint = await createTransferFeeMint(buyer);
+      const buyerFeeToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        feeMint,
+        buyer.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        buyer,
+        feeMint,
+        buyerFeeToken,
+        buyer,
+        INITIAL_BUYER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      // Created by the fill under the request mint's program
+      const sellerFeeToken = await getAssociatedTokenAddress(
+        feeMint,
+        seller.publicKey,
+        false,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, feeMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: feeMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: feeMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerFeeToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerFeeToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // The buyer covers the request mint's transfer fee
+      const sellerFeeBalance = (
+        await getAccount(connection, sellerFeeToken, undefined, TOKEN_2022_PROGRAM_ID)
+      ).amount;
+      expect(Number(sellerFeeBalance)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Resolves transfer-hook accounts on both legs", async () => {
+      const hookedOfferMint = await createTransferHookMint(seller);
+      const hookedRequestMint = await createTransferHookMint(buyer);
+
+      const sellerHookedOfferToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        hookedOfferMint,
+        seller.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const buyerHookedOfferToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        hookedOfferMint,
+        buyer.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const buyerHookedRequestToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        hookedRequestMint,
+        buyer.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const sellerHookedRequestToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        hookedRequestMint,
+        seller.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        seller,
+        hookedOfferMint,
+        sellerHookedOfferToken,
+        seller,
+        INITIAL_SELLER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        buyer,
+        hookedRequestMint,
+        buyerHookedRequestToken,
+        buyer,
+        INITIAL_BUYER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, hookedOfferMint, hookedRequestMint);
+      const vaultPDA = await deriveVault(escrowPDA, hookedOfferMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: hookedOfferMint,
+          requestMint: hookedRequestMint,
+          sellerOfferToken: sellerHookedOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts(hookAccounts(hookedOfferMint))
+        .signers([seller])
+        .rpc();
+
+      // Half the offer, with both mints' hook accounts in one list
+      const FILL_AMOUNT = OFFER_AMOUNT / 2;
+      await program.methods
+        .acceptEscrow(new anchor.BN(FILL_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: hookedOfferMint,
+          requestMint: hookedRequestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerHookedRequestToken,
+          buyerOfferToken: buyerHookedOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerHookedRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          requestTokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts([...hookAccounts(hookedOfferMint), ...hookAccounts(hookedRequestMint)])
+        .signers([buyer])
+        .rpc();
+
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: hookedOfferMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerHookedOfferToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts(hookAccounts(hookedOfferMint))
+        .signers([seller])
+        .rpc();
+
+      expect(
+        Number((await getAccount(connection, buyerHookedOfferToken, undefined, TOKEN_2022_PROGRAM_ID)).amount)
+      ).to.equal(FILL_AMOUNT);
+      expect(
+        Number((await getAccount(connection, sellerHookedRequestToken, undefined, TOKEN_2022_PROGRAM_ID)).amount)
+      ).to.equal(REQUEST_AMOUNT / 2);
+      expect(
+        Number((await getAccount(connection, sellerHookedOfferToken, undefined, TOKEN_2022_PROGRAM_ID)).amount)
+      ).to.equal(INITIAL_SELLER_BALANCE - FILL_AMOUNT);
+
+      // Deposit, fill and refund of the offer; payment of the request
+      const offerCounter = await mockTransferHook.account.counter.fetch(deriveHookPDA("counter", hookedOfferMint));
+      const requestCounter = await mockTransferHook.account.counter.fetch(deriveHookPDA("counter", hookedRequestMint));
+      expect(offerCounter.transfers.toNumber()).to.equal(3);
+      expect(requestCounter.transfers.toNumber()).to.equal(1);
+    });
+
+    it("Fills exactly the offer the buyer asks to receive", async () => {
+      const feeMint = await createTransferFeeMint(seller);
+      const sellerFeeToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        feeMint,
+        seller.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const buyerFeeToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        feeMint,
+        buyer.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        seller,
+        feeMint,
+        sellerFeeToken,
+        seller,
+        INITIAL_SELLER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, feeMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, feeMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: feeMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerFeeToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Enough of the offer is released to cover its transfer fee, and
+      // priced rounding up
+      const netOffer = OFFER_AMOUNT - (OFFER_AMOUNT * TRANSFER_FEE_BPS) / 10_000;
+      const offerOut = netOffer / 2;
+      const released = Math.ceil((offerOut * 10_000) / (10_000 - TRANSFER_FEE_BPS));
+      await program.methods
+        .acceptEscrowExactOut(new anchor.BN(offerOut), null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: feeMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerFeeToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const buyerFeeBalance = (
+        await getAccount(connection, buyerFeeToken, undefined, TOKEN_2022_PROGRAM_ID)
+      ).amount;
+      expect(Number(buyerFeeBalance)).to.equal(offerOut);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        Math.ceil((released * REQUEST_AMOUNT) / netOffer)
+      );
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.offerAmount.toNumber()).to.equal(netOffer - released);
+    });
+
+    it("Rejects permanent-delegate offer mints when configured to", async () => {
+      const mint = Keypair.generate();
+      const mintLen = getMintLen([ExtensionType.PermanentDelegate]);
+      const lamports = await connection.getMinimumBalanceForRentExemption(mintLen);
+      await anchor.web3.sendAndConfirmTransaction(
+        connection,
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: seller.publicKey,
+            newAccountPubkey: mint.publicKey,
+            space: mintLen,
+            lamports,
+            programId: TOKEN_2022_PROGRAM_ID,
+          }),
+          createInitializePermanentDelegateInstruction(mint.publicKey, seller.publicKey, TOKEN_2022_PROGRAM_ID),
+          createInitializeMintInstruction(mint.publicKey, 9, seller.publicKey, null, TOKEN_2022_PROGRAM_ID)
+        ),
+        [seller, mint]
+      );
+      const sellerDelegateToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        mint.publicKey,
+        seller.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        seller,
+        mint.publicKey,
+        sellerDelegateToken,
+        seller,
+        INITIAL_SELLER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const setRejectPermanentDelegate = (reject: boolean) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: reject,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, mint.publicKey, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, mint.publicKey);
+      await setRejectPermanentDelegate(true);
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: mint.publicKey,
+            requestMint: requestMint,
+            sellerOfferToken: sellerDelegateToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - offer mint has a permanent delegate");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("PermanentDelegateMint");
+      } finally {
+        await setRejectPermanentDelegate(false);
+      }
+    });
+
+    it("Fails loudly when the vault no longer backs the escrow", async () => {
+      const mint = Keypair.generate();
+      const mintLen = getMintLen([ExtensionType.PermanentDelegate]);
+      const lamports = await connection.getMinimumBalanceForRentExemption(mintLen);
+      await anchor.web3.sendAndConfirmTransaction(
+        connection,
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: seller.publicKey,
+            newAccountPubkey: mint.publicKey,
+            space: mintLen,
+            lamports,
+            programId: TOKEN_2022_PROGRAM_ID,
+          }),
+          createInitializePermanentDelegateInstruction(mint.publicKey, seller.publicKey, TOKEN_2022_PROGRAM_ID),
+          createInitializeMintInstruction(mint.publicKey, 9, seller.publicKey, null, TOKEN_2022_PROGRAM_ID)
+        ),
+        [seller, mint]
+      );
+      const sellerDelegateToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        mint.publicKey,
+        seller.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        connection,
+        seller,
+        mint.publicKey,
+        sellerDelegateToken,
+        seller,
+        INITIAL_SELLER_BALANCE,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, mint.publicKey, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, mint.publicKey);
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: mint.publicKey,
+          requestMint: requestMint,
+          sellerOfferToken: sellerDelegateToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // The permanent delegate burns from the vault behind the escrow's back
+      await burn(connection, seller, vaultPDA, mint.publicKey, seller, 1, [], undefined, TOKEN_2022_PROGRAM_ID);
+
+      try {
+        await program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: mint.publicKey,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerDelegateToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - vault is short");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("VaultShortfall");
+      }
+    });
+
+    it("Rejects freezable offer mints when configured to", async () => {
+      const freezableMint = await createMint(connection, seller, seller.publicKey, seller.publicKey, 9);
+      const sellerFreezableToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        freezableMint,
+        seller.publicKey
+      );
+      await mintTo(connection, seller, freezableMint, sellerFreezableToken, seller, INITIAL_SELLER_BALANCE);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, freezableMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, freezableMint);
+
+      const setRejectFreezableMints = (reject: boolean) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: reject,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      await setRejectFreezableMints(true);
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: freezableMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerFreezableToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - offer mint has a freeze authority");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("FreezableMint");
+      } finally {
+        await setRejectFreezableMints(false);
+      }
+    });
+
+    it("Marks an escrow with a frozen vault stuck and cancels it once thawed", async () => {
+      const freezableMint = await createMint(connection, seller, seller.publicKey, seller.publicKey, 9);
+      const sellerFreezableToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        freezableMint,
+        seller.publicKey
+      );
+      await mintTo(connection, seller, freezableMint, sellerFreezableToken, seller, INITIAL_SELLER_BALANCE);
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, freezableMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, freezableMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: freezableMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerFreezableToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await freezeAccount(connection, seller, vaultPDA, freezableMint, seller);
+      await program.methods
+        .markEscrowStuck()
+        .accounts({ seller: seller.publicKey, escrow: escrowPDA, vault: vaultPDA })
+        .signers([seller])
+        .rpc();
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.status).to.equal(4);
+
+      // Closing the record would strand the vault
+      try {
+        await program.methods
+          .closeRecord()
+          .accounts({ seller: seller.publicKey, rentRecipient: null, escrow: escrowPDA })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow is stuck");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowStuck");
+      }
+
+      await thawAccount(connection, seller, vaultPDA, freezableMint, seller);
+      await program.methods
+        .cancelEscrow(null)
+        .accounts({
+          authority: seller.publicKey,
+          seller: seller.publicKey,
+          offerMint: freezableMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          sellerOperator: null,
+          session: null,
+          memoProgram: null,
+          vault: vaultPDA,
+          sellerOfferToken: sellerFreezableToken,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: null,
+          treasury: null,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          receiptMint: null,
+          sellerReceiptToken: null,
+          proceedsVault: null,
+          requestMint: null,
+          requestTokenProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+      expect((await getAccount(connection, sellerFreezableToken)).amount).to.equal(BigInt(INITIAL_SELLER_BALANCE));
+    });
+  });
+
+  describe("native SOL", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Wraps SOL into the vault and unwraps it to the buyer", async () => {
+      const solOffer = LAMPORTS_PER_SOL / 2;
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, NATIVE_MINT, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, NATIVE_MINT);
+      const [unwrapPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("unwrap"), escrowPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(solOffer), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: NATIVE_MINT,
+          requestMint: requestMint,
+          sellerOfferToken: null,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(Number((await getAccount(connection, vaultPDA)).amount)).to.equal(solOffer);
+
+      const buyerLamportsBefore = await connection.getBalance(buyer.publicKey);
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(solOffer), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: NATIVE_MINT,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: null,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: unwrapPDA,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // Only the transaction fee is lost on top of the unwrapped SOL
+      const buyerLamportsAfter = await connection.getBalance(buyer.publicKey);
+      expect(buyerLamportsAfter - buyerLamportsBefore).to.be.greaterThan(solOffer - 10_000);
+      expect(await connection.getAccountInfo(unwrapPDA)).to.be.null;
+      expect(await connection.getAccountInfo(vaultPDA)).to.be.null;
+    });
+
+    it("Pays a native SOL request in lamports", async () => {
+      const solRequest = LAMPORTS_PER_SOL / 4;
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, NATIVE_MINT);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(solRequest), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: NATIVE_MINT,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const sellerLamportsBefore = await connection.getBalance(seller.publicKey);
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: NATIVE_MINT,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: null,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: null,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // The seller also recovers the escrow and vault rent
+      const sellerLamportsAfter = await connection.getBalance(seller.publicKey);
+      expect(sellerLamportsAfter - sellerLamportsBefore).to.be.at.least(solRequest);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+    });
+  });
+
+  describe("protocol fee", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Accrues the protocol fee out of the seller's proceeds for the treasury to claim", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const treasuryToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        requestMint,
+        provider.wallet.publicKey
+      );
+      const feeVault = await initializeFeeVault(requestMint);
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, {
+          protocolFeeBps: 100,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 0,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 0,
+          stakeMint: null,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [],
+          feeCurrency: { request: {} },
+          feeSplits: [],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: feeVault,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      const protocolFee = REQUEST_AMOUNT / 100;
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(protocolFee);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT - protocolFee
+      );
+
+      // Fees accrue in the vault until the config authority sweeps them
+      await program.methods
+        .claimFees()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mint: requestMint,
+          feeVault,
+          treasuryToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(0);
+      expect(Number((await getAccount(connection, treasuryToken)).amount)).to.equal(protocolFee);
+    });
+
+    it("Charges the taker their own protocol fee on top of the payment", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const feeVault = await initializeFeeVault(requestMint);
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, {
+          protocolFeeBps: 0,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 0,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 200,
+          stakeMint: null,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [],
+          feeCurrency: { request: {} },
+          feeSplits: [],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      const buyerRequestBefore = Number((await getAccount(connection, buyerRequestToken)).amount);
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: feeVault,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      // The seller's proceeds are untouched; the buyer pays the fee on top
+      const takerFee = (REQUEST_AMOUNT * 2) / 100;
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(takerFee);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(
+        buyerRequestBefore - REQUEST_AMOUNT - takerFee
+      );
+    });
+
+    it("Requires the NFT's metadata to claim the collection fee discount", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const collection = Keypair.generate().publicKey;
+
+      const setDiscountCollection = (discountCollection: PublicKey | null, discountBps: number) =>
+        program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: discountCollection,
+            feeDiscountBps: discountBps,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+
+      await setDiscountCollection(collection, 5000);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: buyerOfferToken,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - missing discount metadata");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MissingGateAccount");
+      } finally {
+        // The config is shared by every test
+        await setDiscountCollection(null, 0);
+      }
+    });
+
+    it("Splits the protocol fee among the configured recipients", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const feeVault = await initializeFeeVault(requestMint);
+      const frontend = Keypair.generate();
+      const frontendToken = await createAssociatedTokenAccount(connection, seller, requestMint, frontend.publicKey);
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, {
+          protocolFeeBps: 100,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 0,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 0,
+          stakeMint: null,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [],
+          feeCurrency: { request: {} },
+          feeSplits: [{ recipient: frontend.publicKey, bps: 4000 }],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: feeVault,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .remainingAccounts([{ pubkey: frontendToken, isWritable: true, isSigner: false }])
+          .signers([buyer])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      const protocolFee = REQUEST_AMOUNT / 100;
+      const frontendFee = (protocolFee * 4000) / 10000;
+      expect(Number((await getAccount(connection, frontendToken)).amount)).to.equal(frontendFee);
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(protocolFee - frontendFee);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT - protocolFee
+      );
+    });
+
+    it("Takes the maker fee out of the offer and the taker fee out of the payment", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const feeVault = await initializeFeeVault(offerMint);
+      const takerFeeVault = await initializeFeeVault(requestMint);
+      const buyerRequestBefore = Number((await getAccount(connection, buyerRequestToken)).amount);
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, {
+          protocolFeeBps: 100,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 0,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 200,
+          stakeMint: null,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [],
+          feeCurrency: { offer: {} },
+          feeSplits: [],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: feeVault,
+            takerFeeVault: takerFeeVault,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      // The maker's fee leaves the vault's offer; the taker's is paid on
+      // top of the request tokens
+      const protocolFee = OFFER_AMOUNT / 100;
+      const takerFee = (REQUEST_AMOUNT * 2) / 100;
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(protocolFee);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT - protocolFee);
+      expect(Number((await getAccount(connection, takerFeeVault)).amount)).to.equal(takerFee);
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(
+        buyerRequestBefore - REQUEST_AMOUNT - takerFee
+      );
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+    });
+
+    it("Burns the configured share of claimed fees", async () => {
+      const treasuryToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        requestMint,
+        provider.wallet.publicKey
+      );
+      const feeVault = await initializeFeeVault(requestMint);
+      await mintTo(connection, buyer, requestMint, feeVault, buyer, 1000);
+      const params = {
+        protocolFeeBps: 0,
+        treasury: provider.wallet.publicKey,
+        maxExpiryDuration: null,
+        requireMintApproval: false,
+        crankBountyLamports: new anchor.BN(0),
+        referralShareBps: 0,
+        maxOpenEscrowsPerSeller: null,
+        rejectPermanentDelegate: false,
+        rejectFreezableMints: false,
+        configDelaySeconds: new anchor.BN(0),
+        feeBurnBps: 2500,
+        protocolTakerFeeBps: 0,
+        stakeMint: null,
+        stakeLockSeconds: new anchor.BN(0),
+        stakeTiers: [],
+        feeCurrency: { request: {} },
+        feeSplits: [],
+        feeDiscountCollection: null,
+        feeDiscountBps: 0,
+        minListingDuration: new anchor.BN(0),
+        crankTipLamports: new anchor.BN(0),
+      };
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, params)
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .claimFees()
+          .accounts({
+            authority: provider.wallet.publicKey,
+            config: configPDA,
+            mint: requestMint,
+            feeVault,
+            treasuryToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+      } finally {
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, { ...params, feeBurnBps: 0 })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      expect(Number((await getAccount(connection, treasuryToken)).amount)).to.equal(750);
+      const mint = await getMint(connection, requestMint);
+      expect(Number(mint.supply)).to.equal(INITIAL_BUYER_BALANCE + 750);
+    });
+
+    it("Discounts the protocol fee for sellers staking the stake mint", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const feeVault = await initializeFeeVault(requestMint);
+      const [stakePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("stake"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      const [stakeVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("stake_vault"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, {
+          protocolFeeBps: 100,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 0,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 0,
+          // Staking the offer mint halves the seller's protocol fee
+          stakeMint: offerMint,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [{ minStaked: new anchor.BN(OFFER_AMOUNT), discountBps: 5000 }],
+          feeCurrency: { request: {} },
+          feeSplits: [],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .stake(new anchor.BN(OFFER_AMOUNT))
+          .accounts({
+            owner: seller.publicKey,
+            config: configPDA,
+            stakeMint: offerMint,
+            stake: stakePDA,
+            stakeVault: stakeVaultPDA,
+            ownerStakeToken: sellerOfferToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: stakePDA,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: feeVault,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      const protocolFee = Math.floor(REQUEST_AMOUNT / 200);
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(protocolFee);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT - protocolFee
+      );
+
+      await program.methods
+        .unstake(new anchor.BN(OFFER_AMOUNT))
+        .accounts({
+          owner: seller.publicKey,
+          stake: stakePDA,
+          stakeVault: stakeVaultPDA,
+          stakeMint: offerMint,
+          ownerStakeToken: sellerOfferToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([seller])
+        .rpc();
+      expect((await program.account.stake.fetch(stakePDA)).amount.toNumber()).to.equal(0);
+    });
+
+    it("Only lets the config authority update the config", async () => {
+      try {
+        await program.methods
+          .updateConfig(seller.publicKey, {
+            protocolFeeBps: 0,
+            treasury: seller.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: seller.publicKey, config: configPDA })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - not the config authority");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+    });
+
+    it("Hands the config authority over once the new authority accepts", async () => {
+      const dao = Keypair.generate();
+      const params = {
+        protocolFeeBps: 0,
+        treasury: provider.wallet.publicKey,
+        maxExpiryDuration: null,
+        requireMintApproval: false,
+        crankBountyLamports: new anchor.BN(0),
+        referralShareBps: 0,
+        maxOpenEscrowsPerSeller: null,
+        rejectPermanentDelegate: false,
+        rejectFreezableMints: false,
+        configDelaySeconds: new anchor.BN(0),
+        feeBurnBps: 0,
+        protocolTakerFeeBps: 0,
+        stakeMint: null,
+        stakeLockSeconds: new anchor.BN(0),
+        stakeTiers: [],
+        feeCurrency: { request: {} },
+        feeSplits: [],
+        feeDiscountCollection: null,
+        feeDiscountBps: 0,
+        minListingDuration: new anchor.BN(0),
+        crankTipLamports: new anchor.BN(0),
+      };
+
+      await program.methods
+        .updateConfig(dao.publicKey, params)
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+      let config = await program.account.config.fetch(configPDA);
+      expect(config.authority.toBase58()).to.equal(provider.wallet.publicKey.toBase58());
+      expect(config.pendingAuthority.toBase58()).to.equal(dao.publicKey.toBase58());
+
+      try {
+        await program.methods
+          .acceptAuthority()
+          .accounts({ newAuthority: seller.publicKey, config: configPDA })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - not the pending authority");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("NoPendingAuthority");
+      }
+
+      await program.methods
+        .acceptAuthority()
+        .accounts({ newAuthority: dao.publicKey, config: configPDA })
+        .signers([dao])
+        .rpc();
+      config = await program.account.config.fetch(configPDA);
+      expect(config.authority.toBase58()).to.equal(dao.publicKey.toBase58());
+      expect(config.pendingAuthority).to.be.null;
+
+      // Hand the shared config back for the other tests
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, params)
+        .accounts({ authority: dao.publicKey, config: configPDA })
+        .signers([dao])
+        .rpc();
+      await program.methods
+        .acceptAuthority()
+        .accounts({ newAuthority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+    });
+
+    it("Queues config changes behind the timelock until cancelled or executed", async () => {
+      const params = {
+        protocolFeeBps: 0,
+        treasury: provider.wallet.publicKey,
+        maxExpiryDuration: null,
+        requireMintApproval: false,
+        crankBountyLamports: new anchor.BN(0),
+        referralShareBps: 0,
+        maxOpenEscrowsPerSeller: null,
+        rejectPermanentDelegate: false,
+        rejectFreezableMints: false,
+        configDelaySeconds: new anchor.BN(3600),
+        feeBurnBps: 0,
+        protocolTakerFeeBps: 0,
+        stakeMint: null,
+        stakeLockSeconds: new anchor.BN(0),
+        stakeTiers: [],
+        feeCurrency: { request: {} },
+        feeSplits: [],
+        feeDiscountCollection: null,
+        feeDiscountBps: 0,
+        minListingDuration: new anchor.BN(0),
+        crankTipLamports: new anchor.BN(0),
+      };
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, params)
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, { ...params, protocolFeeBps: 250 })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+      let config = await program.account.config.fetch(configPDA);
+      expect(config.protocolFeeBps).to.equal(0);
+      expect(config.pendingConfig.protocolFeeBps).to.equal(250);
+
+      try {
+        await program.methods.executePendingConfig().accounts({ config: configPDA }).rpc();
+        expect.fail("Should have thrown an error - delay has not passed");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("ConfigTimelocked");
+      }
+
+      await program.methods
+        .cancelPendingConfig()
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+      config = await program.account.config.fetch(configPDA);
+      expect(config.pendingConfig).to.be.null;
+      expect(config.protocolFeeBps).to.equal(0);
+
+      // Lifting the delay is itself queued; wait it out on a short delay
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, { ...params, configDelaySeconds: new anchor.BN(1) })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+      await new Promise((resolve) => setTimeout(resolve, 2000));
+      await program.methods.executePendingConfig().accounts({ config: configPDA }).rpc();
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, { ...params, configDelaySeconds: new anchor.BN(0) })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+      await new Promise((resolve) => setTimeout(resolve, 2000));
+      await program.methods.executePendingConfig().accounts({ config: configPDA }).rpc();
+      config = await program.account.config.fetch(configPDA);
+      expect(config.configDelaySeconds.toNumber()).to.equal(0);
+    });
+
+    it("Blocks fills while paused but still lets sellers cancel", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .pause()
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        try {
+          await program.methods
+            .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+            .accounts({
+              buyer: buyer.publicKey,
+              payer: buyer.publicKey,
+              seller: seller.publicKey,
+              offerMint: offerMint,
+              requestMint: requestMint,
+              escrow: escrowPDA,
+              rentRecipient: null,
+              vault: vaultPDA,
+              buyerRequestToken: buyerRequestToken,
+              buyerOfferToken: buyerOfferToken,
+              beneficiary: null,
+              beneficiaryOfferToken: null,
+              recipientOfferToken: null,
+              reservation: null,
+              fillCommitment: null,
+              offerUnwrap: null,
+              sellerRequestToken: sellerRequestToken,
+              proceedsRecipientToken: null,
+              market: null,
+              marketFeeToken: null,
+              pairFees: null,
+              sellerStats: null,
+              buyerStats: null,
+              buyerAllowlist: null,
+              config: configPDA,
+              activityLog: null,
+              logWrapper: null,
+              compressionProgram: null,
+              feeVault: null,
+              takerFeeVault: null,
+              referrerToken: null,
+              offerMetadata: null,
+              gateToken: null,
+              gateMetadata: null,
+              discountToken: null,
+              discountMetadata: null,
+              session: null,
+              memoProgram: null,
+              permissionApproval: null,
+              priceUpdate: null,
+              instructions: null,
+              vesting: null,
+              vestingVault: null,
+              stream: null,
+              streamVault: null,
+              proceedsVault: null,
+              pairIndex: pairIndexPDA,
+              registryPage: null,
+              marketIndex: null,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              requestTokenProgram: TOKEN_PROGRAM_ID,
+              associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+              systemProgram: anchor.web3.SystemProgram.programId,
+            })
+            .signers([buyer])
+            .rpc();
+          expect.fail("Should have thrown an error - protocol is paused");
+        } catch (err: any) {
+          expect(err.error.errorCode.code).to.equal("ProtocolPaused");
+        }
+
+        await program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+      } finally {
+        await program.methods
+          .unpause()
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+    });
+
+    it("Rejects listings of banned mints", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [mintPolicyPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("mint_policy"), offerMint.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .setMintPolicy({ banned: {} })
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mint: offerMint,
+          mintPolicy: mintPolicyPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - offer mint is banned");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MintBanned");
+      }
+
+      await program.methods
+        .removeMintPolicy()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mintPolicy: mintPolicyPDA,
+        })
+        .rpc();
+      expect(await connection.getAccountInfo(mintPolicyPDA)).to.be.null;
+    });
+
+    it("Rejects listings from banned sellers", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [sellerBanPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_ban"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .banSeller()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          seller: seller.publicKey,
+          sellerBan: sellerBanPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - seller is banned");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("SellerBanned");
+      }
+
+      await program.methods
+        .unbanSeller()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          sellerBan: sellerBanPDA,
+        })
+        .rpc();
+      expect(await connection.getAccountInfo(sellerBanPDA)).to.be.null;
+    });
+
+    it("Enforces per-mint offer size limits", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [mintLimitsPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("mint_limits"), offerMint.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .setMintLimits(new anchor.BN(OFFER_AMOUNT * 2), null, new anchor.BN(0))
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mint: offerMint,
+          mintLimits: mintLimitsPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - offer below minimum");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("OfferBelowMinimum");
+      }
+
+      await program.methods
+        .removeMintLimits()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mintLimits: mintLimitsPDA,
+        })
+        .rpc();
+      expect(await connection.getAccountInfo(mintLimitsPDA)).to.be.null;
+    });
+
+    it("Splits the protocol fee with the buyer's referrer", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const referrer = Keypair.generate();
+      const feeVault = await initializeFeeVault(requestMint);
+      const referrerToken = await createAssociatedTokenAccount(connection, seller, requestMint, referrer.publicKey);
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, {
+          protocolFeeBps: 1000,
+          treasury: provider.wallet.publicKey,
+          maxExpiryDuration: null,
+          requireMintApproval: false,
+          crankBountyLamports: new anchor.BN(0),
+          referralShareBps: 4000,
+          maxOpenEscrowsPerSeller: null,
+          rejectPermanentDelegate: false,
+          rejectFreezableMints: false,
+          configDelaySeconds: new anchor.BN(0),
+          feeBurnBps: 0,
+          protocolTakerFeeBps: 0,
+          stakeMint: null,
+          stakeLockSeconds: new anchor.BN(0),
+          stakeTiers: [],
+          feeCurrency: { request: {} },
+          feeSplits: [],
+          feeDiscountCollection: null,
+          feeDiscountBps: 0,
+          minListingDuration: new anchor.BN(0),
+          crankTipLamports: new anchor.BN(0),
+        })
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: feeVault,
+            takerFeeVault: null,
+            referrerToken: referrerToken,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, {
+            protocolFeeBps: 0,
+            treasury: provider.wallet.publicKey,
+            maxExpiryDuration: null,
+            requireMintApproval: false,
+            crankBountyLamports: new anchor.BN(0),
+            referralShareBps: 0,
+            maxOpenEscrowsPerSeller: null,
+            rejectPermanentDelegate: false,
+            rejectFreezableMints: false,
+            configDelaySeconds: new anchor.BN(0),
+            feeBurnBps: 0,
+            protocolTakerFeeBps: 0,
+            stakeMint: null,
+            stakeLockSeconds: new anchor.BN(0),
+            stakeTiers: [],
+            feeCurrency: { request: {} },
+            feeSplits: [],
+            feeDiscountCollection: null,
+            feeDiscountBps: 0,
+            minListingDuration: new anchor.BN(0),
+            crankTipLamports: new anchor.BN(0),
+          })
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      const protocolFee = REQUEST_AMOUNT / 10;
+      const referralFee = (protocolFee * 4000) / 10000;
+      expect(Number((await getAccount(connection, referrerToken)).amount)).to.equal(referralFee);
+      expect(Number((await getAccount(connection, feeVault)).amount)).to.equal(protocolFee - referralFee);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(
+        REQUEST_AMOUNT - protocolFee
+      );
+    });
+
+    it("Quotes a fill and reports escrow state through read-only instructions", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const configParams = (protocolFeeBps: number) => ({
+        protocolFeeBps,
+        treasury: provider.wallet.publicKey,
+        maxExpiryDuration: null,
+        requireMintApproval: false,
+        crankBountyLamports: new anchor.BN(0),
+        referralShareBps: 0,
+        maxOpenEscrowsPerSeller: null,
+        rejectPermanentDelegate: false,
+        rejectFreezableMints: false,
+        configDelaySeconds: new anchor.BN(0),
+        feeBurnBps: 0,
+        protocolTakerFeeBps: 0,
+        stakeMint: null,
+        stakeLockSeconds: new anchor.BN(0),
+        stakeTiers: [],
+        feeCurrency: { request: {} },
+        feeSplits: [],
+        feeDiscountCollection: null,
+        feeDiscountBps: 0,
+        minListingDuration: new anchor.BN(0),
+        crankTipLamports: new anchor.BN(0),
+      });
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const state = await program.methods.getEscrowState().accounts({ escrow: escrowPDA }).view();
+      expect(state.status).to.deep.equal({ open: {} });
+      expect(state.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
+      expect(state.expired).to.be.false;
+      expect(state.fillable).to.be.true;
+
+      await program.methods
+        .updateConfig(provider.wallet.publicKey, configParams(100))
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+      try {
+        const quote = await program.methods
+          .getQuote(new anchor.BN(OFFER_AMOUNT / 2))
+          .accounts({
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            market: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyer: null,
+            discountToken: null,
+            discountMetadata: null,
+            offerMetadata: null,
+            config: configPDA,
+            priceUpdate: null,
+          })
+          .view();
+        const requestAmount = REQUEST_AMOUNT / 2;
+        const protocolFee = Math.floor(requestAmount / 100);
+        expect(quote.offerOut.toNumber()).to.equal(OFFER_AMOUNT / 2);
+        expect(quote.requestAmount.toNumber()).to.equal(requestAmount);
+        expect(quote.protocolFee.toNumber()).to.equal(protocolFee);
+        expect(quote.totalCost.toNumber()).to.equal(requestAmount);
+        expect(quote.sellerProceeds.toNumber()).to.equal(requestAmount - protocolFee);
+      } finally {
+        // The config is shared by every test
+        await program.methods
+          .updateConfig(provider.wallet.publicKey, configParams(0))
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+    });
+  });
+
+  describe("nft escrow", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Lists a single NFT", async () => {
+      const nftMint = await createMint(connection, seller, seller.publicKey, null, 0);
+      const sellerNftToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        nftMint,
+        seller.publicKey
+      );
+      await mintTo(connection, seller, nftMint, sellerNftToken, seller, 1);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, nftMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, nftMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(1), new anchor.BN(REQUEST_AMOUNT), null, null, { payRoyalties: false })
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: nftMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerNftToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.nft).to.equal(1);
+      expect(escrow.collection.equals(PublicKey.default)).to.be.true;
+      expect(escrow.offerAmount.toNumber()).to.equal(1);
+    });
+
+    it("Rejects fungible offers in NFT mode", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, { payRoyalties: false })
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerOfferToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - offer is not an NFT");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("NotAnNft");
+      }
+    });
+
+    it("Requires metadata to pay royalties", async () => {
+      const nftMint = await createMint(connection, seller, seller.publicKey, null, 0);
+      const sellerNftToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        nftMint,
+        seller.publicKey
+      );
+      await mintTo(connection, seller, nftMint, sellerNftToken, seller, 1);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, nftMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, nftMint);
+
+      try {
+        await program.methods
+          .createEscrow(new anchor.BN(0), new anchor.BN(1), new anchor.BN(REQUEST_AMOUNT), null, null, { payRoyalties: true })
+          .accounts({
+            seller: seller.publicKey,
+            payer: seller.publicKey,
+            offerMint: nftMint,
+            requestMint: requestMint,
+            sellerOfferToken: sellerNftToken,
+            market: null,
+            offerMetadata: null,
+            buyerAllowlist: null,
+            sellerStake: null,
+            escrow: escrowPDA,
+            vault: vaultPDA,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - metadata is missing");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MissingMetadataAccount");
+      }
+    });
+  });
+
+  describe("cnft escrow", () => {
+    const BUBBLEGUM_PROGRAM_ID = new PublicKey("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY");
+    const COMPRESSION_PROGRAM_ID = new PublicKey("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+    const NOOP_PROGRAM_ID = new PublicKey("noopb9bkMVfRPU8ShW8aGLs1UyUSHCLMgxDCmCdrtYe");
+
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Rejects compressed NFT escrows while paused", async () => {
+      const merkleTree = Keypair.generate().publicKey;
+      const [treeAuthority] = PublicKey.findProgramAddressSync(
+        [merkleTree.toBuffer()],
+        BUBBLEGUM_PROGRAM_ID
+      );
+      const [cnftEscrowPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("cnft_escrow"), merkleTree.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+
+      await program.methods
+        .pause()
+        .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+        .rpc();
+
+      try {
+        await program.methods
+          .createCnftEscrow(
+            { dataHash: Array(32).fill(0), creatorHash: Array(32).fill(0), nonce: new anchor.BN(0), index: 0 },
+            Array(32).fill(0),
+            new anchor.BN(REQUEST_AMOUNT),
+            null
+          )
+          .accounts({
+            seller: seller.publicKey,
+            leafDelegate: seller.publicKey,
+            requestMint: requestMint,
+            config: configPDA,
+            cnftEscrow: cnftEscrowPDA,
+            treeAuthority: treeAuthority,
+            merkleTree: merkleTree,
+            logWrapper: NOOP_PROGRAM_ID,
+            compressionProgram: COMPRESSION_PROGRAM_ID,
+            bubblegumProgram: BUBBLEGUM_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - protocol is paused");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("ProtocolPaused");
+      } finally {
+        await program.methods
+          .unpause()
+          .accounts({ authority: provider.wallet.publicKey, config: configPDA })
+          .rpc();
+      }
+
+      expect(await connection.getAccountInfo(cnftEscrowPDA)).to.be.null;
+    });
+  });
+
+  describe("offer baskets", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Sells every leg of a basket atomically", async () => {
+      const secondMint = await createMint(connection, seller, seller.publicKey, null, 6);
+      const sellerSecondToken = await createAssociatedTokenAccount(
+        connection,
+        seller,
+        secondMint,
+        seller.publicKey
+      );
+      await mintTo(connection, seller, secondMint, sellerSecondToken, seller, INITIAL_SELLER_BALANCE);
+      const buyerSecondToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        secondMint,
+        buyer.publicKey
+      );
+
+      const [basketPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("basket"), seller.publicKey.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const deriveBasketVault = (mint: PublicKey) =>
+        PublicKey.findProgramAddressSync(
+          [Buffer.from("basket_vault"), basketPDA.toBuffer(), mint.toBuffer()],
+          program.programId
+        )[0];
+
+      await program.methods
+        .createBasketEscrow(new anchor.BN(0), new anchor.BN(REQUEST_AMOUNT), null)
+        .accounts({
+          seller: seller.publicKey,
+          requestMint: requestMint,
+          config: configPDA,
+          basket: basketPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      for (const [mint, sellerToken] of [
+        [offerMint, sellerOfferToken],
+        [secondMint, sellerSecondToken],
+      ]) {
+        await program.methods
+          .addBasketLeg(new anchor.BN(OFFER_AMOUNT))
+          .accounts({
+            seller: seller.publicKey,
+            basket: basketPDA,
+            mint: mint,
+            config: configPDA,
+            sellerToken: sellerToken,
+            vault: deriveBasketVault(mint),
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+      }
+
+      const basket = await program.account.basketEscrow.fetch(basketPDA);
+      expect(basket.legs).to.have.length(2);
+
+      await program.methods
+        .acceptBasketEscrow()
+        .accounts({
+          buyer: buyer.publicKey,
+          seller: seller.publicKey,
+          basket: basketPDA,
+          config: configPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          feeVault: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(
+          [
+            [offerMint, buyerOfferToken],
+            [secondMint, buyerSecondToken],
+          ].flatMap(([mint, buyerToken]) => [
+            { pubkey: mint, isSigner: false, isWritable: false },
+            { pubkey: deriveBasketVault(mint), isSigner: false, isWritable: true },
+            { pubkey: buyerToken, isSigner: false, isWritable: true },
+            { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+          ])
+        )
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, buyerSecondToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(await connection.getAccountInfo(basketPDA)).to.be.null;
+      expect(await connection.getAccountInfo(deriveBasketVault(offerMint))).to.be.null;
+    });
+  });
+
+  describe("counter offers", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    async function createEscrowAndCounter(counterAmount: number, deposit: number) {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [counterOfferPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("counter_offer"), escrowPDA.toBuffer(), buyer.publicKey.toBuffer()],
+        program.programId
+      );
+      const [counterVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("counter_vault"), counterOfferPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .createCounterOffer(new anchor.BN(counterAmount), new anchor.BN(deposit))
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: escrowPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          counterOffer: counterOfferPDA,
+          counterVault: counterVaultPDA,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      return { escrowPDA, vaultPDA, counterOfferPDA, counterVaultPDA };
+    }
+
+    it("Settles at the countered price when the seller accepts", async () => {
+      const COUNTER_AMOUNT = REQUEST_AMOUNT / 2;
+      const { escrowPDA, vaultPDA, counterOfferPDA, counterVaultPDA } = await createEscrowAndCounter(
+        COUNTER_AMOUNT,
+        REQUEST_AMOUNT
+      );
+
+      await program.methods
+        .acceptCounterOffer()
+        .accounts({
+          seller: seller.publicKey,
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          counterOffer: counterOfferPDA,
+          counterVault: counterVaultPDA,
+          buyerOfferToken: buyerOfferToken,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          offerMetadata: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(COUNTER_AMOUNT);
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(
+        INITIAL_BUYER_BALANCE - COUNTER_AMOUNT
+      );
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+      expect(await connection.getAccountInfo(counterOfferPDA)).to.be.null;
+      expect(await connection.getAccountInfo(counterVaultPDA)).to.be.null;
+    });
+
+    it("Refunds the deposit when the seller rejects", async () => {
+      const { escrowPDA, counterOfferPDA, counterVaultPDA } = await createEscrowAndCounter(
+        REQUEST_AMOUNT / 2,
+        REQUEST_AMOUNT / 2
+      );
+
+      await program.methods
+        .rejectCounterOffer()
+        .accounts({
+          authority: seller.publicKey,
+          buyer: buyer.publicKey,
+          counterOffer: counterOfferPDA,
+          counterVault: counterVaultPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(INITIAL_BUYER_BALANCE);
+      expect(await connection.getAccountInfo(counterOfferPDA)).to.be.null;
+      expect(await connection.getAccountInfo(escrowPDA)).to.not.be.null;
+    });
+
+    it("Refuses counter offers on a token-gated escrow", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [counterOfferPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("counter_offer"), escrowPDA.toBuffer(), buyer.publicKey.toBuffer()],
+        program.programId
+      );
+      const [counterVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("counter_vault"), counterOfferPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setTokenGate({ mint: { mint: requestMint, minAmount: new anchor.BN(1) } })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Accepting a counter offer never checks the buyer against the gate
+      try {
+        await program.methods
+          .createCounterOffer(new anchor.BN(REQUEST_AMOUNT / 2), new anchor.BN(REQUEST_AMOUNT))
+          .accounts({
+            buyer: buyer.publicKey,
+            escrow: escrowPDA,
+            requestMint: requestMint,
+            buyerRequestToken: buyerRequestToken,
+            counterOffer: counterOfferPDA,
+            counterVault: counterVaultPDA,
+            config: configPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error - escrow is token-gated");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("GatedEscrow");
+      }
+    });
+  });
+
+  describe("bid escrows", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    async function createBid() {
+      const [bidPDA] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("bid_escrow"),
+          buyer.publicKey.toBuffer(),
+          offerMint.toBuffer(),
+          requestMint.toBuffer(),
+          new anchor.BN(0).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+      const [bidVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("bid_vault"), bidPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createBid(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null)
+        .accounts({
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          bid: bidPDA,
+          bidVault: bidVaultPDA,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      return { bidPDA, bidVaultPDA };
+    }
+
+    it("Pays sellers pro rata as they fill a buyer's bid", async () => {
+      const { bidPDA, bidVaultPDA } = await createBid();
+      expect(Number((await getAccount(connection, bidVaultPDA)).amount)).to.equal(REQUEST_AMOUNT);
+
+      const accept = (fillAmount: number) =>
+        program.methods
+          .acceptBid(new anchor.BN(fillAmount))
+          .accounts({
+            seller: seller.publicKey,
+            buyer: buyer.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            bid: bidPDA,
+            bidVault: bidVaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            sellerRequestToken: sellerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            config: configPDA,
+            feeVault: null,
+            takerFeeVault: null,
+            discountToken: null,
+            discountMetadata: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([seller])
+          .rpc();
+
+      await accept(OFFER_AMOUNT / 4);
+      const bid = await program.account.bidEscrow.fetch(bidPDA);
+      expect(bid.offerAmount.toNumber()).to.equal((OFFER_AMOUNT * 3) / 4);
+      expect(bid.requestAmount.toNumber()).to.equal((REQUEST_AMOUNT * 3) / 4);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT / 4);
+
+      await accept((OFFER_AMOUNT * 3) / 4);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(bidPDA)).to.be.null;
+      expect(await connection.getAccountInfo(bidVaultPDA)).to.be.null;
+    });
+
+    it("Rejects bids on banned mints", async () => {
+      const [mintPolicyPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("mint_policy"), offerMint.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .setMintPolicy({ banned: {} })
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          mint: offerMint,
+          mintPolicy: mintPolicyPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      try {
+        await createBid();
+        expect.fail("Should have thrown an error - offer mint is banned");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("MintBanned");
+      }
+    });
+
+    it("Returns the deposit when the buyer cancels their bid", async () => {
+      const { bidPDA, bidVaultPDA } = await createBid();
+
+      await program.methods
+        .cancelBid()
+        .accounts({
+          buyer: buyer.publicKey,
+          bid: bidPDA,
+          bidVault: bidVaultPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(INITIAL_BUYER_BALANCE);
+      expect(await connection.getAccountInfo(bidPDA)).to.be.null;
+    });
+  });
+
+  describe("request for quotes", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Rejects quotes from banned sellers", async () => {
+      const [rfqPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("rfq"), buyer.publicKey.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const [quotePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("rfq_quote"), rfqPDA.toBuffer(), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      const [quoteVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("rfq_quote_vault"), quotePDA.toBuffer()],
+        program.programId
+      );
+      const [sellerBanPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("seller_ban"), seller.publicKey.toBuffer()],
+        program.programId
+      );
+      const deadline = Math.floor(Date.now() / 1000) + 3600;
+      await program.methods
+        .createRfq(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(deadline))
+        .accounts({
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          rfq: rfqPDA,
+          config: configPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      await program.methods
+        .banSeller()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          seller: seller.publicKey,
+          sellerBan: sellerBanPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      try {
+        await program.methods
+          .submitQuote(new anchor.BN(REQUEST_AMOUNT))
+          .accounts({
+            seller: seller.publicKey,
+            rfq: rfqPDA,
+            offerMint: offerMint,
+            sellerOfferToken: sellerOfferToken,
+            quote: quotePDA,
+            quoteVault: quoteVaultPDA,
+            config: configPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error - seller is banned");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("SellerBanned");
+      }
+
+      await program.methods
+        .unbanSeller()
+        .accounts({
+          authority: provider.wallet.publicKey,
+          config: configPDA,
+          sellerBan: sellerBanPDA,
+        })
+        .rpc();
+    });
+
+    it("Fills an RFQ from the chosen quote and releases the others", async () => {
+      const [rfqPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("rfq"), buyer.publicKey.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const deadline = Math.floor(Date.now() / 1000) + 3600;
+      await program.methods
+        .createRfq(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(deadline))
+        .accounts({
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          rfq: rfqPDA,
+          config: configPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      // A rival seller undercuts the first quote
+      const rival = Keypair.generate();
+      await airdrop(rival.publicKey);
+      const rivalOfferToken = await createAssociatedTokenAccount(connection, rival, offerMint, rival.publicKey);
+      const rivalRequestToken = await createAssociatedTokenAccount(connection, rival, requestMint, rival.publicKey);
+      await mintTo(connection, seller, offerMint, rivalOfferToken, seller, OFFER_AMOUNT);
+
+      const quotes = [];
+      for (const [quoter, quoterOfferToken, price] of [
+        [seller, sellerOfferToken, REQUEST_AMOUNT],
+        [rival, rivalOfferToken, REQUEST_AMOUNT / 2],
+      ] as [Keypair, PublicKey, number][]) {
+        const [quotePDA] = PublicKey.findProgramAddressSync(
+          [Buffer.from("rfq_quote"), rfqPDA.toBuffer(), quoter.publicKey.toBuffer()],
+          program.programId
+        );
+        const [quoteVaultPDA] = PublicKey.findProgramAddressSync(
+          [Buffer.from("rfq_quote_vault"), quotePDA.toBuffer()],
+          program.programId
+        );
+        await program.methods
+          .submitQuote(new anchor.BN(price))
+          .accounts({
+            seller: quoter.publicKey,
+            rfq: rfqPDA,
+            offerMint: offerMint,
+            sellerOfferToken: quoterOfferToken,
+            quote: quotePDA,
+            quoteVault: quoteVaultPDA,
+            config: configPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([quoter])
+          .rpc();
+        quotes.push({ quotePDA, quoteVaultPDA });
+      }
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(
+        INITIAL_SELLER_BALANCE - OFFER_AMOUNT
+      );
+
+      await program.methods
+        .acceptQuote()
+        .accounts({
+          buyer: buyer.publicKey,
+          seller: rival.publicKey,
+          rfq: rfqPDA,
+          quote: quotes[1].quotePDA,
+          quoteVault: quotes[1].quoteVaultPDA,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          buyerOfferToken: buyerOfferToken,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: rivalRequestToken,
+          config: configPDA,
+          feeVault: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, rivalRequestToken)).amount)).to.equal(REQUEST_AMOUNT / 2);
+      expect((await program.account.rfq.fetch(rfqPDA)).acceptedQuote.toBase58()).to.equal(
+        quotes[1].quotePDA.toBase58()
+      );
+
+      // Anyone may now release the losing quote
+      await program.methods
+        .releaseQuote()
+        .accounts({
+          authority: buyer.publicKey,
+          seller: seller.publicKey,
+          rfq: rfqPDA,
+          quote: quotes[0].quotePDA,
+          quoteVault: quotes[0].quoteVaultPDA,
+          offerMint: offerMint,
+          sellerOfferToken: sellerOfferToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, sellerOfferToken)).amount)).to.equal(INITIAL_SELLER_BALANCE);
+      expect(await connection.getAccountInfo(quotes[0].quotePDA)).to.be.null;
+
+      await program.methods
+        .closeRfq()
+        .accounts({ buyer: buyer.publicKey, rfq: rfqPDA })
+        .signers([buyer])
+        .rpc();
+      expect(await connection.getAccountInfo(rfqPDA)).to.be.null;
+    });
+  });
+
+  describe("sealed-bid auctions", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    async function waitUntil(timestamp: number) {
+      while ((await connection.getBlockTime(await connection.getSlot())) < timestamp) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+    }
+
+    it("Sells to the highest revealed bid and refunds its leftover deposit", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [auctionPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("auction"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [bidPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("bid"), auctionPDA.toBuffer(), buyer.publicKey.toBuffer()],
+        program.programId
+      );
+      const [bidVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("bid_vault"), bidPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      const commitEndsAt = now + 3;
+      const revealEndsAt = now + 6;
+      await program.methods
+        .createAuction(new anchor.BN(REQUEST_AMOUNT / 2), new anchor.BN(commitEndsAt), new anchor.BN(revealEndsAt))
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          auction: auctionPDA,
+          config: configPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const BID_PRICE = REQUEST_AMOUNT;
+      const DEPOSIT = REQUEST_AMOUNT * 2;
+      const salt = Keypair.generate().publicKey.toBuffer();
+      const commitment = createHash("sha256")
+        .update(Buffer.concat([new anchor.BN(BID_PRICE).toArrayLike(Buffer, "le", 8), salt]))
+        .digest();
+      await program.methods
+        .commitBid(Array.from(commitment), new anchor.BN(DEPOSIT))
+        .accounts({
+          bidder: buyer.publicKey,
+          auction: auctionPDA,
+          requestMint: requestMint,
+          bidderRequestToken: buyerRequestToken,
+          bid: bidPDA,
+          bidVault: bidVaultPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      await waitUntil(commitEndsAt);
+      await program.methods
+        .revealBid(new anchor.BN(BID_PRICE), Array.from(salt))
+        .accounts({
+          bidder: buyer.publicKey,
+          auction: auctionPDA,
+          bid: bidPDA,
+          bidVault: bidVaultPDA,
+        })
+        .signers([buyer])
+        .rpc();
+
+      await waitUntil(revealEndsAt);
+      await program.methods
+        .settleAuction()
+        .accounts({
+          seller: seller.publicKey,
+          winner: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          vault: vaultPDA,
+          auction: auctionPDA,
+          bid: bidPDA,
+          bidVault: bidVaultPDA,
+          winnerOfferToken: buyerOfferToken,
+          winnerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          winnerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          offerMetadata: null,
+          pairIndex: pairIndexPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(BID_PRICE);
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(
+        INITIAL_BUYER_BALANCE - BID_PRICE
+      );
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+      expect(await connection.getAccountInfo(auctionPDA)).to.be.null;
+      expect(await connection.getAccountInfo(bidVaultPDA)).to.be.null;
+    });
+
+    it("Crowdfills an escrow from several contributors pro-rata", async () => {
+      const backer = Keypair.generate();
+      await airdrop(backer.publicKey);
+      const backerRequestToken = await createAssociatedTokenAccount(connection, backer, requestMint, backer.publicKey);
+      await mintTo(connection, buyer, requestMint, backerRequestToken, buyer, REQUEST_AMOUNT / 2);
+      const backerOfferToken = await createAssociatedTokenAccount(connection, backer, offerMint, backer.publicKey);
+
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [crowdfillPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("crowdfill"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [crowdfillVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("crowdfill_vault"), crowdfillPDA.toBuffer()],
+        program.programId
+      );
+      const [crowdfillOfferVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("crowdfill_offer_vault"), crowdfillPDA.toBuffer()],
+        program.programId
+      );
+      const contributionPDA = (contributor: PublicKey) =>
+        PublicKey.findProgramAddressSync(
+          [Buffer.from("contribution"), crowdfillPDA.toBuffer(), contributor.toBuffer()],
+          program.programId
+        )[0];
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      await program.methods
+        .createCrowdfill(new anchor.BN(now + 60))
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          crowdfill: crowdfillPDA,
+          crowdfillVault: crowdfillVaultPDA,
+          crowdfillOfferVault: crowdfillOfferVaultPDA,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      // Each contributor raises half of the request amount
+      for (const [contributor, requestToken] of [
+        [buyer, buyerRequestToken],
+        [backer, backerRequestToken],
+      ] as [Keypair, PublicKey][]) {
+        await program.methods
+          .contribute(new anchor.BN(REQUEST_AMOUNT / 2))
+          .accounts({
+            contributor: contributor.publicKey,
+            crowdfill: crowdfillPDA,
+            crowdfillVault: crowdfillVaultPDA,
+            contribution: contributionPDA(contributor.publicKey),
+            requestMint: requestMint,
+            contributorRequestToken: requestToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([contributor])
+          .rpc();
+      }
+
+      await program.methods
+        .settleCrowdfill()
+        .accounts({
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          crowdfill: crowdfillPDA,
+          crowdfillVault: crowdfillVaultPDA,
+          crowdfillOfferVault: crowdfillOfferVaultPDA,
+          sellerRequestToken: sellerRequestToken,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          crowdfillStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          offerMetadata: null,
+          pairIndex: pairIndexPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+
+      for (const [contributor, offerToken, requestToken] of [
+        [buyer, buyerOfferToken, buyerRequestToken],
+        [backer, backerOfferToken, backerRequestToken],
+      ] as [Keypair, PublicKey, PublicKey][]) {
+        await program.methods
+          .claimCrowdfill()
+          .accounts({
+            contributor: contributor.publicKey,
+            crowdfill: crowdfillPDA,
+            contribution: contributionPDA(contributor.publicKey),
+            crowdfillVault: crowdfillVaultPDA,
+            crowdfillOfferVault: crowdfillOfferVaultPDA,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            contributorOfferToken: offerToken,
+            contributorRequestToken: requestToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([contributor])
+          .rpc();
+        expect(Number((await getAccount(connection, offerToken)).amount)).to.equal(OFFER_AMOUNT / 2);
+      }
+
+      await program.methods
+        .closeCrowdfill()
+        .accounts({
+          seller: seller.publicKey,
+          escrow: null,
+          crowdfill: crowdfillPDA,
+          crowdfillVault: crowdfillVaultPDA,
+          crowdfillOfferVault: crowdfillOfferVaultPDA,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([seller])
+        .rpc();
+      expect(await connection.getAccountInfo(crowdfillPDA)).to.be.null;
+    });
+
+    it("Refuses to top up an escrow being crowdfilled", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [crowdfillPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("crowdfill"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [crowdfillVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("crowdfill_vault"), crowdfillPDA.toBuffer()],
+        program.programId
+      );
+      const [crowdfillOfferVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("crowdfill_offer_vault"), crowdfillPDA.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      const now = await connection.getBlockTime(await connection.getSlot());
+      await program.methods
+        .createCrowdfill(new anchor.BN(now + 60))
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          crowdfill: crowdfillPDA,
+          crowdfillVault: crowdfillVaultPDA,
+          crowdfillOfferVault: crowdfillOfferVaultPDA,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      try {
+        await program.methods
+          .topUpEscrow(new anchor.BN(OFFER_AMOUNT), null)
+          .accounts({
+            authority: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            sellerOperator: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowNotOpen");
+      }
+    });
+  });
+
+  describe("held settlements", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Refunds a disputed settlement the arbiter awards to the buyer", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [settlementPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("settlement"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [settlementVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("settlement_vault"), settlementPDA.toBuffer()],
+        program.programId
+      );
+      const arbiter = Keypair.generate();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setArbitration({ arbiter: arbiter.publicKey, mode: { disputeWindow: { seconds: new anchor.BN(3600) } } })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .holdEscrow(new anchor.BN(REQUEST_AMOUNT))
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: escrowPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          settlement: settlementPDA,
+          settlementVault: settlementVaultPDA,
+          buyerAllowlist: null,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      expect(Number((await getAccount(connection, settlementVaultPDA)).amount)).to.equal(REQUEST_AMOUNT);
+
+      await program.methods
+        .raiseDispute()
+        .accounts({
+          authority: buyer.publicKey,
+          escrow: escrowPDA,
+          settlement: settlementPDA,
+        })
+        .signers([buyer])
+        .rpc();
+
+      await program.methods
+        .resolveDispute(false)
+        .accounts({
+          arbiter: arbiter.publicKey,
+          escrow: escrowPDA,
+          settlement: settlementPDA,
+          sellerReputation: PublicKey.findProgramAddressSync(
+            [Buffer.from("reputation"), seller.publicKey.toBuffer()],
+            program.programId
+          )[0],
+        })
+        .signers([arbiter])
+        .rpc();
+
+      // The settlement's history records each action for the arbiter
+      const { history } = await program.account.settlement.fetch(settlementPDA);
+      expect(history.map((entry) => Object.keys(entry.action)[0])).to.deep.equal([
+        "held",
+        "disputed",
+        "resolvedForBuyer",
+      ]);
+      expect(history[0].actor.equals(buyer.publicKey)).to.be.true;
+      expect(history[0].amount.toNumber()).to.equal(REQUEST_AMOUNT);
+      expect(history[2].actor.equals(arbiter.publicKey)).to.be.true;
+
+      await program.methods
+        .refundSettlement()
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: escrowPDA,
+          settlement: settlementPDA,
+          settlementVault: settlementVaultPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerRequestToken)).amount)).to.equal(INITIAL_BUYER_BALANCE);
+      expect(await connection.getAccountInfo(settlementPDA)).to.be.null;
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.lockedBy.equals(PublicKey.default)).to.be.true;
+      expect(escrow.offerAmount.toNumber()).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Releases a two-of-three settlement once two parties approve", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [settlementPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("settlement"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [settlementVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("settlement_vault"), settlementPDA.toBuffer()],
+        program.programId
+      );
+      const agent = Keypair.generate();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setArbitration({ arbiter: agent.publicKey, mode: { twoOfThree: {} } })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .holdEscrow(new anchor.BN(REQUEST_AMOUNT))
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: escrowPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          settlement: settlementPDA,
+          settlementVault: settlementVaultPDA,
+          buyerAllowlist: null,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const release = () =>
+        program.methods
+          .releaseSettlement()
+          .accounts({
+            seller: seller.publicKey,
+            buyer: buyer.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            settlement: settlementPDA,
+            settlementVault: settlementVaultPDA,
+            buyerOfferToken: buyerOfferToken,
+            buyerRequestToken: buyerRequestToken,
+            sellerRequestToken: sellerRequestToken,
+            market: null,
+            marketFeeToken: null,
+            pairFees: null,
+            sellerStats: null,
+            buyerStats: null,
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            offerMetadata: null,
+            pairIndex: pairIndexPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .rpc();
+
+      for (const party of [seller, agent]) {
+        try {
+          await release();
+          expect.fail("Should have failed before two approvals");
+        } catch (err: any) {
+          expect(err.error.errorCode.code).to.equal("InvalidSettlementStatus");
+        }
+        await program.methods
+          .approveSettlement(true)
+          .accounts({
+            authority: party.publicKey,
+            escrow: escrowPDA,
+            settlement: settlementPDA,
+          })
+          .signers([party])
+          .rpc();
+      }
+
+      await release();
+
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(escrowPDA)).to.be.null;
+      expect(await connection.getAccountInfo(settlementPDA)).to.be.null;
+    });
+
+    it("Releases a dispute-window settlement early when both parties approve", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const [settlementPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("settlement"), escrowPDA.toBuffer()],
+        program.programId
+      );
+      const [settlementVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("settlement_vault"), settlementPDA.toBuffer()],
+        program.programId
+      );
+      const arbiter = Keypair.generate();
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setArbitration({ arbiter: arbiter.publicKey, mode: { disputeWindow: { seconds: new anchor.BN(3600) } } })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .holdEscrow(new anchor.BN(REQUEST_AMOUNT))
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: escrowPDA,
+          requestMint: requestMint,
+          buyerRequestToken: buyerRequestToken,
+          settlement: settlementPDA,
+          settlementVault: settlementVaultPDA,
+          buyerAllowlist: null,
+          config: configPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      const approve = (party: Keypair) =>
+        program.methods
+          .approveSettlement(true)
+          .accounts({
+            authority: party.publicKey,
+            escrow: escrowPDA,
+            settlement: settlementPDA,
+          })
+          .signers([party])
+          .rpc();
+
+      // The arbiter only rules on disputes in this mode
+      try {
+        await approve(arbiter);
+        expect.fail("Should have thrown an error - arbiter cannot approve");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+      await approve(seller);
+      await approve(buyer);
+
+      await program.methods
+        .releaseSettlement()
+        .accounts({
+          seller: seller.publicKey,
+          buyer: buyer.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          settlement: settlementPDA,
+          settlementVault: settlementVaultPDA,
+          buyerOfferToken: buyerOfferToken,
+          buyerRequestToken: buyerRequestToken,
+          sellerRequestToken: sellerRequestToken,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          offerMetadata: null,
+          pairIndex: pairIndexPDA,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(settlementPDA)).to.be.null;
+    });
+  });
+
+  describe("vesting", () => {
+    beforeEach(async () => {
+      await setupTest();
+    });
+
+    it("Vests a fill's offer tokens to the buyer", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const nonce = new anchor.BN(OFFER_AMOUNT).toArrayLike(Buffer, "le", 8);
+      const [vestingPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("vesting"), escrowPDA.toBuffer(), buyer.publicKey.toBuffer(), nonce],
+        program.programId
+      );
+      const [vestingVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("vesting_vault"), escrowPDA.toBuffer(), buyer.publicKey.toBuffer(), nonce],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setVesting({ cliffSeconds: new anchor.BN(0), durationSeconds: new anchor.BN(2) })
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: null,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: vestingPDA,
+          vestingVault: vestingVaultPDA,
+          stream: null,
+          streamVault: null,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(0);
+      expect(Number((await getAccount(connection, vestingVaultPDA)).amount)).to.equal(OFFER_AMOUNT);
+
+      const { endAt } = await program.account.vesting.fetch(vestingPDA);
+      while ((await connection.getBlockTime(await connection.getSlot())) < endAt.toNumber()) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+
+      await program.methods
+        .claimVested()
+        .accounts({
+          beneficiary: buyer.publicKey,
+          vesting: vestingPDA,
+          vestingVault: vestingVaultPDA,
+          offerMint: offerMint,
+          beneficiaryToken: buyerOfferToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(await connection.getAccountInfo(vestingPDA)).to.be.null;
+    });
+
+    it("Streams a fill's proceeds to the seller", async () => {
+      const [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      const vaultPDA = await deriveVault(escrowPDA, offerMint);
+      const nonce = new anchor.BN(OFFER_AMOUNT).toArrayLike(Buffer, "le", 8);
+      const [streamPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("stream"), escrowPDA.toBuffer(), nonce],
+        program.programId
+      );
+      const [streamVaultPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("stream_vault"), escrowPDA.toBuffer(), nonce],
+        program.programId
+      );
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: null,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .setProceedsStream(new anchor.BN(2))
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      await program.methods
+        .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+        .accounts({
+          buyer: buyer.publicKey,
+          payer: buyer.publicKey,
+          seller: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          escrow: escrowPDA,
+          rentRecipient: null,
+          vault: vaultPDA,
+          buyerRequestToken: buyerRequestToken,
+          buyerOfferToken: buyerOfferToken,
+          beneficiary: null,
+          beneficiaryOfferToken: null,
+          recipientOfferToken: null,
+          reservation: null,
+          fillCommitment: null,
+          offerUnwrap: null,
+          sellerRequestToken: sellerRequestToken,
+          proceedsRecipientToken: null,
+          market: null,
+          marketFeeToken: null,
+          pairFees: null,
+          sellerStats: null,
+          buyerStats: null,
+          buyerAllowlist: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          feeVault: null,
+          takerFeeVault: null,
+          referrerToken: null,
+          offerMetadata: null,
+          gateToken: null,
+          gateMetadata: null,
+          discountToken: null,
+          discountMetadata: null,
+          session: null,
+          memoProgram: null,
+          permissionApproval: null,
+          priceUpdate: null,
+          instructions: null,
+          vesting: null,
+          vestingVault: null,
+          stream: streamPDA,
+          streamVault: streamVaultPDA,
+          proceedsVault: null,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          requestTokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+
+      expect(Number((await getAccount(connection, buyerOfferToken)).amount)).to.equal(OFFER_AMOUNT);
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(0);
+      expect(Number((await getAccount(connection, streamVaultPDA)).amount)).to.equal(REQUEST_AMOUNT);
+
+      const { endAt } = await program.account.stream.fetch(streamPDA);
+      while ((await connection.getBlockTime(await connection.getSlot())) < endAt.toNumber()) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+
+      await program.methods
+        .withdrawStream()
+        .accounts({
+          seller: seller.publicKey,
+          stream: streamPDA,
+          streamVault: streamVaultPDA,
+          buyer: buyer.publicKey,
+          requestMint: requestMint,
+          sellerRequestToken: sellerRequestToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([seller])
+        .rpc();
+
+      expect(Number((await getAccount(connection, sellerRequestToken)).amount)).to.equal(REQUEST_AMOUNT);
+      expect(await connection.getAccountInfo(streamPDA)).to.be.null;
+    });
+  });
+
+  describe("collateral locks", () => {
+    const [lockerAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow_locker")],
+      mockLocker.programId
+    );
+
+    let marketPDA: PublicKey;
+    let feeReceiverToken: PublicKey;
+    let escrowPDA: PublicKey;
+    let vaultPDA: PublicKey;
+
+    function deriveMarketPDA(operator: PublicKey, brandingId: number[]): PublicKey {
+      return PublicKey.findProgramAddressSync(
+        [Buffer.from("market"), operator.toBuffer(), Buffer.from(brandingId)],
+        program.programId
+      )[0];
+    }
+
+    beforeEach(async () => {
+      await setupTest();
+
+      const operator = Keypair.generate();
+      const feeReceiver = Keypair.generate();
+      await airdrop(operator.publicKey);
+
+      const brandingId = Array.from(Buffer.alloc(32, 9));
+      marketPDA = deriveMarketPDA(operator.publicKey, brandingId);
+
+      await program.methods
+        .createMarket(brandingId, feeReceiver.publicKey, 0)
+        .accounts({
+          operator: operator.publicKey,
+          market: marketPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+
+      await program.methods
+        .setMarketAllowlist({ lockers: {} }, [mockLocker.programId])
+        .accounts({
+          operator: operator.publicKey,
+          market: marketPDA,
+        })
+        .signers([operator])
+        .rpc();
+
+      feeReceiverToken = await createAssociatedTokenAccount(
+        connection,
+        buyer,
+        requestMint,
+        feeReceiver.publicKey
+      );
+
+      [escrowPDA] = deriveEscrowPDA(seller.publicKey, offerMint, requestMint);
+      vaultPDA = await deriveVault(escrowPDA, offerMint);
+
+      await program.methods
+        .createEscrow(new anchor.BN(0), new anchor.BN(OFFER_AMOUNT), new anchor.BN(REQUEST_AMOUNT), null, null, null)
+        .accounts({
+          seller: seller.publicKey,
+          payer: seller.publicKey,
+          offerMint: offerMint,
+          requestMint: requestMint,
+          sellerOfferToken: sellerOfferToken,
+          market: marketPDA,
+          offerMetadata: null,
+          buyerAllowlist: null,
+          sellerStake: null,
+          escrow: escrowPDA,
+          vault: vaultPDA,
+          pairIndex: pairIndexPDA,
+          registryPage: null,
+          marketIndex: null,
+          config: configPDA,
+          activityLog: null,
+          logWrapper: null,
+          compressionProgram: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+
+      await mockLocker.methods
+        .lock()
+        .accounts({
+          seller: seller.publicKey,
+          escrow: escrowPDA,
+          market: marketPDA,
+          lockerAuthority: lockerAuthority,
+          lockerProgram: mockLocker.programId,
+          escrowProgram: program.programId,
+        })
+        .signers([seller])
+        .rpc();
+    });
+
+    it("Records the locking program on the escrow", async () => {
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.lockedBy.toString()).to.equal(mockLocker.programId.toString());
+    });
+
+    it("Blocks cancel, update and accept while locked", async () => {
+      try {
+        await program.methods
+          .cancelEscrow(null)
+          .accounts({
+            authority: seller.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            sellerOperator: null,
+            session: null,
+            memoProgram: null,
+            vault: vaultPDA,
+            sellerOfferToken: sellerOfferToken,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            config: null,
+            treasury: null,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            receiptMint: null,
+            sellerReceiptToken: null,
+            proceedsVault: null,
+            requestMint: null,
+            requestTokenProgram: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowLocked");
+      }
+
+      try {
+        await program.methods
+          .updateEscrow(new anchor.BN(REQUEST_AMOUNT * 2), null, null)
+          .accounts({
+            authority: seller.publicKey,
+            config: configPDA,
+            escrow: escrowPDA,
+            sellerOperator: null,
+          })
+          .signers([seller])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowLocked");
+      }
+
+      try {
+        await program.methods
+          .acceptEscrow(new anchor.BN(OFFER_AMOUNT), null, null, null, null, null)
+          .accounts({
+            buyer: buyer.publicKey,
+            payer: buyer.publicKey,
+            seller: seller.publicKey,
+            offerMint: offerMint,
+            requestMint: requestMint,
+            escrow: escrowPDA,
+            rentRecipient: null,
+            vault: vaultPDA,
+            buyerRequestToken: buyerRequestToken,
+            buyerOfferToken: buyerOfferToken,
+            beneficiary: null,
+            beneficiaryOfferToken: null,
+            recipientOfferToken: null,
+            reservation: null,
+            fillCommitment: null,
+            offerUnwrap: null,
+            sellerRequestToken: sellerRequestToken,
+            proceedsRecipientToken: null,
+            market: marketPDA,
+            marketFeeToken: feeReceiverToken,
+            pairFees: PublicKey.findProgramAddressSync(
+              [Buffer.from("pair_fees"), marketPDA.toBuffer(), offerMint.toBuffer(), requestMint.toBuffer()],
+              program.programId
+            )[0],
+            sellerStats: PublicKey.findProgramAddressSync(
+              [Buffer.from("trader_stats"), seller.publicKey.toBuffer(), requestMint.toBuffer()],
+              program.programId
+            )[0],
+            buyerStats: PublicKey.findProgramAddressSync(
+              [Buffer.from("trader_stats"), buyer.publicKey.toBuffer(), requestMint.toBuffer()],
+              program.programId
+            )[0],
+            buyerAllowlist: null,
+            config: configPDA,
+            activityLog: null,
+            logWrapper: null,
+            compressionProgram: null,
+            feeVault: null,
+            takerFeeVault: null,
+            referrerToken: null,
+            offerMetadata: null,
+            gateToken: null,
+            gateMetadata: null,
+            discountToken: null,
+            discountMetadata: null,
+            session: null,
+            memoProgram: null,
+            permissionApproval: null,
+            priceUpdate: null,
+            instructions: null,
+            vesting: null,
+            vestingVault: null,
+            stream: null,
+            streamVault: null,
+            proceedsVault: null,
+            pairIndex: pairIndexPDA,
+            registryPage: null,
+            marketIndex: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            requestTokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([buyer])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("EscrowLocked");
+      }
+
+      const vaultBalance = (await getAccount(connection, vaultPDA)).amount;
+      expect(Number(vaultBalance)).to.equal(OFFER_AMOUNT);
+    });
+
+    it("Only lets the locking program unlock", async () => {
+      // A signer posing as the locker of another program
+      const impostor = Keypair.generate();
+
+      try {
+        await program.methods
+          .unlockEscrow()
+          .accounts({
+            escrow: escrowPDA,
+            lockerAuthority: impostor.publicKey,
+            lockerProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([impostor])
+          .rpc();
+        expect.fail("Should have thrown an error");
+      } catch (err: any) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.lockedBy.toString()).to.equal(mockLocker.programId.toString());
+    });
+
+    it("Unlocks through the locking program", async () => {
+      await mockLocker.methods
+        .unlock()
+        .accounts({
+          escrow: escrowPDA,
+          lockerAuthority: lockerAuthority,
+          lockerProgram: mockLocker.programId,
+          escrowProgram: program.programId,
+        })
+        .rpc();
+
+      const escrow = await program.account.escrow.fetch(escrowPDA);
+      expect(escrow.lockedBy.equals(PublicKey.default)).to.be.true;
+
+      await program.methods
+        .updateEscrow(new anchor.BN(REQUEST_AMOUNT * 2), null, null)
+        .accounts({
+          authority: seller.publicKey,
+          config: configPDA,
+          escrow: escrowPDA,
+          sellerOperator: null,
+        })
+        .signers([seller])
+        .rpc();
+
+      const updated = await program.account.escrow.fetch(escrowPDA);
+      expect(updated.requestAmount.toNumber()).to.equal(REQUEST_AMOUNT * 2);
     });
   });
 });